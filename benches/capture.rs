@@ -0,0 +1,80 @@
+//! Cost of the agent's work on the application's thread.
+//!
+//! Run with `cargo bench`. `agent/*` measures a whole capture as done by
+//! `capture_error`, without sending; the other groups measure its parts.
+
+use aivory_monitor::capture;
+use aivory_monitor::{Agent, Config};
+use aivory_monitor_core::hash_fingerprint;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use std::fmt;
+
+#[derive(Debug)]
+struct OrderError {
+    order_id: u64,
+}
+
+impl fmt::Display for OrderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "order {} not found", self.order_id)
+    }
+}
+
+impl std::error::Error for OrderError {}
+
+fn config(api_key: &str) -> Config {
+    Config::builder()
+        .api_key(api_key)
+        .silent()
+        .build()
+        .expect("invalid benchmark configuration")
+}
+
+fn bench_capture(c: &mut Criterion) {
+    let config = config("bench-key");
+    let error = OrderError { order_id: 42 };
+    c.bench_function("capture/error", |b| {
+        b.iter(|| capture::capture_error(black_box(&error), &config))
+    });
+}
+
+fn bench_fingerprint(c: &mut Criterion) {
+    let parts: Vec<String> = (0..10)
+        .map(|i| format!("app::orders::handler_{}:src/orders.rs:{}", i, 100 + i))
+        .collect();
+    c.bench_function("fingerprint/10_frames", |b| {
+        b.iter(|| hash_fingerprint(black_box(&parts)))
+    });
+}
+
+fn bench_serialize(c: &mut Criterion) {
+    let config = config("bench-key");
+    let exc = capture::capture_error(&OrderError { order_id: 42 }, &config);
+    c.bench_function("serialize/event", |b| {
+        b.iter(|| serde_json::to_vec(black_box(&exc)).unwrap())
+    });
+}
+
+fn bench_agent(c: &mut Criterion) {
+    let error = OrderError { order_id: 42 };
+
+    // Never started, so events are dropped once handed to the transport.
+    let agent = Agent::new(config("bench-key"));
+    c.bench_function("agent/capture_error", |b| {
+        b.iter(|| agent.capture_error(black_box(&error), None))
+    });
+
+    let disabled = Agent::new(config(""));
+    c.bench_function("agent/capture_error_disabled", |b| {
+        b.iter(|| disabled.capture_error(black_box(&error), None))
+    });
+}
+
+criterion_group!(
+    benches,
+    bench_capture,
+    bench_fingerprint,
+    bench_serialize,
+    bench_agent
+);
+criterion_main!(benches);
@@ -0,0 +1,152 @@
+//! Grouping fingerprints.
+//!
+//! Both algorithms hash the error type and up to five top non-native,
+//! unfiltered frames. [`fingerprint`] identifies frames by function name and
+//! line number, so recompiling code that moves lines regroups its errors;
+//! [`fingerprint_v2`] identifies them by their normalized [`function_path`]
+//! alone. The hashes of both are part of the protocol and must not change.
+
+use crate::StackFrame;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use sha2::{Digest, Sha256};
+
+/// Fingerprint of an error: its type and up to five top non-native,
+/// unfiltered frames with their line numbers.
+///
+/// ```
+/// use aivory_monitor_core::{fingerprint, StackFrame};
+///
+/// let stack = [
+///     StackFrame::new("parse").file("src/config.rs", 42),
+///     StackFrame::new("main").file("src/main.rs", 7),
+/// ];
+/// assert_eq!(fingerprint("ParseError", &stack), "641243193265140a");
+/// assert_eq!(fingerprint("panic", &[]), "79631cb1b7b63b6e");
+/// ```
+pub fn fingerprint(exception_type: &str, stack_trace: &[StackFrame]) -> String {
+    let mut parts = vec![exception_type.to_string()];
+    parts.extend(
+        significant_frames(stack_trace)
+            .map(|frame| format!("{}:{}", frame.method_name, frame.line_number.unwrap_or(0))),
+    );
+    hash_fingerprint(&parts)
+}
+
+/// Fingerprint of an error: its type and the [`function_path`] of up to five
+/// top non-native, unfiltered frames, independent of line numbers, generic
+/// arguments and symbol hashes.
+///
+/// ```
+/// use aivory_monitor_core::{fingerprint_v2, StackFrame};
+///
+/// let mut parse = StackFrame::new("parse").file("src/config.rs", 42);
+/// parse.module = Some("my_app::config".into());
+/// let mut main = StackFrame::new("main").file("src/main.rs", 7);
+/// main.module = Some("my_app".into());
+/// assert_eq!(fingerprint_v2("ParseError", &[parse.clone(), main.clone()]), "ca200b7409895e3c");
+///
+/// // Moving code to other lines keeps the fingerprint.
+/// parse.line_number = Some(58);
+/// main.line_number = Some(9);
+/// assert_eq!(fingerprint_v2("ParseError", &[parse, main]), "ca200b7409895e3c");
+/// ```
+pub fn fingerprint_v2(exception_type: &str, stack_trace: &[StackFrame]) -> String {
+    let mut parts = vec![exception_type.to_string()];
+    parts.extend(significant_frames(stack_trace).map(function_path));
+    hash_fingerprint(&parts)
+}
+
+/// The frames a fingerprint is computed from.
+fn significant_frames(stack_trace: &[StackFrame]) -> impl Iterator<Item = &StackFrame> {
+    stack_trace
+        .iter()
+        .filter(|frame| !frame.is_native && !frame.is_filtered)
+        .take(5)
+}
+
+/// Path of a frame's function as used by [`fingerprint_v2`]: its module and
+/// name, without generic arguments or a legacy symbol hash, and with closures
+/// and async blocks written the same under both symbol manglings.
+///
+/// ```
+/// use aivory_monitor_core::{function_path, StackFrame};
+///
+/// let mut frame = StackFrame::new("parse::<u64>::{closure#0}");
+/// frame.module = Some("my_app::Parser<alloc::string::String>".into());
+/// assert_eq!(function_path(&frame), "my_app::Parser::parse::{closure}");
+///
+/// let frame = StackFrame::new("my_app::run::{{closure}}::h0123456789abcdef");
+/// assert_eq!(function_path(&frame), "my_app::run::{closure}");
+/// ```
+pub fn function_path(frame: &StackFrame) -> String {
+    let path = match &frame.module {
+        Some(module) => format!("{}::{}", module, frame.method_name),
+        None => frame.method_name.clone(),
+    };
+    let path = strip_generic_args(&path);
+    let mut segments: Vec<&str> = path
+        .split("::")
+        .filter(|segment| !segment.is_empty())
+        .collect();
+    let legacy_hash = segments
+        .last()
+        .and_then(|last| last.strip_prefix('h'))
+        .is_some_and(|hash| hash.len() == 16 && hash.chars().all(|c| c.is_ascii_hexdigit()));
+    if legacy_hash && segments.len() > 1 {
+        segments.pop();
+    }
+    let segments: Vec<String> = segments.into_iter().map(normalize_segment).collect();
+    segments.join("::")
+}
+
+/// Removes generic argument lists following a name, such as `<T>` in
+/// `Vec<T>` and `::<u64>`, keeping qualified paths like `<T as Trait>`.
+fn strip_generic_args(path: &str) -> String {
+    let mut stripped = String::with_capacity(path.len());
+    let mut depth = 0usize;
+    for c in path.chars() {
+        match c {
+            '<' if depth > 0 => depth += 1,
+            '<' if stripped.ends_with(|p: char| p.is_alphanumeric() || p == '_' || p == ':') => {
+                depth = 1;
+                if stripped.ends_with("::") {
+                    stripped.truncate(stripped.len() - 2);
+                }
+            }
+            '>' if depth > 0 => depth -= 1,
+            _ if depth > 0 => {}
+            _ => stripped.push(c),
+        }
+    }
+    stripped
+}
+
+/// Writes `{{closure}}` (legacy mangling) and `{closure#0}` (v0 mangling)
+/// both as `{closure}`, and likewise for other compiler-generated segments.
+fn normalize_segment(segment: &str) -> String {
+    match segment.strip_prefix('{') {
+        Some(inner) => {
+            let inner = inner.trim_start_matches('{').trim_end_matches('}');
+            let name = inner.split('#').next().unwrap_or(inner);
+            format!("{{{}}}", name)
+        }
+        None => segment.to_string(),
+    }
+}
+
+/// Hashes fingerprint components into the 16 hex digit form the backend groups by.
+///
+/// ```
+/// use aivory_monitor_core::hash_fingerprint;
+///
+/// assert_eq!(hash_fingerprint(&["panic".into(), "main:7".into()]), "108d13bc36ea0897");
+/// ```
+pub fn hash_fingerprint(parts: &[String]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(parts.join(":"));
+    let result = hasher.finalize();
+    hex::encode(&result[..8])
+}
@@ -0,0 +1,86 @@
+//! Stack frames and error causes.
+
+use alloc::string::{String, ToString};
+use serde::{Deserialize, Serialize};
+
+/// An error in the `source()` chain of a captured error.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ErrorCause {
+    pub exception_type: String,
+    pub message: String,
+}
+
+/// A single stack frame.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StackFrame {
+    pub method_name: String,
+    /// Path of the enclosing module or type, e.g. `my_crate::orders::Order`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub module: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crate_name: Option<String>,
+    /// Full symbol path, with generic arguments.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub full_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub line_number: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub column_number: Option<u32>,
+    /// Address of the instruction as `0x` hex, for frames that could not be
+    /// symbolicated in the process.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instruction_addr: Option<String>,
+    /// Load address of the binary or library containing `instruction_addr`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_addr: Option<String>,
+    #[serde(default)]
+    pub is_native: bool,
+    #[serde(default)]
+    pub source_available: bool,
+    /// Matched a frame filter and was kept because `keep_filtered_frames` is set.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub is_filtered: bool,
+    /// Inlined by the compiler into the frame below it, so it shares that
+    /// frame's instruction.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub is_inline: bool,
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+impl StackFrame {
+    /// Creates a frame for `method_name` with no source location.
+    pub fn new(method_name: impl Into<String>) -> Self {
+        StackFrame {
+            method_name: method_name.into(),
+            module: None,
+            crate_name: None,
+            full_name: None,
+            file_name: None,
+            file_path: None,
+            line_number: None,
+            column_number: None,
+            instruction_addr: None,
+            image_addr: None,
+            is_native: false,
+            source_available: false,
+            is_filtered: false,
+            is_inline: false,
+        }
+    }
+
+    /// Sets the source file and line.
+    pub fn file(mut self, path: impl Into<String>, line: u32) -> Self {
+        let path = path.into();
+        self.file_name = path.split(['/', '\\']).next_back().map(|s| s.to_string());
+        self.file_path = Some(path);
+        self.line_number = Some(line);
+        self
+    }
+}
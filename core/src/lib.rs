@@ -0,0 +1,47 @@
+//! `no_std` event model and fingerprinting for the AIVory Monitor Rust Agent.
+//!
+//! Firmware (RTIC, Embassy, bare metal) builds [`CrashRecord`]s locally and
+//! hands them to a [`Transport`] of its choosing: a UART, a radio link or
+//! flash storage read back after reset. A gateway running the full
+//! `aivory-monitor` agent forwards them with the same schema and grouping
+//! as events captured on the host.
+//!
+//! Only `alloc` is required. The `postcard` feature adds a compact framed
+//! encoding for serial and RTT links in [`wire`], and `defmt` implements
+//! `defmt::Format` for [`CrashRecord`].
+//!
+//! ```rust,ignore
+//! use aivory_monitor_core::{report, CrashRecord, StackFrame, Transport};
+//!
+//! struct Uart(/* ... */);
+//!
+//! impl Transport for Uart {
+//!     type Error = ();
+//!
+//!     fn send(&mut self, record: &CrashRecord) -> Result<(), ()> {
+//!         let json = serde_json::to_vec(record).map_err(|_| ())?;
+//!         self.write_frame(&json)
+//!     }
+//! }
+//!
+//! let record = CrashRecord::new("SensorTimeout")
+//!     .message("no response from IMU")
+//!     .frame(StackFrame::new("read_imu").file("src/imu.rs", 88));
+//! report(&mut uart, record)?;
+//! ```
+
+#![no_std]
+
+extern crate alloc;
+
+mod fingerprint;
+mod frame;
+mod record;
+mod transport;
+#[cfg(feature = "postcard")]
+pub mod wire;
+
+pub use fingerprint::{fingerprint, fingerprint_v2, function_path, hash_fingerprint};
+pub use frame::{ErrorCause, StackFrame};
+pub use record::CrashRecord;
+pub use transport::{report, Transport};
@@ -0,0 +1,130 @@
+//! Crash records built on the device.
+
+use crate::{ErrorCause, StackFrame};
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::panic::PanicInfo;
+use serde::{Deserialize, Serialize};
+
+/// An error captured on a device without the full agent.
+///
+/// Carries the parts of an event the device knows about. The gateway adds
+/// the event ID, agent identity and runtime details when forwarding it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CrashRecord {
+    pub exception_type: String,
+    #[serde(default)]
+    pub message: String,
+    /// Grouping fingerprint; computed from the type and top frames when not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<String>,
+    /// Stack frames, innermost first.
+    #[serde(default)]
+    pub stack_trace: Vec<StackFrame>,
+    /// Errors in the cause chain, outermost first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub causes: Vec<ErrorCause>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub context: BTreeMap<String, serde_json::Value>,
+    /// Unix time in milliseconds, if the device has a clock. Defaults to the
+    /// time the gateway forwards the record.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub captured_at: Option<i64>,
+}
+
+impl CrashRecord {
+    /// Creates a record for an error of `exception_type`.
+    pub fn new(exception_type: impl Into<String>) -> Self {
+        CrashRecord {
+            exception_type: exception_type.into(),
+            message: String::new(),
+            fingerprint: None,
+            stack_trace: Vec::new(),
+            causes: Vec::new(),
+            context: BTreeMap::new(),
+            captured_at: None,
+        }
+    }
+
+    /// Creates a `panic` record from a `#[panic_handler]`'s argument, with
+    /// the panic location as its only frame.
+    pub fn from_panic(info: &PanicInfo<'_>) -> Self {
+        // `PanicInfo::message` needs a newer compiler; strip the location
+        // line `Display` puts in front of the message instead.
+        let rendered = format!("{}", info);
+        let message = match rendered.split_once('\n') {
+            Some((head, message)) if head.starts_with("panicked at ") => message.to_string(),
+            _ => rendered,
+        };
+
+        let mut record = CrashRecord::new("panic").message(message);
+        if let Some(location) = info.location() {
+            let mut frame = StackFrame::new("<panic>").file(location.file(), location.line());
+            frame.column_number = Some(location.column());
+            record = record.frame(frame);
+        }
+        record
+    }
+
+    /// Sets the error message.
+    pub fn message(mut self, message: impl Into<String>) -> Self {
+        self.message = message.into();
+        self
+    }
+
+    /// Sets the grouping fingerprint.
+    pub fn fingerprint(mut self, fingerprint: impl Into<String>) -> Self {
+        self.fingerprint = Some(fingerprint.into());
+        self
+    }
+
+    /// Appends a stack frame, innermost first.
+    pub fn frame(mut self, frame: StackFrame) -> Self {
+        self.stack_trace.push(frame);
+        self
+    }
+
+    /// Appends an error to the cause chain, outermost first.
+    pub fn cause(mut self, exception_type: impl Into<String>, message: impl Into<String>) -> Self {
+        self.causes.push(ErrorCause {
+            exception_type: exception_type.into(),
+            message: message.into(),
+        });
+        self
+    }
+
+    /// Adds a context value.
+    pub fn context(mut self, key: impl Into<String>, value: impl Into<serde_json::Value>) -> Self {
+        self.context.insert(key.into(), value.into());
+        self
+    }
+
+    /// Sets when the error occurred, as Unix time in milliseconds.
+    pub fn captured_at(mut self, unix_millis: i64) -> Self {
+        self.captured_at = Some(unix_millis);
+        self
+    }
+
+    /// Returns the fingerprint, computing it if none was set.
+    pub fn resolved_fingerprint(&self) -> String {
+        match &self.fingerprint {
+            Some(fingerprint) => fingerprint.clone(),
+            None => crate::fingerprint(&self.exception_type, &self.stack_trace),
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for CrashRecord {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        defmt::write!(f, "{=str}: {=str}", self.exception_type, self.message);
+        if let Some(frame) = self.stack_trace.first() {
+            defmt::write!(f, " at {=str}", frame.method_name);
+            if let (Some(file), Some(line)) = (&frame.file_path, frame.line_number) {
+                defmt::write!(f, " ({=str}:{=u32})", file, line);
+            }
+        }
+    }
+}
@@ -0,0 +1,26 @@
+//! Pluggable delivery of crash records.
+
+use crate::CrashRecord;
+
+/// Delivers crash records off the device, e.g. over a serial link to a
+/// gateway or into flash to be sent after reset.
+pub trait Transport {
+    type Error;
+
+    /// Sends one record.
+    fn send(&mut self, record: &CrashRecord) -> Result<(), Self::Error>;
+}
+
+/// Fills in the record's fingerprint, if unset, and sends it.
+///
+/// Fingerprinting on the device keeps records grouped the same way even if
+/// the gateway only stores them.
+pub fn report<T: Transport + ?Sized>(
+    transport: &mut T,
+    mut record: CrashRecord,
+) -> Result<(), T::Error> {
+    if record.fingerprint.is_none() {
+        record.fingerprint = Some(record.resolved_fingerprint());
+    }
+    transport.send(&record)
+}
@@ -0,0 +1,203 @@
+//! Compact wire format for serial and RTT links.
+//!
+//! Records are encoded with [postcard](https://docs.rs/postcard) and framed
+//! with COBS, so every frame ends in a `0x00` byte and a reader that joins
+//! mid-stream resynchronizes at the next one. Context values are carried as
+//! JSON text, since postcard cannot encode arbitrary JSON directly.
+
+use crate::{CrashRecord, ErrorCause, StackFrame, Transport};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use serde::{Deserialize, Serialize};
+
+/// Version of the wire layout, sent as the first field of every frame.
+pub const WIRE_VERSION: u8 = 1;
+
+/// Why a frame could not be encoded or decoded.
+#[derive(Debug)]
+pub enum WireError {
+    /// The frame was written by an incompatible version of this crate.
+    UnsupportedVersion(u8),
+    /// The frame is truncated or corrupt.
+    Postcard(postcard::Error),
+    /// A context value is not valid JSON.
+    Context(serde_json::Error),
+}
+
+impl fmt::Display for WireError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WireError::UnsupportedVersion(version) => {
+                write!(f, "unsupported wire version {}", version)
+            }
+            WireError::Postcard(e) => write!(f, "invalid frame: {}", e),
+            WireError::Context(e) => write!(f, "invalid context value: {}", e),
+        }
+    }
+}
+
+/// Encodes `record` as one COBS frame, including the trailing `0x00`.
+pub fn encode(record: &CrashRecord) -> Result<Vec<u8>, WireError> {
+    let mut context = Vec::with_capacity(record.context.len());
+    for (key, value) in &record.context {
+        context.push((
+            key.clone(),
+            serde_json::to_string(value).map_err(WireError::Context)?,
+        ));
+    }
+    let wire = WireRecord {
+        version: WIRE_VERSION,
+        exception_type: record.exception_type.clone(),
+        message: record.message.clone(),
+        fingerprint: record.fingerprint.clone(),
+        stack_trace: record.stack_trace.iter().map(WireFrame::from).collect(),
+        causes: record
+            .causes
+            .iter()
+            .map(|cause| (cause.exception_type.clone(), cause.message.clone()))
+            .collect(),
+        context,
+        captured_at: record.captured_at,
+    };
+    postcard::to_allocvec_cobs(&wire).map_err(WireError::Postcard)
+}
+
+/// Decodes one COBS frame, with or without its trailing `0x00`.
+pub fn decode(frame: &[u8]) -> Result<CrashRecord, WireError> {
+    // COBS decoding happens in place and leaves the message at the start of
+    // the buffer. The version comes first so that layout changes are
+    // reported as such rather than as corrupt frames.
+    let mut buf = frame.to_vec();
+    let (version, _) =
+        postcard::take_from_bytes_cobs::<u8>(&mut buf).map_err(WireError::Postcard)?;
+    if version != WIRE_VERSION {
+        return Err(WireError::UnsupportedVersion(version));
+    }
+
+    let wire: WireRecord = postcard::from_bytes(&buf).map_err(WireError::Postcard)?;
+    let mut record = CrashRecord::new(wire.exception_type).message(wire.message);
+    record.fingerprint = wire.fingerprint;
+    record.stack_trace = wire.stack_trace.into_iter().map(StackFrame::from).collect();
+    record.causes = wire
+        .causes
+        .into_iter()
+        .map(|(exception_type, message)| ErrorCause {
+            exception_type,
+            message,
+        })
+        .collect();
+    for (key, value) in wire.context {
+        record.context.insert(
+            key,
+            serde_json::from_str(&value).map_err(WireError::Context)?,
+        );
+    }
+    record.captured_at = wire.captured_at;
+    Ok(record)
+}
+
+/// Sends records as COBS frames through `write`, e.g. to a UART or an RTT
+/// up channel.
+///
+/// ```rust,ignore
+/// let mut channel = rtt_init_print!(...).up.1;
+/// let mut transport = SerialTransport::new(|frame: &[u8]| {
+///     channel.write(frame);
+///     Ok::<(), ()>(())
+/// });
+/// report(&mut transport, record)?;
+/// ```
+pub struct SerialTransport<F> {
+    write: F,
+}
+
+impl<F, E> SerialTransport<F>
+where
+    F: FnMut(&[u8]) -> Result<(), E>,
+{
+    /// Creates a transport writing each encoded frame with `write`.
+    pub fn new(write: F) -> Self {
+        SerialTransport { write }
+    }
+}
+
+/// Error of a [`SerialTransport`].
+#[derive(Debug)]
+pub enum SerialError<E> {
+    Encode(WireError),
+    Write(E),
+}
+
+impl<F, E> Transport for SerialTransport<F>
+where
+    F: FnMut(&[u8]) -> Result<(), E>,
+{
+    type Error = SerialError<E>;
+
+    fn send(&mut self, record: &CrashRecord) -> Result<(), Self::Error> {
+        let frame = encode(record).map_err(SerialError::Encode)?;
+        (self.write)(&frame).map_err(SerialError::Write)
+    }
+}
+
+/// Wire layout of a [`CrashRecord`]. Postcard is not self-describing, so
+/// every field is always written.
+#[derive(Serialize, Deserialize)]
+struct WireRecord {
+    version: u8,
+    exception_type: String,
+    message: String,
+    fingerprint: Option<String>,
+    stack_trace: Vec<WireFrame>,
+    causes: Vec<(String, String)>,
+    context: Vec<(String, String)>,
+    captured_at: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct WireFrame {
+    method_name: String,
+    module: Option<String>,
+    file_path: Option<String>,
+    line_number: Option<u32>,
+    column_number: Option<u32>,
+    is_native: bool,
+    source_available: bool,
+    is_filtered: bool,
+}
+
+impl From<&StackFrame> for WireFrame {
+    fn from(frame: &StackFrame) -> Self {
+        WireFrame {
+            method_name: frame.method_name.clone(),
+            module: frame.module.clone(),
+            file_path: frame.file_path.clone(),
+            line_number: frame.line_number,
+            column_number: frame.column_number,
+            is_native: frame.is_native,
+            source_available: frame.source_available,
+            is_filtered: frame.is_filtered,
+        }
+    }
+}
+
+impl From<WireFrame> for StackFrame {
+    fn from(wire: WireFrame) -> Self {
+        let mut frame = match (wire.file_path, wire.line_number) {
+            (Some(path), Some(line)) => StackFrame::new(wire.method_name).file(path, line),
+            (path, line) => {
+                let mut frame = StackFrame::new(wire.method_name);
+                frame.file_path = path;
+                frame.line_number = line;
+                frame
+            }
+        };
+        frame.module = wire.module;
+        frame.column_number = wire.column_number;
+        frame.is_native = wire.is_native;
+        frame.source_available = wire.source_available;
+        frame.is_filtered = wire.is_filtered;
+        frame
+    }
+}
@@ -0,0 +1,252 @@
+//! Derive macros for the AIVory Monitor Rust Agent.
+//!
+//! `#[derive(Capturable)]` implements `aivory_monitor::capture::Capturable`
+//! for an error type, so its fields are reported as structured
+//! `local_variables` instead of only its `Display` output. It is meant to sit
+//! next to thiserror's `Error` derive and honours its attributes: `#[source]`
+//! and `#[from]` fields are left out, as they are reported as causes.
+//!
+//! ```rust,ignore
+//! use aivory_monitor::capture::Capturable;
+//!
+//! #[derive(Debug, thiserror::Error, Capturable)]
+//! enum OrderError {
+//!     #[error("order {order_id} not found")]
+//!     NotFound { order_id: u64 },
+//!     #[error("payment declined")]
+//!     PaymentDeclined {
+//!         #[capture(serialize)]
+//!         attempt: PaymentAttempt,
+//!         #[capture(skip)]
+//!         card_token: String,
+//!     },
+//! }
+//! ```
+//!
+//! Fields are captured through `Debug`; `#[capture(serialize)]` walks a
+//! `Serialize` field into a variable tree instead, and `#[capture(skip)]`
+//! leaves a field out.
+//!
+//! `#[aivory_monitor::main]` runs a `main` returning `Result` through
+//! `aivory_monitor::main_wrapper`, so the error that ends the program is
+//! captured. Put it above a runtime's attribute such as `#[tokio::main]`.
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, Data, DeriveInput, Field, Fields, Ident, Index, ItemFn, ReturnType};
+
+/// Implements `Capturable`, reporting the error's fields as local variables.
+#[proc_macro_derive(Capturable, attributes(capture))]
+pub fn derive_capturable(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Captures the error returned from `main` with `aivory_monitor::main_wrapper`.
+#[proc_macro_attribute]
+pub fn main(args: TokenStream, item: TokenStream) -> TokenStream {
+    if !args.is_empty() {
+        let error = syn::Error::new(
+            Span::call_site(),
+            "`#[aivory_monitor::main]` takes no arguments",
+        );
+        return error.into_compile_error().into();
+    }
+    let function = parse_macro_input!(item as ItemFn);
+    expand_main(function)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Moves the function's body, with its attributes, into an inner function
+/// that the outer one runs through `main_wrapper`.
+fn expand_main(function: ItemFn) -> syn::Result<TokenStream2> {
+    let ItemFn {
+        attrs,
+        vis,
+        sig,
+        block,
+    } = function;
+    if !sig.inputs.is_empty() || !sig.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &sig,
+            "expected a function without arguments or generics",
+        ));
+    }
+    if let ReturnType::Default = sig.output {
+        return Err(syn::Error::new_spanned(
+            &sig,
+            "expected a function returning `Result`",
+        ));
+    }
+
+    let mut inner = sig.clone();
+    inner.ident = Ident::new("__aivory_main", Span::call_site());
+    let mut outer = sig;
+    // The runtime attribute moves with the body, leaving the outer function synchronous.
+    outer.asyncness = None;
+    let inner_ident = &inner.ident;
+    Ok(quote! {
+        #vis #outer {
+            #(#attrs)*
+            #inner #block
+
+            ::aivory_monitor::main_wrapper(#inner_ident)
+        }
+    })
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let (fields_body, variant_body) = match &input.data {
+        Data::Struct(data) => {
+            let (pattern, variables) = destructure(&data.fields)?;
+            (
+                quote! {
+                    let #name #pattern = self;
+                    ::std::vec![#(#variables),*]
+                },
+                quote! { ::std::option::Option::None },
+            )
+        }
+        Data::Enum(data) => {
+            let mut field_arms = Vec::new();
+            let mut variant_arms = Vec::new();
+            for variant in &data.variants {
+                let ident = &variant.ident;
+                let label = ident.to_string();
+                let (pattern, variables) = destructure(&variant.fields)?;
+                field_arms.push(quote! { #name::#ident #pattern => ::std::vec![#(#variables),*] });
+                let wildcard = match &variant.fields {
+                    Fields::Named(_) => quote! { { .. } },
+                    Fields::Unnamed(_) => quote! { (..) },
+                    Fields::Unit => quote! {},
+                };
+                variant_arms.push(
+                    quote! { #name::#ident #wildcard => ::std::option::Option::Some(#label) },
+                );
+            }
+            if data.variants.is_empty() {
+                (quote! { match *self {} }, quote! { match *self {} })
+            } else {
+                (
+                    quote! { match self { #(#field_arms,)* } },
+                    quote! { match self { #(#variant_arms,)* } },
+                )
+            }
+        }
+        Data::Union(_) => {
+            return Err(syn::Error::new(
+                Span::call_site(),
+                "Capturable cannot be derived for unions",
+            ));
+        }
+    };
+
+    Ok(quote! {
+        impl #impl_generics ::aivory_monitor::capture::Capturable for #name #ty_generics #where_clause {
+            fn variant(&self) -> ::std::option::Option<&'static str> {
+                #variant_body
+            }
+
+            #[allow(unused_variables)]
+            fn fields(&self, config: &::aivory_monitor::Config) -> ::std::vec::Vec<::aivory_monitor::capture::Variable> {
+                #fields_body
+            }
+        }
+    })
+}
+
+/// How a field is captured.
+enum Mode {
+    Debug,
+    Serialize,
+    Skip,
+}
+
+fn mode(field: &Field) -> syn::Result<Mode> {
+    let mut mode = Mode::Debug;
+    for attr in &field.attrs {
+        if attr.path().is_ident("source")
+            || attr.path().is_ident("from")
+            || attr.path().is_ident("backtrace")
+        {
+            mode = Mode::Skip;
+        } else if attr.path().is_ident("capture") {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("skip") {
+                    mode = Mode::Skip;
+                    Ok(())
+                } else if meta.path.is_ident("serialize") {
+                    mode = Mode::Serialize;
+                    Ok(())
+                } else {
+                    Err(meta.error("expected `skip` or `serialize`"))
+                }
+            })?;
+        }
+    }
+    // thiserror treats a field named `source` as the source without an attribute.
+    if field.ident.as_ref().is_some_and(|ident| ident == "source") {
+        mode = Mode::Skip;
+    }
+    Ok(mode)
+}
+
+/// The pattern binding the captured fields, and the expressions building
+/// their variables.
+fn destructure(fields: &Fields) -> syn::Result<(TokenStream2, Vec<TokenStream2>)> {
+    let mut variables = Vec::new();
+    let pattern = match fields {
+        Fields::Named(named) => {
+            let mut bindings = Vec::new();
+            for field in &named.named {
+                let ident = field.ident.as_ref().expect("named field");
+                match mode(field)? {
+                    Mode::Skip => continue,
+                    mode => {
+                        variables.push(variable(&ident.to_string(), ident, mode));
+                        bindings.push(ident.clone());
+                    }
+                }
+            }
+            quote! { { #(#bindings,)* .. } }
+        }
+        Fields::Unnamed(unnamed) => {
+            let mut bindings = Vec::new();
+            for (index, field) in unnamed.unnamed.iter().enumerate() {
+                let binding = format_ident!("field_{}", index);
+                match mode(field)? {
+                    Mode::Skip => bindings.push(quote! { _ }),
+                    mode => {
+                        variables.push(variable(
+                            &Index::from(index).index.to_string(),
+                            &binding,
+                            mode,
+                        ));
+                        bindings.push(quote! { #binding });
+                    }
+                }
+            }
+            quote! { ( #(#bindings),* ) }
+        }
+        Fields::Unit => quote! {},
+    };
+    Ok((pattern, variables))
+}
+
+fn variable(name: &str, binding: &Ident, mode: Mode) -> TokenStream2 {
+    match mode {
+        Mode::Serialize => quote! {
+            ::aivory_monitor::capture::Variable::from_serialize(#name, #binding, config)
+        },
+        _ => quote! {
+            ::aivory_monitor::capture::Variable::from_debug(#name, #binding, config)
+        },
+    }
+}
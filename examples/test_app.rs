@@ -17,24 +17,24 @@ fn main() {
     println!("===========================================");
 
     // Initialize the agent
-    aivory_monitor::init(aivory_monitor::Config {
-        api_key: std::env::var("AIVORY_API_KEY").unwrap_or_else(|_| "test-key-123".to_string()),
-        backend_url: std::env::var("AIVORY_BACKEND_URL")
-            .unwrap_or_else(|_| "ws://localhost:19999/api/monitor/agent/v1".to_string()),
-        environment: std::env::var("AIVORY_ENVIRONMENT")
-            .unwrap_or_else(|_| "development".to_string()),
-        debug: std::env::var("AIVORY_DEBUG")
-            .map(|v| v.to_lowercase() == "true")
-            .unwrap_or(false),
-        ..Default::default()
-    });
+    let config = aivory_monitor::Config::builder()
+        .api_key(std::env::var("AIVORY_API_KEY").unwrap_or_else(|_| "test-key-123".to_string()))
+        .backend_url(
+            std::env::var("AIVORY_BACKEND_URL")
+                .unwrap_or_else(|_| "ws://localhost:19999/api/monitor/agent/v1".to_string()),
+        )
+        .environment(
+            std::env::var("AIVORY_ENVIRONMENT").unwrap_or_else(|_| "development".to_string()),
+        )
+        .build()
+        .expect("invalid AIVory configuration");
+    aivory_monitor::init(config);
 
     // Set user context
-    aivory_monitor::set_user(
-        Some("test-user-001"),
-        Some("tester@example.com"),
-        Some("tester"),
-    );
+    let mut user = aivory_monitor::User::new("test-user-001");
+    user.email = Some("tester@example.com".to_string());
+    user.username = Some("tester".to_string());
+    aivory_monitor::set_user(user);
 
     // Wait for agent to connect
     println!("Waiting for agent to connect...");
@@ -0,0 +1,75 @@
+/*
+ * AIVory Monitor C API.
+ *
+ * Build the library with:
+ *   cargo rustc --release --features cdylib --crate-type cdylib
+ *
+ * Strings are NUL-terminated UTF-8 and are copied before each call returns.
+ */
+
+#ifndef AIVORY_MONITOR_H
+#define AIVORY_MONITOR_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/*
+ * Initializes the global agent. `api_key` and `environment` may be NULL to
+ * use AIVORY_API_KEY and AIVORY_ENVIRONMENT; other settings are read from
+ * AIVORY_* environment variables. Returns 0 on success, -1 if the
+ * configuration is invalid.
+ */
+int aivory_init(const char *api_key, const char *environment);
+
+/*
+ * Captures an event. `exception_type` may be NULL, in which case "Message"
+ * is used.
+ */
+void aivory_capture_message(const char *exception_type, const char *message);
+
+/*
+ * Sets the user affected by subsequent events. Any argument may be NULL;
+ * all NULL clears the user.
+ */
+void aivory_set_user(const char *id, const char *email, const char *username);
+
+/*
+ * Serializes the context active on the current thread into a token for
+ * aivory_scope_enter in another module or library with its own copy of the
+ * agent. Returns NULL on failure; free it with aivory_string_free.
+ */
+char *aivory_scope_export(void);
+
+/*
+ * Makes the scope in `token` active on the current thread until
+ * aivory_scope_exit, so events captured here carry that context. Returns 0
+ * on success, -1 if the token is invalid.
+ */
+int aivory_scope_enter(const char *token);
+
+/*
+ * Ends the scope entered last with aivory_scope_enter on the current thread.
+ * Returns 1 if one was active, 0 otherwise.
+ */
+int aivory_scope_exit(void);
+
+/* Frees a string returned by the agent. NULL is ignored. */
+void aivory_string_free(char *ptr);
+
+/*
+ * Blocks until captured events are sent or `timeout_ms` elapses. Returns 1
+ * if all of them were sent, 0 if some are left or were dropped meanwhile.
+ */
+int aivory_flush(uint32_t timeout_ms);
+
+/* Shuts down the global agent. */
+void aivory_shutdown(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* AIVORY_MONITOR_H */
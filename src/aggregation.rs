@@ -0,0 +1,85 @@
+//! Occurrence aggregation.
+//!
+//! With `aggregation_interval` set, events are held for the interval and
+//! each fingerprint is sent once, as its first occurrence with the number of
+//! occurrences and when the first and last happened. Services that fail the
+//! same way thousands of times a second then send one event per interval
+//! while the counts stay accurate. Panics are never held back.
+
+use crate::capture::{Aggregation, ExceptionCapture};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Distinct fingerprints held per interval; further ones are sent right away.
+const MAX_FINGERPRINTS: usize = 1000;
+
+/// Holds events until the end of the aggregation interval.
+pub(crate) struct Aggregator {
+    state: Mutex<State>,
+}
+
+struct State {
+    window_start: Instant,
+    /// Held events, in order of first occurrence.
+    events: Vec<ExceptionCapture>,
+    /// Index into `events` by project route and fingerprint.
+    index: HashMap<(Option<String>, String), usize>,
+}
+
+impl Aggregator {
+    pub(crate) fn new() -> Self {
+        Aggregator {
+            state: Mutex::new(State {
+                window_start: Instant::now(),
+                events: Vec::new(),
+                index: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Adds an occurrence and returns the events to send now: those of an
+    /// interval that has ended, or `exc` itself if it can't be held.
+    pub(crate) fn add(
+        &self,
+        mut exc: ExceptionCapture,
+        interval: Duration,
+    ) -> Vec<ExceptionCapture> {
+        let state = &mut *self.state.lock();
+        let mut ready = if state.window_start.elapsed() >= interval {
+            state.take()
+        } else {
+            Vec::new()
+        };
+
+        let key = (exc.project_key.clone(), exc.fingerprint.clone());
+        match state.index.get(&key) {
+            Some(&i) => {
+                if let Some(aggregation) = state.events[i].aggregation.as_mut() {
+                    aggregation.count += 1;
+                    aggregation.last_seen = exc.captured_at;
+                }
+            }
+            None if state.events.len() < MAX_FINGERPRINTS => {
+                exc.aggregation = Some(Aggregation::new(&exc));
+                state.index.insert(key, state.events.len());
+                state.events.push(exc);
+            }
+            None => ready.push(exc),
+        }
+        ready
+    }
+
+    /// Removes and returns every held event.
+    pub(crate) fn take(&self) -> Vec<ExceptionCapture> {
+        self.state.lock().take()
+    }
+}
+
+impl State {
+    fn take(&mut self) -> Vec<ExceptionCapture> {
+        self.window_start = Instant::now();
+        self.index.clear();
+        std::mem::take(&mut self.events)
+    }
+}
@@ -0,0 +1,86 @@
+//! Error-rate anomaly hints.
+//!
+//! For every fingerprint the agent keeps two exponentially weighted moving
+//! averages of how often it occurs: a short-term rate over about a minute
+//! and a baseline over about half an hour. An event whose short-term rate is
+//! well above its baseline is flagged with `is_spike`, so the backend can
+//! raise an alert before its own aggregation of the incoming events catches
+//! up. Rates count the occurrences that were captured, so they scale with a
+//! plain `sampling_rate` below 1, which drops events before capture.
+
+use crate::capture::ErrorRate;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Time constant of the short-term rate.
+const SHORT_WINDOW: Duration = Duration::from_secs(60);
+
+/// Time constant of the baseline rate.
+const BASELINE_WINDOW: Duration = Duration::from_secs(30 * 60);
+
+/// How many times its baseline the short-term rate must be to be a spike.
+const SPIKE_FACTOR: f64 = 3.0;
+
+/// Short-term rate, per minute, below which an error is never a spike, so
+/// that an occasional error is not flagged on its second occurrence.
+const MIN_SPIKE_RATE: f64 = 10.0;
+
+/// Fingerprints tracked before the rates are cleared.
+const MAX_FINGERPRINTS: usize = 10_000;
+
+/// Tracks the error rate of each fingerprint.
+pub(crate) struct SpikeDetector {
+    rates: Mutex<HashMap<String, Rates>>,
+}
+
+/// Moving averages of one fingerprint, in occurrences per second.
+struct Rates {
+    short: f64,
+    baseline: f64,
+    updated: Instant,
+}
+
+impl SpikeDetector {
+    pub(crate) fn new() -> Self {
+        SpikeDetector {
+            rates: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Records an occurrence of `fingerprint` and returns its rates.
+    pub(crate) fn observe(&self, fingerprint: &str) -> ErrorRate {
+        let now = Instant::now();
+        let mut rates = self.rates.lock();
+        if rates.len() >= MAX_FINGERPRINTS && !rates.contains_key(fingerprint) {
+            rates.clear();
+        }
+        let entry = rates.entry(fingerprint.to_string()).or_insert(Rates {
+            short: 0.0,
+            baseline: 0.0,
+            updated: now,
+        });
+        let elapsed = now.duration_since(entry.updated);
+        entry.short = add_occurrence(entry.short, elapsed, SHORT_WINDOW);
+        entry.baseline = add_occurrence(entry.baseline, elapsed, BASELINE_WINDOW);
+        entry.updated = now;
+
+        let per_minute = entry.short * 60.0;
+        let baseline_per_minute = entry.baseline * 60.0;
+        ErrorRate {
+            per_minute,
+            baseline_per_minute,
+            rate_delta: per_minute - baseline_per_minute,
+            is_spike: per_minute >= MIN_SPIKE_RATE
+                && per_minute >= SPIKE_FACTOR * baseline_per_minute,
+        }
+    }
+}
+
+/// Decays `rate` over `elapsed` and adds one occurrence, for an average
+/// with time constant `window`. A burst of `n` occurrences raises the rate
+/// by about `n / window`.
+fn add_occurrence(rate: f64, elapsed: Duration, window: Duration) -> f64 {
+    let window = window.as_secs_f64();
+    rate * (-elapsed.as_secs_f64() / window).exp() + 1.0 / window
+}
@@ -0,0 +1,105 @@
+//! Local audit log of event deliveries.
+//!
+//! With `audit_log` set, the ID, fingerprint and delivery outcome of every
+//! event is appended to a file as a JSON line, so what an agent transmitted
+//! can be answered later without asking the backend:
+//!
+//! ```text
+//! {"time":"2024-05-14T09:21:07.512Z","event_id":"6f1c…","fingerprint":"33531eb21c64911a","outcome":"sent"}
+//! {"time":"2024-05-14T09:21:07.538Z","event_id":"6f1c…","outcome":"acked"}
+//! {"time":"2024-05-14T09:21:08.004Z","event_id":"9a07…","fingerprint":"5d2e90c4a1f3b877","outcome":"dropped","reason":"sampled_out"}
+//! ```
+//!
+//! Only identifiers are written, never event content.
+
+use crate::capture::ExceptionCapture;
+use crate::diagnostics::DropReason;
+use chrono::{SecondsFormat, Utc};
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::Instant;
+
+/// Identifies the event a transport frame carries.
+#[derive(Clone)]
+pub(crate) struct EventRef {
+    pub(crate) id: String,
+    pub(crate) fingerprint: String,
+    /// When the event was handed to the transport, for its latency.
+    pub(crate) queued_at: Instant,
+}
+
+impl EventRef {
+    pub(crate) fn new(exc: &ExceptionCapture) -> Self {
+        EventRef {
+            id: exc.id.clone(),
+            fingerprint: exc.fingerprint.clone(),
+            queued_at: Instant::now(),
+        }
+    }
+}
+
+/// What happened to an event.
+#[derive(Clone, Copy)]
+pub(crate) enum Outcome {
+    /// Written to the WebSocket connection.
+    #[cfg_attr(target_os = "wasi", allow(dead_code))]
+    Sent,
+    /// Confirmed by the backend: an `ack` message, or a successful HTTP response.
+    Acked,
+    /// Not delivered.
+    Dropped(DropReason),
+}
+
+#[derive(Serialize)]
+struct Entry<'a> {
+    time: String,
+    event_id: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fingerprint: Option<&'a str>,
+    outcome: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<&'static str>,
+}
+
+/// Append-only JSON lines file of event outcomes.
+pub(crate) struct AuditLog {
+    file: Mutex<File>,
+}
+
+impl AuditLog {
+    /// Opens `path` for appending, creating it if needed.
+    pub(crate) fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(AuditLog {
+            file: Mutex::new(file),
+        })
+    }
+
+    pub(crate) fn record(
+        &self,
+        id: &str,
+        fingerprint: Option<&str>,
+        outcome: Outcome,
+    ) -> io::Result<()> {
+        let (outcome, reason) = match outcome {
+            Outcome::Sent => ("sent", None),
+            Outcome::Acked => ("acked", None),
+            Outcome::Dropped(reason) => ("dropped", Some(reason.as_str())),
+        };
+        let entry = Entry {
+            time: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            event_id: id,
+            fingerprint,
+            outcome,
+            reason,
+        };
+
+        // One write per line keeps concurrent agents appending to the same file intact.
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+        self.file.lock().write_all(line.as_bytes())
+    }
+}
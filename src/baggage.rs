@@ -0,0 +1,172 @@
+//! W3C trace baggage.
+//!
+//! Baggage carries business identifiers, such as the tenant or the
+//! experiment a request belongs to, through every service handling it, in
+//! the [`baggage`](https://www.w3.org/TR/baggage/) header. Entries set with
+//! [`set_baggage`](crate::set_baggage) or [`Scope::baggage`], and those of
+//! the `baggage` header of a scope's [`RequestContext`], are sent with every
+//! event captured meanwhile in its `baggage` field:
+//!
+//! ```rust,no_run
+//! use aivory_monitor::scope::{self, Scope};
+//! use aivory_monitor::RequestContext;
+//!
+//! let request = RequestContext::new("GET", "https://shop.example.com/cart")
+//!     .header("baggage", "tenant=acme,experiment=new-cart");
+//! scope::with_scope(Scope::new().request(request), || {
+//!     aivory_monitor::set_baggage("order_id", "o-1042");
+//!     // Errors captured here carry tenant, experiment and order_id.
+//! });
+//! ```
+//!
+//! [`header_value`] formats the current entries for the requests the
+//! service makes in turn, so the next service receives them as well:
+//!
+//! ```rust,no_run
+//! # let mut headers: Vec<(&str, String)> = Vec::new();
+//! if let Some(baggage) = aivory_monitor::baggage::header_value() {
+//!     headers.push((aivory_monitor::baggage::HEADER, baggage));
+//! }
+//! ```
+//!
+//! [`Scope::baggage`]: crate::scope::Scope::baggage
+//! [`RequestContext`]: crate::RequestContext
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Name of the header baggage is propagated in.
+pub const HEADER: &str = "baggage";
+
+/// Entries kept from a header and sent in one, as the specification requires
+/// at least.
+const MAX_MEMBERS: usize = 180;
+
+/// Length of the header sent, beyond which further entries are left out.
+const MAX_BYTES: usize = 8192;
+
+/// Parses the value of a `baggage` header.
+///
+/// Values are percent-decoded and entry properties are ignored. Malformed
+/// entries are skipped; of repeated keys, the last one wins.
+///
+/// ```rust
+/// use aivory_monitor::baggage;
+///
+/// let entries = baggage::parse("tenant=acme, note=50%25%20off;ttl=60, =x, experiment=new-cart");
+/// assert_eq!(entries.len(), 3);
+/// assert_eq!(entries["tenant"], "acme");
+/// assert_eq!(entries["note"], "50% off");
+/// assert_eq!(entries["experiment"], "new-cart");
+/// ```
+pub fn parse(header: &str) -> BTreeMap<String, String> {
+    let mut entries = BTreeMap::new();
+    for member in header.split(',').take(MAX_MEMBERS) {
+        // Properties follow the value after `;`.
+        let member = member.split(';').next().unwrap_or_default();
+        let Some((key, value)) = member.split_once('=') else {
+            continue;
+        };
+        let key = key.trim();
+        if !is_token(key) {
+            continue;
+        }
+        if let Some(value) = percent_decode(value.trim()) {
+            entries.insert(key.to_string(), value);
+        }
+    }
+    entries
+}
+
+/// Formats entries as the value of a `baggage` header, percent-encoding the
+/// values.
+///
+/// Entries whose key is not a valid header token are left out, as are those
+/// past the header's size limit.
+///
+/// ```rust
+/// use aivory_monitor::baggage;
+/// use std::collections::BTreeMap;
+///
+/// let entries = BTreeMap::from([
+///     ("tenant".to_string(), "acme".to_string()),
+///     ("note".to_string(), "50% off".to_string()),
+/// ]);
+/// assert_eq!(baggage::format(&entries), "note=50%25%20off,tenant=acme");
+/// assert_eq!(baggage::parse(&baggage::format(&entries)), entries);
+/// ```
+pub fn format(entries: &BTreeMap<String, String>) -> String {
+    let mut header = String::new();
+    for (key, value) in entries
+        .iter()
+        .filter(|(key, _)| is_token(key))
+        .take(MAX_MEMBERS)
+    {
+        let mut member = format!("{}=", key);
+        percent_encode(value, &mut member);
+        let separator = usize::from(!header.is_empty());
+        if header.len() + separator + member.len() > MAX_BYTES {
+            break;
+        }
+        if separator == 1 {
+            header.push(',');
+        }
+        header.push_str(&member);
+    }
+    header
+}
+
+/// The `baggage` header for a request made now: the entries of the current
+/// thread and its active scopes, or `None` if there are none.
+pub fn header_value() -> Option<String> {
+    Some(format(&crate::scope::baggage())).filter(|header| !header.is_empty())
+}
+
+/// Whether `key` is a header token, which baggage keys must be.
+fn is_token(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+/// Characters a value may contain unencoded: printable ASCII except space,
+/// `"`, `,`, `;`, `\` and `%`.
+fn is_value_char(b: u8) -> bool {
+    matches!(b, 0x21 | 0x23..=0x24 | 0x26..=0x2B | 0x2D..=0x3A | 0x3C..=0x5B | 0x5D..=0x7E)
+}
+
+fn percent_encode(value: &str, out: &mut String) {
+    for b in value.bytes() {
+        if is_value_char(b) {
+            out.push(b as char);
+        } else {
+            let _ = write!(out, "%{:02X}", b);
+        }
+    }
+}
+
+/// Decodes a value, or returns `None` if it is not valid UTF-8 once decoded.
+fn percent_decode(value: &str) -> Option<String> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| value.get(i + 1..i + 3))
+            .flatten()
+            .filter(|hex| hex.bytes().all(|b| b.is_ascii_hexdigit()))
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(b) => {
+                decoded.push(b);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
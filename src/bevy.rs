@@ -0,0 +1,217 @@
+//! [Bevy](https://bevyengine.org) plugin for game telemetry.
+//!
+//! [`AivoryPlugin`] reports errors returned by systems, commands and
+//! observers, and tags every event, including panics, with the game's
+//! recent frame time and entity count. Games can hit the same error on
+//! every frame, so reports are rate limited per error and overall.
+//!
+//! ```rust,ignore
+//! use aivory_monitor::bevy::AivoryPlugin;
+//!
+//! fn main() {
+//!     aivory_monitor::init(config);
+//!
+//!     App::new()
+//!         .add_plugins((DefaultPlugins, AivoryPlugin::default()))
+//!         .run();
+//! }
+//! ```
+
+use crate::capture::{debug_type_name, Mechanism};
+use crate::EventBuilder;
+use ::bevy::app::{App, Last, Plugin};
+use ::bevy::ecs::entity::Entities;
+use ::bevy::ecs::error::{self, BevyError, ErrorContext};
+use ::bevy::ecs::system::Local;
+use aivory_monitor_core::hash_fingerprint;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde_json::json;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// How often frame diagnostics are refreshed in the agent's context.
+const DIAGNOSTICS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Distinct errors tracked for rate limiting before old entries are pruned.
+const MAX_TRACKED_ERRORS: usize = 256;
+
+static LIMITER: Lazy<Mutex<Limiter>> =
+    Lazy::new(|| Mutex::new(Limiter::new(&AivoryPlugin::default())));
+
+/// Bevy plugin that reports system errors and frame diagnostics.
+#[derive(Clone, Debug)]
+pub struct AivoryPlugin {
+    /// Report errors returned by systems, commands and observers.
+    ///
+    /// Installs [`capture_system_error`] as the app's error handler unless
+    /// one is already set; call it from your own handler in that case.
+    pub capture_system_errors: bool,
+    /// Minimum time between two reports of the same error.
+    pub repeat_interval: Duration,
+    /// Maximum system error reports per minute across all errors.
+    pub max_reports_per_minute: u32,
+}
+
+impl Default for AivoryPlugin {
+    fn default() -> Self {
+        AivoryPlugin {
+            capture_system_errors: true,
+            repeat_interval: Duration::from_secs(60),
+            max_reports_per_minute: 10,
+        }
+    }
+}
+
+impl Plugin for AivoryPlugin {
+    fn build(&self, app: &mut App) {
+        *LIMITER.lock() = Limiter::new(self);
+
+        if self.capture_system_errors {
+            if app.get_error_handler().is_none() {
+                app.set_error_handler(capture_system_error);
+            } else if let Some(client) = crate::client() {
+                client.config().logger.warn(format_args!(
+                    "Bevy error handler already set; call aivory_monitor::bevy::capture_system_error from it"
+                ));
+            }
+        }
+
+        app.add_systems(Last, record_frame_diagnostics);
+    }
+}
+
+/// Error handler that reports the error, subject to rate limiting, and logs it.
+///
+/// Installed by [`AivoryPlugin`]; call it from a custom handler to keep
+/// reporting when the app sets its own.
+pub fn capture_system_error(err: BevyError, ctx: ErrorContext) {
+    // `Display` appends the backtrace, when captured, after the first line.
+    let message = err
+        .to_string()
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .to_string();
+    let name = ctx.name().to_string();
+
+    if let Some(suppressed) = LIMITER.lock().allow(format!("{name}:{message}")) {
+        let exception_type = debug_type_name(&format!("{err:?}"));
+        let fingerprint =
+            hash_fingerprint(&[exception_type.clone(), ctx.kind().to_string(), name.clone()]);
+        let mut bevy = json!({ "kind": ctx.kind(), "name": name });
+        if suppressed > 0 {
+            bevy["suppressed"] = suppressed.into();
+        }
+        crate::capture_event(
+            EventBuilder::new(exception_type)
+                .message(message)
+                .fingerprint(fingerprint)
+                .mechanism(Mechanism::Middleware)
+                .context("bevy", bevy),
+        );
+    }
+
+    error::error(err, ctx);
+}
+
+struct FrameWindow {
+    started: Instant,
+    frames: u32,
+}
+
+/// Publishes average frame time and entity count as the `game` context.
+fn record_frame_diagnostics(mut window: Local<Option<FrameWindow>>, entities: &Entities) {
+    let now = Instant::now();
+    let window = window.get_or_insert_with(|| FrameWindow {
+        started: now,
+        frames: 0,
+    });
+    window.frames += 1;
+
+    let elapsed = now.duration_since(window.started);
+    if elapsed < DIAGNOSTICS_INTERVAL {
+        return;
+    }
+
+    let frame_time_ms = elapsed.as_secs_f64() * 1000.0 / f64::from(window.frames);
+    let _ = crate::set_context_typed(
+        "game",
+        &json!({
+            "frame_time_ms": (frame_time_ms * 100.0).round() / 100.0,
+            "fps": (1000.0 / frame_time_ms).round(),
+            "entity_count": entities.count_spawned(),
+        }),
+    );
+    *window = FrameWindow {
+        started: now,
+        frames: 0,
+    };
+}
+
+struct Seen {
+    last_sent: Instant,
+    suppressed: u64,
+}
+
+/// Limits reports per distinct error and per minute overall.
+struct Limiter {
+    repeat_interval: Duration,
+    max_per_minute: u32,
+    window_start: Instant,
+    sent_in_window: u32,
+    seen: HashMap<String, Seen>,
+}
+
+impl Limiter {
+    fn new(plugin: &AivoryPlugin) -> Self {
+        Limiter {
+            repeat_interval: plugin.repeat_interval,
+            max_per_minute: plugin.max_reports_per_minute,
+            window_start: Instant::now(),
+            sent_in_window: 0,
+            seen: HashMap::new(),
+        }
+    }
+
+    /// Returns the number of reports of `key` suppressed since the last one
+    /// if this one may be sent, otherwise records it as suppressed.
+    fn allow(&mut self, key: String) -> Option<u64> {
+        let now = Instant::now();
+        if now.duration_since(self.window_start) >= Duration::from_secs(60) {
+            self.window_start = now;
+            self.sent_in_window = 0;
+        }
+
+        if let Some(seen) = self.seen.get_mut(&key) {
+            if now.duration_since(seen.last_sent) < self.repeat_interval
+                || self.sent_in_window >= self.max_per_minute
+            {
+                seen.suppressed += 1;
+                return None;
+            }
+            let suppressed = std::mem::take(&mut seen.suppressed);
+            seen.last_sent = now;
+            self.sent_in_window += 1;
+            return Some(suppressed);
+        }
+
+        if self.sent_in_window >= self.max_per_minute {
+            return None;
+        }
+        if self.seen.len() >= MAX_TRACKED_ERRORS {
+            let repeat_interval = self.repeat_interval;
+            self.seen
+                .retain(|_, seen| now.duration_since(seen.last_sent) < repeat_interval);
+        }
+        self.seen.insert(
+            key,
+            Seen {
+                last_sent: now,
+                suppressed: 0,
+            },
+        );
+        self.sent_in_window += 1;
+        Some(0)
+    }
+}
@@ -0,0 +1,535 @@
+//! AIVory Monitor command-line tool.
+//!
+//! `aivory doctor` loads the configuration the way the agent does (from
+//! `AIVORY_*` environment variables, or a file given with `--config`), then
+//! checks each step of reaching the backend: DNS, TCP, proxy, TLS, and
+//! registration with the API key, finishing with a test event. It prints a
+//! pass/fail line per check and exits with status 1 if any failed.
+//!
+//! `aivory spool inspect` lists the events agents on this host could not send
+//! and left in the state directory, or prints those of one executable;
+//! `aivory spool replay` sends them.
+//!
+//! Build it with `cargo install aivory-monitor --features cli`.
+
+use aivory_monitor::transport::Spool;
+use aivory_monitor::{Client, Config, ConfigBuilder, ConnectionState, TransportMode};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+const USAGE: &str = "\
+Usage: aivory doctor [--config <path>] [--timeout <seconds>] [--verbose]
+       aivory spool inspect [<executable>] [--config <path>]
+       aivory spool replay [<executable>] [--config <path>] [--timeout <seconds>] [--verbose]
+
+doctor         Checks that this host can reach the AIVory backend with the
+               current configuration, and sends a test event.
+spool inspect  Lists the executables that left unsent events in the state
+               directory, or prints those of <executable>, one per line.
+spool replay   Sends the unsent events of <executable>, or of all executables.
+
+Options:
+  --config <path>      Load configuration from a TOML or YAML file
+  --timeout <seconds>  Time allowed for each network check, or to send the
+                       events of each executable (default: 10)
+  --verbose            Show the agent's own log output
+";
+
+enum Command {
+    Doctor,
+    SpoolInspect,
+    SpoolReplay,
+}
+
+struct Options {
+    config: Option<String>,
+    timeout: Duration,
+    verbose: bool,
+    executable: Option<String>,
+}
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let command = match args.next().as_deref() {
+        Some("doctor") => Command::Doctor,
+        Some("spool") => match args.next().as_deref() {
+            Some("inspect") => Command::SpoolInspect,
+            Some("replay") => Command::SpoolReplay,
+            Some(other) => {
+                eprintln!("Unknown spool command: {}\n\n{}", other, USAGE);
+                return ExitCode::from(2);
+            }
+            None => {
+                eprint!("{}", USAGE);
+                return ExitCode::from(2);
+            }
+        },
+        Some("-h") | Some("--help") | Some("help") => {
+            print!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Some(other) => {
+            eprintln!("Unknown command: {}\n\n{}", other, USAGE);
+            return ExitCode::from(2);
+        }
+        None => {
+            eprint!("{}", USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    match parse_options(args, !matches!(command, Command::Doctor)) {
+        Ok(options) => match command {
+            Command::Doctor => doctor(&options),
+            Command::SpoolInspect => spool_inspect(&options),
+            Command::SpoolReplay => spool_replay(&options),
+        },
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, USAGE);
+            ExitCode::from(2)
+        }
+    }
+}
+
+/// Parses the options following the command, and the executable if the
+/// command takes one.
+fn parse_options(
+    mut args: impl Iterator<Item = String>,
+    takes_executable: bool,
+) -> Result<Options, String> {
+    let mut options = Options {
+        config: None,
+        timeout: Duration::from_secs(10),
+        verbose: false,
+        executable: None,
+    };
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => options.config = Some(args.next().ok_or("--config needs a path")?),
+            "--timeout" => {
+                let secs = args.next().ok_or("--timeout needs a number of seconds")?;
+                let secs: u64 = secs
+                    .parse()
+                    .map_err(|_| format!("Invalid timeout: {}", secs))?;
+                options.timeout = Duration::from_secs(secs.max(1));
+            }
+            "--verbose" => options.verbose = true,
+            _ if takes_executable && options.executable.is_none() && !arg.starts_with('-') => {
+                options.executable = Some(arg);
+            }
+            _ => return Err(format!("Unknown option: {}", arg)),
+        }
+    }
+    Ok(options)
+}
+
+/// Collects check results and prints them as they complete.
+#[derive(Default)]
+struct Report {
+    failed: usize,
+}
+
+impl Report {
+    fn pass(&mut self, check: &str, detail: impl std::fmt::Display) {
+        println!("[pass] {:<8} {}", check, detail);
+    }
+
+    fn warn(&mut self, check: &str, detail: impl std::fmt::Display) {
+        println!("[warn] {:<8} {}", check, detail);
+    }
+
+    fn fail(&mut self, check: &str, detail: impl std::fmt::Display) {
+        println!("[FAIL] {:<8} {}", check, detail);
+        self.failed += 1;
+    }
+
+    fn skip(&mut self, check: &str, detail: impl std::fmt::Display) {
+        println!("[skip] {:<8} {}", check, detail);
+    }
+
+    fn finish(self) -> ExitCode {
+        println!();
+        if self.failed == 0 {
+            println!("All checks passed.");
+            ExitCode::SUCCESS
+        } else {
+            println!("{} check(s) failed.", self.failed);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn doctor(options: &Options) -> ExitCode {
+    let mut report = Report::default();
+
+    let Some(config) = check_config(options, &mut report) else {
+        return report.finish();
+    };
+
+    let url = match config.transport() {
+        TransportMode::Http => config.http_url(),
+        TransportMode::WebSocket => config.websocket_url(),
+    };
+    let Ok(url) = url::Url::parse(&url) else {
+        report.fail("config", format_args!("invalid backend URL {}", url));
+        return report.finish();
+    };
+    let host = url.host_str().unwrap_or_default().to_string();
+    let port = url.port_or_known_default().unwrap_or(443);
+    let secure = matches!(url.scheme(), "wss" | "https");
+
+    check_proxy(&host, &mut report);
+
+    let Some(addrs) = check_dns(&host, port, &mut report) else {
+        skip_network(&mut report, &["tcp", "tls", "auth", "event"]);
+        return report.finish();
+    };
+
+    let Some(stream) = check_tcp(&addrs, options.timeout, &mut report) else {
+        skip_network(&mut report, &["tls", "auth", "event"]);
+        return report.finish();
+    };
+
+    if secure {
+        if !check_tls(&host, stream, options.timeout, &mut report) {
+            skip_network(&mut report, &["auth", "event"]);
+            return report.finish();
+        }
+    } else {
+        drop(stream);
+        report.skip("tls", format_args!("{} is not encrypted", url.scheme()));
+    }
+
+    check_agent(config, options.timeout, &mut report);
+    report.finish()
+}
+
+fn skip_network(report: &mut Report, checks: &[&str]) {
+    for check in checks {
+        report.skip(check, "an earlier check failed");
+    }
+}
+
+/// Loads the configuration from the file given with `--config`, or from the
+/// environment.
+fn load_config(options: &Options) -> Result<Config, String> {
+    let builder = match &options.config {
+        Some(path) => ConfigBuilder::from_file(path).map_err(|e| format!("{}: {}", path, e))?,
+        None => Config::builder(),
+    };
+    let builder = if options.verbose {
+        builder
+    } else {
+        builder.silent()
+    };
+    builder.build().map_err(|e| e.to_string())
+}
+
+fn check_config(options: &Options, report: &mut Report) -> Option<Config> {
+    let config = match load_config(options) {
+        Ok(config) => config,
+        Err(e) => {
+            report.fail("config", e);
+            return None;
+        }
+    };
+
+    if config.api_key().is_empty() {
+        report.fail(
+            "config",
+            "no API key; set AIVORY_API_KEY or api_key in the config file",
+        );
+        return None;
+    }
+
+    let source = match config.config_path() {
+        Some(path) => path.display().to_string(),
+        None => "environment".to_string(),
+    };
+    report.pass(
+        "config",
+        format_args!(
+            "{} (api key {}, {:?} transport, environment {})",
+            source,
+            mask(config.api_key()),
+            config.transport(),
+            config.environment()
+        ),
+    );
+    Some(config)
+}
+
+/// Shows only the last four characters of a secret.
+fn mask(secret: &str) -> String {
+    let chars: Vec<char> = secret.chars().collect();
+    if chars.len() <= 8 {
+        return "*".repeat(chars.len());
+    }
+    let tail: String = chars[chars.len() - 4..].iter().collect();
+    format!("****{}", tail)
+}
+
+fn check_proxy(host: &str, report: &mut Report) {
+    let proxy = [
+        "HTTPS_PROXY",
+        "https_proxy",
+        "HTTP_PROXY",
+        "http_proxy",
+        "ALL_PROXY",
+        "all_proxy",
+    ]
+    .iter()
+    .find_map(|name| {
+        std::env::var(name)
+            .ok()
+            .filter(|v| !v.is_empty())
+            .map(|v| (name, v))
+    });
+    let Some((name, value)) = proxy else {
+        report.pass("proxy", "no proxy configured, connecting directly");
+        return;
+    };
+
+    let no_proxy = std::env::var("NO_PROXY")
+        .or_else(|_| std::env::var("no_proxy"))
+        .unwrap_or_default();
+    let bypassed = no_proxy.split(',').map(str::trim).any(|entry| {
+        let entry = entry.trim_start_matches('.');
+        entry == "*"
+            || (!entry.is_empty() && (host == entry || host.ends_with(&format!(".{}", entry))))
+    });
+    if bypassed {
+        report.pass(
+            "proxy",
+            format_args!("{} is in NO_PROXY, connecting directly", host),
+        );
+    } else {
+        report.warn(
+            "proxy",
+            format_args!(
+                "{}={} is set, but the agent does not use proxies; {} must be reachable directly",
+                name, value, host
+            ),
+        );
+    }
+}
+
+fn check_dns(host: &str, port: u16, report: &mut Report) -> Option<Vec<SocketAddr>> {
+    let started = Instant::now();
+    match (host, port).to_socket_addrs() {
+        Ok(addrs) => {
+            let addrs: Vec<SocketAddr> = addrs.collect();
+            if addrs.is_empty() {
+                report.fail("dns", format_args!("{} has no addresses", host));
+                return None;
+            }
+            let list: Vec<String> = addrs.iter().map(|a| a.ip().to_string()).collect();
+            report.pass(
+                "dns",
+                format_args!(
+                    "{} -> {} ({} ms)",
+                    host,
+                    list.join(", "),
+                    started.elapsed().as_millis()
+                ),
+            );
+            Some(addrs)
+        }
+        Err(e) => {
+            report.fail("dns", format_args!("{}: {}", host, e));
+            None
+        }
+    }
+}
+
+fn check_tcp(addrs: &[SocketAddr], timeout: Duration, report: &mut Report) -> Option<TcpStream> {
+    let mut errors = Vec::new();
+    for addr in addrs {
+        let started = Instant::now();
+        match TcpStream::connect_timeout(addr, timeout) {
+            Ok(stream) => {
+                report.pass(
+                    "tcp",
+                    format_args!(
+                        "connected to {} ({} ms)",
+                        addr,
+                        started.elapsed().as_millis()
+                    ),
+                );
+                return Some(stream);
+            }
+            Err(e) => errors.push(format!("{}: {}", addr, e)),
+        }
+    }
+    report.fail("tcp", errors.join("; "));
+    None
+}
+
+fn check_tls(host: &str, stream: TcpStream, timeout: Duration, report: &mut Report) -> bool {
+    let _ = stream.set_read_timeout(Some(timeout));
+    let _ = stream.set_write_timeout(Some(timeout));
+    let connector = match native_tls::TlsConnector::new() {
+        Ok(connector) => connector,
+        Err(e) => {
+            report.fail("tls", format_args!("could not set up TLS: {}", e));
+            return false;
+        }
+    };
+    let started = Instant::now();
+    match connector.connect(host, stream) {
+        Ok(_) => {
+            report.pass(
+                "tls",
+                format_args!(
+                    "handshake with {} ({} ms)",
+                    host,
+                    started.elapsed().as_millis()
+                ),
+            );
+            true
+        }
+        Err(e) => {
+            report.fail("tls", format_args!("handshake with {} failed: {}", host, e));
+            false
+        }
+    }
+}
+
+/// Starts an agent, waits for the backend to accept the API key, and sends
+/// a test event that the backend must acknowledge.
+fn check_agent(config: Config, timeout: Duration, report: &mut Report) {
+    let transport = config.transport();
+    let client = Client::new(config);
+
+    // Over WebSocket events are only sent once registered; over HTTP the
+    // test event is what tells whether the key is accepted.
+    if transport == TransportMode::WebSocket {
+        wait_for_backend(&client, timeout);
+    }
+    let sent = client.send_test_event();
+
+    let diagnostics = client.diagnostics();
+    let last_error = diagnostics
+        .last_error
+        .clone()
+        .unwrap_or_else(|| "no response".to_string());
+    if diagnostics.registered {
+        report.pass("auth", "backend accepted the API key");
+    } else if diagnostics.connection_state == ConnectionState::AuthFailed {
+        report.fail("auth", "backend rejected the API key");
+    } else {
+        report.fail(
+            "auth",
+            format_args!("no answer within {:?}: {}", timeout, last_error),
+        );
+    }
+
+    match sent {
+        Ok(id) => report.pass("event", format_args!("test event {} acknowledged", id)),
+        Err(e) => report.fail("event", e),
+    }
+
+    client.shutdown();
+}
+
+/// Waits until the backend accepted or rejected the API key.
+fn wait_for_backend(client: &Client, timeout: Duration) {
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        let diagnostics = client.diagnostics();
+        if diagnostics.registered || diagnostics.connection_state == ConnectionState::AuthFailed {
+            return;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+/// Loads the configuration and the spools `options` select: that of the
+/// executable given, or all those in the state directory.
+fn load_spools(options: &Options) -> Result<(Config, Vec<Spool>), String> {
+    let config = load_config(options)?;
+    let spools = match &options.executable {
+        Some(executable) => match Spool::for_executable(&config, executable) {
+            Some(spool) => vec![spool],
+            None => {
+                return Err(
+                    "No private state directory; set AIVORY_STATE_DIR or state_dir".to_string(),
+                )
+            }
+        },
+        None => Spool::list(&config),
+    };
+    Ok((config, spools))
+}
+
+fn spool_inspect(options: &Options) -> ExitCode {
+    let spools = match load_spools(options) {
+        Ok((_, spools)) => spools,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if options.executable.is_some() {
+        // One message per line, for piping into tools such as `jq`.
+        for json in spools.iter().flat_map(Spool::messages) {
+            println!("{}", json);
+        }
+        return ExitCode::SUCCESS;
+    }
+
+    if spools.is_empty() {
+        println!("No unsent events.");
+    }
+    for spool in &spools {
+        println!(
+            "{:<24} {:>5} message(s)  {}",
+            spool.executable(),
+            spool.messages().len(),
+            spool.path().display()
+        );
+    }
+    ExitCode::SUCCESS
+}
+
+fn spool_replay(options: &Options) -> ExitCode {
+    let (config, spools) = match load_spools(options) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let spools: Vec<Spool> = spools
+        .into_iter()
+        .filter(|spool| !spool.messages().is_empty())
+        .collect();
+    if spools.is_empty() {
+        println!("No unsent events.");
+        return ExitCode::SUCCESS;
+    }
+    if config.api_key().is_empty() {
+        eprintln!("No API key; set AIVORY_API_KEY or api_key in the config file");
+        return ExitCode::FAILURE;
+    }
+
+    let client = Client::new(config);
+    let mut failed = false;
+    for spool in &spools {
+        match client.replay_spool(spool, options.timeout) {
+            Ok(sent) => println!("{}: sent {} message(s)", spool.executable(), sent),
+            Err(e) => {
+                println!("{}: {}", spool.executable(), e);
+                failed = true;
+            }
+        }
+    }
+    client.shutdown();
+    if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
@@ -0,0 +1,107 @@
+//! Breadcrumbs: what the application did before an error.
+//!
+//! Breadcrumbs are recorded on the innermost active [scope](crate::scope),
+//! or on the current thread's [`Hub`](crate::Hub) outside of scopes, keeping
+//! the last `max_breadcrumbs` of each. Every event is sent with the
+//! breadcrumbs of the thread and scopes it is captured in, up to
+//! `max_breadcrumbs` of them, in the same envelope; one request's steps do
+//! not show up in the errors of another handled meanwhile. Breadcrumbs below
+//! `min_breadcrumb_level`, or the level set for their category with
+//! `breadcrumb_level`, and those of ignored categories are discarded when
+//! recorded:
+//!
+//! ```rust,no_run
+//! use aivory_monitor::breadcrumbs::{Breadcrumb, BreadcrumbLevel};
+//!
+//! aivory_monitor::add_breadcrumb(
+//!     Breadcrumb::new("payment", "charging card")
+//!         .level(BreadcrumbLevel::Info)
+//!         .data("amount_cents", 4200),
+//! );
+//! ```
+
+use crate::clock;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::str::FromStr;
+
+/// Severity of a breadcrumb, from least to most severe.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum BreadcrumbLevel {
+    /// Detail useful only while debugging.
+    Debug,
+    /// Normal operation, such as a request sent or a job started.
+    #[default]
+    Info,
+    /// Something unexpected the application recovered from.
+    Warning,
+    /// A failure.
+    Error,
+}
+
+impl FromStr for BreadcrumbLevel {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "debug" => Ok(BreadcrumbLevel::Debug),
+            "info" => Ok(BreadcrumbLevel::Info),
+            "warn" | "warning" => Ok(BreadcrumbLevel::Warning),
+            "error" => Ok(BreadcrumbLevel::Error),
+            _ => Err(()),
+        }
+    }
+}
+
+/// A single step recorded before an error.
+#[derive(Clone, Debug, Serialize)]
+pub struct Breadcrumb {
+    /// When the step happened, as RFC 3339.
+    pub timestamp: String,
+    /// What kind of step this is, such as `http`, `db` or `navigation`.
+    pub category: String,
+    pub message: String,
+    pub level: BreadcrumbLevel,
+    /// Structured details, such as the URL and status of a request.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub data: BTreeMap<String, serde_json::Value>,
+}
+
+impl Breadcrumb {
+    /// Creates an [`Info`](BreadcrumbLevel::Info) breadcrumb timestamped now.
+    pub fn new(category: impl Into<String>, message: impl Into<String>) -> Self {
+        Breadcrumb {
+            timestamp: clock::now().to_rfc3339(),
+            category: category.into(),
+            message: message.into(),
+            level: BreadcrumbLevel::Info,
+            data: BTreeMap::new(),
+        }
+    }
+
+    /// Sets the severity.
+    pub fn level(mut self, level: BreadcrumbLevel) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Adds a structured detail.
+    pub fn data(mut self, key: impl Into<String>, value: impl Into<serde_json::Value>) -> Self {
+        self.data.insert(key.into(), value.into());
+        self
+    }
+}
+
+/// Appends `breadcrumb` to `trail`, dropping the oldest ones beyond `max`.
+pub(crate) fn push(trail: &mut VecDeque<Breadcrumb>, breadcrumb: Breadcrumb, max: usize) {
+    if max == 0 {
+        return;
+    }
+    while trail.len() >= max {
+        trail.pop_front();
+    }
+    trail.push_back(breadcrumb);
+}
@@ -1,48 +1,371 @@
 //! Error and panic capture functionality.
 
-use crate::config::Config;
+use crate::clock;
+use crate::config::{Config, ConfigError};
+use aivory_monitor_core::{fingerprint as calculate_fingerprint, fingerprint_v2};
 use backtrace::Backtrace;
-use chrono::Utc;
-use serde::Serialize;
-use sha2::{Sha256, Digest};
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::collections::{BTreeMap, HashMap};
 use std::error::Error;
+use std::sync::Arc;
 use uuid::Uuid;
 
+mod attachment;
+mod event;
+mod threads;
+mod variable;
+
+pub use aivory_monitor_core::{CrashRecord, ErrorCause, StackFrame};
+#[cfg(feature = "derive")]
+pub use aivory_monitor_derive::Capturable;
+pub use attachment::Attachment;
+pub use event::EventBuilder;
+pub(crate) use threads::all_threads;
+pub use variable::CapturedValue;
+
+/// Version of the [`ExceptionCapture`] format this agent writes.
+///
+/// The format only ever grows: fields are added, never renamed, removed or
+/// given another type, and every field added since version 1 has a default.
+/// Readers fill in the defaults for events of older agents and ignore the
+/// fields they do not know in events of newer ones. Each release that adds
+/// fields bumps the version, so the backend can tell which fields an event
+/// could have carried.
+pub const SCHEMA_VERSION: u32 = 2;
+
 /// Captured exception data.
-#[derive(Clone, Serialize)]
+///
+/// Events round-trip through serde, and older events without the newer
+/// fields still deserialize:
+///
+/// ```rust
+/// use aivory_monitor::capture::SCHEMA_VERSION;
+/// use aivory_monitor::{Config, EventBuilder, ExceptionCapture};
+///
+/// let event = EventBuilder::new("JobFailed").message("exit 137").build(&Config::default());
+/// let json = serde_json::to_value(&event).unwrap();
+/// let parsed: ExceptionCapture = serde_json::from_value(json.clone()).unwrap();
+/// assert_eq!(parsed.schema_version, SCHEMA_VERSION);
+/// assert_eq!(serde_json::to_value(&parsed).unwrap(), json);
+///
+/// // As sent by a 1.0 agent.
+/// let legacy: ExceptionCapture = serde_json::from_str(r#"{
+///     "id": "8c1f", "exception_type": "panic", "message": "boom", "fingerprint": "a1b2",
+///     "stack_trace": [], "local_variables": {}, "context": {},
+///     "captured_at": "2026-01-05T10:00:00+00:00", "agent_id": "agent-1", "environment": "production",
+///     "runtime_info": { "runtime": "rust", "runtime_version": "1.0.0", "platform": "linux", "arch": "x86_64" }
+/// }"#).unwrap();
+/// assert_eq!(legacy.schema_version, 1);
+/// assert_eq!(legacy.sample_rate, 1.0);
+/// ```
+#[derive(Clone, Serialize, Deserialize)]
 pub struct ExceptionCapture {
+    /// Version of the event format, see [`SCHEMA_VERSION`]; 1 for events of
+    /// agents that predate versioning.
+    #[serde(default = "legacy_schema_version")]
+    pub schema_version: u32,
     pub id: String,
     pub exception_type: String,
     pub message: String,
     pub fingerprint: String,
+    /// Fingerprints of both algorithms when `fingerprint` was computed from
+    /// the type and stack, so the backend can move groups from one to the
+    /// other; see [`FingerprintVersion`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprints: Option<Fingerprints>,
     pub stack_trace: Vec<StackFrame>,
+    /// `server` when frames carry only their `instruction_addr`, because the
+    /// binary has no symbols; the backend then symbolicates them with the
+    /// debug files of the build, identified by `runtime_info.build_id`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub symbolication: Option<String>,
+    /// Errors in the `source()` chain, outermost first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub causes: Vec<ErrorCause>,
+    /// The individual failures of an aggregate error, such as the errors of
+    /// several tasks or validations; see [`capture_aggregate_error`].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exceptions: Vec<ExceptionValue>,
     pub local_variables: HashMap<String, Variable>,
     pub context: HashMap<String, serde_json::Value>,
     pub captured_at: String,
     pub agent_id: String,
     pub environment: String,
+    /// See [`ConfigBuilder::service_name`](crate::ConfigBuilder::service_name).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service_name: Option<String>,
+    /// See [`ConfigBuilder::server_name`](crate::ConfigBuilder::server_name).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_name: Option<String>,
+    /// API key of the project the event is routed to, see
+    /// [`ConfigBuilder::route_module`](crate::ConfigBuilder::route_module).
+    /// Sent in the envelope rather than in the event, so events written
+    /// locally do not contain it.
+    #[serde(skip)]
+    pub project_key: Option<String>,
     pub runtime_info: crate::config::RuntimeInfo,
+    /// Request or job correlation ID from the active scope, see
+    /// [`set_correlation_id`](crate::set_correlation_id).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
+    /// Feature flag variants from the active scopes, see
+    /// [`set_feature_flag`](crate::set_feature_flag).
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub feature_flags: BTreeMap<String, String>,
+    /// [`baggage`](crate::baggage) of the current thread and the active
+    /// scopes, see [`set_baggage`](crate::set_baggage).
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub baggage: BTreeMap<String, String>,
+    /// Time since the outermost active scope was entered, such as how long a
+    /// request had been running.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub elapsed_ms: Option<u64>,
+    /// Awaited operations the error passed through, innermost first; see
+    /// [`context_span`](crate::context_span).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub await_trail: Vec<crate::context_span::AwaitSpan>,
+    /// HTTP request being served, from the event or the active scope.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request: Option<crate::RequestContext>,
+    /// Low-cardinality name of what was being served, such as
+    /// `GET /users/:id`; see [`request`](crate::request).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transaction: Option<String>,
+    /// Probability with which this event was kept by sampling, so counts can
+    /// be extrapolated.
+    #[serde(default = "full_sample_rate")]
+    pub sample_rate: f64,
+    /// Set when the event stands for several occurrences, see `aggregation_interval`.
+    #[serde(default, flatten, skip_serializing_if = "Option::is_none")]
+    pub aggregation: Option<Aggregation>,
+    /// How often the fingerprint has been occurring, see `detect_spikes`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_rate: Option<ErrorRate>,
+    /// Stacks of all threads of the process, see `capture_all_threads`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub threads: Vec<ThreadTrace>,
+    /// How the event was captured.
+    #[serde(default)]
+    pub mechanism: Mechanism,
+    /// Whether the application dealt with the error and kept running, as
+    /// opposed to a crash; stability metrics count only unhandled events.
+    #[serde(default = "handled_by_default")]
+    pub handled: bool,
+    /// Files sent with the event, from the event and the active scopes.
+    #[serde(skip)]
+    pub attachments: Vec<Attachment>,
+    /// The agent's breadcrumbs when the event was captured, oldest first.
+    #[serde(skip)]
+    pub breadcrumbs: Vec<crate::Breadcrumb>,
+}
+
+fn legacy_schema_version() -> u32 {
+    1
+}
+
+fn full_sample_rate() -> f64 {
+    1.0
+}
+
+fn handled_by_default() -> bool {
+    true
+}
+
+impl ExceptionCapture {
+    /// Starts building an event by hand; see [`EventBuilder`].
+    pub fn builder(exception_type: impl Into<String>) -> EventBuilder {
+        EventBuilder::new(exception_type)
+    }
+}
+
+/// How an event was captured.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Mechanism {
+    /// A panic reported by the panic hook.
+    PanicHook,
+    /// A panic from `unwrap()` or `expect()` on a `Result` or `Option`.
+    Unwrap,
+    /// A fatal signal or OS-level crash.
+    Signal,
+    /// An error or response reported by a framework integration.
+    Middleware,
+    /// A Tokio task that panicked or was cancelled, from its `JoinError`.
+    JoinError,
+    /// An error returned from `main`, reported by
+    /// [`main_wrapper`](crate::main_wrapper).
+    MainReturn,
+    /// A panic in cleanup code contained by
+    /// [`capture_on_drop_panic`](crate::drop_guard::capture_on_drop_panic)
+    /// that the panic hook did not report.
+    DropGuard,
+    /// A call to one of the `capture_*` functions.
+    #[default]
+    Manual,
+}
+
+/// Algorithm computing an event's `fingerprint` from its type and stack.
+///
+/// Both are sent in `fingerprints` regardless, so a backend can link the
+/// groups of either while the switch to `v2` is rolled out.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FingerprintVersion {
+    /// Function names and line numbers of the top frames, so changes that
+    /// move lines regroup errors.
+    #[default]
+    V1,
+    /// Normalized function paths of the top frames, without line numbers,
+    /// generic arguments or symbol hashes.
+    V2,
+}
+
+impl std::str::FromStr for FingerprintVersion {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "v1" | "1" => Ok(FingerprintVersion::V1),
+            "v2" | "2" => Ok(FingerprintVersion::V2),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Fingerprints of an event by algorithm.
+///
+/// The values of a given type and stack never change between releases:
+///
+/// ```rust
+/// use aivory_monitor::capture::{FingerprintVersion, Fingerprints, StackFrame};
+///
+/// let mut frame = StackFrame::new("parse").file("src/config.rs", 42);
+/// frame.module = Some("my_app::config".into());
+/// let fingerprints = Fingerprints::new("ParseError", &[frame]);
+/// assert_eq!(fingerprints.v1, "9788120e8e3c3ddc");
+/// assert_eq!(fingerprints.v2, "04e4cbc445f88a40");
+/// assert_eq!(fingerprints.get(FingerprintVersion::V2), "04e4cbc445f88a40");
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fingerprints {
+    pub v1: String,
+    pub v2: String,
+}
+
+impl Fingerprints {
+    /// Computes both fingerprints of an error of `exception_type` with `stack_trace`.
+    pub fn new(exception_type: &str, stack_trace: &[StackFrame]) -> Self {
+        Fingerprints {
+            v1: calculate_fingerprint(exception_type, stack_trace),
+            v2: fingerprint_v2(exception_type, stack_trace),
+        }
+    }
+
+    /// The fingerprint computed by `version`.
+    pub fn get(&self, version: FingerprintVersion) -> &str {
+        match version {
+            FingerprintVersion::V1 => &self.v1,
+            FingerprintVersion::V2 => &self.v2,
+        }
+    }
 }
 
-/// A single stack frame.
-#[derive(Clone, Serialize)]
-pub struct StackFrame {
-    pub method_name: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub file_name: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub file_path: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub line_number: Option<u32>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub column_number: Option<u32>,
-    pub is_native: bool,
-    pub source_available: bool,
+/// Groups `exc` by `key` and its stack, in place of its type.
+fn regroup(exc: &mut ExceptionCapture, key: &str, config: &Config) {
+    let fingerprints = Fingerprints::new(key, &exc.stack_trace);
+    exc.fingerprint = fingerprints.get(config.fingerprint_version).to_string();
+    exc.fingerprints = Some(fingerprints);
+}
+
+/// Occurrences of a fingerprint within one aggregation interval.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Aggregation {
+    /// Number of occurrences, including the one the event describes.
+    pub count: u64,
+    /// When the first occurrence was captured.
+    pub first_seen: String,
+    /// When the last occurrence was captured.
+    pub last_seen: String,
+}
+
+impl Aggregation {
+    pub(crate) fn new(first: &ExceptionCapture) -> Self {
+        Aggregation {
+            count: 1,
+            first_seen: first.captured_at.clone(),
+            last_seen: first.captured_at.clone(),
+        }
+    }
+}
+
+/// How often an event's fingerprint has been occurring in this process.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ErrorRate {
+    /// Occurrences per minute, averaged over about the last minute.
+    pub per_minute: f64,
+    /// Occurrences per minute, averaged over about the last half hour.
+    pub baseline_per_minute: f64,
+    /// `per_minute` minus `baseline_per_minute`.
+    pub rate_delta: f64,
+    /// Whether the fingerprint suddenly occurs far more often than its
+    /// baseline.
+    pub is_spike: bool,
+}
+
+/// The stack of one thread of the process when a fatal event was captured.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ThreadTrace {
+    /// OS thread ID.
+    pub id: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// The thread the event was captured on, whose stack is the event's
+    /// `stack_trace`.
+    pub crashed: bool,
+    /// Empty for the crashed thread and for threads that could not be sampled.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stack_trace: Vec<StackFrame>,
+}
+
+/// One of the failures wrapped by an aggregate error.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ExceptionValue {
+    pub exception_type: String,
+    pub message: String,
+    /// Where this failure occurred, if known; empty otherwise.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stack_trace: Vec<StackFrame>,
+    /// Errors in the `source()` chain of this failure, outermost first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub causes: Vec<ErrorCause>,
+}
+
+impl ExceptionValue {
+    /// Creates a failure of `exception_type` with no stack trace or causes.
+    pub fn new(exception_type: impl Into<String>, message: impl Into<String>) -> Self {
+        ExceptionValue {
+            exception_type: exception_type.into(),
+            message: message.into(),
+            stack_trace: Vec::new(),
+            causes: Vec::new(),
+        }
+    }
+
+    /// Describes `error` with its type, message and causes, and the stack
+    /// trace it provides with the `error-provider` feature.
+    pub fn from_error<E: Error + ?Sized>(error: &E, config: &Config) -> Self {
+        ExceptionValue {
+            exception_type: error_type_name(error),
+            message: error.to_string(),
+            stack_trace: provided_stack_trace(error, config).unwrap_or_default(),
+            causes: error_chain(error.source()),
+        }
+    }
 }
 
 /// A captured variable.
-#[derive(Clone, Serialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Variable {
     pub name: String,
     #[serde(rename = "type")]
@@ -50,11 +373,11 @@ pub struct Variable {
     pub value: String,
     pub is_null: bool,
     pub is_truncated: bool,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub children: Option<HashMap<String, Variable>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub array_elements: Option<Vec<Variable>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub array_length: Option<usize>,
 }
 
@@ -69,143 +392,1164 @@ impl<E: Error> CaptureError for E {
     }
 }
 
+/// Errors whose fields are reported as structured `local_variables`.
+///
+/// Derive it with `#[derive(Capturable)]` (the `derive` feature) next to
+/// thiserror's `Error`, and report such errors with
+/// [`capture_typed_error`](crate::capture_typed_error):
+///
+/// ```rust,ignore
+/// use aivory_monitor::capture::Capturable;
+///
+/// #[derive(Debug, thiserror::Error, Capturable)]
+/// enum OrderError {
+///     #[error("order {order_id} not found")]
+///     NotFound { order_id: u64 },
+///     #[error("payment declined")]
+///     PaymentDeclined {
+///         #[capture(serialize)]
+///         attempt: PaymentAttempt,
+///         #[capture(skip)]
+///         card_token: String,
+///     },
+/// }
+/// ```
+///
+/// Fields are captured through `Debug`, or walked into a variable tree with
+/// `#[capture(serialize)]`; `#[capture(skip)]` leaves one out, and `#[source]`
+/// and `#[from]` fields are reported as causes instead.
+pub trait Capturable: Error {
+    /// Name of the enum variant this error is, reported as `error_variant`.
+    fn variant(&self) -> Option<&'static str> {
+        None
+    }
+
+    /// Variables for the fields of this error, built with the capture
+    /// limits of `config`.
+    fn fields(&self, config: &Config) -> Vec<Variable>;
+}
+
+/// Captures an error with its fields as local variables; see [`Capturable`].
+pub fn capture_typed_error<E: Capturable>(error: &E, config: &Config) -> ExceptionCapture {
+    let mut exc = capture_error(error, config);
+    for variable in error.fields(config) {
+        exc.local_variables.insert(variable.name.clone(), variable);
+    }
+    if let Some(variant) = error.variant() {
+        exc.context
+            .insert("error_variant".to_string(), serde_json::json!(variant));
+    }
+    exc
+}
+
 /// Captures an error with stack trace.
+///
+/// Errors with a `kind`, such as `std::io::Error` and `ParseIntError`, are
+/// grouped per kind, which is reported as the `error_kind` context value.
 pub fn capture_error<E: Error + ?Sized>(error: &E, config: &Config) -> ExceptionCapture {
-    let stack_trace = capture_stack_trace();
-    let exception_type = std::any::type_name::<E>()
-        .split("::")
-        .last()
-        .unwrap_or("Error")
-        .to_string();
-    let message = error.to_string();
-    let fingerprint = calculate_fingerprint(&exception_type, &stack_trace);
+    let exception_type = error_type_name(error);
+    let stack_trace =
+        provided_stack_trace(error, config).unwrap_or_else(|| capture_stack_trace(config));
+    let exc = build_error_capture(
+        exception_type,
+        error.to_string(),
+        error.source(),
+        stack_trace,
+        config,
+    );
+    with_error_kind(exc, &format!("{:?}", error), config)
+}
+
+/// Captures an error that wraps several failures, each reported in
+/// `exceptions` with its own type, message and causes instead of only in the
+/// error's message.
+///
+/// The event is grouped by the aggregate error alone. At most 32 failures are
+/// reported; `exception_count` in the context holds the total when there
+/// were more.
+///
+/// ```rust
+/// use aivory_monitor::capture::capture_aggregate_error;
+/// use aivory_monitor::Config;
+///
+/// use std::num::ParseIntError;
+///
+/// #[derive(Debug)]
+/// struct InvalidRows(Vec<ParseIntError>);
+///
+/// impl std::fmt::Display for InvalidRows {
+///     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+///         write!(f, "{} rows are invalid", self.0.len())
+///     }
+/// }
+///
+/// impl std::error::Error for InvalidRows {}
+///
+/// let rows = ["12", "x", "300"];
+/// let errors = InvalidRows(rows.iter().filter_map(|row| row.parse::<u8>().err()).collect());
+/// let exc = capture_aggregate_error(&errors, &errors.0, &Config::default());
+/// assert_eq!(exc.exception_type, "InvalidRows");
+/// assert_eq!(exc.exceptions.len(), 2);
+/// assert_eq!(exc.exceptions[0].exception_type, "ParseIntError");
+/// assert_eq!(exc.exceptions[1].message, "number too large to fit in target type");
+/// ```
+pub fn capture_aggregate_error<'a, E, I, F>(
+    error: &E,
+    failures: I,
+    config: &Config,
+) -> ExceptionCapture
+where
+    E: Error + ?Sized,
+    I: IntoIterator<Item = &'a F>,
+    F: Error + ?Sized + 'a,
+{
+    let mut exc = capture_error(error, config);
+    let mut count = 0;
+    for failure in failures {
+        count += 1;
+        if exc.exceptions.len() < MAX_EXCEPTIONS {
+            exc.exceptions
+                .push(ExceptionValue::from_error(failure, config));
+        }
+    }
+    if count > MAX_EXCEPTIONS {
+        exc.context
+            .insert("exception_count".to_string(), serde_json::json!(count));
+    }
+    exc
+}
+
+/// Most failures of an aggregate error reported in `exceptions`.
+const MAX_EXCEPTIONS: usize = 32;
+
+/// Names an error by its type, or for a type-erased error by the type at the
+/// start of its `Debug` output.
+fn error_type_name<E: Error + ?Sized>(error: &E) -> String {
+    let type_name = std::any::type_name::<E>();
+    if type_name.starts_with("dyn ") || type_name.contains("<dyn ") {
+        debug_type_name(&format!("{:?}", error))
+    } else {
+        short_type_name(type_name)
+    }
+}
+
+/// Captures an error reported as `exception_type` instead of its type name,
+/// e.g. `io::NotFound`, which also groups it under that name.
+pub fn capture_error_as<E: Error + ?Sized>(
+    exception_type: &str,
+    error: &E,
+    config: &Config,
+) -> ExceptionCapture {
+    let mut exc = capture_error(error, config);
+    exc.exception_type = exception_type.to_string();
+    regroup(&mut exc, exception_type, config);
+    exc
+}
+
+/// Captures a type-erased error such as the contents of a `Box<dyn Error>`.
+///
+/// The concrete type is recovered by downcasting where possible, so
+/// `exception_type` names the underlying error rather than `dyn Error`.
+pub fn capture_dyn_error(error: &(dyn Error + 'static), config: &Config) -> ExceptionCapture {
+    let stack_trace =
+        provided_stack_trace(error, config).unwrap_or_else(|| capture_stack_trace(config));
+    let exc = build_error_capture(
+        dyn_type_name(error),
+        error.to_string(),
+        error.source(),
+        stack_trace,
+        config,
+    );
+    with_error_kind(exc, &format!("{:?}", error), config)
+}
+
+/// Adds the kind found in an error's `Debug` output as `error_kind`, and
+/// groups the event by type and kind.
+fn with_error_kind(mut exc: ExceptionCapture, debug: &str, config: &Config) -> ExceptionCapture {
+    if let Some(kind) = error_kind(debug) {
+        let key = format!("{}::{}", exc.exception_type, kind);
+        regroup(&mut exc, &key, config);
+        exc.context
+            .insert("error_kind".to_string(), serde_json::json!(kind));
+    }
+    exc
+}
+
+/// The kind of an error from its `Debug` output: the `kind` field of
+/// `std::io::Error`, `ParseIntError`, `ParseFloatError` and the many errors
+/// modelled on them, or `io::Error`'s `Kind(NotFound)` form.
+fn error_kind(debug: &str) -> Option<String> {
+    let rest = match debug.split_once("kind: ") {
+        // Not a `kind` inside a string field.
+        Some((before, rest)) if !before.contains('"') => rest,
+        _ => debug.strip_prefix("Kind(")?,
+    };
+    let kind: String = rest
+        .chars()
+        .take_while(|c| c.is_alphanumeric() || *c == '_')
+        .collect();
+    kind.starts_with(|c: char| c.is_ascii_uppercase())
+        .then_some(kind)
+}
+
+/// Returns the backtrace an error (or one of its sources) carries, via `Error::provide`.
+///
+/// Errors built with thiserror's `#[backtrace]` or anyhow record where they
+/// were created, which is more useful than where they were reported.
+#[cfg(feature = "error-provider")]
+fn provided_stack_trace<E: Error + ?Sized>(error: &E, config: &Config) -> Option<Vec<StackFrame>> {
+    use std::backtrace::{Backtrace, BacktraceStatus};
+
+    let backtrace = std::error::request_ref::<Backtrace>(error).or_else(|| {
+        let mut source = error.source();
+        while let Some(error) = source {
+            if let Some(backtrace) = std::error::request_ref::<Backtrace>(error) {
+                return Some(backtrace);
+            }
+            source = error.source();
+        }
+        None
+    })?;
+
+    if backtrace.status() != BacktraceStatus::Captured {
+        return None;
+    }
+    Some(
+        parse_backtrace(&backtrace.to_string())
+            .into_iter()
+            .filter_map(|s| s.into_stack_frame(config))
+            .take(MAX_FRAMES)
+            .collect(),
+    )
+}
+
+#[cfg(not(feature = "error-provider"))]
+fn provided_stack_trace<E: Error + ?Sized>(
+    _error: &E,
+    _config: &Config,
+) -> Option<Vec<StackFrame>> {
+    None
+}
+
+fn build_error_capture(
+    exception_type: String,
+    message: String,
+    source: Option<&(dyn Error + 'static)>,
+    stack_trace: Vec<StackFrame>,
+    config: &Config,
+) -> ExceptionCapture {
+    let fingerprints = Fingerprints::new(&exception_type, &stack_trace);
 
     ExceptionCapture {
+        schema_version: SCHEMA_VERSION,
         id: Uuid::new_v4().to_string(),
         exception_type,
         message,
-        fingerprint,
+        fingerprint: fingerprints.get(config.fingerprint_version).to_string(),
+        fingerprints: Some(fingerprints),
+        symbolication: symbolication(&stack_trace),
         stack_trace,
+        causes: error_chain(source),
+        exceptions: Vec::new(),
         local_variables: HashMap::new(),
         context: HashMap::new(),
-        captured_at: Utc::now().to_rfc3339(),
+        captured_at: clock::now().to_rfc3339(),
         agent_id: config.agent_id.clone(),
         environment: config.environment.clone(),
+        service_name: config.service_name.clone(),
+        server_name: config.server_name.clone(),
+        project_key: None,
         runtime_info: config.runtime_info(),
+        correlation_id: None,
+        feature_flags: BTreeMap::new(),
+        baggage: BTreeMap::new(),
+        elapsed_ms: None,
+        await_trail: Vec::new(),
+        request: None,
+        sample_rate: 1.0,
+        aggregation: None,
+        error_rate: None,
+        transaction: None,
+        threads: Vec::new(),
+        mechanism: Mechanism::Manual,
+        handled: true,
+        attachments: Vec::new(),
+        breadcrumbs: Vec::new(),
+    }
+}
+
+/// Maximum number of `source()` links recorded.
+const MAX_CAUSES: usize = 16;
+
+fn error_chain(mut source: Option<&(dyn Error + 'static)>) -> Vec<ErrorCause> {
+    let mut causes = Vec::new();
+    while let Some(error) = source {
+        if causes.len() >= MAX_CAUSES {
+            break;
+        }
+        causes.push(ErrorCause {
+            exception_type: dyn_type_name(error),
+            message: error.to_string(),
+        });
+        source = error.source();
+    }
+    causes
+}
+
+/// Names a type-erased error by downcasting to well-known error types,
+/// falling back to the type name at the start of its `Debug` output.
+fn dyn_type_name(error: &(dyn Error + 'static)) -> String {
+    macro_rules! downcast {
+        ($($ty:ty),* $(,)?) => {
+            $(
+                if error.is::<$ty>() {
+                    return short_type_name(std::any::type_name::<$ty>());
+                }
+            )*
+        };
+    }
+
+    downcast!(
+        std::io::Error,
+        std::fmt::Error,
+        std::num::ParseIntError,
+        std::num::ParseFloatError,
+        std::num::TryFromIntError,
+        std::str::ParseBoolError,
+        std::str::Utf8Error,
+        std::string::FromUtf8Error,
+        std::char::ParseCharError,
+        std::net::AddrParseError,
+        std::env::VarError,
+        std::time::SystemTimeError,
+        std::sync::mpsc::RecvError,
+        serde_json::Error,
+        url::ParseError,
+        ConfigError,
+    );
+
+    debug_type_name(&format!("{:?}", error))
+}
+
+/// Extracts the leading type name from `Debug` output such as `MyError { .. }`.
+pub(crate) fn debug_type_name(debug: &str) -> String {
+    let name: String = debug
+        .chars()
+        .take_while(|c| c.is_alphanumeric() || *c == '_')
+        .collect();
+    if name.starts_with(|c: char| c.is_ascii_uppercase()) {
+        name
+    } else {
+        "Error".to_string()
+    }
+}
+
+/// Strips the module path and generic arguments from a type name.
+///
+/// Types named just `Error` keep their crate, or for the standard library
+/// their module, as in `io::Error` or `serde_json::Error`.
+fn short_type_name(type_name: &str) -> String {
+    let base = type_name.split('<').next().unwrap_or(type_name);
+    let segments: Vec<&str> = base.split("::").collect();
+    match segments.as_slice() {
+        ["std" | "core" | "alloc", module, .., "Error"] => format!("{}::Error", module),
+        [krate, .., "Error"] if segments.len() > 1 => format!("{}::Error", krate),
+        [.., name] if !name.is_empty() => name.to_string(),
+        _ => "Error".to_string(),
+    }
+}
+
+/// Message and fields recovered from a custom panic payload.
+#[derive(Clone, Debug, Default)]
+pub struct PanicPayload {
+    pub message: String,
+    /// Reported under the `panic_payload` context key.
+    pub fields: HashMap<String, serde_json::Value>,
+}
+
+impl PanicPayload {
+    /// Creates a payload with `message` and no fields.
+    pub fn new(message: impl Into<String>) -> Self {
+        PanicPayload {
+            message: message.into(),
+            fields: HashMap::new(),
+        }
+    }
+
+    /// Adds a field.
+    pub fn field(mut self, key: impl Into<String>, value: impl Into<serde_json::Value>) -> Self {
+        self.fields.insert(key.into(), value.into());
+        self
+    }
+}
+
+/// Function that recognizes a panic payload type and describes it.
+pub type PanicPayloadExtractor =
+    Arc<dyn Fn(&(dyn Any + Send)) -> Option<PanicPayload> + Send + Sync>;
+
+/// Describes a panic payload, trying `&str`, `String` and then each extractor in order.
+pub(crate) fn extract_panic_payload(
+    payload: &(dyn Any + Send),
+    extractors: &[PanicPayloadExtractor],
+) -> PanicPayload {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        return PanicPayload::new(*s);
+    }
+    if let Some(s) = payload.downcast_ref::<String>() {
+        return PanicPayload::new(s.clone());
+    }
+    extractors
+        .iter()
+        .find_map(|extract| extract(payload))
+        .unwrap_or_else(|| PanicPayload::new("panic occurred"))
+}
+
+/// When panics include a stack trace.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PanicBacktrace {
+    /// Always capture a stack trace.
+    #[default]
+    Always,
+    /// Follow `RUST_BACKTRACE` / `RUST_LIB_BACKTRACE`, like the standard panic message.
+    Env,
+    /// Never capture a stack trace.
+    Never,
+}
+
+impl std::str::FromStr for PanicBacktrace {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "always" | "1" | "true" | "full" => Ok(PanicBacktrace::Always),
+            "env" => Ok(PanicBacktrace::Env),
+            "never" | "0" | "false" => Ok(PanicBacktrace::Never),
+            _ => Err(()),
+        }
     }
 }
 
 /// Captures a panic with stack trace.
+///
+/// `location` is the panic site as `file:line:column`, which becomes the
+/// top frame.
 pub fn capture_panic(message: &str, location: Option<String>, config: &Config) -> ExceptionCapture {
-    let stack_trace = capture_stack_trace();
-    let fingerprint = calculate_fingerprint("panic", &stack_trace);
+    let site = location.as_deref().and_then(PanicSite::parse);
+    build_panic_capture(message, site, capture_stack_trace(config), config)
+}
+
+/// Captures a Tokio task that panicked or was cancelled, from its `JoinError`.
+///
+/// A `JoinError` keeps the panic message but not where the panic occurred,
+/// so the stack trace is that of the caller; tasks spawned with
+/// [`spawn_monitored`](crate::task::spawn_monitored) are reported with it.
+#[cfg(not(target_os = "wasi"))]
+pub fn capture_join_error(error: &tokio::task::JoinError, config: &Config) -> ExceptionCapture {
+    let message = crate::task::join_error_message(error);
+    let mut exc = if error.is_panic() {
+        capture_panic(&message, None, config)
+    } else {
+        build_error_capture(
+            "TaskCancelled".to_string(),
+            message,
+            None,
+            capture_stack_trace(config),
+            config,
+        )
+    };
+    exc.mechanism = Mechanism::JoinError;
+    exc.context.insert(
+        "task_id".to_string(),
+        serde_json::json!(error.id().to_string()),
+    );
+    exc
+}
+
+/// Captures an error returned from `main`, which ends the program.
+///
+/// `Box<dyn Error>` and its `Send`/`Sync` variants are captured like
+/// [`capture_dyn_error`], with their causes; other types by their `Debug`
+/// output, which is what Rust prints when `main` returns an error.
+pub fn capture_main_error<E: std::fmt::Debug + 'static>(
+    error: &E,
+    config: &Config,
+) -> ExceptionCapture {
+    let any = error as &dyn Any;
+    let mut exc = if let Some(error) = any.downcast_ref::<Box<dyn Error>>() {
+        capture_dyn_error(&**error, config)
+    } else if let Some(error) = any.downcast_ref::<Box<dyn Error + Send + Sync>>() {
+        capture_dyn_error(&**error, config)
+    } else if let Some(error) = any.downcast_ref::<Box<dyn Error + Send>>() {
+        capture_dyn_error(&**error, config)
+    } else {
+        let exception_type = short_type_name(std::any::type_name::<E>());
+        build_error_capture(
+            exception_type,
+            format!("{:?}", error),
+            None,
+            capture_stack_trace(config),
+            config,
+        )
+    };
+    exc.mechanism = Mechanism::MainReturn;
+    exc.handled = false;
+    exc
+}
+
+/// Captures a future whose single poll took `elapsed`, over `threshold`.
+///
+/// The backtrace is taken once the poll has returned, so it shows what polls
+/// the future; the innermost frame names the future's type and where it was
+/// instrumented, which also groups repeats.
+#[cfg(not(target_os = "wasi"))]
+pub(crate) fn capture_slow_poll(
+    future_type: &str,
+    location: &std::panic::Location<'_>,
+    elapsed: std::time::Duration,
+    threshold: std::time::Duration,
+    config: &Config,
+) -> ExceptionCapture {
+    let origin =
+        StackFrame::new(future_type).file(config.map_path(location.file()), location.line());
+    let mut stack_trace = vec![origin];
+    stack_trace.extend(capture_stack_trace(config).into_iter().take(MAX_FRAMES - 1));
+
+    let message = format!(
+        "{} blocked the executor for {:?} in a single poll",
+        future_type, elapsed
+    );
+    let mut exc = build_error_capture("SlowPoll".to_string(), message, None, stack_trace, config);
+    exc.context
+        .insert("level".to_string(), serde_json::json!("warning"));
+    exc.context.insert(
+        "slow_poll".to_string(),
+        serde_json::json!({
+            "duration_ms": elapsed.as_millis() as u64,
+            "threshold_ms": threshold.as_millis() as u64,
+        }),
+    );
+    exc
+}
+
+/// Captures a panic from inside the panic hook.
+///
+/// The backtrace is taken with `std::backtrace` according to
+/// `config.panic_backtrace`, and the hook and panic machinery frames are
+/// trimmed so the trace starts at the code that panicked.
+pub(crate) fn capture_panic_in_hook(
+    message: &str,
+    location: Option<&std::panic::Location<'_>>,
+    config: &Config,
+) -> ExceptionCapture {
+    let enabled = match config.panic_backtrace {
+        PanicBacktrace::Always => true,
+        PanicBacktrace::Env => backtrace_enabled_by_env(),
+        PanicBacktrace::Never => false,
+    };
+    let backtrace = enabled.then(Backtrace::new);
+    let symbols = backtrace.as_ref().map(resolved_symbols).unwrap_or_default();
+    // `expect()` is only recognizable by the frame it panics from.
+    let unwrap = symbols.iter().any(|symbol| {
+        UNWRAP_MACHINERY
+            .iter()
+            .any(|prefix| symbol.name.starts_with(prefix))
+    });
+    let mut stack_trace = panic_stack_trace(symbols, config);
+    if let Some(backtrace) = backtrace.as_ref().filter(|_| stack_trace.is_empty()) {
+        stack_trace = unsymbolicated_stack_trace(backtrace);
+    }
+    let site = location.map(|location| PanicSite {
+        file: location.file(),
+        line: location.line(),
+        column: location.column(),
+    });
+    let mut exc = build_panic_capture(message, site, stack_trace, config);
+    if unwrap {
+        exc.mechanism = Mechanism::Unwrap;
+    }
+    exc
+}
+
+/// Whether `RUST_LIB_BACKTRACE`, or `RUST_BACKTRACE` when it is not set,
+/// enables backtraces, as for `std::backtrace::Backtrace::capture`.
+fn backtrace_enabled_by_env() -> bool {
+    std::env::var_os("RUST_LIB_BACKTRACE")
+        .or_else(|| std::env::var_os("RUST_BACKTRACE"))
+        .is_some_and(|value| value != "0")
+}
+
+/// Where a panic occurred, from its `Location`.
+struct PanicSite<'a> {
+    file: &'a str,
+    line: u32,
+    column: u32,
+}
+
+impl<'a> PanicSite<'a> {
+    /// Parses `file:line:column`.
+    fn parse(location: &'a str) -> Option<Self> {
+        let mut parts = location.rsplitn(3, ':');
+        let column = parts.next()?.parse().ok()?;
+        let line = parts.next()?.parse().ok()?;
+        Some(PanicSite {
+            file: parts.next()?,
+            line,
+            column,
+        })
+    }
+
+    /// Whether `frame` is at this site.
+    fn is_at(&self, frame: &StackFrame) -> bool {
+        frame.line_number == Some(self.line)
+            && frame.file_name.as_deref() == self.file.rsplit(['/', '\\']).next()
+    }
+
+    /// Makes the site frame zero of `stack_trace`.
+    ///
+    /// Frames above the site are those of `#[track_caller]` functions, which
+    /// report their caller as the site, and are dropped. Without a frame at
+    /// the site, e.g. in release builds without debug info or when no
+    /// backtrace was taken, one is made from the location alone.
+    fn into_top_frame(self, mut stack_trace: Vec<StackFrame>, config: &Config) -> Vec<StackFrame> {
+        if let Some(index) = stack_trace.iter().position(|frame| self.is_at(frame)) {
+            stack_trace.drain(..index);
+            stack_trace[0].column_number = Some(self.column);
+            return stack_trace;
+        }
+        let is_native = self.file.contains(".rustup") || self.file.contains("registry");
+        let mut frame = StackFrame::new("<unknown>").file(config.map_path(self.file), self.line);
+        frame.column_number = Some(self.column);
+        frame.is_native = is_native;
+        frame.source_available = !is_native;
+        stack_trace.insert(0, frame);
+        stack_trace.truncate(MAX_FRAMES);
+        stack_trace
+    }
+}
+
+fn build_panic_capture(
+    message: &str,
+    site: Option<PanicSite<'_>>,
+    stack_trace: Vec<StackFrame>,
+    config: &Config,
+) -> ExceptionCapture {
+    let stack_trace = match site {
+        Some(site) => site.into_top_frame(stack_trace, config),
+        None => stack_trace,
+    };
+    let fingerprints = Fingerprints::new("panic", &stack_trace);
 
     let mut context = HashMap::new();
     context.insert("panic".to_string(), serde_json::json!(true));
-    if let Some(loc) = location {
-        context.insert("location".to_string(), serde_json::json!(loc));
-    }
 
     ExceptionCapture {
+        schema_version: SCHEMA_VERSION,
         id: Uuid::new_v4().to_string(),
         exception_type: "panic".to_string(),
         message: message.to_string(),
-        fingerprint,
+        fingerprint: fingerprints.get(config.fingerprint_version).to_string(),
+        fingerprints: Some(fingerprints),
+        symbolication: symbolication(&stack_trace),
         stack_trace,
+        causes: Vec::new(),
+        exceptions: Vec::new(),
         local_variables: HashMap::new(),
         context,
-        captured_at: Utc::now().to_rfc3339(),
+        captured_at: clock::now().to_rfc3339(),
         agent_id: config.agent_id.clone(),
         environment: config.environment.clone(),
+        service_name: config.service_name.clone(),
+        server_name: config.server_name.clone(),
+        project_key: None,
         runtime_info: config.runtime_info(),
+        correlation_id: None,
+        feature_flags: BTreeMap::new(),
+        baggage: BTreeMap::new(),
+        elapsed_ms: None,
+        await_trail: Vec::new(),
+        request: None,
+        sample_rate: 1.0,
+        aggregation: None,
+        error_rate: None,
+        transaction: None,
+        threads: Vec::new(),
+        mechanism: if is_unwrap_message(message) {
+            Mechanism::Unwrap
+        } else {
+            Mechanism::PanicHook
+        },
+        handled: false,
+        attachments: Vec::new(),
+        breadcrumbs: Vec::new(),
     }
 }
 
-fn capture_stack_trace() -> Vec<StackFrame> {
-    let bt = Backtrace::new();
-    let mut frames = Vec::new();
+/// Whether a panic message is the one `unwrap()` on a `Result` or `Option`
+/// panics with; `expect()` panics with the caller's message instead.
+fn is_unwrap_message(message: &str) -> bool {
+    message.starts_with("called `Result::unwrap") || message.starts_with("called `Option::unwrap")
+}
 
-    for frame in bt.frames() {
-        for symbol in frame.symbols() {
-            let method_name = symbol
-                .name()
-                .map(|n| n.to_string())
-                .unwrap_or_else(|| "<unknown>".to_string());
-
-            // Skip internal frames
-            if method_name.starts_with("std::")
-                || method_name.starts_with("core::")
-                || method_name.starts_with("backtrace::")
-                || method_name.starts_with("aivory_monitor::capture")
-            {
-                continue;
-            }
+/// Symbols that belong to the panic runtime rather than the panicking code.
+const PANIC_MACHINERY: &[&str] = &[
+    "std::panicking::",
+    "core::panicking::",
+    "std::panic::panic_any",
+    "std::sys::backtrace::__rust_end_short_backtrace",
+    "std::sys_common::backtrace::__rust_end_short_backtrace",
+    "rust_begin_unwind",
+    "__rustc::rust_begin_unwind",
+];
+
+/// Symbols `unwrap()` and `expect()` on a `Result` or `Option` panic from.
+const UNWRAP_MACHINERY: &[&str] = &[
+    "core::option::unwrap_failed",
+    "core::option::expect_failed",
+    "core::result::unwrap_failed",
+];
+
+/// A symbol parsed from a rendered backtrace or resolved from an address.
+struct RawFrame {
+    name: String,
+    file: Option<String>,
+    line: Option<u32>,
+    column: Option<u32>,
+    /// Inlined into the next symbol, which shares its address.
+    is_inline: bool,
+}
+
+impl RawFrame {
+    /// Builds the frame of this symbol, or `None` if it is filtered out.
+    fn into_stack_frame(self, config: &Config) -> Option<StackFrame> {
+        let mut frame = stack_frame(&self.name, self.file, self.line, self.column, config)?;
+        frame.is_inline = self.is_inline;
+        Some(frame)
+    }
+}
+
+/// Builds the frames of a backtrace taken in the panic hook, starting at the
+/// panic origin.
+fn panic_stack_trace(symbols: Vec<RawFrame>, config: &Config) -> Vec<StackFrame> {
+    // Everything up to the last panic machinery frame above the origin is
+    // the hook and the panic runtime.
+    let is_machinery = |name: &str| {
+        PANIC_MACHINERY
+            .iter()
+            .chain(UNWRAP_MACHINERY)
+            .any(|prefix| name.starts_with(prefix))
+    };
+    let start = match symbols.iter().position(|s| is_machinery(&s.name)) {
+        Some(first) => {
+            first
+                + symbols[first..]
+                    .iter()
+                    .take_while(|s| is_machinery(&s.name))
+                    .count()
+        }
+        None => 0,
+    };
+
+    symbols
+        .into_iter()
+        .skip(start)
+        .filter_map(|s| s.into_stack_frame(config))
+        .take(MAX_FRAMES)
+        .collect()
+}
 
-            let file_path = symbol.filename().map(|p| p.to_string_lossy().to_string());
-            let file_name = file_path.as_ref().and_then(|p| {
-                p.split(['/', '\\']).next_back().map(|s| s.to_string())
-            });
-
-            let is_native = file_path
-                .as_ref()
-                .map(|p| p.contains(".rustup") || p.contains("registry"))
-                .unwrap_or(true);
-
-            frames.push(StackFrame {
-                method_name: extract_function_name(&method_name),
-                file_name,
-                file_path: file_path.clone(),
-                line_number: symbol.lineno(),
-                column_number: symbol.colno(),
-                is_native,
-                source_available: !is_native && file_path.is_some(),
-            });
-
-            if frames.len() >= 50 {
-                break;
+/// Parses the `Display` output of a `std::backtrace::Backtrace` into one
+/// entry per symbol, inlined ones included.
+///
+/// The rendering numbers inlined symbols like separate frames, so none of
+/// them is marked `is_inline`.
+#[cfg(feature = "error-provider")]
+fn parse_backtrace(rendered: &str) -> Vec<RawFrame> {
+    let mut symbols: Vec<RawFrame> = Vec::new();
+    for line in rendered.lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(location) = line.strip_prefix("at ") {
+            if let Some(last) = symbols.last_mut() {
+                let mut parts = location.rsplitn(3, ':');
+                last.column = parts.next().and_then(|c| c.parse().ok());
+                last.line = parts.next().and_then(|l| l.parse().ok());
+                last.file = parts.next().map(|f| f.to_string());
             }
+            continue;
         }
+        let name = match line.split_once(": ") {
+            Some((index, name)) if index.chars().all(|c| c.is_ascii_digit()) => name,
+            _ => line,
+        };
+        symbols.push(RawFrame {
+            name: name.to_string(),
+            file: None,
+            line: None,
+            column: None,
+            is_inline: false,
+        });
+    }
 
-        if frames.len() >= 50 {
-            break;
+    symbols
+}
+
+/// Appended to strings cut at `max_string_length`.
+const TRUNCATION_MARKER: &str = "…[truncated]";
+
+/// Context key counting the entries dropped by `max_context_keys`.
+const DROPPED_CONTEXT_KEYS: &str = "_dropped_context_keys";
+
+/// Applies [`limit_value`] to every entry of `context` and keeps at most
+/// `max_context_keys` of them, the first in key order.
+pub(crate) fn limit_context(
+    context: HashMap<String, serde_json::Value>,
+    config: &Config,
+) -> HashMap<String, serde_json::Value> {
+    let mut entries: Vec<_> = context.into_iter().collect();
+    entries.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+    let mut limited = HashMap::new();
+    for (key, value) in entries {
+        insert_context(&mut limited, key, value, config);
+    }
+    limited
+}
+
+/// Inserts a context entry with [`limit_value`] applied. Once `context` has
+/// `max_context_keys` entries, new keys are dropped and counted under
+/// `_dropped_context_keys` instead.
+pub(crate) fn insert_context(
+    context: &mut HashMap<String, serde_json::Value>,
+    key: String,
+    value: serde_json::Value,
+    config: &Config,
+) {
+    let keys = context.len() - usize::from(context.contains_key(DROPPED_CONTEXT_KEYS));
+    if keys >= config.max_context_keys && !context.contains_key(&key) {
+        let dropped = context
+            .get(DROPPED_CONTEXT_KEYS)
+            .and_then(serde_json::Value::as_u64)
+            .unwrap_or(0);
+        context.insert(DROPPED_CONTEXT_KEYS.to_string(), (dropped + 1).into());
+        return;
+    }
+    context.insert(key, limit_value(value, config));
+}
+
+/// Applies the configured depth, string length and collection size limits to `value`.
+///
+/// Strings are cut at `max_string_length` characters and marked with
+/// `…[truncated]`, arrays and objects keep their first `max_collection_size`
+/// entries, and anything nested deeper than `max_capture_depth` is replaced
+/// by a placeholder string.
+pub(crate) fn limit_value(value: serde_json::Value, config: &Config) -> serde_json::Value {
+    limit_value_at(value, config, 0)
+}
+
+fn limit_value_at(value: serde_json::Value, config: &Config, depth: usize) -> serde_json::Value {
+    use serde_json::Value;
+
+    match value {
+        Value::Array(_) | Value::Object(_) if depth >= config.max_capture_depth => {
+            Value::String("<max depth exceeded>".to_string())
         }
+        Value::String(s) if s.chars().count() > config.max_string_length => Value::String(
+            s.chars()
+                .take(config.max_string_length)
+                .chain(TRUNCATION_MARKER.chars())
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(
+            items
+                .into_iter()
+                .take(config.max_collection_size)
+                .map(|item| limit_value_at(item, config, depth + 1))
+                .collect(),
+        ),
+        Value::Object(entries) => Value::Object(
+            entries
+                .into_iter()
+                .take(config.max_collection_size)
+                .map(|(k, v)| (k, limit_value_at(v, config, depth + 1)))
+                .collect(),
+        ),
+        other => other,
     }
+}
+
+/// Maximum number of frames kept in a stack trace.
+pub(crate) const MAX_FRAMES: usize = 50;
+
+fn capture_stack_trace(config: &Config) -> Vec<StackFrame> {
+    let bt = Backtrace::new();
+    let frames: Vec<StackFrame> = resolved_symbols(&bt)
+        .into_iter()
+        .filter_map(|symbol| symbol.into_stack_frame(config))
+        .take(MAX_FRAMES)
+        .collect();
 
+    if frames.is_empty() {
+        return unsymbolicated_stack_trace(&bt);
+    }
     frames
 }
 
-fn extract_function_name(full_name: &str) -> String {
-    // Extract just the function name from a fully qualified name
-    // e.g., "my_crate::module::function" -> "function"
-    full_name
-        .split("::")
-        .last()
-        .unwrap_or(full_name)
-        .to_string()
+/// The symbols of a resolved backtrace, one per function: the functions
+/// inlined at an address come first, marked `is_inline`, followed by the
+/// function the instruction physically belongs to.
+fn resolved_symbols(bt: &Backtrace) -> Vec<RawFrame> {
+    let mut symbols = Vec::new();
+    for frame in bt.frames() {
+        let resolved = frame.symbols();
+        symbols.extend(resolved.iter().enumerate().map(|(index, symbol)| {
+            RawFrame {
+                // Without hashes and crate disambiguators, as std renders them.
+                name: symbol
+                    .name()
+                    .map(|n| format!("{:#}", n))
+                    .unwrap_or_else(|| "<unknown>".to_string()),
+                file: symbol.filename().map(|p| p.to_string_lossy().to_string()),
+                line: symbol.lineno(),
+                column: symbol.colno(),
+                is_inline: index + 1 < resolved.len(),
+            }
+        }));
+    }
+    symbols
 }
 
-fn calculate_fingerprint(exception_type: &str, stack_trace: &[StackFrame]) -> String {
-    let mut parts = vec![exception_type.to_string()];
+/// Frames by address alone, for a backtrace without a single usable symbol,
+/// as in stripped release builds. The agent's own frames cannot be told apart
+/// and are included.
+fn unsymbolicated_stack_trace(bt: &Backtrace) -> Vec<StackFrame> {
+    bt.frames()
+        .iter()
+        .take(MAX_FRAMES)
+        .map(|frame| {
+            let mut raw = StackFrame::new("<unknown>");
+            raw.instruction_addr = Some(format!("{:#x}", frame.ip() as usize));
+            raw.image_addr = image_address(frame).map(|base| format!("{:#x}", base));
+            // Without a source location, like other frames without one.
+            raw.is_native = true;
+            raw
+        })
+        .collect()
+}
 
-    let mut added = 0;
-    for frame in stack_trace {
-        if added >= 5 {
-            break;
+/// Load address of the binary or library containing `frame`.
+fn image_address(frame: &backtrace::BacktraceFrame) -> Option<usize> {
+    #[cfg(target_os = "linux")]
+    {
+        // SAFETY: `Dl_info` is plain data, for which all zeroes is valid.
+        let mut info: libc::Dl_info = unsafe { std::mem::zeroed() };
+        // SAFETY: `info` is a valid out pointer; `dladdr` only looks the address up.
+        let found = unsafe { libc::dladdr(frame.ip(), &mut info) };
+        (found != 0).then_some(info.dli_fbase as usize)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        frame.module_base_address().map(|base| base as usize)
+    }
+}
+
+/// The `symbolication` hint of an event with `stack_trace`.
+fn symbolication(stack_trace: &[StackFrame]) -> Option<String> {
+    stack_trace
+        .iter()
+        .any(|frame| frame.instruction_addr.is_some())
+        .then(|| "server".to_string())
+}
+
+/// Prefixes of runtime frames that are always filtered.
+const BUILTIN_SKIP_PREFIXES: &[&str] = &["std::", "core::", "alloc::", "backtrace::"];
+
+/// Unqualified symbols from the C runtime and compiler shims, always filtered.
+const RUNTIME_SYMBOLS: &[&str] = &[
+    "<unknown>",
+    "__rust_try",
+    "main",
+    "_start",
+    "__libc_start_main",
+    "__libc_start_call_main",
+    "start_thread",
+    "clone",
+    "clone3",
+    "__clone",
+];
+
+/// Builds a frame for a resolved symbol, or `None` if it is filtered out.
+///
+/// Agent frames are always dropped. Runtime frames and those matching
+/// `frame_skip_prefixes` are dropped too, unless `keep_filtered_frames` is
+/// set, in which case they are kept and marked `is_filtered`.
+pub(crate) fn stack_frame(
+    method_name: &str,
+    file_path: Option<String>,
+    line_number: Option<u32>,
+    column_number: Option<u32>,
+    config: &Config,
+) -> Option<StackFrame> {
+    let path = symbol_path(method_name);
+    if path.starts_with("aivory_monitor::") {
+        return None;
+    }
+    let is_filtered = RUNTIME_SYMBOLS.contains(&method_name)
+        || RUNTIME_SYMBOLS.contains(&path.as_str())
+        || BUILTIN_SKIP_PREFIXES
+            .iter()
+            .any(|prefix| path.starts_with(prefix))
+        || config
+            .frame_skip_prefixes
+            .iter()
+            .any(|prefix| path.starts_with(prefix.as_str()));
+    if is_filtered && !config.keep_filtered_frames {
+        return None;
+    }
+
+    let file_name = file_path
+        .as_ref()
+        .and_then(|p| p.split(['/', '\\']).next_back().map(|s| s.to_string()));
+
+    let is_native = file_path
+        .as_ref()
+        .map(|p| p.contains(".rustup") || p.contains("registry"))
+        .unwrap_or(true);
+
+    let parts = symbol_parts(method_name);
+    let file_path = file_path.map(|p| config.map_path(&p));
+
+    Some(StackFrame {
+        method_name: parts.method_name,
+        module: parts.module,
+        crate_name: parts.crate_name,
+        full_name: Some(parts.full_name),
+        file_name,
+        source_available: !is_native && file_path.is_some(),
+        file_path,
+        line_number,
+        column_number,
+        instruction_addr: None,
+        image_addr: None,
+        is_native,
+        is_filtered,
+        is_inline: false,
+    })
+}
+
+/// Removes the `[1a2b3c]` crate disambiguators of v0 mangling and the
+/// `::h1a2b3c..` hash suffix of legacy mangling from a demangled symbol.
+fn demangled_name(symbol: &str) -> String {
+    let mut name = String::with_capacity(symbol.len());
+    let mut rest = symbol;
+    while let Some(open) = rest.find('[') {
+        let after_ident = rest[..open].ends_with(|c: char| c.is_alphanumeric() || c == '_');
+        let disambiguator = rest[open + 1..].find(']').filter(|&len| {
+            len > 0
+                && rest[open + 1..open + 1 + len]
+                    .chars()
+                    .all(|c| c.is_ascii_hexdigit())
+        });
+        match disambiguator {
+            Some(len) if after_ident => {
+                name.push_str(&rest[..open]);
+                rest = &rest[open + len + 2..];
+            }
+            _ => {
+                name.push_str(&rest[..=open]);
+                rest = &rest[open + 1..];
+            }
         }
-        if frame.is_native {
-            continue;
+    }
+    name.push_str(rest);
+
+    if let Some((path, hash)) = name.rsplit_once("::h") {
+        if hash.len() == 16 && hash.chars().all(|c| c.is_ascii_hexdigit()) {
+            name.truncate(path.len());
         }
+    }
+    name
+}
 
-        parts.push(format!(
-            "{}:{}",
-            frame.method_name,
-            frame.line_number.unwrap_or(0)
-        ));
-        added += 1;
+/// Normalizes a demangled symbol for prefix matching.
+///
+/// Besides [`demangled_name`], strips the `<&dyn ` that starts trait impl
+/// symbols such as `<my_crate::Type as core::ops::Fn>::call`. Impls for
+/// generic parameters or primitives (`<T as alloc::string::ToString>`) are
+/// attributed to the trait's crate.
+fn symbol_path(symbol: &str) -> String {
+    let name = demangled_name(symbol);
+    if let Some((self_ty, trait_path)) = name.strip_prefix('<').and_then(|n| n.split_once(" as ")) {
+        if !self_ty.contains("::") {
+            return trait_path.to_string();
+        }
     }
+    let path = name.trim_start_matches(['<', '&']);
+    path.strip_prefix("dyn ").unwrap_or(path).to_string()
+}
+
+/// Splits a path on `::` outside of generic arguments.
+fn path_segments(path: &str) -> Vec<&str> {
+    let mut segments = Vec::new();
+    let mut depth = 0usize;
+    let mut start = 0;
+    let bytes = path.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'<' => depth += 1,
+            b'>' => depth = depth.saturating_sub(1),
+            b':' if depth == 0 && bytes.get(i + 1) == Some(&b':') => {
+                segments.push(&path[start..i]);
+                i += 2;
+                start = i;
+                continue;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    segments.push(&path[start..]);
+    segments
+}
 
-    let mut hasher = Sha256::new();
-    hasher.update(parts.join(":"));
-    let result = hasher.finalize();
-    hex::encode(&result[..8])
+/// Function, module and crate of a symbol.
+struct SymbolParts {
+    full_name: String,
+    method_name: String,
+    module: Option<String>,
+    crate_name: Option<String>,
+}
+
+/// Splits a symbol such as `my_crate::orders::Order::new` into its parts.
+///
+/// Turbofish arguments are dropped from the function name, and closures keep
+/// their enclosing function (`process::{{closure}}`) so they stay distinguishable.
+fn symbol_parts(symbol: &str) -> SymbolParts {
+    let full_name = demangled_name(symbol);
+    let mut segments = path_segments(&full_name);
+    while segments.len() > 1 && segments.last().is_some_and(|s| s.starts_with('<')) {
+        segments.pop();
+    }
+
+    let last = segments.len() - 1;
+    let function_start = if last > 0 && segments[last].starts_with('{') {
+        last - 1
+    } else {
+        last
+    };
+    let method_name = segments[function_start..].join("::");
+    let module = (function_start > 0).then(|| segments[..function_start].join("::"));
+
+    let crate_name = symbol_path(symbol)
+        .split_once("::")
+        .map(|(first, _)| first.to_string());
+
+    SymbolParts {
+        full_name,
+        method_name,
+        module,
+        crate_name,
+    }
 }
@@ -0,0 +1,58 @@
+//! Files sent along with an event.
+
+use base64::Engine;
+use serde::{Serialize, Serializer};
+
+/// A file sent in the same envelope as the event it belongs to, such as the
+/// request body that failed to parse or a config file.
+///
+/// The backend receives the event and its attachments together or not at
+/// all, so an attachment never arrives without its event.
+///
+/// ```rust,no_run
+/// use aivory_monitor::capture::Attachment;
+/// use aivory_monitor::EventBuilder;
+///
+/// # let body = Vec::new();
+/// let event = EventBuilder::new("InvalidPayload")
+///     .message("order payload did not parse")
+///     .attachment(Attachment::new("payload.json", body).content_type("application/json"));
+///
+/// aivory_monitor::capture_event(event);
+/// ```
+#[derive(Clone, Debug, Serialize)]
+pub struct Attachment {
+    /// File name shown in the backend.
+    pub filename: String,
+    /// MIME type of `data`.
+    pub content_type: String,
+    /// File contents, sent base64-encoded.
+    #[serde(serialize_with = "serialize_base64")]
+    pub data: Vec<u8>,
+}
+
+impl Attachment {
+    /// Creates an attachment of type `application/octet-stream`.
+    pub fn new(filename: impl Into<String>, data: impl Into<Vec<u8>>) -> Self {
+        Attachment {
+            filename: filename.into(),
+            content_type: "application/octet-stream".to_string(),
+            data: data.into(),
+        }
+    }
+
+    /// Creates a `text/plain` attachment.
+    pub fn text(filename: impl Into<String>, text: impl Into<String>) -> Self {
+        Attachment::new(filename, text.into()).content_type("text/plain")
+    }
+
+    /// Sets the MIME type.
+    pub fn content_type(mut self, content_type: impl Into<String>) -> Self {
+        self.content_type = content_type.into();
+        self
+    }
+}
+
+fn serialize_base64<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&base64::engine::general_purpose::STANDARD.encode(data))
+}
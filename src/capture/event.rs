@@ -0,0 +1,241 @@
+//! Manual event construction.
+
+use super::{
+    Attachment, CrashRecord, ErrorCause, ExceptionCapture, ExceptionValue, Fingerprints, Mechanism,
+    StackFrame, Variable, SCHEMA_VERSION,
+};
+use crate::clock;
+use crate::config::Config;
+use crate::request::RequestContext;
+use chrono::{DateTime, TimeZone, Utc};
+use std::collections::{BTreeMap, HashMap};
+use uuid::Uuid;
+
+/// Builder for an [`ExceptionCapture`] assembled from the caller's own data.
+///
+/// Useful for failures that did not originate as a Rust error in this
+/// process, such as errors deserialized from a job queue or crash reports
+/// in a foreign format. No stack trace is captured automatically.
+///
+/// ```rust,no_run
+/// use aivory_monitor::capture::StackFrame;
+/// use aivory_monitor::ExceptionCapture;
+///
+/// let event = ExceptionCapture::builder("JobFailed")
+///     .message("worker exited with status 137")
+///     .frame(StackFrame::new("process_batch").file("jobs/batch.py", 42))
+///     .context("queue", "billing");
+///
+/// aivory_monitor::capture_event(event);
+/// ```
+#[derive(Clone)]
+pub struct EventBuilder {
+    exception_type: String,
+    message: String,
+    fingerprint: Option<String>,
+    stack_trace: Vec<StackFrame>,
+    causes: Vec<ErrorCause>,
+    exceptions: Vec<ExceptionValue>,
+    local_variables: HashMap<String, Variable>,
+    context: HashMap<String, serde_json::Value>,
+    request: Option<RequestContext>,
+    captured_at: Option<DateTime<Utc>>,
+    mechanism: Mechanism,
+    handled: bool,
+    attachments: Vec<Attachment>,
+}
+
+impl EventBuilder {
+    /// Creates a builder for an event of `exception_type`.
+    pub fn new(exception_type: impl Into<String>) -> Self {
+        EventBuilder {
+            exception_type: exception_type.into(),
+            message: String::new(),
+            fingerprint: None,
+            stack_trace: Vec::new(),
+            causes: Vec::new(),
+            exceptions: Vec::new(),
+            local_variables: HashMap::new(),
+            context: HashMap::new(),
+            request: None,
+            captured_at: None,
+            mechanism: Mechanism::Manual,
+            handled: true,
+            attachments: Vec::new(),
+        }
+    }
+
+    /// Sets the error message.
+    pub fn message(mut self, message: impl Into<String>) -> Self {
+        self.message = message.into();
+        self
+    }
+
+    /// Sets the grouping fingerprint. Defaults to a hash of the type and top frames.
+    pub fn fingerprint(mut self, fingerprint: impl Into<String>) -> Self {
+        self.fingerprint = Some(fingerprint.into());
+        self
+    }
+
+    /// Appends a stack frame, innermost first.
+    pub fn frame(mut self, frame: StackFrame) -> Self {
+        self.stack_trace.push(frame);
+        self
+    }
+
+    /// Replaces the stack trace.
+    pub fn stack_trace(mut self, frames: Vec<StackFrame>) -> Self {
+        self.stack_trace = frames;
+        self
+    }
+
+    /// Appends an error to the cause chain, outermost first.
+    pub fn cause(mut self, exception_type: impl Into<String>, message: impl Into<String>) -> Self {
+        self.causes.push(ErrorCause {
+            exception_type: exception_type.into(),
+            message: message.into(),
+        });
+        self
+    }
+
+    /// Adds one of the failures of an aggregate error; see
+    /// [`ExceptionCapture::exceptions`].
+    pub fn exception(mut self, exception: ExceptionValue) -> Self {
+        self.exceptions.push(exception);
+        self
+    }
+
+    /// Adds a local variable.
+    pub fn variable(mut self, variable: Variable) -> Self {
+        self.local_variables.insert(variable.name.clone(), variable);
+        self
+    }
+
+    /// Adds a context value.
+    pub fn context(mut self, key: impl Into<String>, value: impl Into<serde_json::Value>) -> Self {
+        self.context.insert(key.into(), value.into());
+        self
+    }
+
+    /// Sets the HTTP request being served. Defaults to that of the active scope.
+    pub fn request(mut self, request: RequestContext) -> Self {
+        self.request = Some(request);
+        self
+    }
+
+    /// Sets when the error occurred. Defaults to the time of capture.
+    pub fn captured_at(mut self, at: DateTime<Utc>) -> Self {
+        self.captured_at = Some(at);
+        self
+    }
+
+    /// Sets how the event was captured. Defaults to [`Mechanism::Manual`].
+    pub fn mechanism(mut self, mechanism: Mechanism) -> Self {
+        self.mechanism = mechanism;
+        self
+    }
+
+    /// Sets whether the application recovered from the error. Defaults to
+    /// `true`; pass `false` for crashes.
+    pub fn handled(mut self, handled: bool) -> Self {
+        self.handled = handled;
+        self
+    }
+
+    /// Adds a file sent with the event.
+    pub fn attachment(mut self, attachment: Attachment) -> Self {
+        self.attachments.push(attachment);
+        self
+    }
+
+    /// Builds the event, filling in agent identity and runtime details from `config`.
+    pub fn build(self, config: &Config) -> ExceptionCapture {
+        // A fingerprint set by hand replaces both algorithms.
+        let (fingerprint, fingerprints) = match self.fingerprint {
+            Some(fingerprint) => (fingerprint, None),
+            None => {
+                let fingerprints = Fingerprints::new(&self.exception_type, &self.stack_trace);
+                (
+                    fingerprints.get(config.fingerprint_version).to_string(),
+                    Some(fingerprints),
+                )
+            }
+        };
+
+        ExceptionCapture {
+            schema_version: SCHEMA_VERSION,
+            id: Uuid::new_v4().to_string(),
+            exception_type: self.exception_type,
+            message: self.message,
+            fingerprint,
+            fingerprints,
+            symbolication: super::symbolication(&self.stack_trace),
+            stack_trace: self.stack_trace,
+            causes: self.causes,
+            exceptions: self.exceptions,
+            local_variables: self.local_variables,
+            context: self.context,
+            captured_at: self.captured_at.unwrap_or_else(clock::now).to_rfc3339(),
+            agent_id: config.agent_id.clone(),
+            environment: config.environment.clone(),
+            service_name: config.service_name.clone(),
+            server_name: config.server_name.clone(),
+            project_key: None,
+            runtime_info: config.runtime_info(),
+            correlation_id: None,
+            feature_flags: BTreeMap::new(),
+            baggage: BTreeMap::new(),
+            elapsed_ms: None,
+            await_trail: Vec::new(),
+            request: self.request,
+            sample_rate: 1.0,
+            aggregation: None,
+            error_rate: None,
+            transaction: None,
+            threads: Vec::new(),
+            mechanism: self.mechanism,
+            handled: self.handled,
+            attachments: self.attachments,
+            breadcrumbs: Vec::new(),
+        }
+    }
+}
+
+/// Forwards a record built on a device with `aivory-monitor-core`.
+///
+/// ```rust,no_run
+/// use aivory_monitor::capture::CrashRecord;
+///
+/// fn on_frame(bytes: &[u8]) {
+///     if let Ok(record) = serde_json::from_slice::<CrashRecord>(bytes) {
+///         aivory_monitor::capture_event(record.into());
+///     }
+/// }
+/// ```
+impl From<CrashRecord> for EventBuilder {
+    fn from(record: CrashRecord) -> Self {
+        // Records from `CrashRecord::from_panic` come from a `#[panic_handler]`.
+        let crashed = record.exception_type == "panic";
+        EventBuilder {
+            exception_type: record.exception_type,
+            message: record.message,
+            fingerprint: record.fingerprint,
+            stack_trace: record.stack_trace,
+            causes: record.causes,
+            exceptions: Vec::new(),
+            local_variables: HashMap::new(),
+            context: record.context.into_iter().collect(),
+            request: None,
+            captured_at: record
+                .captured_at
+                .and_then(|millis| Utc.timestamp_millis_opt(millis).single()),
+            mechanism: if crashed {
+                Mechanism::PanicHook
+            } else {
+                Mechanism::Manual
+            },
+            handled: !crashed,
+            attachments: Vec::new(),
+        }
+    }
+}
@@ -0,0 +1,198 @@
+//! Stacks of every thread of the process, for fatal events.
+//!
+//! On Linux, each other thread is interrupted in turn with a real-time signal
+//! whose handler records the return addresses on its stack; they are
+//! symbolized afterwards on the capturing thread. Elsewhere nothing is listed.
+
+use super::ThreadTrace;
+use crate::config::Config;
+
+/// Most threads listed per event.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+const MAX_THREADS: usize = 64;
+
+/// Lists the threads of the process, the calling one marked `crashed`, with
+/// the stacks of the others.
+#[cfg(target_os = "linux")]
+pub(crate) fn all_threads(config: &Config) -> Vec<ThreadTrace> {
+    linux::all_threads(config)
+}
+
+/// Lists the threads of the process; not supported on this platform.
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn all_threads(_config: &Config) -> Vec<ThreadTrace> {
+    Vec::new()
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::{ThreadTrace, MAX_THREADS};
+    use crate::capture::{RawFrame, StackFrame, MAX_FRAMES};
+    use crate::config::Config;
+    use once_cell::sync::OnceCell;
+    use parking_lot::Mutex;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::time::{Duration, Instant};
+
+    /// How long a thread gets to record its stack.
+    const SAMPLE_TIMEOUT: Duration = Duration::from_millis(100);
+
+    /// Symbol of the trampoline the kernel returns through after a signal
+    /// handler; frames up to it belong to the handler.
+    const SIGNAL_TRAMPOLINE: &str = "__restore_rt";
+
+    /// Held while sampling, as the handler writes to the statics below.
+    static SAMPLING: Mutex<()> = Mutex::new(());
+    static ADDRESSES: [AtomicUsize; MAX_FRAMES] = [const { AtomicUsize::new(0) }; MAX_FRAMES];
+    static LEN: AtomicUsize = AtomicUsize::new(0);
+    static DONE: AtomicBool = AtomicBool::new(false);
+    /// Set when a thread did not answer in time; its handler may still write
+    /// to [`ADDRESSES`] later, so no further threads are sampled.
+    static STALLED: AtomicBool = AtomicBool::new(false);
+    static INSTALLED: OnceCell<bool> = OnceCell::new();
+
+    fn signal() -> libc::c_int {
+        libc::SIGRTMIN() + 3
+    }
+
+    extern "C" fn record_stack(_signal: libc::c_int) {
+        let mut len = 0;
+        // Only records addresses: no allocation, locks or symbolization.
+        unsafe {
+            backtrace::trace_unsynchronized(|frame| {
+                ADDRESSES[len].store(frame.ip() as usize, Ordering::Relaxed);
+                len += 1;
+                len < MAX_FRAMES
+            });
+        }
+        LEN.store(len, Ordering::Relaxed);
+        DONE.store(true, Ordering::Release);
+    }
+
+    fn install() -> bool {
+        *INSTALLED.get_or_init(|| unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = record_stack as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(signal(), &action, std::ptr::null_mut()) == 0
+        })
+    }
+
+    pub(super) fn all_threads(config: &Config) -> Vec<ThreadTrace> {
+        // Another thread is already sampling, e.g. for a simultaneous panic.
+        let Some(_sampling) = SAMPLING.try_lock_for(SAMPLE_TIMEOUT) else {
+            return Vec::new();
+        };
+        let Ok(entries) = std::fs::read_dir("/proc/self/task") else {
+            return Vec::new();
+        };
+        let mut ids: Vec<libc::pid_t> = entries
+            .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+            .collect();
+        ids.sort_unstable();
+        ids.truncate(MAX_THREADS);
+
+        let current = unsafe { libc::syscall(libc::SYS_gettid) } as libc::pid_t;
+        let can_sample = install();
+        ids.into_iter()
+            .map(|id| {
+                let crashed = id == current;
+                let stack_trace = if crashed || !can_sample {
+                    Vec::new()
+                } else {
+                    sample(id, config)
+                };
+                ThreadTrace {
+                    id: id as u64,
+                    name: thread_name(id),
+                    crashed,
+                    stack_trace,
+                }
+            })
+            .collect()
+    }
+
+    /// Interrupts thread `id` and symbolizes the stack it records.
+    fn sample(id: libc::pid_t, config: &Config) -> Vec<StackFrame> {
+        if STALLED.load(Ordering::Relaxed) || blocks_signal(id) {
+            return Vec::new();
+        }
+        DONE.store(false, Ordering::Relaxed);
+        let sent = unsafe { libc::syscall(libc::SYS_tgkill, libc::getpid(), id, signal()) } == 0;
+        if !sent {
+            // The thread has exited since it was listed.
+            return Vec::new();
+        }
+        let deadline = Instant::now() + SAMPLE_TIMEOUT;
+        while !DONE.load(Ordering::Acquire) {
+            if Instant::now() >= deadline {
+                STALLED.store(true, Ordering::Relaxed);
+                return Vec::new();
+            }
+            std::thread::yield_now();
+        }
+
+        let addresses: Vec<usize> = ADDRESSES[..LEN.load(Ordering::Relaxed)]
+            .iter()
+            .map(|address| address.load(Ordering::Relaxed))
+            .collect();
+        symbolize(&addresses, config)
+    }
+
+    /// Whether thread `id` has the sampling signal blocked, per its `SigBlk` mask.
+    fn blocks_signal(id: libc::pid_t) -> bool {
+        let Ok(status) = std::fs::read_to_string(format!("/proc/self/task/{}/status", id)) else {
+            return true;
+        };
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix("SigBlk:"))
+            .and_then(|mask| u64::from_str_radix(mask.trim(), 16).ok())
+            .is_some_and(|mask| mask & (1 << (signal() - 1)) != 0)
+    }
+
+    fn thread_name(id: libc::pid_t) -> Option<String> {
+        std::fs::read_to_string(format!("/proc/self/task/{}/comm", id))
+            .ok()
+            .map(|name| name.trim_end().to_string())
+            .filter(|name| !name.is_empty())
+    }
+
+    fn symbolize(addresses: &[usize], config: &Config) -> Vec<StackFrame> {
+        let mut symbols = Vec::new();
+        for &address in addresses {
+            // Return addresses point past the call; look up the call itself.
+            let first = symbols.len();
+            backtrace::resolve(
+                address.saturating_sub(1) as *mut std::ffi::c_void,
+                |symbol| {
+                    symbols.push(RawFrame {
+                        name: symbol
+                            .name()
+                            .map(|n| n.to_string())
+                            .unwrap_or_else(|| "<unknown>".to_string()),
+                        file: symbol.filename().map(|p| p.to_string_lossy().to_string()),
+                        line: symbol.lineno(),
+                        column: symbol.colno(),
+                        is_inline: true,
+                    });
+                },
+            );
+            // All but the last symbol of an address were inlined into it.
+            if let Some(last) = symbols[first..].last_mut() {
+                last.is_inline = false;
+            }
+        }
+        let start = symbols
+            .iter()
+            .position(|symbol| symbol.name == SIGNAL_TRAMPOLINE)
+            .map_or(0, |trampoline| trampoline + 1);
+        symbols
+            .into_iter()
+            .skip(start)
+            .filter_map(|symbol| symbol.into_stack_frame(config))
+            .take(MAX_FRAMES)
+            .collect()
+    }
+}
@@ -0,0 +1,584 @@
+//! Conversion of arbitrary values into [`Variable`] trees.
+//!
+//! Values are walked through `serde` (or formatted through `Debug`) with the
+//! capture limits from [`Config`] applied as they are visited, so very large
+//! or self-referential values never have to be materialized in full.
+
+use super::Variable;
+use crate::config::Config;
+use serde::ser::{self, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{self, Write as _};
+
+/// Placeholder for values nested deeper than `max_capture_depth`.
+const MAX_DEPTH_PLACEHOLDER: &str = "<max depth exceeded>";
+
+/// Placeholder for a value that contains itself.
+const CYCLE_PLACEHOLDER: &str = "<cycle>";
+
+/// Alias for [`Variable`] when built from an arbitrary value.
+pub type CapturedValue = Variable;
+
+impl Variable {
+    /// Builds a variable tree from a serializable value.
+    ///
+    /// Nesting stops at `max_capture_depth`, sequences and maps keep their
+    /// first `max_collection_size` entries, strings are cut at
+    /// `max_string_length`, and a value reached again through one of its own
+    /// fields is reported as `<cycle>` instead of being walked forever.
+    pub fn from_serialize<T: Serialize + ?Sized>(
+        name: impl Into<String>,
+        value: &T,
+        config: &Config,
+    ) -> Self {
+        let limits = Limits::from(config);
+        let ancestors = RefCell::new(Vec::new());
+        let walker = Walker {
+            limits: &limits,
+            ancestors: &ancestors,
+            depth: 0,
+        };
+        walker.child(name.into(), value)
+    }
+
+    /// Builds a leaf variable from a value's `Debug` output, cut at `max_string_length`.
+    ///
+    /// Formatting stops as soon as the limit is reached, so `Debug`
+    /// implementations that recurse through cycles still terminate.
+    pub fn from_debug<T: fmt::Debug + ?Sized>(
+        name: impl Into<String>,
+        value: &T,
+        config: &Config,
+    ) -> Self {
+        let mut out = BoundedWriter {
+            buf: String::new(),
+            remaining: config.max_string_length,
+            truncated: false,
+        };
+        let _ = write!(out, "{:?}", value);
+
+        Variable {
+            name: name.into(),
+            var_type: readable_type_name(std::any::type_name::<T>()),
+            value: out.buf,
+            is_null: false,
+            is_truncated: out.truncated,
+            children: None,
+            array_elements: None,
+            array_length: None,
+        }
+    }
+}
+
+struct Limits {
+    depth: usize,
+    string_length: usize,
+    collection_size: usize,
+}
+
+impl From<&Config> for Limits {
+    fn from(config: &Config) -> Self {
+        Limits {
+            depth: config.max_capture_depth,
+            string_length: config.max_string_length,
+            collection_size: config.max_collection_size,
+        }
+    }
+}
+
+/// Writer that stops accepting output once `remaining` characters were written.
+struct BoundedWriter {
+    buf: String,
+    remaining: usize,
+    truncated: bool,
+}
+
+impl fmt::Write for BoundedWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            if self.remaining == 0 {
+                self.truncated = true;
+                return Err(fmt::Error);
+            }
+            self.buf.push(c);
+            self.remaining -= 1;
+        }
+        Ok(())
+    }
+}
+
+/// A value currently being walked, identified by address and type.
+///
+/// The type is part of the key because a struct and its first field share
+/// an address.
+type Ancestor = (*const (), &'static str);
+
+#[derive(Clone, Copy)]
+struct Walker<'a> {
+    limits: &'a Limits,
+    ancestors: &'a RefCell<Vec<Ancestor>>,
+    depth: usize,
+}
+
+impl<'a> Walker<'a> {
+    fn child<T: Serialize + ?Sized>(self, name: String, value: &T) -> Variable {
+        let type_name = std::any::type_name::<T>();
+        let key = (value as *const T as *const (), type_name);
+        let var_type = readable_type_name(type_name);
+
+        if self.ancestors.borrow().contains(&key) {
+            return placeholder(name, var_type, CYCLE_PLACEHOLDER);
+        }
+
+        self.ancestors.borrow_mut().push(key);
+        let serializer = VariableSerializer {
+            walker: self,
+            name: name.clone(),
+            var_type: var_type.clone(),
+        };
+        let result = value.serialize(serializer);
+        self.ancestors.borrow_mut().pop();
+
+        result.unwrap_or_else(|e| {
+            placeholder(name, var_type, &format!("<serialization failed: {}>", e))
+        })
+    }
+
+    fn nested(self) -> Self {
+        Walker {
+            depth: self.depth + 1,
+            ..self
+        }
+    }
+
+    fn too_deep(self) -> bool {
+        self.depth >= self.limits.depth
+    }
+}
+
+fn placeholder(name: String, var_type: String, value: &str) -> Variable {
+    Variable {
+        name,
+        var_type,
+        value: value.to_string(),
+        is_null: false,
+        is_truncated: true,
+        children: None,
+        array_elements: None,
+        array_length: None,
+    }
+}
+
+/// Shortens `&alloc::vec::Vec<alloc::string::String>` to `Vec<String>`.
+fn readable_type_name(type_name: &str) -> String {
+    let mut out = String::with_capacity(type_name.len());
+    let mut segment = String::new();
+    for c in type_name.chars().filter(|c| *c != '&') {
+        if c.is_alphanumeric() || c == '_' || c == ':' {
+            segment.push(c);
+        } else {
+            out.push_str(segment.rsplit("::").next().unwrap_or(&segment));
+            segment.clear();
+            out.push(c);
+        }
+    }
+    out.push_str(segment.rsplit("::").next().unwrap_or(&segment));
+    out
+}
+
+/// Error raised by a value's `Serialize` implementation.
+#[derive(Debug)]
+struct SerializeError(String);
+
+impl fmt::Display for SerializeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for SerializeError {}
+
+impl ser::Error for SerializeError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        SerializeError(msg.to_string())
+    }
+}
+
+struct VariableSerializer<'a> {
+    walker: Walker<'a>,
+    name: String,
+    var_type: String,
+}
+
+impl<'a> VariableSerializer<'a> {
+    fn leaf(self, value: String) -> Result<Variable, SerializeError> {
+        let limit = self.walker.limits.string_length;
+        let is_truncated = value.chars().count() > limit;
+        let value = if is_truncated {
+            value.chars().take(limit).collect()
+        } else {
+            value
+        };
+
+        Ok(Variable {
+            name: self.name,
+            var_type: self.var_type,
+            value,
+            is_null: false,
+            is_truncated,
+            children: None,
+            array_elements: None,
+            array_length: None,
+        })
+    }
+
+    fn null(self) -> Result<Variable, SerializeError> {
+        Ok(Variable {
+            name: self.name,
+            var_type: self.var_type,
+            value: "null".to_string(),
+            is_null: true,
+            is_truncated: false,
+            children: None,
+            array_elements: None,
+            array_length: None,
+        })
+    }
+
+    fn seq(self) -> SeqBuilder<'a> {
+        SeqBuilder {
+            ser: self,
+            elements: Vec::new(),
+            len: 0,
+        }
+    }
+
+    fn map(self, summary: String) -> MapBuilder<'a> {
+        MapBuilder {
+            ser: self,
+            summary,
+            children: HashMap::new(),
+            len: 0,
+            pending_key: None,
+        }
+    }
+}
+
+macro_rules! serialize_display {
+    ($($method:ident: $ty:ty),* $(,)?) => {
+        $(
+            fn $method(self, v: $ty) -> Result<Variable, SerializeError> {
+                self.leaf(v.to_string())
+            }
+        )*
+    };
+}
+
+impl<'a> ser::Serializer for VariableSerializer<'a> {
+    type Ok = Variable;
+    type Error = SerializeError;
+    type SerializeSeq = SeqBuilder<'a>;
+    type SerializeTuple = SeqBuilder<'a>;
+    type SerializeTupleStruct = SeqBuilder<'a>;
+    type SerializeTupleVariant = SeqBuilder<'a>;
+    type SerializeMap = MapBuilder<'a>;
+    type SerializeStruct = MapBuilder<'a>;
+    type SerializeStructVariant = MapBuilder<'a>;
+
+    serialize_display!(
+        serialize_bool: bool,
+        serialize_i8: i8,
+        serialize_i16: i16,
+        serialize_i32: i32,
+        serialize_i64: i64,
+        serialize_i128: i128,
+        serialize_u8: u8,
+        serialize_u16: u16,
+        serialize_u32: u32,
+        serialize_u64: u64,
+        serialize_u128: u128,
+        serialize_f32: f32,
+        serialize_f64: f64,
+        serialize_char: char,
+        serialize_str: &str,
+    );
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Variable, SerializeError> {
+        let shown = &v[..v.len().min(self.walker.limits.collection_size)];
+        let mut variable = self.leaf(hex::encode(shown))?;
+        variable.is_truncated |= shown.len() < v.len();
+        variable.array_length = Some(v.len());
+        Ok(variable)
+    }
+
+    fn serialize_none(self) -> Result<Variable, SerializeError> {
+        self.null()
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Variable, SerializeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Variable, SerializeError> {
+        self.null()
+    }
+
+    fn serialize_unit_struct(self, name: &'static str) -> Result<Variable, SerializeError> {
+        self.leaf(name.to_string())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<Variable, SerializeError> {
+        self.leaf(variant.to_string())
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Variable, SerializeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Variable, SerializeError> {
+        let mut map = self.map(variant.to_string());
+        ser::SerializeStruct::serialize_field(&mut map, variant, value)?;
+        ser::SerializeStruct::end(map)
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, SerializeError> {
+        Ok(self.seq())
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, SerializeError> {
+        Ok(self.seq())
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, SerializeError> {
+        Ok(self.seq())
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, SerializeError> {
+        Ok(self.seq())
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, SerializeError> {
+        Ok(self.map("{...}".to_string()))
+    }
+
+    fn serialize_struct(
+        self,
+        name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, SerializeError> {
+        Ok(self.map(format!("{} {{...}}", name)))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, SerializeError> {
+        Ok(self.map(format!("{} {{...}}", variant)))
+    }
+}
+
+/// Collects sequence elements up to `max_collection_size`.
+struct SeqBuilder<'a> {
+    ser: VariableSerializer<'a>,
+    elements: Vec<Variable>,
+    len: usize,
+}
+
+impl SeqBuilder<'_> {
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) {
+        let walker = self.ser.walker;
+        if !walker.too_deep() && self.elements.len() < walker.limits.collection_size {
+            let name = format!("[{}]", self.len);
+            self.elements.push(walker.nested().child(name, value));
+        }
+        self.len += 1;
+    }
+
+    fn finish(self) -> Result<Variable, SerializeError> {
+        let too_deep = self.ser.walker.too_deep();
+        let len = self.len;
+        let is_truncated = too_deep || self.elements.len() < len;
+        let value = if too_deep {
+            MAX_DEPTH_PLACEHOLDER.to_string()
+        } else {
+            format!("[{} item{}]", len, if len == 1 { "" } else { "s" })
+        };
+
+        Ok(Variable {
+            name: self.ser.name,
+            var_type: self.ser.var_type,
+            value,
+            is_null: false,
+            is_truncated,
+            children: None,
+            array_elements: (!too_deep).then_some(self.elements),
+            array_length: Some(len),
+        })
+    }
+}
+
+macro_rules! impl_seq {
+    ($($trait:ident :: $method:ident),* $(,)?) => {
+        $(
+            impl ser::$trait for SeqBuilder<'_> {
+                type Ok = Variable;
+                type Error = SerializeError;
+
+                fn $method<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerializeError> {
+                    self.push(value);
+                    Ok(())
+                }
+
+                fn end(self) -> Result<Variable, SerializeError> {
+                    self.finish()
+                }
+            }
+        )*
+    };
+}
+
+impl_seq!(
+    SerializeSeq::serialize_element,
+    SerializeTuple::serialize_element,
+    SerializeTupleStruct::serialize_field,
+    SerializeTupleVariant::serialize_field,
+);
+
+/// Collects map entries and struct fields up to `max_collection_size`.
+struct MapBuilder<'a> {
+    ser: VariableSerializer<'a>,
+    summary: String,
+    children: HashMap<String, Variable>,
+    len: usize,
+    pending_key: Option<String>,
+}
+
+impl MapBuilder<'_> {
+    fn accepts_more(&self) -> bool {
+        let walker = self.ser.walker;
+        !walker.too_deep() && self.children.len() < walker.limits.collection_size
+    }
+
+    fn insert<T: Serialize + ?Sized>(&mut self, key: String, value: &T) {
+        if self.accepts_more() {
+            let child = self.ser.walker.nested().child(key.clone(), value);
+            self.children.insert(key, child);
+        }
+        self.len += 1;
+    }
+
+    fn finish(self) -> Result<Variable, SerializeError> {
+        let too_deep = self.ser.walker.too_deep();
+        let is_truncated = too_deep || self.children.len() < self.len;
+        let value = if too_deep {
+            MAX_DEPTH_PLACEHOLDER.to_string()
+        } else {
+            self.summary
+        };
+
+        Ok(Variable {
+            name: self.ser.name,
+            var_type: self.ser.var_type,
+            value,
+            is_null: false,
+            is_truncated,
+            children: (!too_deep).then_some(self.children),
+            array_elements: None,
+            array_length: None,
+        })
+    }
+}
+
+impl ser::SerializeMap for MapBuilder<'_> {
+    type Ok = Variable;
+    type Error = SerializeError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), SerializeError> {
+        if self.accepts_more() {
+            let key = match serde_json::to_value(key) {
+                Ok(serde_json::Value::String(s)) => s,
+                Ok(other) => other.to_string(),
+                Err(e) => return Err(ser::Error::custom(e)),
+            };
+            self.pending_key = Some(key);
+        }
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerializeError> {
+        match self.pending_key.take() {
+            Some(key) => self.insert(key, value),
+            None => self.len += 1,
+        }
+        Ok(())
+    }
+
+    fn end(self) -> Result<Variable, SerializeError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStruct for MapBuilder<'_> {
+    type Ok = Variable;
+    type Error = SerializeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), SerializeError> {
+        self.insert(key.to_string(), value);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Variable, SerializeError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStructVariant for MapBuilder<'_> {
+    type Ok = Variable;
+    type Error = SerializeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), SerializeError> {
+        self.insert(key.to_string(), value);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Variable, SerializeError> {
+        self.finish()
+    }
+}
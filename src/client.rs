@@ -0,0 +1,150 @@
+//! Handle-based API for running several agents in one process.
+
+#[cfg(not(target_os = "wasi"))]
+use crate::runtime;
+#[cfg(not(target_os = "wasi"))]
+use crate::{internal, DeliveryError, EventId, SendError};
+use crate::{Agent, Config, TransportMode};
+#[cfg(not(target_os = "wasi"))]
+use std::collections::HashMap;
+#[cfg(not(target_os = "wasi"))]
+use std::future::Future;
+use std::ops::Deref;
+use std::sync::Arc;
+
+/// Cheaply cloneable handle to a running [`Agent`].
+///
+/// Each client has its own configuration, connection and context, so a
+/// process can report to several projects or API keys at once — for example
+/// a plugin host and the plugins it loads. The global functions such as
+/// [`crate::capture_error`] use the client created by [`crate::init`],
+/// unless the thread's [`Hub`](crate::Hub) is bound to another.
+///
+/// ```rust,no_run
+/// use aivory_monitor::{Client, Config};
+///
+/// let plugin = Client::new(Config::builder().api_key("plugin-key").build().unwrap());
+/// plugin.capture_error(&std::io::Error::new(std::io::ErrorKind::Other, "plugin failed"), None);
+/// plugin.shutdown();
+/// ```
+///
+/// Only the global client installs the panic hook.
+#[derive(Clone)]
+pub struct Client {
+    agent: Arc<Agent>,
+}
+
+impl Client {
+    /// Creates an agent and starts it on a dedicated background runtime, or
+    /// on async-std's executor with the `async-std` feature.
+    ///
+    /// With the HTTP transport no runtime or thread is started; events are
+    /// sent from the thread that captures them. In dry-run mode nothing is
+    /// started and events are written to stderr.
+    pub fn new(config: Config) -> Self {
+        let client = Client {
+            agent: Arc::new(Agent::new(config)),
+        };
+
+        #[cfg(feature = "hot-reload")]
+        if let Some(path) = client.agent.config.read().config_path.clone() {
+            crate::reload::watch(path, client.agent.clone());
+        }
+
+        if client.agent.config.read().dry_run {
+            client.agent.start_dry_run();
+            return client;
+        }
+
+        crate::session::report_ended(&client.agent.connection, &client.agent.config.read());
+
+        #[cfg(any(windows, target_os = "macos"))]
+        if client.agent.config.read().report_os_crashes {
+            crate::os_crashes::report_last_crashes(client.agent.clone());
+        }
+
+        #[cfg(feature = "source-upload")]
+        if client.agent.config.read().upload_sources {
+            crate::sources::upload(client.agent.clone());
+        }
+
+        if client.agent.config.read().transport() == TransportMode::Http {
+            client.agent.start_http();
+            return client;
+        }
+
+        #[cfg(not(target_os = "wasi"))]
+        runtime::start(client.agent.clone());
+
+        client
+    }
+
+    /// Captures an error and waits until the backend acknowledges it; see
+    /// [`crate::capture_error_and_wait`].
+    ///
+    /// The event is captured, with the current thread's context, when this is
+    /// called, and sent on a separate thread even if the future is dropped.
+    #[cfg(not(target_os = "wasi"))]
+    pub fn capture_error_and_wait<E: std::error::Error>(
+        &self,
+        error: &E,
+        context: Option<HashMap<String, serde_json::Value>>,
+    ) -> impl Future<Output = Result<EventId, DeliveryError>> + Send + 'static {
+        let sent = self
+            .agent
+            .capture_unsampled(context, |config| {
+                crate::capture::capture_error(error, config)
+            })
+            .and_then(|exc| {
+                let (tx, rx) = tokio::sync::oneshot::channel();
+                let agent = self.agent.clone();
+                std::thread::Builder::new()
+                    .name("aivory-delivery".to_string())
+                    .spawn(move || {
+                        internal::mark_agent_thread();
+                        let _ =
+                            tx.send(agent.connection.send_acknowledged(exc, crate::ACK_TIMEOUT));
+                    })
+                    .map_err(|e| {
+                        SendError::Timeout(Some(format!("failed to start sending: {}", e)))
+                    })?;
+                Ok(rx)
+            });
+        async move {
+            match sent?.await {
+                Ok(result) => Ok(result?),
+                Err(_) => Err(DeliveryError::Send(SendError::Timeout(None))),
+            }
+        }
+    }
+
+    /// Returns the underlying agent.
+    pub fn agent(&self) -> &Arc<Agent> {
+        &self.agent
+    }
+
+    /// Disconnects and stops this client's background runtime.
+    ///
+    /// With the HTTP transport, first tries to send unsent events for up to
+    /// the configured `http_timeout`.
+    pub fn shutdown(&self) {
+        if self.agent.connection.is_http() {
+            self.agent
+                .flush_blocking(self.agent.config.read().http_timeout);
+            self.agent.stop_now();
+        } else if self.agent.connection.is_dry_run() {
+            self.agent.stop_now();
+        } else {
+            #[cfg(not(target_os = "wasi"))]
+            runtime::block_on(self.agent.stop());
+        }
+    }
+}
+
+impl Deref for Client {
+    type Target = Agent;
+
+    fn deref(&self) -> &Agent {
+        &self.agent
+    }
+}
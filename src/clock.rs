@@ -0,0 +1,52 @@
+//! Server-synchronized clock.
+//!
+//! Host clocks drift, which makes `captured_at` unreliable for ordering
+//! events across machines. The transport measures the offset between the
+//! local clock and the backend during registration (and on `time_sync`
+//! replies) and all event timestamps are corrected by it.
+
+use chrono::{DateTime, Duration, Utc};
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+
+static OFFSET_MS: AtomicI64 = AtomicI64::new(0);
+static SYNCED: AtomicBool = AtomicBool::new(false);
+
+/// Returns the current time corrected by the measured server offset.
+pub fn now() -> DateTime<Utc> {
+    Utc::now() + Duration::milliseconds(OFFSET_MS.load(Ordering::Relaxed))
+}
+
+/// Returns the current corrected time in milliseconds since the epoch.
+pub fn now_millis() -> i64 {
+    now().timestamp_millis()
+}
+
+/// Returns the measured skew (server minus local clock) in milliseconds,
+/// or `None` if no synchronization has happened yet.
+pub fn skew_millis() -> Option<i64> {
+    if SYNCED.load(Ordering::Relaxed) {
+        Some(OFFSET_MS.load(Ordering::Relaxed))
+    } else {
+        None
+    }
+}
+
+/// Records a round-trip measurement.
+///
+/// `sent_at` and `received_at` are local timestamps taken around the request,
+/// `server_time` is the backend's timestamp for it. The server is assumed to
+/// have stamped the reply halfway through the round trip.
+#[cfg_attr(target_os = "wasi", allow(dead_code))]
+pub(crate) fn record_sync(sent_at: i64, received_at: i64, server_time: i64) -> i64 {
+    let midpoint = sent_at + (received_at - sent_at) / 2;
+    let offset = server_time - midpoint;
+    OFFSET_MS.store(offset, Ordering::Relaxed);
+    SYNCED.store(true, Ordering::Relaxed);
+    offset
+}
+
+/// Returns the uncorrected local time in milliseconds, for round-trip measurements.
+#[cfg_attr(target_os = "wasi", allow(dead_code))]
+pub(crate) fn local_millis() -> i64 {
+    Utc::now().timestamp_millis()
+}
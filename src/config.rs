@@ -1,73 +1,536 @@
 //! Agent configuration.
 
+mod builder;
+mod environment;
+mod file;
+mod identity;
+
+pub use builder::ConfigBuilder;
+pub use environment::{detect_environment, DetectedEnvironment, EnvironmentSource};
+pub(crate) use identity::{anonymize_hostname, exe_name, resolve as resolve_identity};
+pub(crate) use identity::{create_state_dir, open_private, write_private};
+pub use identity::{default_state_dir, HostnameMode};
+
+use crate::breadcrumbs::BreadcrumbLevel;
+use crate::capture::{ExceptionCapture, FingerprintVersion, PanicBacktrace, PanicPayloadExtractor};
+use crate::logger::{LogLevel, LogOutput, Logger};
+use crate::request::{ClientIpMode, StatusClass, TransactionNormalizer};
+use crate::sampling::{Sampler, SamplingMode};
+use crate::transport::{HttpSender, TransportMode};
+use std::collections::BTreeMap;
 use std::env;
-use uuid::Uuid;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
 
 /// Agent configuration.
+///
+/// Build one with [`Config::builder`], load one with [`Config::from_file`],
+/// or use [`Config::default`] to read everything from `AIVORY_*` environment
+/// variables.
 #[derive(Clone)]
 pub struct Config {
     /// AIVory API key.
-    pub api_key: String,
-    /// Backend WebSocket URL.
-    pub backend_url: String,
+    pub(crate) api_key: String,
+    /// Projects other than the API key's that matching events are sent to,
+    /// first match wins.
+    pub(crate) project_routes: Vec<ProjectRoute>,
+    /// Backend URL.
+    pub(crate) backend_url: String,
+    /// Backend URLs tried in order when the primary one is unreachable.
+    pub(crate) fallback_urls: Vec<String>,
+    /// How events are delivered.
+    pub(crate) transport: TransportMode,
+    /// Timeout of each request made by the HTTP transport.
+    pub(crate) http_timeout: Duration,
+    /// Performs HTTP transport requests, or `None` for the built-in client.
+    pub(crate) http_sender: Option<HttpSender>,
+    /// Interval of WebSocket pings; zero disables them.
+    pub(crate) ping_interval: Duration,
+    /// How long a WebSocket write, or the answer to an event or ping, may
+    /// take before the connection is torn down; zero disables the check.
+    pub(crate) liveness_timeout: Duration,
+    /// Interval of WebSocket heartbeats, if enabled.
+    pub(crate) heartbeat_interval: Option<Duration>,
+    /// Write events to stderr instead of sending them.
+    pub(crate) dry_run: bool,
+    /// File recording the delivery outcome of every event, if any.
+    pub(crate) audit_log: Option<PathBuf>,
     /// Environment name.
-    pub environment: String,
+    pub(crate) environment: String,
+    /// Where `environment` came from.
+    pub(crate) environment_source: EnvironmentSource,
     /// Sampling rate (0.0 - 1.0).
-    pub sampling_rate: f64,
+    pub(crate) sampling_rate: f64,
+    /// Events per minute adaptive sampling aims for, replacing `sampling_rate`.
+    pub(crate) target_events_per_minute: Option<u32>,
+    /// How the sampling rate is applied to each event.
+    pub(crate) sampling_mode: SamplingMode,
+    /// Callback choosing the sampling rate per event.
+    pub(crate) sampler: Option<Sampler>,
+    /// Interval over which repeated fingerprints are sent once, if enabled.
+    pub(crate) aggregation_interval: Option<Duration>,
+    /// Whether events carry their fingerprint's error rate and spike flag.
+    pub(crate) detect_spikes: bool,
+    /// Consecutive backend failures that pause capture; zero disables the breaker.
+    pub(crate) circuit_breaker_threshold: u32,
+    /// How long capture is paused before a probe event is sent.
+    pub(crate) circuit_breaker_cooldown: Duration,
+    /// Most bytes per second written to the backend, if limited.
+    pub(crate) max_bytes_per_second: Option<u64>,
     /// Maximum capture depth for variables.
-    pub max_capture_depth: usize,
+    pub(crate) max_capture_depth: usize,
     /// Maximum string length to capture.
-    pub max_string_length: usize,
+    pub(crate) max_string_length: usize,
     /// Maximum collection size to capture.
-    pub max_collection_size: usize,
+    pub(crate) max_collection_size: usize,
+    /// Most entries of global and per-thread context.
+    pub(crate) max_context_keys: usize,
+    /// Most breadcrumbs kept; zero disables them.
+    pub(crate) max_breadcrumbs: usize,
+    /// Least severe breadcrumb level kept.
+    pub(crate) min_breadcrumb_level: BreadcrumbLevel,
+    /// Least severe level kept per category, in place of `min_breadcrumb_level`.
+    pub(crate) breadcrumb_levels: BTreeMap<String, BreadcrumbLevel>,
+    /// Categories whose breadcrumbs are discarded.
+    pub(crate) ignore_breadcrumb_categories: Vec<String>,
     /// Enable debug logging.
-    pub debug: bool,
+    pub(crate) debug: bool,
+    /// Most verbose internal log level when `debug` is off.
+    pub(crate) log_level: LogLevel,
+    /// Destination for internal log messages.
+    pub(crate) logger: Logger,
     /// Hostname.
-    pub hostname: String,
+    pub(crate) hostname: String,
+    /// How the hostname is reported.
+    pub(crate) hostname_mode: HostnameMode,
+    /// Name of the service the process runs, when several share a host.
+    pub(crate) service_name: Option<String>,
+    /// Name of the server or instance, in place of the hostname.
+    pub(crate) server_name: Option<String>,
     /// Agent ID.
-    pub agent_id: String,
+    pub(crate) agent_id: String,
+    /// Whether `agent_id` was set explicitly rather than generated.
+    pub(crate) agent_id_explicit: bool,
+    /// Whether the agent ID is persisted across restarts.
+    pub(crate) persist_agent_id: bool,
+    /// Directory for persisted agent state, or `None` for the platform default.
+    pub(crate) state_dir: Option<PathBuf>,
+    /// Base64-encoded key the spool of unsent events is encrypted with.
+    pub(crate) spool_encryption_key: Option<String>,
+    /// Whether the spool is encrypted with a key kept in the OS keychain.
+    pub(crate) spool_encryption_keychain: bool,
+    /// Number of times this agent ID has been started, including this one.
+    pub(crate) incarnation: u64,
+    /// Path of the file this configuration was loaded from, if any.
+    pub(crate) config_path: Option<PathBuf>,
+    /// When panics include a stack trace.
+    pub(crate) panic_backtrace: PanicBacktrace,
+    /// How long the panic hook flushes for in `panic = "abort"` builds, and
+    /// `main_wrapper` after `main` returned an error.
+    pub(crate) panic_flush_timeout: Duration,
+    /// Whether panic events include the stacks of all threads.
+    pub(crate) capture_all_threads: bool,
+    /// Whether crashes of earlier runs recorded by the OS are reported.
+    pub(crate) report_os_crashes: bool,
+    /// Additional symbol prefixes whose stack frames are filtered.
+    pub(crate) frame_skip_prefixes: Vec<String>,
+    /// Keep filtered frames, marked `is_filtered`, instead of dropping them.
+    pub(crate) keep_filtered_frames: bool,
+    /// Algorithm computing `fingerprint`; events carry both in `fingerprints`.
+    pub(crate) fingerprint_version: FingerprintVersion,
+    /// Source path prefix rewrites, first match wins.
+    pub(crate) path_prefix_map: Vec<PathMapping>,
+    /// Replace the home directory in source paths with `~`.
+    pub(crate) scrub_home_dir: bool,
+    /// Environment variables added to event context, read once at startup.
+    pub(crate) capture_env_vars: Vec<String>,
+    /// Add the command line and working directory to runtime info.
+    pub(crate) capture_command_line: bool,
+    /// Response status classes reported as events by `request::record_response`.
+    pub(crate) capture_status_classes: Vec<StatusClass>,
+    /// How client IP addresses in requests and users are reported.
+    pub(crate) capture_client_ip: ClientIpMode,
+    /// Callback naming transactions after request paths without a route.
+    pub(crate) transaction_normalizer: Option<TransactionNormalizer>,
+    /// Callback scrubbing the command line after the built-in redaction.
+    pub(crate) command_line_scrubber: Option<CommandLineScrubber>,
+    /// Add the git revision and uncommitted changes outside of production.
+    pub(crate) capture_vcs_diff: bool,
+    /// Size limit of the uncommitted changes diff.
+    pub(crate) max_vcs_diff_bytes: usize,
+    /// Callback scrubbing the uncommitted changes diff.
+    pub(crate) vcs_diff_scrubber: Option<VcsDiffScrubber>,
+    /// Extractors tried, in order, on panic payloads that are not strings.
+    pub(crate) panic_payload_extractors: Vec<PanicPayloadExtractor>,
+    /// Identifies the build the process runs, see `upload_sources`.
+    pub(crate) build_id: Option<String>,
+    /// Upload the application's source files once connected.
+    #[cfg(feature = "source-upload")]
+    pub(crate) upload_sources: bool,
+    /// Directory whose source files are uploaded, or `None` for the working directory.
+    #[cfg(feature = "source-upload")]
+    pub(crate) source_root: Option<PathBuf>,
+    /// Patterns, in `.gitignore` syntax, of source files not to upload.
+    #[cfg(feature = "source-upload")]
+    pub(crate) source_exclude: Vec<String>,
+    /// Also write events to the systemd journal.
+    #[cfg(feature = "journald")]
+    pub(crate) journald: bool,
+    /// How long to flush events for after a termination signal.
+    #[cfg(feature = "shutdown-hooks")]
+    pub(crate) shutdown_timeout: Duration,
+    /// Terminate after flushing on a signal, as the signal would have.
+    #[cfg(feature = "shutdown-hooks")]
+    pub(crate) exit_on_signal: bool,
 }
 
+/// Async runtime frames filtered by default, on top of `std`, `core`, `alloc` and `backtrace`.
+pub const DEFAULT_FRAME_SKIP_PREFIXES: &[&str] = &[
+    "tokio::",
+    "futures::",
+    "futures_util::",
+    "futures_core::",
+    "futures_executor::",
+];
+
 impl Default for Config {
     fn default() -> Self {
+        let mut config = Config::base();
+        config.apply_env();
+        config
+    }
+}
+
+impl Config {
+    /// Hard-coded defaults, before any file or environment layer is applied.
+    fn base() -> Self {
+        #[cfg(not(target_os = "wasi"))]
         let hostname = hostname::get()
             .map(|h| h.to_string_lossy().to_string())
             .unwrap_or_else(|_| "unknown".to_string());
+        #[cfg(target_os = "wasi")]
+        let hostname = env::var("HOSTNAME").unwrap_or_else(|_| "unknown".to_string());
+        let detected = detect_environment();
 
         Config {
-            api_key: env::var("AIVORY_API_KEY").unwrap_or_default(),
-            backend_url: env::var("AIVORY_BACKEND_URL")
-                .unwrap_or_else(|_| "wss://api.aivory.net/ws/agent".to_string()),
-            environment: env::var("AIVORY_ENVIRONMENT")
-                .unwrap_or_else(|_| "production".to_string()),
-            sampling_rate: env::var("AIVORY_SAMPLING_RATE")
-                .ok()
-                .and_then(|s| s.parse().ok())
-                .unwrap_or(1.0),
-            max_capture_depth: env::var("AIVORY_MAX_DEPTH")
-                .ok()
-                .and_then(|s| s.parse().ok())
-                .unwrap_or(10),
-            max_string_length: env::var("AIVORY_MAX_STRING_LENGTH")
-                .ok()
-                .and_then(|s| s.parse().ok())
-                .unwrap_or(1000),
-            max_collection_size: env::var("AIVORY_MAX_COLLECTION_SIZE")
-                .ok()
-                .and_then(|s| s.parse().ok())
-                .unwrap_or(100),
-            debug: env::var("AIVORY_DEBUG")
-                .map(|s| s.to_lowercase() == "true")
-                .unwrap_or(false),
+            api_key: String::new(),
+            project_routes: Vec::new(),
+            backend_url: "wss://api.aivory.net/ws/agent".to_string(),
+            fallback_urls: Vec::new(),
+            transport: TransportMode::detect(),
+            http_timeout: Duration::from_secs(5),
+            http_sender: None,
+            ping_interval: Duration::from_secs(30),
+            liveness_timeout: Duration::from_secs(15),
+            heartbeat_interval: Some(Duration::from_secs(30)),
+            dry_run: false,
+            audit_log: None,
+            environment: detected.name,
+            environment_source: detected.source,
+            sampling_rate: 1.0,
+            target_events_per_minute: None,
+            sampling_mode: SamplingMode::Random,
+            sampler: None,
+            aggregation_interval: None,
+            detect_spikes: true,
+            circuit_breaker_threshold: 5,
+            circuit_breaker_cooldown: Duration::from_secs(30),
+            max_bytes_per_second: None,
+            max_capture_depth: 10,
+            max_string_length: 1000,
+            max_collection_size: 100,
+            max_context_keys: 100,
+            max_breadcrumbs: 100,
+            min_breadcrumb_level: BreadcrumbLevel::Debug,
+            breadcrumb_levels: BTreeMap::new(),
+            ignore_breadcrumb_categories: Vec::new(),
+            debug: false,
+            log_level: LogLevel::Info,
+            logger: Logger::default(),
             hostname,
-            agent_id: format!("agent-{}-{}",
-                hex::encode(&chrono::Utc::now().timestamp().to_be_bytes()[4..]),
-                &Uuid::new_v4().to_string()[..8]),
+            hostname_mode: HostnameMode::Plain,
+            service_name: None,
+            server_name: None,
+            agent_id: identity::generate_agent_id(),
+            agent_id_explicit: false,
+            persist_agent_id: true,
+            state_dir: None,
+            spool_encryption_key: None,
+            spool_encryption_keychain: false,
+            incarnation: 0,
+            config_path: None,
+            panic_backtrace: PanicBacktrace::Always,
+            panic_flush_timeout: Duration::from_secs(2),
+            capture_all_threads: false,
+            report_os_crashes: true,
+            frame_skip_prefixes: DEFAULT_FRAME_SKIP_PREFIXES
+                .iter()
+                .map(|p| p.to_string())
+                .collect(),
+            keep_filtered_frames: false,
+            fingerprint_version: FingerprintVersion::V1,
+            path_prefix_map: Vec::new(),
+            scrub_home_dir: true,
+            capture_env_vars: Vec::new(),
+            capture_status_classes: Vec::new(),
+            capture_client_ip: ClientIpMode::Full,
+            transaction_normalizer: None,
+            capture_command_line: false,
+            command_line_scrubber: None,
+            capture_vcs_diff: false,
+            max_vcs_diff_bytes: 16 * 1024,
+            vcs_diff_scrubber: None,
+            panic_payload_extractors: Vec::new(),
+            build_id: None,
+            #[cfg(feature = "source-upload")]
+            upload_sources: false,
+            #[cfg(feature = "source-upload")]
+            source_root: None,
+            #[cfg(feature = "source-upload")]
+            source_exclude: Vec::new(),
+            #[cfg(feature = "journald")]
+            journald: false,
+            #[cfg(feature = "shutdown-hooks")]
+            shutdown_timeout: Duration::from_secs(2),
+            #[cfg(feature = "shutdown-hooks")]
+            exit_on_signal: true,
         }
     }
-}
 
-impl Config {
+    /// Overrides settings with any `AIVORY_*` environment variables that are set.
+    fn apply_env(&mut self) {
+        if let Ok(api_key) = env::var("AIVORY_API_KEY") {
+            self.api_key = api_key;
+        }
+        if let Ok(url) = env::var("AIVORY_BACKEND_URL") {
+            self.backend_url = url;
+        }
+        if let Ok(urls) = env::var("AIVORY_FALLBACK_URLS") {
+            self.fallback_urls = urls
+                .split(',')
+                .map(str::trim)
+                .filter(|url| !url.is_empty())
+                .map(String::from)
+                .collect();
+        }
+        if let Some(transport) = env_parse("AIVORY_TRANSPORT") {
+            self.transport = transport;
+        }
+        if let Some(ms) = env_parse("AIVORY_PING_INTERVAL_MS") {
+            self.ping_interval = Duration::from_millis(ms);
+        }
+        if let Some(ms) = env_parse("AIVORY_LIVENESS_TIMEOUT_MS") {
+            self.liveness_timeout = Duration::from_millis(ms);
+        }
+        if let Some(ms) = env_parse::<u64>("AIVORY_HEARTBEAT_INTERVAL_MS") {
+            self.heartbeat_interval =
+                Some(Duration::from_millis(ms)).filter(|interval| !interval.is_zero());
+        }
+        if let Some(dry_run) = env_parse("AIVORY_DRY_RUN") {
+            self.dry_run = dry_run;
+        }
+        if let Some(path) = env::var_os("AIVORY_AUDIT_LOG") {
+            self.audit_log = Some(PathBuf::from(path));
+        }
+        if let Ok(environment) = env::var("AIVORY_ENVIRONMENT") {
+            self.environment = environment;
+            self.environment_source = EnvironmentSource::Explicit;
+        }
+        if let Ok(service_name) = env::var("AIVORY_SERVICE_NAME") {
+            self.service_name = Some(service_name);
+        }
+        if let Ok(server_name) = env::var("AIVORY_SERVER_NAME") {
+            self.server_name = Some(server_name);
+        }
+        if let Some(rate) = env_parse("AIVORY_SAMPLING_RATE") {
+            self.sampling_rate = rate;
+        }
+        if let Some(target) = env_parse("AIVORY_TARGET_EVENTS_PER_MINUTE") {
+            self.target_events_per_minute = Some(target);
+        }
+        if let Some(mode) = env_parse("AIVORY_SAMPLING_MODE") {
+            self.sampling_mode = mode;
+        }
+        if let Some(ms) = env_parse("AIVORY_AGGREGATION_INTERVAL_MS") {
+            self.aggregation_interval = Some(Duration::from_millis(ms));
+        }
+        if let Some(detect) = env_parse("AIVORY_DETECT_SPIKES") {
+            self.detect_spikes = detect;
+        }
+        if let Some(threshold) = env_parse("AIVORY_CIRCUIT_BREAKER_THRESHOLD") {
+            self.circuit_breaker_threshold = threshold;
+        }
+        if let Some(ms) = env_parse("AIVORY_CIRCUIT_BREAKER_COOLDOWN_MS") {
+            self.circuit_breaker_cooldown = Duration::from_millis(ms);
+        }
+        if let Some(limit) = env_parse("AIVORY_MAX_BYTES_PER_SECOND") {
+            self.max_bytes_per_second = Some(limit);
+        }
+        if let Some(depth) = env_parse("AIVORY_MAX_DEPTH") {
+            self.max_capture_depth = depth;
+        }
+        if let Some(len) = env_parse("AIVORY_MAX_STRING_LENGTH") {
+            self.max_string_length = len;
+        }
+        if let Some(size) = env_parse("AIVORY_MAX_COLLECTION_SIZE") {
+            self.max_collection_size = size;
+        }
+        if let Some(max) = env_parse("AIVORY_MAX_CONTEXT_KEYS") {
+            self.max_context_keys = max;
+        }
+        if let Some(max) = env_parse("AIVORY_MAX_BREADCRUMBS") {
+            self.max_breadcrumbs = max;
+        }
+        if let Some(level) = env_parse("AIVORY_MIN_BREADCRUMB_LEVEL") {
+            self.min_breadcrumb_level = level;
+        }
+        if let Ok(levels) = env::var("AIVORY_BREADCRUMB_LEVELS") {
+            self.breadcrumb_levels = levels
+                .split(',')
+                .filter_map(|entry| entry.split_once('='))
+                .filter_map(|(category, level)| {
+                    Some((category.trim().to_string(), level.parse().ok()?))
+                })
+                .collect();
+        }
+        if let Ok(categories) = env::var("AIVORY_IGNORE_BREADCRUMB_CATEGORIES") {
+            self.ignore_breadcrumb_categories = categories
+                .split(',')
+                .map(str::trim)
+                .filter(|category| !category.is_empty())
+                .map(String::from)
+                .collect();
+        }
+        if let Ok(debug) = env::var("AIVORY_DEBUG") {
+            self.debug = debug.to_lowercase() == "true";
+        }
+        if let Some(level) = env_parse("AIVORY_LOG_LEVEL") {
+            self.log_level = level;
+        }
+        if let Some(mode) = env_parse("AIVORY_HOSTNAME_MODE") {
+            self.hostname_mode = mode;
+        }
+        if let Some(dir) = env::var_os("AIVORY_STATE_DIR") {
+            self.state_dir = Some(PathBuf::from(dir));
+        }
+        if let Ok(key) = env::var("AIVORY_SPOOL_ENCRYPTION_KEY") {
+            self.spool_encryption_key = Some(key);
+        }
+        if let Some(keychain) = env_parse("AIVORY_SPOOL_ENCRYPTION_KEYCHAIN") {
+            self.spool_encryption_keychain = keychain;
+        }
+        if let Ok(names) = env::var("AIVORY_CAPTURE_ENV_VARS") {
+            self.capture_env_vars = names
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(String::from)
+                .collect();
+        }
+        if let Some(capture) = env_parse("AIVORY_CAPTURE_COMMAND_LINE") {
+            self.capture_command_line = capture;
+        }
+        if let Ok(classes) = env::var("AIVORY_CAPTURE_STATUS_CLASSES") {
+            self.capture_status_classes = classes
+                .split(',')
+                .filter(|class| !class.trim().is_empty())
+                .filter_map(|class| class.parse().ok())
+                .collect();
+        }
+        if let Some(mode) = env_parse("AIVORY_CAPTURE_CLIENT_IP") {
+            self.capture_client_ip = mode;
+        }
+        if let Some(mode) = env_parse("AIVORY_PANIC_BACKTRACE") {
+            self.panic_backtrace = mode;
+        }
+        if let Some(ms) = env_parse("AIVORY_PANIC_FLUSH_TIMEOUT_MS") {
+            self.panic_flush_timeout = Duration::from_millis(ms);
+        }
+        if let Some(capture) = env_parse("AIVORY_CAPTURE_ALL_THREADS") {
+            self.capture_all_threads = capture;
+        }
+        if let Some(report) = env_parse("AIVORY_REPORT_OS_CRASHES") {
+            self.report_os_crashes = report;
+        }
+        if let Some(version) = env_parse("AIVORY_FINGERPRINT_VERSION") {
+            self.fingerprint_version = version;
+        }
+        if let Some(capture) = env_parse("AIVORY_CAPTURE_VCS_DIFF") {
+            self.capture_vcs_diff = capture;
+        }
+        if let Some(bytes) = env_parse("AIVORY_MAX_VCS_DIFF_BYTES") {
+            self.max_vcs_diff_bytes = bytes;
+        }
+        if let Ok(build_id) = env::var("AIVORY_BUILD_ID") {
+            self.build_id = Some(build_id);
+        }
+        #[cfg(feature = "source-upload")]
+        if let Some(upload) = env_parse("AIVORY_UPLOAD_SOURCES") {
+            self.upload_sources = upload;
+        }
+        #[cfg(feature = "source-upload")]
+        if let Ok(root) = env::var("AIVORY_SOURCE_ROOT") {
+            self.source_root = Some(PathBuf::from(root));
+        }
+        #[cfg(feature = "journald")]
+        if let Some(journald) = env_parse("AIVORY_JOURNALD") {
+            self.journald = journald;
+        }
+        #[cfg(feature = "shutdown-hooks")]
+        if let Some(ms) = env_parse("AIVORY_SHUTDOWN_TIMEOUT_MS") {
+            self.shutdown_timeout = Duration::from_millis(ms);
+        }
+    }
+
+    /// Applies the file layer and then the environment layer on top of the defaults.
+    ///
+    /// The active environment is taken from `AIVORY_ENVIRONMENT` if set,
+    /// otherwise from the file's top-level `environment` key.
+    fn load_file(path: &Path) -> Result<Self, ConfigError> {
+        let file = file::FileConfig::load(path)?;
+
+        let mut config = Config::base();
+        file.apply(&mut config);
+
+        let environment =
+            env::var("AIVORY_ENVIRONMENT").unwrap_or_else(|_| config.environment.clone());
+        if let Some(section) = file.environments.get(&environment) {
+            section.apply(&mut config);
+        }
+
+        config.apply_env();
+        config.config_path = Some(path.to_path_buf());
+        Ok(config)
+    }
+
+    /// Returns a builder seeded from `AIVORY_*` environment variables.
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::new()
+    }
+
+    /// Loads and validates configuration from a TOML (or, with the `yaml`
+    /// feature, YAML) file.
+    ///
+    /// Settings are layered with the following precedence, lowest first:
+    /// built-in defaults, the file's top-level keys, the file's
+    /// `[environments.<name>]` section for the active environment, and
+    /// finally `AIVORY_*` environment variables. Use
+    /// [`ConfigBuilder::from_file`] to override values in code on top.
+    ///
+    /// ```toml
+    /// api_key = "your-api-key"
+    /// sampling_rate = 1.0
+    ///
+    /// [environments.production]
+    /// sampling_rate = 0.25
+    /// ```
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        ConfigBuilder::from_file(path)?.build()
+    }
+
     /// Creates a new configuration with the given API key.
     pub fn new(api_key: impl Into<String>) -> Self {
         Config {
@@ -76,31 +539,517 @@ impl Config {
         }
     }
 
-    /// Sets the environment.
-    pub fn environment(mut self, env: impl Into<String>) -> Self {
-        self.environment = env.into();
-        self
+    /// Checks that every setting is usable.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        for backend_url in std::iter::once(&self.backend_url).chain(&self.fallback_urls) {
+            let url = url::Url::parse(backend_url).map_err(|e| ConfigError::InvalidUrl {
+                url: backend_url.clone(),
+                message: e.to_string(),
+            })?;
+            if !matches!(url.scheme(), "ws" | "wss" | "http" | "https") {
+                return Err(ConfigError::UnsupportedScheme(url.scheme().to_string()));
+            }
+        }
+
+        if !(0.0..=1.0).contains(&self.sampling_rate) {
+            return Err(ConfigError::InvalidSamplingRate(self.sampling_rate));
+        }
+
+        if self.target_events_per_minute == Some(0) {
+            return Err(ConfigError::ZeroLimit("target_events_per_minute"));
+        }
+        if self.aggregation_interval == Some(Duration::ZERO) {
+            return Err(ConfigError::ZeroLimit("aggregation_interval"));
+        }
+        if self.heartbeat_interval == Some(Duration::ZERO) {
+            return Err(ConfigError::ZeroLimit("heartbeat_interval"));
+        }
+        if self.max_bytes_per_second == Some(0) {
+            return Err(ConfigError::ZeroLimit("max_bytes_per_second"));
+        }
+        if self.max_capture_depth == 0 {
+            return Err(ConfigError::ZeroLimit("max_capture_depth"));
+        }
+        if self.max_string_length == 0 {
+            return Err(ConfigError::ZeroLimit("max_string_length"));
+        }
+        if self.max_collection_size == 0 {
+            return Err(ConfigError::ZeroLimit("max_collection_size"));
+        }
+        if self.max_context_keys == 0 {
+            return Err(ConfigError::ZeroLimit("max_context_keys"));
+        }
+        // Without the feature, a key would be ignored and events spooled in plain text.
+        if cfg!(not(feature = "spool-encryption")) && self.spool_encryption_key.is_some() {
+            return Err(ConfigError::FeatureDisabled {
+                setting: "spool_encryption_key",
+                feature: "spool-encryption",
+            });
+        }
+        if cfg!(not(feature = "spool-encryption-keychain")) && self.spool_encryption_keychain {
+            return Err(ConfigError::FeatureDisabled {
+                setting: "spool_encryption_keychain",
+                feature: "spool-encryption-keychain",
+            });
+        }
+        #[cfg(feature = "spool-encryption")]
+        if self.spool_encryption_key.is_some() && self.spool_key().is_none() {
+            return Err(ConfigError::InvalidSpoolEncryptionKey);
+        }
+
+        Ok(())
+    }
+
+    /// AIVory API key.
+    pub fn api_key(&self) -> &str {
+        &self.api_key
+    }
+
+    /// Backend URL.
+    pub fn backend_url(&self) -> &str {
+        &self.backend_url
+    }
+
+    /// How events are delivered.
+    pub fn transport(&self) -> TransportMode {
+        if cfg!(target_os = "wasi") {
+            // WASI has no threads to run the WebSocket connection on.
+            TransportMode::Http
+        } else {
+            self.transport
+        }
+    }
+
+    /// Timeout of each request made by the HTTP transport.
+    pub fn http_timeout(&self) -> Duration {
+        self.http_timeout
+    }
+
+    /// Interval of WebSocket pings; zero if they are disabled.
+    pub fn ping_interval(&self) -> Duration {
+        self.ping_interval
+    }
+
+    /// How long the backend may take to answer before the connection is
+    /// torn down; zero if the check is disabled.
+    pub fn liveness_timeout(&self) -> Duration {
+        self.liveness_timeout
+    }
+
+    /// Interval of WebSocket heartbeats; `None` if they are disabled.
+    pub fn heartbeat_interval(&self) -> Option<Duration> {
+        self.heartbeat_interval
+    }
+
+    /// Whether events are written to stderr instead of being sent.
+    pub fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// File recording the delivery outcome of every event, if any.
+    pub fn audit_log(&self) -> Option<&Path> {
+        self.audit_log.as_deref()
+    }
+
+    /// Backend URL with the scheme the HTTP transport uses, e.g. `https` for `wss`.
+    pub fn http_url(&self) -> String {
+        with_scheme(&self.backend_url, &[("ws", "http"), ("wss", "https")])
+    }
+
+    /// Backend URL with the scheme the WebSocket transport uses, e.g. `wss` for `https`.
+    pub fn websocket_url(&self) -> String {
+        with_scheme(&self.backend_url, &[("http", "ws"), ("https", "wss")])
+    }
+
+    /// Backend URLs tried in order when the primary one is unreachable.
+    pub fn fallback_urls(&self) -> &[String] {
+        &self.fallback_urls
+    }
+
+    /// The primary and fallback URLs with the scheme the HTTP transport uses.
+    pub(crate) fn http_urls(&self) -> Vec<String> {
+        self.backend_urls(&[("ws", "http"), ("wss", "https")])
+    }
+
+    /// The primary and fallback URLs with the scheme the WebSocket transport uses.
+    #[cfg_attr(target_os = "wasi", allow(dead_code))]
+    pub(crate) fn websocket_urls(&self) -> Vec<String> {
+        self.backend_urls(&[("http", "ws"), ("https", "wss")])
+    }
+
+    fn backend_urls(&self, mapping: &[(&str, &str)]) -> Vec<String> {
+        std::iter::once(&self.backend_url)
+            .chain(&self.fallback_urls)
+            .map(|url| with_scheme(url, mapping))
+            .collect()
+    }
+
+    /// Environment name.
+    pub fn environment(&self) -> &str {
+        &self.environment
+    }
+
+    /// Whether the environment was set explicitly or auto-detected, and from which signal.
+    pub fn environment_source(&self) -> EnvironmentSource {
+        self.environment_source
+    }
+
+    /// Sampling rate (0.0 - 1.0).
+    pub fn sampling_rate(&self) -> f64 {
+        self.sampling_rate
+    }
+
+    /// Events per minute adaptive sampling aims for, if enabled.
+    pub fn target_events_per_minute(&self) -> Option<u32> {
+        self.target_events_per_minute
+    }
+
+    /// How the sampling rate is applied to each event.
+    pub fn sampling_mode(&self) -> SamplingMode {
+        self.sampling_mode
+    }
+
+    /// Interval over which repeated fingerprints are sent once, if enabled.
+    pub fn aggregation_interval(&self) -> Option<Duration> {
+        self.aggregation_interval
+    }
+
+    /// Whether events carry their fingerprint's error rate and spike flag.
+    pub fn detect_spikes(&self) -> bool {
+        self.detect_spikes
+    }
+
+    /// Consecutive backend failures that pause capture; zero if the circuit
+    /// breaker is disabled.
+    pub fn circuit_breaker_threshold(&self) -> u32 {
+        self.circuit_breaker_threshold
+    }
+
+    /// How long capture is paused before a probe event is sent.
+    pub fn circuit_breaker_cooldown(&self) -> Duration {
+        self.circuit_breaker_cooldown
+    }
+
+    /// Most bytes per second written to the backend, if limited.
+    pub fn max_bytes_per_second(&self) -> Option<u64> {
+        self.max_bytes_per_second
+    }
+
+    /// Maximum capture depth for variables.
+    pub fn max_capture_depth(&self) -> usize {
+        self.max_capture_depth
+    }
+
+    /// Maximum string length to capture.
+    pub fn max_string_length(&self) -> usize {
+        self.max_string_length
+    }
+
+    /// Maximum collection size to capture.
+    pub fn max_collection_size(&self) -> usize {
+        self.max_collection_size
+    }
+
+    /// Most entries of global and per-thread context.
+    pub fn max_context_keys(&self) -> usize {
+        self.max_context_keys
+    }
+
+    /// Most breadcrumbs kept.
+    pub fn max_breadcrumbs(&self) -> usize {
+        self.max_breadcrumbs
+    }
+
+    /// Least severe breadcrumb level kept, unless set for the category.
+    pub fn min_breadcrumb_level(&self) -> BreadcrumbLevel {
+        self.min_breadcrumb_level
+    }
+
+    /// Least severe level kept for each category it is set for.
+    pub fn breadcrumb_levels(&self) -> &BTreeMap<String, BreadcrumbLevel> {
+        &self.breadcrumb_levels
+    }
+
+    /// Categories whose breadcrumbs are discarded.
+    pub fn ignore_breadcrumb_categories(&self) -> &[String] {
+        &self.ignore_breadcrumb_categories
+    }
+
+    /// Whether a breadcrumb of `category` and `level` is kept.
+    pub(crate) fn keeps_breadcrumb(&self, category: &str, level: BreadcrumbLevel) -> bool {
+        let min = self
+            .breadcrumb_levels
+            .get(category)
+            .copied()
+            .unwrap_or(self.min_breadcrumb_level);
+        level >= min
+            && !self
+                .ignore_breadcrumb_categories
+                .iter()
+                .any(|ignored| ignored == category)
+    }
+
+    /// Whether debug logging is enabled.
+    pub fn debug(&self) -> bool {
+        self.debug
+    }
+
+    /// Configured internal log level, ignoring `debug`.
+    pub fn log_level(&self) -> LogLevel {
+        self.log_level
+    }
+
+    /// Internal log level after applying `debug`.
+    pub fn effective_log_level(&self) -> LogLevel {
+        if self.debug {
+            LogLevel::Debug
+        } else {
+            self.log_level
+        }
+    }
+
+    /// Logger for internal agent messages.
+    pub fn logger(&self) -> &Logger {
+        &self.logger
+    }
+
+    /// Points the logger at `output`, keeping the current level.
+    pub(crate) fn set_log_output(&mut self, output: LogOutput) {
+        self.logger = Logger::new(output, self.logger.level());
+    }
+
+    /// Hostname, as reported once the agent has started.
+    pub fn hostname(&self) -> &str {
+        &self.hostname
+    }
+
+    /// How the hostname is reported.
+    pub fn hostname_mode(&self) -> HostnameMode {
+        self.hostname_mode
+    }
+
+    /// Name of the service the process runs, if set.
+    pub fn service_name(&self) -> Option<&str> {
+        self.service_name.as_deref()
+    }
+
+    /// Name of the server or instance the process runs on, if set.
+    pub fn server_name(&self) -> Option<&str> {
+        self.server_name.as_deref()
+    }
+
+    /// Agent ID.
+    pub fn agent_id(&self) -> &str {
+        &self.agent_id
+    }
+
+    /// Number of times this agent ID has been started, including this one.
+    ///
+    /// Zero until the agent has resolved its persisted identity on startup.
+    pub fn incarnation(&self) -> u64 {
+        self.incarnation
+    }
+
+    /// Directory for persisted agent state, if overridden.
+    pub fn state_dir(&self) -> Option<&Path> {
+        self.state_dir.as_deref()
+    }
+
+    /// Directory for persisted agent state: the configured one, or the
+    /// user's own, but never the shared temp directory
+    /// [`default_state_dir`] falls back to. `None` if there is neither, in
+    /// which case nothing is persisted.
+    pub(crate) fn private_state_dir(&self) -> Option<PathBuf> {
+        self.state_dir.clone().or_else(identity::user_state_dir)
+    }
+
+    /// Whether the spool of unsent events is encrypted, with a configured key
+    /// or one kept in the OS keychain.
+    #[cfg(feature = "spool-encryption")]
+    pub fn spool_encrypted(&self) -> bool {
+        self.spool_encryption_key.is_some() || self.spool_encryption_keychain
+    }
+
+    /// The decoded spool encryption key, or `None` if unset or not 32 bytes
+    /// of base64.
+    #[cfg(feature = "spool-encryption")]
+    pub(crate) fn spool_key(&self) -> Option<[u8; 32]> {
+        use base64::Engine;
+        let key = base64::engine::general_purpose::STANDARD
+            .decode(self.spool_encryption_key.as_deref()?.trim());
+        key.ok()?.try_into().ok()
+    }
+
+    /// When panics include a stack trace.
+    pub fn panic_backtrace(&self) -> PanicBacktrace {
+        self.panic_backtrace
+    }
+
+    /// How long the panic hook flushes for before a `panic = "abort"` build
+    /// aborts; zero if it does not.
+    pub fn panic_flush_timeout(&self) -> Duration {
+        self.panic_flush_timeout
+    }
+
+    /// Whether panic events include the stacks of all threads.
+    pub fn capture_all_threads(&self) -> bool {
+        self.capture_all_threads
+    }
+
+    /// Whether crashes of earlier runs recorded by the OS are reported.
+    pub fn report_os_crashes(&self) -> bool {
+        self.report_os_crashes
+    }
+
+    /// Whether events outside of production carry the git revision and
+    /// uncommitted changes of the checkout the process runs from.
+    pub fn capture_vcs_diff(&self) -> bool {
+        self.capture_vcs_diff
+    }
+
+    /// Size limit of the uncommitted changes diff, in bytes.
+    pub fn max_vcs_diff_bytes(&self) -> usize {
+        self.max_vcs_diff_bytes
+    }
+
+    /// Identifies the build the process runs, if set or computed for
+    /// `upload_sources`.
+    pub fn build_id(&self) -> Option<&str> {
+        self.build_id.as_deref()
+    }
+
+    /// Whether the application's source files are uploaded once connected.
+    #[cfg(feature = "source-upload")]
+    pub fn upload_sources(&self) -> bool {
+        self.upload_sources
+    }
+
+    /// Directory whose source files are uploaded, if not the working directory.
+    #[cfg(feature = "source-upload")]
+    pub fn source_root(&self) -> Option<&Path> {
+        self.source_root.as_deref()
+    }
+
+    /// Patterns of source files that are not uploaded.
+    #[cfg(feature = "source-upload")]
+    pub fn source_exclude(&self) -> &[String] {
+        &self.source_exclude
+    }
+
+    /// Whether events are also written to the systemd journal.
+    #[cfg(feature = "journald")]
+    pub fn journald(&self) -> bool {
+        self.journald
+    }
+
+    /// How long events are flushed for after a termination signal.
+    #[cfg(feature = "shutdown-hooks")]
+    pub fn shutdown_timeout(&self) -> Duration {
+        self.shutdown_timeout
+    }
+
+    /// Whether the process terminates after flushing on a signal.
+    #[cfg(feature = "shutdown-hooks")]
+    pub fn exit_on_signal(&self) -> bool {
+        self.exit_on_signal
+    }
+
+    /// Additional symbol prefixes whose stack frames are filtered.
+    pub fn frame_skip_prefixes(&self) -> &[String] {
+        &self.frame_skip_prefixes
+    }
+
+    /// Whether filtered frames are kept and marked instead of dropped.
+    pub fn keep_filtered_frames(&self) -> bool {
+        self.keep_filtered_frames
+    }
+
+    /// Algorithm computing the `fingerprint` of events.
+    pub fn fingerprint_version(&self) -> FingerprintVersion {
+        self.fingerprint_version
+    }
+
+    /// Routes of events to other projects, in the order they are tried.
+    pub fn project_routes(&self) -> &[ProjectRoute] {
+        &self.project_routes
+    }
+
+    /// API key of the project `exc` is routed to, or `None` for the
+    /// project of [`api_key`](Self::api_key).
+    pub(crate) fn project_key(&self, exc: &ExceptionCapture) -> Option<String> {
+        self.project_routes
+            .iter()
+            .find(|route| route.matcher.matches(exc))
+            .map(|route| route.api_key.clone())
+    }
+
+    /// Source path prefix rewrites, in the order they are tried.
+    pub fn path_prefix_map(&self) -> &[PathMapping] {
+        &self.path_prefix_map
+    }
+
+    /// Whether the home directory in source paths is replaced with `~`.
+    pub fn scrub_home_dir(&self) -> bool {
+        self.scrub_home_dir
+    }
+
+    /// Environment variables added to event context.
+    pub fn capture_env_vars(&self) -> &[String] {
+        &self.capture_env_vars
+    }
+
+    /// Whether the command line and working directory are added to runtime info.
+    pub fn capture_command_line(&self) -> bool {
+        self.capture_command_line
     }
 
-    /// Sets the backend URL.
-    pub fn backend_url(mut self, url: impl Into<String>) -> Self {
-        self.backend_url = url.into();
-        self
+    /// Response status classes reported as events.
+    pub fn capture_status_classes(&self) -> &[StatusClass] {
+        &self.capture_status_classes
     }
 
-    /// Sets the sampling rate.
-    pub fn sampling_rate(mut self, rate: f64) -> Self {
-        self.sampling_rate = rate;
-        self
+    /// How client IP addresses are reported.
+    pub fn capture_client_ip(&self) -> ClientIpMode {
+        self.capture_client_ip
+    }
+
+    /// Rewrites a source path for reporting.
+    ///
+    /// The first matching `path_prefix_map` rule is applied; otherwise the
+    /// home directory is replaced with `~` if `scrub_home_dir` is set.
+    pub fn map_path(&self, path: &str) -> String {
+        for mapping in &self.path_prefix_map {
+            if let Some(rest) = path.strip_prefix(mapping.from.as_str()) {
+                return format!("{}{}", mapping.to, rest);
+            }
+        }
+
+        if self.scrub_home_dir {
+            let home = env::var("HOME").or_else(|_| env::var("USERPROFILE"));
+            if let Ok(home) = home {
+                let home = home.trim_end_matches(['/', '\\']);
+                if !home.is_empty() {
+                    if let Some(rest) = path.strip_prefix(home) {
+                        if rest.is_empty() || rest.starts_with(['/', '\\']) {
+                            return format!("~{}", rest);
+                        }
+                    }
+                }
+            }
+        }
+
+        path.to_string()
     }
 
-    /// Enables debug logging.
-    pub fn debug(mut self, debug: bool) -> Self {
-        self.debug = debug;
-        self
+    /// Path of the file this configuration was loaded from, if any.
+    pub fn config_path(&self) -> Option<&Path> {
+        self.config_path.as_deref()
     }
 
-    /// Determines if the current event should be sampled.
+    /// Determines if the current event should be sampled at `sampling_rate`.
+    ///
+    /// Only used in [`SamplingMode::Random`] without `target_events_per_minute`
+    /// or a sampler callback.
     pub fn should_sample(&self) -> bool {
         if self.sampling_rate >= 1.0 {
             return true;
@@ -118,20 +1067,311 @@ impl Config {
             runtime_version: env!("CARGO_PKG_VERSION").to_string(),
             platform: std::env::consts::OS.to_string(),
             arch: std::env::consts::ARCH.to_string(),
+            clock_skew_ms: crate::clock::skew_millis(),
+            args: self.capture_command_line.then(|| self.command_line()),
+            cwd: self
+                .capture_command_line
+                .then(|| std::env::current_dir().ok())
+                .flatten()
+                .map(|dir| self.map_path(&dir.to_string_lossy())),
+            build_id: self.build_id.clone(),
+            #[cfg(windows)]
+            session: crate::windows::session(),
+            #[cfg(not(windows))]
+            session: None,
+        }
+    }
+
+    /// The process's arguments with secrets redacted.
+    fn command_line(&self) -> Vec<String> {
+        self.scrub_command_line(std::env::args_os().map(|arg| arg.to_string_lossy().into_owned()))
+    }
+
+    /// Redacts secrets in a command line, program first, with the built-in
+    /// rules and then the `command_line_scrubber`.
+    pub(crate) fn scrub_command_line(&self, args: impl Iterator<Item = String>) -> Vec<String> {
+        let args = redact_args(args);
+        match &self.command_line_scrubber {
+            Some(scrubber) => scrubber(args),
+            None => args,
+        }
+    }
+}
+
+/// Function that scrubs the command line, given the arguments after the
+/// built-in redaction.
+pub type CommandLineScrubber = Arc<dyn Fn(Vec<String>) -> Vec<String> + Send + Sync>;
+
+/// Function that scrubs the diff of uncommitted changes before it is cut to
+/// `max_vcs_diff_bytes`.
+pub type VcsDiffScrubber = Arc<dyn Fn(String) -> String + Send + Sync>;
+
+/// Flag names whose values are redacted from the command line.
+const SECRET_FLAG_WORDS: &[&str] = &[
+    "password",
+    "passwd",
+    "secret",
+    "token",
+    "apikey",
+    "api-key",
+    "api_key",
+    "auth",
+    "credential",
+];
+
+/// Replaces the values of flags such as `--password=…` or `--token …`.
+fn redact_args(args: impl Iterator<Item = String>) -> Vec<String> {
+    let is_secret = |flag: &str| {
+        let flag = flag.to_ascii_lowercase();
+        SECRET_FLAG_WORDS.iter().any(|word| flag.contains(word))
+    };
+
+    let mut redacted = Vec::new();
+    let mut redact_next = false;
+    for arg in args {
+        if redact_next && !arg.starts_with('-') {
+            redacted.push("[redacted]".to_string());
+            redact_next = false;
+            continue;
+        }
+        redact_next = false;
+        if arg.starts_with('-') {
+            match arg.split_once('=') {
+                Some((flag, _)) if is_secret(flag) => {
+                    redacted.push(format!("{}=[redacted]", flag));
+                    continue;
+                }
+                Some(_) => {}
+                None => redact_next = is_secret(&arg),
+            }
+        }
+        redacted.push(arg);
+    }
+    redacted
+}
+
+/// Error raised while loading configuration.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The configuration file could not be read.
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// The configuration file could not be parsed.
+    Parse { path: PathBuf, message: String },
+    /// The file extension does not map to a supported format.
+    UnsupportedFormat(PathBuf),
+    /// The backend URL could not be parsed.
+    InvalidUrl { url: String, message: String },
+    /// The backend URL uses a scheme other than `ws`, `wss`, `http` or `https`.
+    UnsupportedScheme(String),
+    /// The sampling rate is outside `0.0..=1.0`.
+    InvalidSamplingRate(f64),
+    /// A limit that must be positive was set to zero.
+    ZeroLimit(&'static str),
+    /// The spool encryption key is not 32 bytes of base64.
+    InvalidSpoolEncryptionKey,
+    /// A setting was given that needs a feature the agent was built without.
+    FeatureDisabled {
+        setting: &'static str,
+        feature: &'static str,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io { path, source } => {
+                write!(
+                    f,
+                    "failed to read config file {}: {}",
+                    path.display(),
+                    source
+                )
+            }
+            ConfigError::Parse { path, message } => {
+                write!(
+                    f,
+                    "failed to parse config file {}: {}",
+                    path.display(),
+                    message
+                )
+            }
+            ConfigError::UnsupportedFormat(path) => {
+                write!(f, "unsupported config file format: {}", path.display())
+            }
+            ConfigError::InvalidUrl { url, message } => {
+                write!(f, "invalid backend URL {:?}: {}", url, message)
+            }
+            ConfigError::UnsupportedScheme(scheme) => {
+                write!(
+                    f,
+                    "backend URL scheme must be ws, wss, http or https, got {:?}",
+                    scheme
+                )
+            }
+            ConfigError::InvalidSamplingRate(rate) => {
+                write!(f, "sampling rate must be between 0.0 and 1.0, got {}", rate)
+            }
+            ConfigError::ZeroLimit(name) => write!(f, "{} must be greater than zero", name),
+            ConfigError::InvalidSpoolEncryptionKey => {
+                write!(f, "spool_encryption_key must be 32 bytes, base64-encoded")
+            }
+            ConfigError::FeatureDisabled { setting, feature } => {
+                write!(
+                    f,
+                    "{} is set, but the agent was built without the `{}` feature",
+                    setting, feature
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Replaces the URL's scheme using the first matching `(from, to)` pair.
+fn with_scheme(url: &str, mapping: &[(&str, &str)]) -> String {
+    for (from, to) in mapping {
+        if let Some(rest) = url
+            .strip_prefix(from)
+            .and_then(|rest| rest.strip_prefix("://"))
+        {
+            return format!("{}://{}", to, rest);
+        }
+    }
+    url.to_string()
+}
+
+fn env_parse<T: FromStr>(key: &str) -> Option<T> {
+    env::var(key).ok().and_then(|s| s.parse().ok())
+}
+
+/// Sends the events a matcher selects to another project, e.g. the code of
+/// one team in a monolith shared by several.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize)]
+pub struct ProjectRoute {
+    /// Events the route applies to.
+    #[serde(flatten)]
+    pub matcher: RouteMatcher,
+    /// API key of the project the events are sent to.
+    pub api_key: String,
+}
+
+/// Events a [`ProjectRoute`] applies to.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RouteMatcher {
+    /// Events with a stack frame in a module path, such as `my_app::billing`,
+    /// or below it; a crate name matches the whole crate.
+    Module(String),
+    /// Events whose context has `value` under `key`, as set with
+    /// [`Scope::context`](crate::scope::Scope::context). Values other than
+    /// strings are compared in their JSON form, such as `7` or `true`.
+    Tag { key: String, value: String },
+}
+
+impl RouteMatcher {
+    /// Whether the route applies to `exc`.
+    ///
+    /// ```rust
+    /// use aivory_monitor::capture::{EventBuilder, StackFrame};
+    /// use aivory_monitor::config::RouteMatcher;
+    /// use aivory_monitor::Config;
+    ///
+    /// let mut frame = StackFrame::new("total");
+    /// frame.module = Some("my_app::billing::invoice".into());
+    /// let event = EventBuilder::new("BillingError")
+    ///     .frame(frame)
+    ///     .context("team", "payments")
+    ///     .build(&Config::default());
+    ///
+    /// assert!(RouteMatcher::Module("my_app::billing".into()).matches(&event));
+    /// assert!(RouteMatcher::Module("my_app".into()).matches(&event));
+    /// assert!(!RouteMatcher::Module("my_app::bill".into()).matches(&event));
+    /// let tag = |value: &str| RouteMatcher::Tag { key: "team".into(), value: value.into() };
+    /// assert!(tag("payments").matches(&event));
+    /// assert!(!tag("search").matches(&event));
+    /// ```
+    pub fn matches(&self, exc: &ExceptionCapture) -> bool {
+        match self {
+            RouteMatcher::Module(prefix) => exc.stack_trace.iter().any(|frame| {
+                let path = aivory_monitor_core::function_path(frame);
+                // Trait impls, as in `<my_app::billing::Invoice as Display>::fmt`.
+                let path = path.strip_prefix('<').unwrap_or(&path);
+                path.strip_prefix(prefix.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+            }),
+            RouteMatcher::Tag { key, value } => match exc.context.get(key) {
+                Some(serde_json::Value::String(s)) => s == value,
+                Some(other) => {
+                    serde_json::from_str::<serde_json::Value>(value).is_ok_and(|v| v == *other)
+                }
+                None => false,
+            },
         }
     }
 }
 
+/// Rewrite rule for source paths, e.g. from a CI workspace to the repository root.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize)]
+pub struct PathMapping {
+    /// Prefix to replace.
+    pub from: String,
+    /// Replacement.
+    pub to: String,
+}
+
 /// Runtime information.
-#[derive(Clone, serde::Serialize)]
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
 pub struct RuntimeInfo {
     pub runtime: String,
     pub runtime_version: String,
     pub platform: String,
     pub arch: String,
+    /// Measured server-minus-local clock offset applied to timestamps.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clock_skew_ms: Option<i64>,
+    /// Command line, with secrets redacted, if `capture_command_line` is set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub args: Option<Vec<String>>,
+    /// Working directory, if `capture_command_line` is set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<String>,
+    /// Build the process runs, which uploaded sources are keyed by.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub build_id: Option<String>,
+    /// Desktop session the process runs in; Windows only.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<SessionInfo>,
+}
+
+/// The Windows session a process runs in.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct SessionInfo {
+    /// Terminal Services session ID; 0 is the non-interactive services session.
+    pub id: u32,
+    /// `Console` for the local desktop, `RDP-Tcp#N` for a remote one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Whether the session has a desktop a user can see, i.e. is not session 0.
+    pub interactive: bool,
+    /// Whether the session is a Remote Desktop session.
+    pub remote: bool,
+    /// Name of the machine a Remote Desktop session is connected from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_name: Option<String>,
 }
 
-mod rand {
+pub(crate) mod rand {
     use std::cell::Cell;
     use std::time::{SystemTime, UNIX_EPOCH};
 
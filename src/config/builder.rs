@@ -0,0 +1,829 @@
+//! Validated configuration builder.
+
+use super::{
+    Config, ConfigError, EnvironmentSource, HostnameMode, PathMapping, ProjectRoute, RouteMatcher,
+};
+use crate::breadcrumbs::BreadcrumbLevel;
+use crate::capture::{FingerprintVersion, PanicBacktrace, PanicPayload};
+use crate::logger::{LogLevel, LogOutput};
+use crate::request::{ClientIpMode, StatusClass};
+use crate::sampling::{SamplingContext, SamplingMode};
+use crate::transport::{HttpRequest, TransportMode};
+use std::any::Any;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Builder for [`Config`].
+///
+/// Values set on the builder take precedence over environment variables and
+/// configuration files. [`ConfigBuilder::build`] validates the result so that
+/// bad settings are reported up front instead of at connect time.
+///
+/// ```rust,no_run
+/// use aivory_monitor::Config;
+///
+/// let config = Config::builder()
+///     .api_key("your-api-key")
+///     .environment("production")
+///     .sampling_rate(0.5)
+///     .build()
+///     .expect("invalid AIVory configuration");
+/// ```
+#[derive(Clone)]
+pub struct ConfigBuilder {
+    config: Config,
+}
+
+impl ConfigBuilder {
+    /// Creates a builder seeded from `AIVORY_*` environment variables.
+    pub fn new() -> Self {
+        ConfigBuilder {
+            config: Config::default(),
+        }
+    }
+
+    /// Creates a builder seeded from a configuration file.
+    ///
+    /// See [`Config::from_file`] for how the file and environment are layered.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        Ok(ConfigBuilder {
+            config: Config::load_file(path.as_ref())?,
+        })
+    }
+
+    /// Sets the API key.
+    pub fn api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.config.api_key = api_key.into();
+        self
+    }
+
+    /// Sends events with a stack frame in `module`, such as `my_app::billing`,
+    /// or below it to the project of `api_key` instead.
+    ///
+    /// Routes, including those of [`route_tag`](Self::route_tag), are tried
+    /// in the order they are added; the first match wins. Events no route
+    /// matches go to the project of [`api_key`](Self::api_key).
+    ///
+    /// ```rust,no_run
+    /// use aivory_monitor::Config;
+    ///
+    /// let config = Config::builder()
+    ///     .api_key("platform-key")
+    ///     .route_module("my_app::billing", "billing-key")
+    ///     .route_module("search_engine", "search-key")
+    ///     .build();
+    /// ```
+    pub fn route_module(mut self, module: impl Into<String>, api_key: impl Into<String>) -> Self {
+        self.config.project_routes.push(ProjectRoute {
+            matcher: RouteMatcher::Module(module.into()),
+            api_key: api_key.into(),
+        });
+        self
+    }
+
+    /// Sends events whose context has `value` under `key`, typically set by
+    /// a [`Scope`](crate::scope::Scope) around a team's handlers, to the
+    /// project of `api_key` instead. See [`route_module`](Self::route_module)
+    /// for the order routes are tried in.
+    pub fn route_tag(
+        mut self,
+        key: impl Into<String>,
+        value: impl Into<String>,
+        api_key: impl Into<String>,
+    ) -> Self {
+        self.config.project_routes.push(ProjectRoute {
+            matcher: RouteMatcher::Tag {
+                key: key.into(),
+                value: value.into(),
+            },
+            api_key: api_key.into(),
+        });
+        self
+    }
+
+    /// Sets the backend URL. Must use the `ws`, `wss`, `http` or `https`
+    /// scheme; it is switched to match the transport.
+    pub fn backend_url(mut self, url: impl Into<String>) -> Self {
+        self.config.backend_url = url.into();
+        self
+    }
+
+    /// Sets backend URLs to fail over to, in order, when the primary one is
+    /// unreachable, e.g. the endpoint of another region. Each reconnect
+    /// starts again from the primary URL.
+    ///
+    /// ```rust,no_run
+    /// use aivory_monitor::Config;
+    ///
+    /// let config = Config::builder()
+    ///     .backend_url("wss://eu.api.aivory.net/ws/agent")
+    ///     .fallback_urls(["wss://us.api.aivory.net/ws/agent"])
+    ///     .build();
+    /// ```
+    pub fn fallback_urls<I, S>(mut self, urls: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.config.fallback_urls = urls.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Sets how events are delivered, overriding [`TransportMode::detect`].
+    pub fn transport(mut self, transport: TransportMode) -> Self {
+        self.config.transport = transport;
+        self
+    }
+
+    /// Sets the timeout of each request made by the HTTP transport. Defaults to 5 seconds.
+    pub fn http_timeout(mut self, timeout: Duration) -> Self {
+        self.config.http_timeout = timeout;
+        self
+    }
+
+    /// Sets how often the WebSocket transport pings the backend, so that
+    /// [`liveness_timeout`](Self::liveness_timeout) also catches dead
+    /// connections while idle. Defaults to 30 seconds; zero disables pings.
+    pub fn ping_interval(mut self, interval: Duration) -> Self {
+        self.config.ping_interval = interval;
+        self
+    }
+
+    /// Sets how long a WebSocket write, or the backend's answer to an event
+    /// or ping, may take. Past it the connection counts as dead and the
+    /// agent reconnects, instead of sending events into a half-open
+    /// connection. Defaults to 15 seconds; zero disables the check.
+    pub fn liveness_timeout(mut self, timeout: Duration) -> Self {
+        self.config.liveness_timeout = timeout;
+        self
+    }
+
+    /// Sets how often the WebSocket transport sends a heartbeat with the
+    /// agent's health, or `None` to send none, e.g. on battery-powered
+    /// devices. Defaults to 30 seconds.
+    pub fn heartbeat_interval(mut self, interval: Option<Duration>) -> Self {
+        self.config.heartbeat_interval = interval;
+        self
+    }
+
+    /// Performs HTTP transport requests with `sender` instead of the built-in
+    /// client. Required on WASI, where the built-in client is unavailable.
+    ///
+    /// `sender` makes a blocking POST and returns the response status code.
+    pub fn http_sender(
+        mut self,
+        sender: impl Fn(&HttpRequest) -> Result<u16, String> + Send + Sync + 'static,
+    ) -> Self {
+        self.config.http_sender = Some(Arc::new(sender));
+        self
+    }
+
+    /// Captures, scrubs and serializes events as usual, but writes them to
+    /// stderr, pretty-printed, instead of sending them. No connection is
+    /// made and no API key is needed.
+    ///
+    /// Shows exactly what data would leave the process before enabling the
+    /// agent in production.
+    pub fn dry_run(mut self, enabled: bool) -> Self {
+        self.config.dry_run = enabled;
+        self
+    }
+
+    /// Appends the ID, fingerprint and delivery outcome of every event to
+    /// `path` as JSON lines: `sent`, `acked` by the backend, or `dropped`
+    /// with the reason. Event content is not written.
+    pub fn audit_log(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.audit_log = Some(path.into());
+        self
+    }
+
+    /// Sets the environment, overriding auto-detection.
+    pub fn environment(mut self, env: impl Into<String>) -> Self {
+        self.config.environment = env.into();
+        self.config.environment_source = EnvironmentSource::Explicit;
+        self
+    }
+
+    /// Sets the sampling rate. Must be between 0.0 and 1.0.
+    pub fn sampling_rate(mut self, rate: f64) -> Self {
+        self.config.sampling_rate = rate;
+        self
+    }
+
+    /// Enables adaptive sampling, keeping about `events` events a minute
+    /// instead of a fixed `sampling_rate`. The first event of each
+    /// fingerprint is always kept.
+    pub fn target_events_per_minute(mut self, events: u32) -> Self {
+        self.config.target_events_per_minute = Some(events);
+        self
+    }
+
+    /// Sets how the sampling rate is applied to each event.
+    ///
+    /// [`SamplingMode::Deterministic`] keeps or drops all events of a trace
+    /// together; set the trace with [`Scope::trace_id`](crate::scope::Scope::trace_id).
+    pub fn sampling_mode(mut self, mode: SamplingMode) -> Self {
+        self.config.sampling_mode = mode;
+        self
+    }
+
+    /// Chooses the sampling rate of each event with `sampler`, which returns
+    /// a rate between 0.0 and 1.0.
+    ///
+    /// The callback sees the event with all context applied, and the rate
+    /// it would otherwise be sampled at as [`SamplingContext::default_rate`].
+    ///
+    /// ```rust,no_run
+    /// use aivory_monitor::Config;
+    ///
+    /// let config = Config::builder()
+    ///     .sampler(|event| match event.context("tenant_tier").and_then(|tier| tier.as_str()) {
+    ///         Some("enterprise") => 1.0,
+    ///         _ if event.exception_type() == "TimeoutError" => 0.05,
+    ///         _ => event.default_rate(),
+    ///     })
+    ///     .build();
+    /// ```
+    pub fn sampler(
+        mut self,
+        sampler: impl Fn(&SamplingContext<'_>) -> f64 + Send + Sync + 'static,
+    ) -> Self {
+        self.config.sampler = Some(Arc::new(sampler));
+        self
+    }
+
+    /// Enables aggregation: within each `interval`, repeated fingerprints are
+    /// sent once with their `count`, `first_seen` and `last_seen`.
+    ///
+    /// Events are sent at the end of the interval rather than right away,
+    /// except panics. [`flush_blocking`](crate::flush_blocking) and shutdown
+    /// send what is held.
+    pub fn aggregation_interval(mut self, interval: Duration) -> Self {
+        self.config.aggregation_interval = Some(interval);
+        self
+    }
+
+    /// Tracks how often each fingerprint occurs and adds the rates to its
+    /// events as `error_rate`, with `is_spike` set when the rate over the
+    /// last minute is well above that over the last half hour. Defaults to
+    /// true.
+    pub fn detect_spikes(mut self, detect: bool) -> Self {
+        self.config.detect_spikes = detect;
+        self
+    }
+
+    /// Sets how many consecutive backend failures pause capture. Defaults to
+    /// 5; zero disables the circuit breaker.
+    pub fn circuit_breaker_threshold(mut self, failures: u32) -> Self {
+        self.config.circuit_breaker_threshold = failures;
+        self
+    }
+
+    /// Sets how long capture is paused before a probe event is sent.
+    /// Defaults to 30 seconds.
+    pub fn circuit_breaker_cooldown(mut self, cooldown: Duration) -> Self {
+        self.config.circuit_breaker_cooldown = cooldown;
+        self
+    }
+
+    /// Limits how many bytes per second are written to the backend. Bursts
+    /// wait in the outbound queue; see [`AgentDiagnostics::queue_depth`](crate::AgentDiagnostics::queue_depth).
+    pub fn max_bytes_per_second(mut self, bytes: u64) -> Self {
+        self.config.max_bytes_per_second = Some(bytes);
+        self
+    }
+
+    /// Sets the maximum capture depth for variables.
+    pub fn max_capture_depth(mut self, depth: usize) -> Self {
+        self.config.max_capture_depth = depth;
+        self
+    }
+
+    /// Sets the maximum string length to capture.
+    pub fn max_string_length(mut self, len: usize) -> Self {
+        self.config.max_string_length = len;
+        self
+    }
+
+    /// Sets the maximum collection size to capture.
+    pub fn max_collection_size(mut self, size: usize) -> Self {
+        self.config.max_collection_size = size;
+        self
+    }
+
+    /// Sets how many entries the global and each thread's context keep, in
+    /// key order; further keys are dropped and counted under
+    /// `_dropped_context_keys`. Defaults to 100.
+    pub fn max_context_keys(mut self, max: usize) -> Self {
+        self.config.max_context_keys = max;
+        self
+    }
+
+    /// Sets how many of the latest breadcrumbs are kept and sent with each
+    /// event. Defaults to 100; zero disables breadcrumbs.
+    pub fn max_breadcrumbs(mut self, max: usize) -> Self {
+        self.config.max_breadcrumbs = max;
+        self
+    }
+
+    /// Discards breadcrumbs less severe than `level` when they are recorded,
+    /// unless a level is set for their category. Defaults to
+    /// [`Debug`](BreadcrumbLevel::Debug), which keeps all of them.
+    pub fn min_breadcrumb_level(mut self, level: BreadcrumbLevel) -> Self {
+        self.config.min_breadcrumb_level = level;
+        self
+    }
+
+    /// Discards breadcrumbs of `category` less severe than `level`, in place
+    /// of [`min_breadcrumb_level`](Self::min_breadcrumb_level).
+    ///
+    /// ```rust,no_run
+    /// use aivory_monitor::{BreadcrumbLevel, Config};
+    ///
+    /// // Keep debug breadcrumbs, except chatty HTTP ones.
+    /// let config = Config::builder()
+    ///     .breadcrumb_level("http", BreadcrumbLevel::Info)
+    ///     .build();
+    /// ```
+    pub fn breadcrumb_level(mut self, category: impl Into<String>, level: BreadcrumbLevel) -> Self {
+        self.config.breadcrumb_levels.insert(category.into(), level);
+        self
+    }
+
+    /// Discards all breadcrumbs of the given categories.
+    pub fn ignore_breadcrumb_categories<I, S>(mut self, categories: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.config.ignore_breadcrumb_categories = categories.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Enables debug logging.
+    pub fn debug(mut self, debug: bool) -> Self {
+        self.config.debug = debug;
+        self
+    }
+
+    /// Sets the most verbose internal log level. `debug(true)` raises it to
+    /// [`LogLevel::Debug`].
+    pub fn log_level(mut self, level: LogLevel) -> Self {
+        self.config.log_level = level;
+        self
+    }
+
+    /// Sets where internal log messages are written.
+    pub fn log_output(mut self, output: LogOutput) -> Self {
+        self.config.set_log_output(output);
+        self
+    }
+
+    /// Sends internal log messages to `callback` instead of stdout/stderr.
+    pub fn logger(self, callback: impl Fn(LogLevel, &str) + Send + Sync + 'static) -> Self {
+        self.log_output(LogOutput::Callback(Arc::new(callback)))
+    }
+
+    /// Registers an extractor for custom panic payloads, such as those passed to
+    /// [`std::panic::panic_any`].
+    ///
+    /// Extractors are tried in registration order after the built-in `&str`
+    /// and `String` handling; the first to return `Some` wins.
+    ///
+    /// ```rust,no_run
+    /// use aivory_monitor::capture::PanicPayload;
+    /// use aivory_monitor::Config;
+    ///
+    /// struct InvariantViolation {
+    ///     invariant: &'static str,
+    ///     order_id: u64,
+    /// }
+    ///
+    /// let config = Config::builder()
+    ///     .panic_payload_extractor(|payload| {
+    ///         payload.downcast_ref::<InvariantViolation>().map(|v| {
+    ///             PanicPayload::new(format!("invariant violated: {}", v.invariant))
+    ///                 .field("order_id", v.order_id)
+    ///         })
+    ///     })
+    ///     .build();
+    /// ```
+    pub fn panic_payload_extractor(
+        mut self,
+        extractor: impl Fn(&(dyn Any + Send)) -> Option<PanicPayload> + Send + Sync + 'static,
+    ) -> Self {
+        self.config
+            .panic_payload_extractors
+            .push(Arc::new(extractor));
+        self
+    }
+
+    /// Disables all internal log output.
+    pub fn silent(self) -> Self {
+        self.log_output(LogOutput::Silent)
+    }
+
+    /// Overrides the reported hostname.
+    pub fn hostname(mut self, hostname: impl Into<String>) -> Self {
+        self.config.hostname = hostname.into();
+        self
+    }
+
+    /// Sets how the hostname is reported, for organizations that treat
+    /// hostnames as sensitive. Defaults to [`HostnameMode::Plain`].
+    ///
+    /// The hashed and alias forms are stable per machine, so events can
+    /// still be grouped by host.
+    pub fn hostname_mode(mut self, mode: HostnameMode) -> Self {
+        self.config.hostname_mode = mode;
+        self
+    }
+
+    /// Names the service the process runs, such as `checkout-api`, reported
+    /// on registration and with every event so services sharing a host and
+    /// a project can be told apart.
+    pub fn service_name(mut self, service_name: impl Into<String>) -> Self {
+        self.config.service_name = Some(service_name.into());
+        self
+    }
+
+    /// Names the server or instance the process runs on, such as a pod or
+    /// VM name, reported on registration and with every event. Unlike the
+    /// hostname it is sent as set, whatever the `hostname_mode`.
+    pub fn server_name(mut self, server_name: impl Into<String>) -> Self {
+        self.config.server_name = Some(server_name.into());
+        self
+    }
+
+    /// Overrides the generated agent ID.
+    pub fn agent_id(mut self, agent_id: impl Into<String>) -> Self {
+        self.config.agent_id = agent_id.into();
+        self.config.agent_id_explicit = true;
+        self
+    }
+
+    /// Sets whether the agent ID is persisted across restarts. Enabled by
+    /// default.
+    ///
+    /// The persisted ID belongs to the executable, not the process: several
+    /// processes of one program on a host share it, and set
+    /// [`agent_id`](Self::agent_id) or [`server_name`](Self::server_name) to
+    /// tell them apart.
+    pub fn persist_agent_id(mut self, persist: bool) -> Self {
+        self.config.persist_agent_id = persist;
+        self
+    }
+
+    /// Overrides the directory used for persisted agent state.
+    pub fn state_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.config.state_dir = Some(dir.into());
+        self
+    }
+
+    /// Encrypts the events spooled in the state directory when they could not
+    /// be sent with `key`: 32 bytes, base64-encoded, such as the output of
+    /// `openssl rand -base64 32`.
+    ///
+    /// Messages spooled with a different key, or encrypted ones read without
+    /// a key, are kept in the spool but not sent. [`build`](Self::build)
+    /// fails if `key` is invalid.
+    #[cfg(feature = "spool-encryption")]
+    pub fn spool_encryption_key(mut self, key: impl Into<String>) -> Self {
+        self.config.spool_encryption_key = Some(key.into());
+        self
+    }
+
+    /// Encrypts the spool with a key kept in the OS keychain: the macOS
+    /// Keychain, the Windows Credential Manager, or the Secret Service on
+    /// Linux. The first agent to find none there stores a random one.
+    ///
+    /// A key set with [`spool_encryption_key`](Self::spool_encryption_key)
+    /// takes precedence. If the keychain can't be read, nothing is spooled.
+    #[cfg(feature = "spool-encryption-keychain")]
+    pub fn spool_encryption_keychain(mut self, enabled: bool) -> Self {
+        self.config.spool_encryption_keychain = enabled;
+        self
+    }
+
+    /// Sets when panics include a stack trace. Defaults to [`PanicBacktrace::Always`].
+    pub fn panic_backtrace(mut self, mode: PanicBacktrace) -> Self {
+        self.config.panic_backtrace = mode;
+        self
+    }
+
+    /// Sets how long the panic hook blocks to send the panic event when the
+    /// binary is built with `panic = "abort"`, where the process ends as soon
+    /// as the hook returns and queued events would otherwise be lost. Has no
+    /// effect with unwinding panics. Defaults to 2 seconds; zero disables it.
+    pub fn panic_flush_timeout(mut self, timeout: Duration) -> Self {
+        self.config.panic_flush_timeout = timeout;
+        self
+    }
+
+    /// Adds the stack of every thread of the process to panic events, listed
+    /// under `threads`, so a panic caused by a deadlock or a stuck lock holder
+    /// shows what the other threads were doing. Other threads are interrupted
+    /// briefly with a real-time signal; only supported on Linux. Defaults to
+    /// false.
+    pub fn capture_all_threads(mut self, capture: bool) -> Self {
+        self.config.capture_all_threads = capture;
+        self
+    }
+
+    /// Reports crashes of earlier runs of the executable that the OS
+    /// recorded, Windows Error Reporting records and macOS `.ips` crash
+    /// reports, once at startup.
+    /// Defaults to true.
+    pub fn report_os_crashes(mut self, report: bool) -> Self {
+        self.config.report_os_crashes = report;
+        self
+    }
+
+    /// Sets the ID of the build the process runs, reported with every event
+    /// and keying the sources uploaded with `upload_sources`. With that
+    /// setting, defaults to a hash of the executable.
+    pub fn build_id(mut self, build_id: impl Into<String>) -> Self {
+        self.config.build_id = Some(build_id.into());
+        self
+    }
+
+    /// Uploads a compressed bundle of the application's `.rs` files once
+    /// connected, keyed by the build ID, so fixes are generated against the
+    /// exact source of the running build even if the repository is private.
+    /// Files ignored by `.gitignore` or matching `source_exclude` are left
+    /// out. Defaults to false.
+    #[cfg(feature = "source-upload")]
+    pub fn upload_sources(mut self, upload: bool) -> Self {
+        self.config.upload_sources = upload;
+        self
+    }
+
+    /// Sets the directory whose source files are uploaded. Defaults to the
+    /// working directory.
+    #[cfg(feature = "source-upload")]
+    pub fn source_root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.config.source_root = Some(root.into());
+        self
+    }
+
+    /// Leaves source files matching `pattern`, in `.gitignore` syntax
+    /// relative to the source root, out of the upload; e.g. `"src/secrets/"`.
+    #[cfg(feature = "source-upload")]
+    pub fn exclude_source(mut self, pattern: impl Into<String>) -> Self {
+        self.config.source_exclude.push(pattern.into());
+        self
+    }
+
+    /// Also writes every event to the systemd journal as a structured entry.
+    /// Defaults to false.
+    #[cfg(feature = "journald")]
+    pub fn journald(mut self, enabled: bool) -> Self {
+        self.config.journald = enabled;
+        self
+    }
+
+    /// Sets how long events are flushed for after SIGTERM or SIGINT.
+    /// Defaults to 2 seconds.
+    #[cfg(feature = "shutdown-hooks")]
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.config.shutdown_timeout = timeout;
+        self
+    }
+
+    /// Sets whether the process terminates after flushing on SIGTERM or
+    /// SIGINT. Defaults to true; disable it only if the application handles
+    /// these signals itself, since the agent's handler replaces the default.
+    #[cfg(feature = "shutdown-hooks")]
+    pub fn exit_on_signal(mut self, exit: bool) -> Self {
+        self.config.exit_on_signal = exit;
+        self
+    }
+
+    /// Filters stack frames whose symbol starts with `prefix`, e.g. `"hyper::"`.
+    pub fn skip_frame_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.config.frame_skip_prefixes.push(prefix.into());
+        self
+    }
+
+    /// Replaces the filtered symbol prefixes, including the defaults in
+    /// [`DEFAULT_FRAME_SKIP_PREFIXES`](super::DEFAULT_FRAME_SKIP_PREFIXES).
+    ///
+    /// `std`, `core`, `alloc` and `backtrace` frames are always filtered, and
+    /// the agent's own frames are always dropped.
+    pub fn frame_skip_prefixes<I, S>(mut self, prefixes: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.config.frame_skip_prefixes = prefixes.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Keeps filtered frames, marked `is_filtered`, instead of dropping them.
+    pub fn keep_filtered_frames(mut self, keep: bool) -> Self {
+        self.config.keep_filtered_frames = keep;
+        self
+    }
+
+    /// Sets the algorithm computing the `fingerprint` events are grouped by.
+    /// Defaults to [`FingerprintVersion::V1`]; both fingerprints are sent in
+    /// `fingerprints` either way.
+    pub fn fingerprint_version(mut self, version: FingerprintVersion) -> Self {
+        self.config.fingerprint_version = version;
+        self
+    }
+
+    /// Rewrites source paths starting with `from` to start with `to` instead.
+    ///
+    /// Rules are tried in the order they are added; the first match wins.
+    ///
+    /// ```rust,no_run
+    /// use aivory_monitor::Config;
+    ///
+    /// let config = Config::builder()
+    ///     .path_prefix_map("/build/workspace/", "")
+    ///     .build();
+    /// ```
+    pub fn path_prefix_map(mut self, from: impl Into<String>, to: impl Into<String>) -> Self {
+        self.config.path_prefix_map.push(PathMapping {
+            from: from.into(),
+            to: to.into(),
+        });
+        self
+    }
+
+    /// Sets whether the home directory in source paths is replaced with `~`. Enabled by default.
+    pub fn scrub_home_dir(mut self, scrub: bool) -> Self {
+        self.config.scrub_home_dir = scrub;
+        self
+    }
+
+    /// Adds the process's command line and working directory to the runtime
+    /// info of every event, often the fastest clue to why a CLI tool or batch
+    /// job failed. Off by default.
+    ///
+    /// Values of flags whose name mentions a password, secret, token, API key,
+    /// auth or credential are replaced with `[redacted]`, as in
+    /// `--password=[redacted]` or `--token [redacted]`. Use
+    /// [`command_line_scrubber`](Self::command_line_scrubber) for anything else.
+    pub fn capture_command_line(mut self, capture: bool) -> Self {
+        self.config.capture_command_line = capture;
+        self
+    }
+
+    /// Scrubs the captured command line further, given the arguments after
+    /// the built-in redaction, program first. Also applies to the command
+    /// lines of failed children of
+    /// [`MonitoredCommand`](crate::process::MonitoredCommand).
+    ///
+    /// ```rust
+    /// use aivory_monitor::Config;
+    ///
+    /// let config = Config::builder()
+    ///     .capture_command_line(true)
+    ///     .command_line_scrubber(|args| {
+    ///         args.into_iter()
+    ///             .map(|arg| if arg.starts_with("postgres://") { "[dsn]".to_string() } else { arg })
+    ///             .collect()
+    ///     })
+    ///     .build();
+    /// ```
+    pub fn command_line_scrubber(
+        mut self,
+        scrubber: impl Fn(Vec<String>) -> Vec<String> + Send + Sync + 'static,
+    ) -> Self {
+        self.config.command_line_scrubber = Some(Arc::new(scrubber));
+        self
+    }
+
+    /// Sets whether events carry the git revision, branch and diff of
+    /// uncommitted changes of the checkout the process runs from, under the
+    /// `vcs` context key. Read once at startup, and never in the `production`
+    /// environment. Changes to `.env` files, `*.pem` and `*.key` files and
+    /// files with `secret` in their name are left out; use
+    /// [`vcs_diff_scrubber`](Self::vcs_diff_scrubber) for anything else.
+    /// Defaults to false, as the diff is source code.
+    pub fn capture_vcs_diff(mut self, capture: bool) -> Self {
+        self.config.capture_vcs_diff = capture;
+        self
+    }
+
+    /// Sets the size limit of the uncommitted changes diff; longer diffs are
+    /// cut at a line end and marked `diff_truncated`. Defaults to 16 KiB.
+    pub fn max_vcs_diff_bytes(mut self, bytes: usize) -> Self {
+        self.config.max_vcs_diff_bytes = bytes;
+        self
+    }
+
+    /// Scrubs the diff of uncommitted changes before it is cut to size.
+    ///
+    /// ```rust
+    /// use aivory_monitor::Config;
+    ///
+    /// let config = Config::builder()
+    ///     .vcs_diff_scrubber(|diff| diff.replace("hunter2", "[redacted]"))
+    ///     .build();
+    /// ```
+    pub fn vcs_diff_scrubber(
+        mut self,
+        scrubber: impl Fn(String) -> String + Send + Sync + 'static,
+    ) -> Self {
+        self.config.vcs_diff_scrubber = Some(Arc::new(scrubber));
+        self
+    }
+
+    /// Adds the listed environment variables to the context of every event,
+    /// under `env_vars`. Only the variables named here are read, once at
+    /// startup; variables that are not set are left out.
+    ///
+    /// ```rust
+    /// use aivory_monitor::Config;
+    ///
+    /// let config = Config::builder()
+    ///     .capture_env_vars(["DEPLOY_ID", "REGION"])
+    ///     .build();
+    /// ```
+    pub fn capture_env_vars<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.config.capture_env_vars = names.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Reports responses recorded with
+    /// [`request::record_response`](crate::request::record_response) as
+    /// events when their status is in one of `classes`, even if no Rust error
+    /// surfaced. Defaults to none.
+    ///
+    /// ```rust,no_run
+    /// use aivory_monitor::request::StatusClass;
+    /// use aivory_monitor::Config;
+    ///
+    /// let config = Config::builder()
+    ///     .capture_status_classes([StatusClass::SERVER_ERROR])
+    ///     .build();
+    /// ```
+    pub fn capture_status_classes(
+        mut self,
+        classes: impl IntoIterator<Item = StatusClass>,
+    ) -> Self {
+        self.config.capture_status_classes = classes.into_iter().collect();
+        self
+    }
+
+    /// Sets how the IP addresses of clients are reported: the client IP of
+    /// requests, proxy headers such as `X-Forwarded-For` and the IP address
+    /// of users, whichever integration set them. Defaults to
+    /// [`ClientIpMode::Full`].
+    ///
+    /// ```rust,no_run
+    /// use aivory_monitor::request::ClientIpMode;
+    /// use aivory_monitor::Config;
+    ///
+    /// let config = Config::builder()
+    ///     .capture_client_ip(ClientIpMode::Anonymized)
+    ///     .build();
+    /// ```
+    pub fn capture_client_ip(mut self, mode: ClientIpMode) -> Self {
+        self.config.capture_client_ip = mode;
+        self
+    }
+
+    /// Names the transactions of requests without a route template after
+    /// the path `normalizer` returns, instead of the path with IDs replaced
+    /// by [`normalize_path`](crate::request::normalize_path).
+    ///
+    /// ```rust,no_run
+    /// use aivory_monitor::request::normalize_path;
+    /// use aivory_monitor::Config;
+    ///
+    /// let config = Config::builder()
+    ///     .transaction_normalizer(|path| match path.strip_prefix("/files/") {
+    ///         Some(_) => "/files/*".to_string(),
+    ///         None => normalize_path(path),
+    ///     })
+    ///     .build();
+    /// ```
+    pub fn transaction_normalizer(
+        mut self,
+        normalizer: impl Fn(&str) -> String + Send + Sync + 'static,
+    ) -> Self {
+        self.config.transaction_normalizer = Some(Arc::new(normalizer));
+        self
+    }
+
+    /// Validates the settings and returns the configuration.
+    pub fn build(self) -> Result<Config, ConfigError> {
+        self.config.validate()?;
+        Ok(self.config)
+    }
+}
+
+impl Default for ConfigBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,61 @@
+//! Environment auto-detection.
+
+use serde::Serialize;
+use std::env;
+
+/// Where the configured environment name came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EnvironmentSource {
+    /// Set explicitly via builder, configuration file or `AIVORY_ENVIRONMENT`.
+    Explicit,
+    /// `CI` is set, as on most CI providers.
+    Ci,
+    /// `KUBERNETES_SERVICE_HOST` is set.
+    Kubernetes,
+    /// `DYNO` is set, as on Heroku.
+    Heroku,
+    /// The agent was compiled with debug assertions enabled.
+    DebugBuild,
+    /// No signal matched.
+    Default,
+}
+
+/// Result of environment auto-detection.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DetectedEnvironment {
+    /// Inferred environment name.
+    pub name: String,
+    /// Signal the name was inferred from.
+    pub source: EnvironmentSource,
+}
+
+/// Infers the environment name from well-known process signals.
+///
+/// Signals are checked in order: `CI` (`"ci"`), `KUBERNETES_SERVICE_HOST`
+/// and `DYNO` (`"production"`), a debug build (`"development"`), and
+/// finally `"production"`.
+pub fn detect_environment() -> DetectedEnvironment {
+    let (name, source) = if is_truthy("CI") {
+        ("ci", EnvironmentSource::Ci)
+    } else if env::var_os("KUBERNETES_SERVICE_HOST").is_some() {
+        ("production", EnvironmentSource::Kubernetes)
+    } else if env::var_os("DYNO").is_some() {
+        ("production", EnvironmentSource::Heroku)
+    } else if cfg!(debug_assertions) {
+        ("development", EnvironmentSource::DebugBuild)
+    } else {
+        ("production", EnvironmentSource::Default)
+    };
+
+    DetectedEnvironment {
+        name: name.to_string(),
+        source,
+    }
+}
+
+fn is_truthy(key: &str) -> bool {
+    env::var(key)
+        .map(|v| !v.is_empty() && v != "0" && !v.eq_ignore_ascii_case("false"))
+        .unwrap_or(false)
+}
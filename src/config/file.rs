@@ -0,0 +1,311 @@
+//! Configuration file loading.
+
+use super::{Config, ConfigError, EnvironmentSource, HostnameMode, PathMapping, ProjectRoute};
+use crate::breadcrumbs::BreadcrumbLevel;
+use crate::capture::{FingerprintVersion, PanicBacktrace};
+use crate::logger::LogLevel;
+use crate::request::{ClientIpMode, StatusClass};
+use crate::sampling::SamplingMode;
+use crate::transport::TransportMode;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// On-disk representation of the agent configuration.
+///
+/// Every key is optional so a file only needs to list what it overrides.
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(super) struct FileConfig {
+    api_key: Option<String>,
+    backend_url: Option<String>,
+    fallback_urls: Option<Vec<String>>,
+    transport: Option<TransportMode>,
+    http_timeout_ms: Option<u64>,
+    ping_interval_ms: Option<u64>,
+    liveness_timeout_ms: Option<u64>,
+    heartbeat_interval_ms: Option<u64>,
+    dry_run: Option<bool>,
+    audit_log: Option<PathBuf>,
+    environment: Option<String>,
+    sampling_rate: Option<f64>,
+    target_events_per_minute: Option<u32>,
+    sampling_mode: Option<SamplingMode>,
+    aggregation_interval_ms: Option<u64>,
+    detect_spikes: Option<bool>,
+    circuit_breaker_threshold: Option<u32>,
+    circuit_breaker_cooldown_ms: Option<u64>,
+    max_bytes_per_second: Option<u64>,
+    max_capture_depth: Option<usize>,
+    max_string_length: Option<usize>,
+    max_collection_size: Option<usize>,
+    max_context_keys: Option<usize>,
+    max_breadcrumbs: Option<usize>,
+    min_breadcrumb_level: Option<BreadcrumbLevel>,
+    breadcrumb_levels: Option<BTreeMap<String, BreadcrumbLevel>>,
+    ignore_breadcrumb_categories: Option<Vec<String>>,
+    debug: Option<bool>,
+    log_level: Option<LogLevel>,
+    hostname: Option<String>,
+    hostname_mode: Option<HostnameMode>,
+    service_name: Option<String>,
+    server_name: Option<String>,
+    agent_id: Option<String>,
+    persist_agent_id: Option<bool>,
+    state_dir: Option<PathBuf>,
+    spool_encryption_key: Option<String>,
+    spool_encryption_keychain: Option<bool>,
+    panic_backtrace: Option<PanicBacktrace>,
+    panic_flush_timeout_ms: Option<u64>,
+    capture_all_threads: Option<bool>,
+    report_os_crashes: Option<bool>,
+    frame_skip_prefixes: Option<Vec<String>>,
+    keep_filtered_frames: Option<bool>,
+    fingerprint_version: Option<FingerprintVersion>,
+    path_prefix_map: Option<Vec<PathMapping>>,
+    project_routes: Option<Vec<ProjectRoute>>,
+    scrub_home_dir: Option<bool>,
+    capture_env_vars: Option<Vec<String>>,
+    capture_command_line: Option<bool>,
+    capture_status_classes: Option<Vec<StatusClass>>,
+    capture_client_ip: Option<ClientIpMode>,
+    capture_vcs_diff: Option<bool>,
+    max_vcs_diff_bytes: Option<usize>,
+    build_id: Option<String>,
+    #[cfg(feature = "source-upload")]
+    upload_sources: Option<bool>,
+    #[cfg(feature = "source-upload")]
+    source_root: Option<PathBuf>,
+    #[cfg(feature = "source-upload")]
+    source_exclude: Option<Vec<String>>,
+    #[cfg(feature = "journald")]
+    journald: Option<bool>,
+    #[cfg(feature = "shutdown-hooks")]
+    shutdown_timeout_ms: Option<u64>,
+    #[cfg(feature = "shutdown-hooks")]
+    exit_on_signal: Option<bool>,
+    /// Per-environment overrides, keyed by environment name.
+    #[serde(default)]
+    pub(super) environments: HashMap<String, FileConfig>,
+}
+
+impl FileConfig {
+    /// Reads and parses a configuration file, picking the format from its extension.
+    pub(super) fn load(path: &Path) -> Result<Self, ConfigError> {
+        let contents = std::fs::read_to_string(path).map_err(|source| ConfigError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+
+        let parse_error = |message: String| ConfigError::Parse {
+            path: path.to_path_buf(),
+            message,
+        };
+
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => toml::from_str(&contents).map_err(|e| parse_error(e.to_string())),
+            #[cfg(feature = "yaml")]
+            Some("yaml") | Some("yml") => {
+                serde_yaml::from_str(&contents).map_err(|e| parse_error(e.to_string()))
+            }
+            _ => Err(ConfigError::UnsupportedFormat(path.to_path_buf())),
+        }
+    }
+
+    /// Applies the keys present in this file (or section) to `config`.
+    pub(super) fn apply(&self, config: &mut Config) {
+        if let Some(api_key) = &self.api_key {
+            config.api_key = api_key.clone();
+        }
+        if let Some(url) = &self.backend_url {
+            config.backend_url = url.clone();
+        }
+        if let Some(urls) = &self.fallback_urls {
+            config.fallback_urls = urls.clone();
+        }
+        if let Some(transport) = self.transport {
+            config.transport = transport;
+        }
+        if let Some(timeout) = self.http_timeout_ms {
+            config.http_timeout = Duration::from_millis(timeout);
+        }
+        if let Some(ms) = self.ping_interval_ms {
+            config.ping_interval = Duration::from_millis(ms);
+        }
+        if let Some(ms) = self.liveness_timeout_ms {
+            config.liveness_timeout = Duration::from_millis(ms);
+        }
+        if let Some(ms) = self.heartbeat_interval_ms {
+            config.heartbeat_interval =
+                Some(Duration::from_millis(ms)).filter(|interval| !interval.is_zero());
+        }
+        if let Some(dry_run) = self.dry_run {
+            config.dry_run = dry_run;
+        }
+        if let Some(path) = &self.audit_log {
+            config.audit_log = Some(path.clone());
+        }
+        if let Some(environment) = &self.environment {
+            config.environment = environment.clone();
+            config.environment_source = EnvironmentSource::Explicit;
+        }
+        if let Some(rate) = self.sampling_rate {
+            config.sampling_rate = rate;
+        }
+        if let Some(target) = self.target_events_per_minute {
+            config.target_events_per_minute = Some(target);
+        }
+        if let Some(mode) = self.sampling_mode {
+            config.sampling_mode = mode;
+        }
+        if let Some(ms) = self.aggregation_interval_ms {
+            config.aggregation_interval = Some(Duration::from_millis(ms));
+        }
+        if let Some(detect) = self.detect_spikes {
+            config.detect_spikes = detect;
+        }
+        if let Some(threshold) = self.circuit_breaker_threshold {
+            config.circuit_breaker_threshold = threshold;
+        }
+        if let Some(ms) = self.circuit_breaker_cooldown_ms {
+            config.circuit_breaker_cooldown = Duration::from_millis(ms);
+        }
+        if let Some(limit) = self.max_bytes_per_second {
+            config.max_bytes_per_second = Some(limit);
+        }
+        if let Some(depth) = self.max_capture_depth {
+            config.max_capture_depth = depth;
+        }
+        if let Some(len) = self.max_string_length {
+            config.max_string_length = len;
+        }
+        if let Some(size) = self.max_collection_size {
+            config.max_collection_size = size;
+        }
+        if let Some(max) = self.max_context_keys {
+            config.max_context_keys = max;
+        }
+        if let Some(max) = self.max_breadcrumbs {
+            config.max_breadcrumbs = max;
+        }
+        if let Some(level) = self.min_breadcrumb_level {
+            config.min_breadcrumb_level = level;
+        }
+        if let Some(levels) = &self.breadcrumb_levels {
+            config.breadcrumb_levels = levels.clone();
+        }
+        if let Some(categories) = &self.ignore_breadcrumb_categories {
+            config.ignore_breadcrumb_categories = categories.clone();
+        }
+        if let Some(debug) = self.debug {
+            config.debug = debug;
+        }
+        if let Some(level) = self.log_level {
+            config.log_level = level;
+        }
+        if let Some(hostname) = &self.hostname {
+            config.hostname = hostname.clone();
+        }
+        if let Some(mode) = self.hostname_mode {
+            config.hostname_mode = mode;
+        }
+        if let Some(service_name) = &self.service_name {
+            config.service_name = Some(service_name.clone());
+        }
+        if let Some(server_name) = &self.server_name {
+            config.server_name = Some(server_name.clone());
+        }
+        if let Some(agent_id) = &self.agent_id {
+            config.agent_id = agent_id.clone();
+            config.agent_id_explicit = true;
+        }
+        if let Some(persist) = self.persist_agent_id {
+            config.persist_agent_id = persist;
+        }
+        if let Some(dir) = &self.state_dir {
+            config.state_dir = Some(dir.clone());
+        }
+        if let Some(key) = &self.spool_encryption_key {
+            config.spool_encryption_key = Some(key.clone());
+        }
+        if let Some(keychain) = self.spool_encryption_keychain {
+            config.spool_encryption_keychain = keychain;
+        }
+        if let Some(mode) = self.panic_backtrace {
+            config.panic_backtrace = mode;
+        }
+        if let Some(ms) = self.panic_flush_timeout_ms {
+            config.panic_flush_timeout = Duration::from_millis(ms);
+        }
+        if let Some(capture) = self.capture_all_threads {
+            config.capture_all_threads = capture;
+        }
+        if let Some(report) = self.report_os_crashes {
+            config.report_os_crashes = report;
+        }
+        if let Some(prefixes) = &self.frame_skip_prefixes {
+            config.frame_skip_prefixes = prefixes.clone();
+        }
+        if let Some(keep) = self.keep_filtered_frames {
+            config.keep_filtered_frames = keep;
+        }
+        if let Some(version) = self.fingerprint_version {
+            config.fingerprint_version = version;
+        }
+        if let Some(map) = &self.path_prefix_map {
+            config.path_prefix_map = map.clone();
+        }
+        if let Some(routes) = &self.project_routes {
+            config.project_routes = routes.clone();
+        }
+        if let Some(scrub) = self.scrub_home_dir {
+            config.scrub_home_dir = scrub;
+        }
+        if let Some(names) = &self.capture_env_vars {
+            config.capture_env_vars = names.clone();
+        }
+        if let Some(capture) = self.capture_command_line {
+            config.capture_command_line = capture;
+        }
+        if let Some(classes) = &self.capture_status_classes {
+            config.capture_status_classes = classes.clone();
+        }
+        if let Some(mode) = self.capture_client_ip {
+            config.capture_client_ip = mode;
+        }
+        if let Some(capture) = self.capture_vcs_diff {
+            config.capture_vcs_diff = capture;
+        }
+        if let Some(bytes) = self.max_vcs_diff_bytes {
+            config.max_vcs_diff_bytes = bytes;
+        }
+        if let Some(build_id) = &self.build_id {
+            config.build_id = Some(build_id.clone());
+        }
+        #[cfg(feature = "source-upload")]
+        if let Some(upload) = self.upload_sources {
+            config.upload_sources = upload;
+        }
+        #[cfg(feature = "source-upload")]
+        if let Some(root) = &self.source_root {
+            config.source_root = Some(root.clone());
+        }
+        #[cfg(feature = "source-upload")]
+        if let Some(patterns) = &self.source_exclude {
+            config.source_exclude = patterns.clone();
+        }
+        #[cfg(feature = "journald")]
+        if let Some(journald) = self.journald {
+            config.journald = journald;
+        }
+        #[cfg(feature = "shutdown-hooks")]
+        if let Some(ms) = self.shutdown_timeout_ms {
+            config.shutdown_timeout = Duration::from_millis(ms);
+        }
+        #[cfg(feature = "shutdown-hooks")]
+        if let Some(exit) = self.exit_on_signal {
+            config.exit_on_signal = exit;
+        }
+    }
+}
@@ -0,0 +1,272 @@
+//! Persisted agent identity.
+
+use super::Config;
+use aivory_monitor_core::hash_fingerprint;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+/// How the hostname is reported to the backend.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HostnameMode {
+    /// The hostname as is.
+    #[default]
+    Plain,
+    /// `host-` and the first 16 hex digits of the hostname's SHA-256. Stable
+    /// per machine, but anyone who knows the hostname can compute it.
+    Hashed,
+    /// A random `host-` alias, generated once and stored in the state
+    /// directory, that reveals nothing about the hostname. Falls back to
+    /// [`Hashed`](HostnameMode::Hashed) if the alias can't be stored.
+    Alias,
+}
+
+impl std::str::FromStr for HostnameMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "plain" => Ok(HostnameMode::Plain),
+            "hashed" | "hash" => Ok(HostnameMode::Hashed),
+            "alias" => Ok(HostnameMode::Alias),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Contents of the agent state file.
+#[derive(Serialize, Deserialize)]
+struct AgentState {
+    agent_id: String,
+    #[serde(default)]
+    incarnation: u64,
+}
+
+/// Generates a fresh, random agent ID.
+pub(super) fn generate_agent_id() -> String {
+    format!(
+        "agent-{}-{}",
+        hex::encode(&chrono::Utc::now().timestamp().to_be_bytes()[4..]),
+        &Uuid::new_v4().to_string()[..8]
+    )
+}
+
+/// Returns the platform-appropriate directory for agent state.
+///
+/// `$XDG_STATE_HOME/aivory` (or `~/.local/state/aivory`) on Linux,
+/// `~/Library/Application Support/aivory` on macOS and
+/// `%LOCALAPPDATA%\aivory` on Windows, falling back to the temp directory.
+pub fn default_state_dir() -> PathBuf {
+    user_state_dir().unwrap_or_else(|| env::temp_dir().join("aivory"))
+}
+
+/// [`default_state_dir`] without the fallback to the temp directory, which
+/// other local users can read and write to.
+pub(crate) fn user_state_dir() -> Option<PathBuf> {
+    let base = if cfg!(target_os = "windows") {
+        env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        env::var_os("HOME").map(|h| PathBuf::from(h).join("Library").join("Application Support"))
+    } else {
+        env::var_os("XDG_STATE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|h| PathBuf::from(h).join(".local").join("state")))
+    };
+
+    base.map(|base| base.join("aivory"))
+}
+
+/// Creates the state directory `dir` and its missing parents, accessible to
+/// the owner only, as the files in it describe the process and its events.
+pub(crate) fn create_state_dir(dir: &Path) -> io::Result<()> {
+    let mut builder = std::fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(dir)
+}
+
+/// Opens `path` with `options`, creating it readable by the owner only.
+pub(crate) fn open_private(options: &mut OpenOptions, path: &Path) -> io::Result<File> {
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(options, 0o600);
+    options.open(path)
+}
+
+/// Replaces the file at `path` with `contents`, readable by the owner only.
+///
+/// The contents go to a new file with a random name first, so readers never
+/// see a partial file and no other process can have planted it.
+pub(crate) fn write_private(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let tmp = path.with_extension(format!("{}.tmp", Uuid::new_v4().simple()));
+    let written = open_private(OpenOptions::new().write(true).create_new(true), &tmp)
+        .and_then(|mut file| file.write_all(contents))
+        .and_then(|_| fs::rename(&tmp, path));
+    if written.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    written
+}
+
+/// Loads the persisted agent ID into `config` and bumps its incarnation counter.
+///
+/// The state file is keyed by executable name so that different programs on
+/// the same host keep distinct identities, while processes of the same one
+/// share its ID; a lock next to it keeps those starting together from
+/// counting the same incarnation. Any I/O failure leaves the generated ID in
+/// place; identity persistence never prevents startup.
+pub(crate) fn resolve(config: &mut Config) {
+    if !config.persist_agent_id {
+        return;
+    }
+
+    let Some(dir) = config.private_state_dir() else {
+        config.logger.debug(format_args!(
+            "No private state directory; the agent ID is not persisted"
+        ));
+        return;
+    };
+    let path = dir.join(state_file_name());
+    // Released when dropped, after the state is written.
+    let _lock = match lock_state(&dir, &path) {
+        Ok(lock) => Some(lock),
+        Err(e) => {
+            config
+                .logger
+                .debug(format_args!("Failed to lock {}: {}", path.display(), e));
+            None
+        }
+    };
+
+    let previous = read_state(&path);
+    let state = match previous {
+        Some(state) if !config.agent_id_explicit || state.agent_id == config.agent_id => {
+            AgentState {
+                agent_id: state.agent_id,
+                incarnation: state.incarnation + 1,
+            }
+        }
+        _ => AgentState {
+            agent_id: config.agent_id.clone(),
+            incarnation: 1,
+        },
+    };
+
+    if let Err(e) = write_state(&dir, &path, &state) {
+        config.logger.debug(format_args!(
+            "Failed to persist agent ID to {}: {}",
+            path.display(),
+            e
+        ));
+    }
+
+    config.agent_id = state.agent_id;
+    config.incarnation = state.incarnation;
+}
+
+/// Replaces the hostname as `hostname_mode` asks, before it is reported.
+pub(crate) fn anonymize_hostname(config: &mut Config) {
+    match config.hostname_mode {
+        HostnameMode::Plain => {}
+        HostnameMode::Hashed => config.hostname = hashed_hostname(&config.hostname),
+        HostnameMode::Alias => {
+            let alias = match config.private_state_dir() {
+                Some(dir) => host_alias(&dir).map_err(|e| format!("{}: {}", dir.display(), e)),
+                None => Err("no private state directory".to_string()),
+            };
+            config.hostname = match alias {
+                Ok(alias) => alias,
+                Err(e) => {
+                    config
+                        .logger
+                        .debug(format_args!("Failed to store host alias in {}", e));
+                    hashed_hostname(&config.hostname)
+                }
+            };
+        }
+    }
+}
+
+fn hashed_hostname(hostname: &str) -> String {
+    format!("host-{}", hash_fingerprint(&[hostname.to_string()]))
+}
+
+/// Returns the host alias stored in `dir`, creating it on first use.
+fn host_alias(dir: &Path) -> io::Result<String> {
+    let path = dir.join("host-alias");
+    if let Ok(alias) = std::fs::read_to_string(&path) {
+        if !alias.trim().is_empty() {
+            return Ok(alias.trim().to_string());
+        }
+    }
+
+    create_state_dir(dir)?;
+    let alias = format!("host-{}", &Uuid::new_v4().simple().to_string()[..16]);
+    // `create_new` so that processes starting together agree on one alias.
+    match open_private(OpenOptions::new().write(true).create_new(true), &path) {
+        Ok(mut file) => match file.write_all(alias.as_bytes()) {
+            Ok(()) => Ok(alias),
+            Err(e) => {
+                // An empty file would leave everyone else without an alias.
+                let _ = fs::remove_file(&path);
+                Err(e)
+            }
+        },
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+            // Created by another process that may not have written it yet.
+            for _ in 0..50 {
+                let alias = fs::read_to_string(&path)?;
+                if !alias.trim().is_empty() {
+                    return Ok(alias.trim().to_string());
+                }
+                std::thread::sleep(std::time::Duration::from_millis(10));
+            }
+            Err(io::Error::new(
+                io::ErrorKind::Other,
+                "host alias file is empty",
+            ))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+fn state_file_name() -> String {
+    format!("agent-{}.json", exe_name())
+}
+
+/// Name of the running executable, which keys per-program state files.
+pub(crate) fn exe_name() -> String {
+    env::current_exe()
+        .ok()
+        .and_then(|p| p.file_stem().map(|s| s.to_string_lossy().to_string()))
+        .unwrap_or_else(|| "default".to_string())
+}
+
+/// Takes the lock on the state file at `path`, waiting for other processes
+/// to release it. The state file itself is replaced on every write, so the
+/// lock is held on a file beside it.
+fn lock_state(dir: &Path, path: &Path) -> io::Result<File> {
+    use fs2::FileExt;
+    create_state_dir(dir)?;
+    let lock = open_private(
+        OpenOptions::new().write(true).create(true),
+        &path.with_extension("lock"),
+    )?;
+    lock.lock_exclusive()?;
+    Ok(lock)
+}
+
+fn read_state(path: &Path) -> Option<AgentState> {
+    let contents = std::fs::read_to_string(path).ok()?;
+    serde_json::from_str(&contents).ok()
+}
+
+fn write_state(dir: &Path, path: &Path, state: &AgentState) -> std::io::Result<()> {
+    create_state_dir(dir)?;
+    let json = serde_json::to_string(state)?;
+    write_private(path, json.as_bytes())
+}
@@ -0,0 +1,141 @@
+//! Instrumentation for message queue consumers.
+//!
+//! [`process_message`] runs a handler inside a [`Scope`] carrying the
+//! message's queue, partition and offset, so handler errors and panics are
+//! reported with that metadata under the `messaging` context key.
+//!
+//! ```rust,no_run
+//! use aivory_monitor::consumer::{self, MessageContext};
+//!
+//! # fn handle(body: &[u8]) -> Result<(), std::io::Error> { Ok(()) }
+//! # let body = b"";
+//! let message = MessageContext::new("kafka", "orders").partition(3).offset(1042);
+//! consumer::process_message(message, || handle(body))?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use crate::scope::{self, Scope};
+use serde::Serialize;
+use std::future::Future;
+
+/// Metadata of a consumed message.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct MessageContext {
+    /// Messaging system, e.g. `kafka` or `rabbitmq`.
+    pub system: String,
+    /// Queue or topic the message was consumed from.
+    pub destination: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub partition: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub routing_key: Option<String>,
+    /// Whether the broker has delivered this message before.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub redelivered: Option<bool>,
+}
+
+impl MessageContext {
+    /// Creates metadata for a message from `destination` on `system`.
+    pub fn new(system: impl Into<String>, destination: impl Into<String>) -> Self {
+        MessageContext {
+            system: system.into(),
+            destination: destination.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Sets the partition.
+    pub fn partition(mut self, partition: i32) -> Self {
+        self.partition = Some(partition);
+        self
+    }
+
+    /// Sets the offset within the partition.
+    pub fn offset(mut self, offset: i64) -> Self {
+        self.offset = Some(offset);
+        self
+    }
+
+    /// Sets the message ID.
+    pub fn message_id(mut self, id: impl Into<String>) -> Self {
+        self.message_id = Some(id.into());
+        self
+    }
+
+    /// Sets the routing key.
+    pub fn routing_key(mut self, key: impl Into<String>) -> Self {
+        self.routing_key = Some(key.into());
+        self
+    }
+
+    /// Sets whether the message was redelivered.
+    pub fn redelivered(mut self, redelivered: bool) -> Self {
+        self.redelivered = Some(redelivered);
+        self
+    }
+
+    /// Creates metadata from a Kafka message.
+    #[cfg(feature = "rdkafka")]
+    pub fn from_kafka<M: rdkafka::Message>(message: &M) -> Self {
+        MessageContext::new("kafka", message.topic())
+            .partition(message.partition())
+            .offset(message.offset())
+    }
+
+    /// Creates metadata from an AMQP delivery consumed from `queue`.
+    #[cfg(feature = "lapin")]
+    pub fn from_amqp(queue: &str, delivery: &lapin::message::Delivery) -> Self {
+        let mut context = MessageContext::new("rabbitmq", queue)
+            .routing_key(delivery.routing_key.as_str())
+            .redelivered(delivery.redelivered);
+        if let Some(id) = delivery.properties.message_id() {
+            context.message_id = Some(id.to_string());
+        }
+        context
+    }
+
+    fn scope(&self) -> Scope {
+        let value = serde_json::to_value(self).unwrap_or_default();
+        Scope::new().context("messaging", value)
+    }
+}
+
+/// Runs a message handler, reporting its error or panic with the message metadata.
+///
+/// The handler's result is returned unchanged; panics are reported by the
+/// panic hook and then continue to unwind.
+pub fn process_message<T, E>(
+    message: MessageContext,
+    handler: impl FnOnce() -> Result<T, E>,
+) -> Result<T, E>
+where
+    E: std::error::Error,
+{
+    scope::with_scope(message.scope(), || {
+        let result = handler();
+        if let Err(e) = &result {
+            crate::capture_error(e);
+        }
+        result
+    })
+}
+
+/// Async version of [`process_message`].
+pub async fn process_message_async<T, E, F>(message: MessageContext, handler: F) -> Result<T, E>
+where
+    E: std::error::Error,
+    F: Future<Output = Result<T, E>>,
+{
+    scope::scoped(message.scope(), async move {
+        let result = handler.await;
+        if let Err(e) = &result {
+            crate::capture_error(e);
+        }
+        result
+    })
+    .await
+}
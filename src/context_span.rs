@@ -0,0 +1,224 @@
+//! Trails of the awaited operations an error passed through.
+//!
+//! Backtraces of async code mostly show the executor: the frames of the
+//! `async fn`s an error came from are gone once it is returned. Marking the
+//! operations worth knowing about with [`ContextSpanExt::context_span`]
+//! records, for each one that resolves to an error, its name, how long it
+//! was awaited and where, as the error propagates outwards. Events capturing
+//! the error carry the operations as `await_trail`, innermost first:
+//!
+//! ```rust,no_run
+//! use aivory_monitor::context_span::ContextSpanExt;
+//!
+//! # struct User;
+//! # async fn fetch_user(_id: u64) -> Result<User, std::io::Error> { unimplemented!() }
+//! # async fn charge(_user: &User) -> Result<(), std::io::Error> { unimplemented!() }
+//! async fn checkout(user_id: u64) -> Result<(), std::io::Error> {
+//!     let user = fetch_user(user_id).context_span("fetch_user").await?;
+//!     charge(&user).context_span("charge").await
+//! }
+//!
+//! # async fn run() {
+//! if let Err(e) = checkout(42).context_span("checkout").await {
+//!     aivory_monitor::capture_error(&e);
+//! }
+//! # }
+//! ```
+//!
+//! ```json
+//! "await_trail": [
+//!   { "operation": "fetch_user", "duration_ms": 812.4, "location": "src/checkout.rs:6" },
+//!   { "operation": "checkout", "duration_ms": 813.0, "location": "src/checkout.rs:12" }
+//! ]
+//! ```
+//!
+//! Errors keep their type, so `?` works as before. An operation that fails
+//! while another is awaited joins the outer one's trail, replacing any
+//! earlier failure in it that was recovered from; an operation that completes
+//! successfully discards its trail. The trail of an outermost operation is
+//! matched to an event by the error's `Display` output, which must equal the
+//! event's message or that of one of its causes, the most recent trail
+//! winning, and is included in one event at most. Trails no event claims
+//! within a minute are discarded.
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt::Display;
+use std::future::Future;
+use std::panic::Location;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+/// Trails kept for events still to be captured, oldest dropped first.
+const MAX_PENDING: usize = 64;
+
+/// How long a trail waits for an event capturing its error.
+const MAX_AGE: Duration = Duration::from_secs(60);
+
+/// Trails of outermost operations not yet claimed by an event.
+static PENDING: Mutex<VecDeque<Pending>> = Mutex::new(VecDeque::new());
+
+thread_local! {
+    /// Trails of the inner operations of the operations being polled on this
+    /// thread, innermost operation last.
+    static OPEN: RefCell<Vec<Vec<AwaitSpan>>> = const { RefCell::new(Vec::new()) };
+}
+
+struct Pending {
+    /// `Display` output of the error the operation resolved to.
+    message: String,
+    recorded_at: Instant,
+    trail: Vec<AwaitSpan>,
+}
+
+/// An awaited operation that resolved to an error, in an event's `await_trail`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AwaitSpan {
+    /// Name given to [`context_span`](ContextSpanExt::context_span).
+    pub operation: String,
+    /// Time from the first poll to the error.
+    pub duration_ms: f64,
+    /// Where the operation was awaited, as `file:line`.
+    pub location: String,
+}
+
+/// Adds [`context_span`](Self::context_span) to futures resolving to a `Result`.
+pub trait ContextSpanExt<T, E>: Future<Output = Result<T, E>> + Sized {
+    /// Records `operation`, the time it was awaited and this call's location
+    /// if the future resolves to an error, for the event capturing it.
+    #[track_caller]
+    fn context_span(self, operation: impl Into<String>) -> ContextSpan<Self> {
+        ContextSpan {
+            future: Box::pin(self),
+            operation: operation.into(),
+            location: Location::caller(),
+            started: None,
+            inner: Vec::new(),
+        }
+    }
+}
+
+impl<F, T, E: Display> ContextSpanExt<T, E> for F where F: Future<Output = Result<T, E>> {}
+
+/// Future returned by [`ContextSpanExt::context_span`].
+pub struct ContextSpan<F> {
+    future: Pin<Box<F>>,
+    operation: String,
+    location: &'static Location<'static>,
+    started: Option<Instant>,
+    /// Trail of the inner operation that failed last.
+    inner: Vec<AwaitSpan>,
+}
+
+impl<F, T, E> Future for ContextSpan<F>
+where
+    F: Future<Output = Result<T, E>>,
+    E: Display,
+{
+    type Output = Result<T, E>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let started = *self.started.get_or_insert_with(Instant::now);
+        let open = Open::enter(std::mem::take(&mut self.inner));
+        let poll = self.future.as_mut().poll(cx);
+        let mut trail = open.exit();
+        match &poll {
+            Poll::Ready(Err(error)) => {
+                trail.push(AwaitSpan {
+                    operation: std::mem::take(&mut self.operation),
+                    duration_ms: started.elapsed().as_secs_f64() * 1000.0,
+                    location: format!("{}:{}", self.location.file(), self.location.line()),
+                });
+                finish(error, trail);
+            }
+            Poll::Ready(Ok(_)) => {}
+            Poll::Pending => self.inner = trail,
+        }
+        poll
+    }
+}
+
+/// The trail of an operation's inner operations while it is polled, taken
+/// back when the poll returns or unwinds.
+struct Open {
+    depth: usize,
+}
+
+impl Open {
+    fn enter(inner: Vec<AwaitSpan>) -> Self {
+        let depth = OPEN
+            .try_with(|open| {
+                let mut open = open.borrow_mut();
+                open.push(inner);
+                open.len()
+            })
+            .unwrap_or(0);
+        Open { depth }
+    }
+
+    fn exit(self) -> Vec<AwaitSpan> {
+        let inner = OPEN
+            .try_with(|open| {
+                open.borrow_mut()
+                    .get_mut(self.depth.wrapping_sub(1))
+                    .map(std::mem::take)
+            })
+            .ok()
+            .flatten();
+        inner.unwrap_or_default()
+    }
+}
+
+impl Drop for Open {
+    fn drop(&mut self) {
+        let _ = OPEN.try_with(|open| open.borrow_mut().truncate(self.depth.saturating_sub(1)));
+    }
+}
+
+/// Hands the trail of a failed operation to the operation awaiting it, or
+/// keeps it for the event capturing `error` if there is none.
+fn finish(error: &impl Display, trail: Vec<AwaitSpan>) {
+    let mut trail = Some(trail);
+    let _ = OPEN.try_with(|open| {
+        if let Some(outer) = open.borrow_mut().last_mut() {
+            *outer = trail.take().unwrap_or_default();
+        }
+    });
+    let Some(trail) = trail else {
+        return;
+    };
+    let mut pending = PENDING.lock();
+    if pending.len() >= MAX_PENDING {
+        pending.pop_front();
+    }
+    pending.push_back(Pending {
+        message: error.to_string(),
+        recorded_at: Instant::now(),
+        trail,
+    });
+}
+
+/// Removes and returns the most recent trail whose error has the first of
+/// `messages` that any trail has.
+pub(crate) fn take_trail<'a>(messages: impl IntoIterator<Item = &'a str>) -> Vec<AwaitSpan> {
+    // Never wait, as this also runs in the panic hook.
+    let Some(mut pending) = PENDING.try_lock() else {
+        return Vec::new();
+    };
+    pending.retain(|entry| entry.recorded_at.elapsed() < MAX_AGE);
+    if pending.is_empty() {
+        return Vec::new();
+    }
+    for message in messages {
+        if let Some(index) = pending.iter().rposition(|entry| entry.message == message) {
+            return pending
+                .remove(index)
+                .map(|entry| entry.trail)
+                .unwrap_or_default();
+        }
+    }
+    Vec::new()
+}
@@ -0,0 +1,109 @@
+//! Check-ins for cron and other scheduled jobs.
+//!
+//! A job reports when it starts and how it finished, so the backend can
+//! alert on runs that fail, hang or never happen, even when no exception
+//! is thrown.
+//!
+//! ```rust,no_run
+//! use aivory_monitor::cron::{self, Status};
+//! use std::time::Instant;
+//!
+//! let started = Instant::now();
+//! let id = cron::check_in("nightly-report", Status::Start, None);
+//! // ... run the job ...
+//! cron::check_in_with_id(id, "nightly-report", Status::Ok, Some(started.elapsed()));
+//! ```
+
+use crate::clock;
+use serde::Serialize;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// State of a job run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    /// The job started.
+    Start,
+    /// The job finished successfully.
+    Ok,
+    /// The job failed.
+    Error,
+}
+
+/// Payload of a `check_in` message.
+#[derive(Clone, Debug, Serialize)]
+pub struct CheckIn {
+    /// Shared by the start and finish check-ins of one run.
+    pub check_in_id: String,
+    /// Name of the monitored job.
+    pub monitor: String,
+    pub status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    pub environment: String,
+    pub agent_id: String,
+    pub timestamp: String,
+}
+
+impl CheckIn {
+    pub(crate) fn new(
+        check_in_id: String,
+        monitor: &str,
+        status: Status,
+        duration: Option<Duration>,
+        config: &crate::Config,
+    ) -> Self {
+        CheckIn {
+            check_in_id,
+            monitor: monitor.to_string(),
+            status,
+            duration_ms: duration.map(|d| d.as_millis() as u64),
+            environment: config.environment.clone(),
+            agent_id: config.agent_id.clone(),
+            timestamp: clock::now().to_rfc3339(),
+        }
+    }
+}
+
+/// Sends a check-in for `monitor` using the global agent and returns its ID.
+///
+/// Pass the ID returned for [`Status::Start`] to [`check_in_with_id`] when
+/// the run finishes so the backend can pair the two.
+pub fn check_in(monitor: &str, status: Status, duration: Option<Duration>) -> String {
+    check_in_with_id(Uuid::new_v4().to_string(), monitor, status, duration)
+}
+
+/// Sends a check-in for an existing run using the global agent.
+pub fn check_in_with_id(
+    check_in_id: String,
+    monitor: &str,
+    status: Status,
+    duration: Option<Duration>,
+) -> String {
+    if let Some(client) = crate::client() {
+        client.check_in_with_id(check_in_id.clone(), monitor, status, duration);
+    }
+    check_in_id
+}
+
+/// Runs `job`, reporting its start, outcome and duration under `monitor`.
+///
+/// ```rust,no_run
+/// let rows = aivory_monitor::cron::monitor("nightly-report", || {
+///     // ... run the job ...
+///     Ok::<_, std::io::Error>(42)
+/// });
+/// ```
+pub fn monitor<T, E>(monitor: &str, job: impl FnOnce() -> Result<T, E>) -> Result<T, E> {
+    let started = Instant::now();
+    let id = check_in(monitor, Status::Start, None);
+    let result = job();
+    let status = if result.is_ok() {
+        Status::Ok
+    } else {
+        Status::Error
+    };
+    check_in_with_id(id, monitor, status, Some(started.elapsed()));
+    result
+}
@@ -0,0 +1,388 @@
+//! Agent health counters.
+//!
+//! The transport and capture paths record into a shared [`Diagnostics`]
+//! instance; [`crate::diagnostics()`] returns a point-in-time
+//! [`AgentDiagnostics`] snapshot that applications can expose on their own
+//! admin or health endpoints.
+
+use crate::audit::{AuditLog, Outcome};
+use crate::latency::Latency;
+use once_cell::sync::OnceCell;
+use parking_lot::RwLock;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// State of the backend connection.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionState {
+    /// Not connected, and not currently trying to connect.
+    #[default]
+    Disconnected,
+    /// Connecting or waiting to reconnect.
+    Connecting,
+    /// Connected to the backend.
+    Connected,
+    /// The backend rejected the API key; no further attempts are made.
+    AuthFailed,
+}
+
+impl ConnectionState {
+    /// Every connection state.
+    pub const ALL: [ConnectionState; 4] = [
+        ConnectionState::Disconnected,
+        ConnectionState::Connecting,
+        ConnectionState::Connected,
+        ConnectionState::AuthFailed,
+    ];
+
+    /// Returns the snake_case name used in serialized diagnostics.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ConnectionState::Disconnected => "disconnected",
+            ConnectionState::Connecting => "connecting",
+            ConnectionState::Connected => "connected",
+            ConnectionState::AuthFailed => "auth_failed",
+        }
+    }
+
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => ConnectionState::Connecting,
+            2 => ConnectionState::Connected,
+            3 => ConnectionState::AuthFailed,
+            _ => ConnectionState::Disconnected,
+        }
+    }
+
+    fn as_u8(self) -> u8 {
+        match self {
+            ConnectionState::Disconnected => 0,
+            ConnectionState::Connecting => 1,
+            ConnectionState::Connected => 2,
+            ConnectionState::AuthFailed => 3,
+        }
+    }
+}
+
+/// Why an event was not delivered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DropReason {
+    /// Discarded by sampling.
+    SampledOut,
+    /// No backend connection was available.
+    NotConnected,
+    /// The event could not be serialized.
+    SerializationFailed,
+    /// Writing to the WebSocket failed.
+    SendFailed,
+    /// Dropped from a full outbound queue to make room for a more urgent message.
+    QueueFull,
+    /// Not captured while the circuit breaker paused capture.
+    CircuitOpen,
+}
+
+impl DropReason {
+    /// Every drop reason, in counter order.
+    pub const ALL: [DropReason; 6] = [
+        DropReason::SampledOut,
+        DropReason::NotConnected,
+        DropReason::SerializationFailed,
+        DropReason::SendFailed,
+        DropReason::QueueFull,
+        DropReason::CircuitOpen,
+    ];
+
+    /// Returns the snake_case name used in serialized diagnostics.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DropReason::SampledOut => "sampled_out",
+            DropReason::NotConnected => "not_connected",
+            DropReason::SerializationFailed => "serialization_failed",
+            DropReason::SendFailed => "send_failed",
+            DropReason::QueueFull => "queue_full",
+            DropReason::CircuitOpen => "circuit_open",
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Point-in-time snapshot of agent health.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct AgentDiagnostics {
+    /// Current backend connection state.
+    pub connection_state: ConnectionState,
+    /// Messages waiting in the outbound queue.
+    pub queue_depth: usize,
+    /// Events written to the backend connection.
+    pub events_sent: u64,
+    /// Events not delivered, by reason. Reasons that never occurred are omitted.
+    pub events_dropped: BTreeMap<DropReason, u64>,
+    /// Most recent connection or backend error.
+    pub last_error: Option<String>,
+    /// Number of reconnect attempts since startup.
+    pub reconnect_count: u64,
+    /// Bytes written to the backend connection, including heartbeats.
+    pub bytes_sent: u64,
+    /// Whether capture is paused because the backend keeps failing.
+    pub circuit_open: bool,
+    /// Whether the backend has accepted the API key: it answered the
+    /// registration on the current WebSocket connection, or accepted a
+    /// request over HTTP.
+    pub registered: bool,
+}
+
+/// Time the application spent inside the agent capturing events.
+///
+/// Covers everything done on the caller's thread for a capture, from
+/// collecting the stack to handing the event to the transport; sending
+/// happens in the background and is not included. Captures skipped because
+/// the agent is disabled are not counted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct OverheadStats {
+    /// Captures measured, including those sampled out or held back by the
+    /// circuit breaker.
+    pub captures: u64,
+    /// Total time spent capturing, in microseconds.
+    pub total_us: u64,
+    /// Longest single capture, in microseconds.
+    pub max_us: u64,
+}
+
+impl OverheadStats {
+    /// Average time per capture.
+    pub fn mean(&self) -> Duration {
+        match self.captures {
+            0 => Duration::ZERO,
+            n => Duration::from_micros(self.total_us / n),
+        }
+    }
+
+    /// Longest single capture.
+    pub fn max(&self) -> Duration {
+        Duration::from_micros(self.max_us)
+    }
+
+    /// Whether the average capture took no longer than `budget`.
+    pub fn within_budget(&self, budget: Duration) -> bool {
+        self.mean() <= budget
+    }
+}
+
+/// Shared, lock-free counters behind [`AgentDiagnostics`].
+#[derive(Default)]
+pub struct Diagnostics {
+    state: AtomicU8,
+    queue_depth: AtomicUsize,
+    /// Queued messages dropped instead of written, which a flush reports.
+    lost: AtomicU64,
+    events_sent: AtomicU64,
+    dropped: [AtomicU64; DropReason::ALL.len()],
+    /// Values of `dropped` as of the last client report.
+    reported: [AtomicU64; DropReason::ALL.len()],
+    last_error: RwLock<Option<String>>,
+    reconnect_count: AtomicU64,
+    bytes_sent: AtomicU64,
+    circuit_open: AtomicBool,
+    registered: AtomicBool,
+    audit_log: OnceCell<AuditLog>,
+    overhead_captures: AtomicU64,
+    overhead_total_us: AtomicU64,
+    overhead_max_us: AtomicU64,
+    latency: Latency,
+}
+
+impl Diagnostics {
+    /// Creates zeroed counters.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the current connection state.
+    pub fn connection_state(&self) -> ConnectionState {
+        ConnectionState::from_u8(self.state.load(Ordering::Relaxed))
+    }
+
+    pub(crate) fn set_connection_state(&self, state: ConnectionState) {
+        self.state.store(state.as_u8(), Ordering::Relaxed);
+        if state != ConnectionState::Connected {
+            self.registered.store(false, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn set_registered(&self) {
+        self.registered.store(true, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_enqueued(&self) {
+        self.queue_depth.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_dequeued(&self) {
+        // Saturate rather than wrap if a dequeue races a reset.
+        let _ = self
+            .queue_depth
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |d| d.checked_sub(1));
+    }
+
+    /// Empties the queue depth counter when the connection goes away,
+    /// counting what was still queued as lost.
+    #[cfg_attr(target_os = "wasi", allow(dead_code))]
+    pub(crate) fn reset_queue_depth(&self) {
+        let depth = self.queue_depth.swap(0, Ordering::Relaxed);
+        self.lost.fetch_add(depth as u64, Ordering::Relaxed);
+    }
+
+    /// Records a queued message that left the queue without being written.
+    pub(crate) fn record_lost(&self) {
+        self.lost.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Number of queued messages dropped instead of written since startup.
+    pub(crate) fn lost(&self) -> u64 {
+        self.lost.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn record_sent(&self, bytes: usize, is_event: bool) {
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+        if is_event {
+            self.events_sent.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn record_drop(&self, reason: DropReason) {
+        self.dropped[reason.index()].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Drops recorded since the previous call, by reason, for the client
+    /// report sent with the next event.
+    pub(crate) fn take_unreported_drops(&self) -> BTreeMap<DropReason, u64> {
+        DropReason::ALL
+            .iter()
+            .filter_map(|&reason| {
+                let count = self.dropped[reason.index()].load(Ordering::Relaxed);
+                let reported = self.reported[reason.index()].swap(count, Ordering::Relaxed);
+                let unreported = count.saturating_sub(reported);
+                (unreported > 0).then_some((reason, unreported))
+            })
+            .collect()
+    }
+
+    pub(crate) fn record_error(&self, error: impl Into<String>) {
+        *self.last_error.write() = Some(error.into());
+    }
+
+    #[cfg_attr(target_os = "wasi", allow(dead_code))]
+    pub(crate) fn record_reconnect(&self) {
+        self.reconnect_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_circuit_open(&self, open: bool) {
+        self.circuit_open.store(open, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_audit_log(&self, log: AuditLog) {
+        let _ = self.audit_log.set(log);
+    }
+
+    /// Appends an event's outcome to the audit log, if one is open.
+    pub(crate) fn audit(&self, id: &str, fingerprint: Option<&str>, outcome: Outcome) {
+        if let Some(log) = self.audit_log.get() {
+            if let Err(e) = log.record(id, fingerprint, outcome) {
+                self.record_error(format!("audit log write failed: {}", e));
+            }
+        }
+    }
+
+    /// Starts timing a capture; the time is recorded when the guard drops.
+    pub(crate) fn time_capture(&self) -> CaptureTimer<'_> {
+        CaptureTimer {
+            diagnostics: self,
+            started: Instant::now(),
+        }
+    }
+
+    /// Stage durations of the ingest path since the last heartbeat.
+    pub(crate) fn latency(&self) -> &Latency {
+        &self.latency
+    }
+
+    /// Returns the time spent capturing so far.
+    pub fn overhead(&self) -> OverheadStats {
+        OverheadStats {
+            captures: self.overhead_captures.load(Ordering::Relaxed),
+            total_us: self.overhead_total_us.load(Ordering::Relaxed),
+            max_us: self.overhead_max_us.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Takes a snapshot of all counters.
+    pub fn snapshot(&self) -> AgentDiagnostics {
+        let events_dropped = DropReason::ALL
+            .iter()
+            .filter_map(|&reason| {
+                let count = self.dropped[reason.index()].load(Ordering::Relaxed);
+                (count > 0).then_some((reason, count))
+            })
+            .collect();
+
+        AgentDiagnostics {
+            connection_state: self.connection_state(),
+            queue_depth: self.queue_depth.load(Ordering::Relaxed),
+            events_sent: self.events_sent.load(Ordering::Relaxed),
+            events_dropped,
+            last_error: self.last_error.read().clone(),
+            reconnect_count: self.reconnect_count.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            circuit_open: self.circuit_open.load(Ordering::Relaxed),
+            registered: self.registered.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Records the duration of one capture into [`Diagnostics`] when dropped, so
+/// captures that return early are measured too.
+pub(crate) struct CaptureTimer<'a> {
+    diagnostics: &'a Diagnostics,
+    started: Instant,
+}
+
+impl Drop for CaptureTimer<'_> {
+    fn drop(&mut self) {
+        let elapsed = self.started.elapsed();
+        let us = elapsed.as_micros() as u64;
+        let diagnostics = self.diagnostics;
+        diagnostics.latency.captured(elapsed);
+        diagnostics
+            .overhead_captures
+            .fetch_add(1, Ordering::Relaxed);
+        diagnostics
+            .overhead_total_us
+            .fetch_add(us, Ordering::Relaxed);
+        diagnostics.overhead_max_us.fetch_max(us, Ordering::Relaxed);
+    }
+}
+
+/// Resident memory of the process in bytes, where the platform exposes it.
+#[cfg(target_os = "linux")]
+pub(crate) fn resident_memory_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let kb = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?;
+    let kb: u64 = kb.trim().trim_end_matches("kB").trim().parse().ok()?;
+    Some(kb * 1024)
+}
+
+/// Resident memory of the process in bytes, where the platform exposes it.
+#[cfg(not(target_os = "linux"))]
+#[cfg_attr(target_os = "wasi", allow(dead_code))]
+pub(crate) fn resident_memory_bytes() -> Option<u64> {
+    None
+}
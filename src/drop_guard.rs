@@ -0,0 +1,148 @@
+//! Panics in destructors.
+//!
+//! Cleanup code is a frequent source of secondary failures: flushing a
+//! buffer, rolling back a transaction or removing a temporary file in `Drop`
+//! can fail as well. A panic there is worse than elsewhere, as a destructor
+//! that panics while the thread is already unwinding from another panic
+//! aborts the process, losing the original panic and everything still queued.
+//!
+//! [`capture_on_drop_panic`] runs cleanup code and contains a panic in it:
+//! the panic is reported like any other, with the context values
+//! `in_drop: true` and whether the thread was `unwinding`, and then
+//! discarded instead of escaping the destructor. Errors captured from the
+//! cleanup code carry the same context.
+//!
+//! ```rust,no_run
+//! use aivory_monitor::drop_guard::capture_on_drop_panic;
+//!
+//! struct TempDir(std::path::PathBuf);
+//!
+//! impl Drop for TempDir {
+//!     fn drop(&mut self) {
+//!         capture_on_drop_panic(|| {
+//!             if let Err(e) = std::fs::remove_dir_all(&self.0) {
+//!                 aivory_monitor::capture_error(&e);
+//!             }
+//!         });
+//!     }
+//! }
+//! ```
+//!
+//! [`DropGuardExt::monitor_drop`] does the same for the whole destructor of
+//! a value whose type is not under the application's control:
+//!
+//! ```rust,no_run
+//! use aivory_monitor::drop_guard::DropGuardExt;
+//!
+//! # fn open_connection() -> std::net::TcpStream { unimplemented!() }
+//! let connection = open_connection().monitor_drop();
+//! connection.peer_addr().ok();
+//! ```
+
+use crate::capture::Mechanism;
+use crate::scope::{self, Scope};
+use std::fmt;
+use std::mem::ManuallyDrop;
+use std::ops::{Deref, DerefMut};
+use std::panic::{self, AssertUnwindSafe};
+
+/// Runs `f`, reporting a panic in it and returning `None` instead of letting
+/// the panic escape; meant for cleanup code in `Drop` implementations.
+///
+/// Events captured while `f` runs, including its panic, carry the context
+/// values `in_drop: true` and `unwinding`, which is true when a destructor
+/// runs because of another panic. A panic the panic hook did not report,
+/// for example because the application replaced the hook, is reported from
+/// its message with the `drop_guard` mechanism.
+pub fn capture_on_drop_panic<R>(f: impl FnOnce() -> R) -> Option<R> {
+    contain(drop_scope(), f)
+}
+
+/// Adds [`monitor_drop`](Self::monitor_drop) to every type.
+pub trait DropGuardExt: Sized {
+    /// Wraps this value so a panic in its destructor is reported and
+    /// contained; see [`capture_on_drop_panic`]. Events from the destructor
+    /// also carry the value's type as `dropped_type`.
+    fn monitor_drop(self) -> DropGuard<Self> {
+        DropGuard {
+            value: ManuallyDrop::new(self),
+        }
+    }
+}
+
+impl<T> DropGuardExt for T {}
+
+/// A value whose destructor runs through [`capture_on_drop_panic`], returned
+/// by [`DropGuardExt::monitor_drop`]. Dereferences to the value.
+pub struct DropGuard<T> {
+    value: ManuallyDrop<T>,
+}
+
+impl<T> DropGuard<T> {
+    /// Returns the value, which is then dropped without the guard.
+    pub fn into_inner(guard: Self) -> T {
+        let mut guard = ManuallyDrop::new(guard);
+        // SAFETY: the guard is never dropped, so the value is taken only once.
+        unsafe { ManuallyDrop::take(&mut guard.value) }
+    }
+}
+
+impl<T> Deref for DropGuard<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for DropGuard<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for DropGuard<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("DropGuard").field(&*self.value).finish()
+    }
+}
+
+impl<T> Drop for DropGuard<T> {
+    fn drop(&mut self) {
+        // SAFETY: the value is not accessed again after the guard's drop.
+        let value = unsafe { ManuallyDrop::take(&mut self.value) };
+        let scope = drop_scope().context("dropped_type", std::any::type_name::<T>());
+        contain(scope, move || drop(value));
+    }
+}
+
+fn drop_scope() -> Scope {
+    Scope::new()
+        .context("in_drop", true)
+        .context("unwinding", std::thread::panicking())
+}
+
+/// Runs `f` with `scope` active, reporting and discarding a panic in it.
+fn contain<R>(scope: Scope, f: impl FnOnce() -> R) -> Option<R> {
+    scope::with_scope(scope, || {
+        // Whether the panic this thread may be unwinding from was reported.
+        let outer_reported = crate::task::take_panic_reported();
+        let result = panic::catch_unwind(AssertUnwindSafe(f));
+        let reported = crate::task::take_panic_reported();
+        if outer_reported {
+            crate::task::mark_panic_reported();
+        }
+        let payload = match result {
+            Ok(value) => return Some(value),
+            Err(payload) => payload,
+        };
+        if !reported {
+            if let Some(client) = crate::hub::client() {
+                client.capture_panic_payload(&*payload, Mechanism::DropGuard);
+            }
+        }
+        // A payload whose own destructor panics must not escape either.
+        let _ = panic::catch_unwind(AssertUnwindSafe(move || drop(payload)));
+        None
+    })
+}
@@ -0,0 +1,90 @@
+//! Host-side bridge for crash records sent by devices over serial or RTT.
+//!
+//! Devices encode [`CrashRecord`]s with `aivory-monitor-core`'s `postcard`
+//! feature and write them to a UART or an RTT up channel. The host reads the
+//! link, decodes the frames and forwards the records through the agent.
+//!
+//! ```rust,no_run
+//! use std::fs::File;
+//!
+//! aivory_monitor::init(aivory_monitor::Config::default());
+//! let port = File::open("/dev/ttyACM0")?;
+//! aivory_monitor::embedded::forward(port)?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use crate::capture::CrashRecord;
+use std::io::{self, Read};
+
+pub use aivory_monitor_core::wire::{decode, encode, WireError, WIRE_VERSION};
+
+/// Frames longer than this are discarded, so a link that never sends a
+/// delimiter can't grow the buffer without bound.
+const MAX_FRAME_LEN: usize = 64 * 1024;
+
+/// Splits a byte stream into COBS frames and decodes them.
+#[derive(Debug, Default)]
+pub struct FrameDecoder {
+    buf: Vec<u8>,
+    overflowed: bool,
+}
+
+impl FrameDecoder {
+    /// Creates a decoder with an empty buffer.
+    pub fn new() -> Self {
+        FrameDecoder::default()
+    }
+
+    /// Feeds bytes read from the link and returns the frames they complete.
+    ///
+    /// A partial frame at the end is kept for the next call.
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<Result<CrashRecord, WireError>> {
+        let mut records = Vec::new();
+        for &byte in bytes {
+            if byte != 0 {
+                if self.buf.len() < MAX_FRAME_LEN {
+                    self.buf.push(byte);
+                } else {
+                    self.overflowed = true;
+                }
+                continue;
+            }
+
+            if !self.buf.is_empty() && !self.overflowed {
+                records.push(decode(&self.buf));
+            }
+            self.buf.clear();
+            self.overflowed = false;
+        }
+        records
+    }
+}
+
+/// Reads frames from `reader` until it ends and forwards each record to the
+/// global agent. Frames that fail to decode are logged and skipped.
+pub fn forward<R: Read>(mut reader: R) -> io::Result<()> {
+    let mut decoder = FrameDecoder::new();
+    let mut chunk = [0u8; 4096];
+    loop {
+        let read = match reader.read(&mut chunk) {
+            Ok(0) => return Ok(()),
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+
+        for record in decoder.push(&chunk[..read]) {
+            match record {
+                Ok(record) => crate::capture_event(record.into()),
+                Err(e) => {
+                    if let Some(client) = crate::client() {
+                        client
+                            .config()
+                            .logger
+                            .warn(format_args!("Dropped device frame: {}", e));
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,195 @@
+//! C ABI for embedding the agent in non-Rust hosts.
+//!
+//! Build the shared library with
+//! `cargo rustc --release --features cdylib --crate-type cdylib` and include
+//! `include/aivory_monitor.h`. Strings are NUL-terminated UTF-8 and are copied
+//! before the call returns; invalid UTF-8 is replaced rather than rejected.
+//!
+//! ```c
+//! #include "aivory_monitor.h"
+//!
+//! int main(void) {
+//!     if (aivory_init(NULL, "production") != 0) return 1;
+//!     aivory_set_user("user-123", NULL, NULL);
+//!     aivory_capture_message("PaymentError", "card declined");
+//!     aivory_flush(2000);
+//!     aivory_shutdown();
+//!     return 0;
+//! }
+//! ```
+
+use crate::logger::Logger;
+use crate::scope::{self, Scope};
+use crate::{Config, EventBuilder, User};
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::panic::{catch_unwind, UnwindSafe};
+use std::time::Duration;
+
+/// Exception type used when `aivory_capture_message` is given none.
+const DEFAULT_MESSAGE_TYPE: &str = "Message";
+
+/// Initializes the global agent.
+///
+/// `api_key` and `environment` may be NULL to use `AIVORY_API_KEY` and
+/// `AIVORY_ENVIRONMENT`; every other setting is read from `AIVORY_*`
+/// environment variables. Returns 0 on success and -1 if the configuration
+/// is invalid.
+///
+/// # Safety
+///
+/// Non-NULL arguments must point to NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn aivory_init(api_key: *const c_char, environment: *const c_char) -> c_int {
+    let api_key = string(api_key);
+    let environment = string(environment);
+    guard(-1, move || {
+        let mut builder = Config::builder();
+        if let Some(api_key) = api_key {
+            builder = builder.api_key(api_key);
+        }
+        if let Some(environment) = environment {
+            builder = builder.environment(environment);
+        }
+        match builder.build() {
+            Ok(config) => {
+                crate::init(config);
+                0
+            }
+            Err(e) => {
+                Logger::default().error(format_args!("Invalid configuration: {}", e));
+                -1
+            }
+        }
+    })
+}
+
+/// Captures an event with the given exception type and message.
+///
+/// `exception_type` may be NULL, in which case `"Message"` is used.
+///
+/// # Safety
+///
+/// Non-NULL arguments must point to NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn aivory_capture_message(
+    exception_type: *const c_char,
+    message: *const c_char,
+) {
+    let exception_type = string(exception_type).unwrap_or_else(|| DEFAULT_MESSAGE_TYPE.to_string());
+    let message = string(message).unwrap_or_default();
+    guard((), move || {
+        crate::capture_event(EventBuilder::new(exception_type).message(message));
+    })
+}
+
+/// Sets the user affected by subsequent events. Any argument may be NULL;
+/// passing NULL for all three clears the user.
+///
+/// # Safety
+///
+/// Non-NULL arguments must point to NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn aivory_set_user(
+    id: *const c_char,
+    email: *const c_char,
+    username: *const c_char,
+) {
+    let user = User {
+        id: string(id),
+        email: string(email),
+        username: string(username),
+        ..Default::default()
+    };
+    guard((), move || {
+        if user.is_empty() {
+            crate::clear_user();
+        } else {
+            crate::set_user(user);
+        }
+    })
+}
+
+/// Serializes the context active on the current thread, including that of
+/// Rust scopes, into a token for `aivory_scope_enter` in another module.
+///
+/// Returns NULL on failure; free the token with `aivory_string_free`.
+#[no_mangle]
+pub extern "C" fn aivory_scope_export() -> *mut c_char {
+    guard(std::ptr::null_mut(), || {
+        CString::new(scope::export_token()).map_or(std::ptr::null_mut(), CString::into_raw)
+    })
+}
+
+/// Makes the scope in `token`, from `aivory_scope_export` or
+/// `scope::export_token`, active on the current thread until
+/// `aivory_scope_exit`. Returns 0 on success and -1 if the token is invalid.
+///
+/// # Safety
+///
+/// `token` must be NULL or point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn aivory_scope_enter(token: *const c_char) -> c_int {
+    let token = string(token).unwrap_or_default();
+    guard(-1, move || match Scope::from_token(&token) {
+        Ok(entered) => {
+            scope::enter(entered);
+            0
+        }
+        Err(_) => -1,
+    })
+}
+
+/// Ends the scope entered last with `aivory_scope_enter` on the current
+/// thread. Returns 1 if one was active and 0 otherwise.
+#[no_mangle]
+pub extern "C" fn aivory_scope_exit() -> c_int {
+    guard(0, || c_int::from(scope::exit()))
+}
+
+/// Frees a string returned by the agent, such as a scope token. NULL is ignored.
+///
+/// # Safety
+///
+/// `ptr` must be NULL or a string returned by the agent that was not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn aivory_string_free(ptr: *mut c_char) {
+    if !ptr.is_null() {
+        drop(CString::from_raw(ptr));
+    }
+}
+
+/// Blocks until captured events are sent or `timeout_ms` elapses.
+///
+/// Returns 1 if all of them were sent, and 0 if some are left or were
+/// dropped meanwhile.
+#[no_mangle]
+pub extern "C" fn aivory_flush(timeout_ms: u32) -> c_int {
+    guard(0, move || {
+        c_int::from(crate::flush_blocking(Duration::from_millis(u64::from(
+            timeout_ms,
+        ))))
+    })
+}
+
+/// Shuts down the global agent. Call it from a thread not running a Tokio runtime.
+#[no_mangle]
+pub extern "C" fn aivory_shutdown() {
+    guard((), crate::shutdown)
+}
+
+/// Copies a nullable C string.
+///
+/// # Safety
+///
+/// `ptr` must be NULL or point to a NUL-terminated string.
+unsafe fn string(ptr: *const c_char) -> Option<String> {
+    if ptr.is_null() {
+        return None;
+    }
+    Some(CStr::from_ptr(ptr).to_string_lossy().into_owned())
+}
+
+/// Runs `f`, returning `fallback` instead of unwinding into the host.
+fn guard<T>(fallback: T, f: impl FnOnce() -> T + UnwindSafe) -> T {
+    catch_unwind(f).unwrap_or(fallback)
+}
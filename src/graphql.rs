@@ -0,0 +1,246 @@
+//! [async-graphql](https://docs.rs/async-graphql) integration.
+//!
+//! [`AivoryExtension`] reports resolver errors with the operation name, the
+//! query with its literal values removed, and the path of the failing field.
+//! With the `tracing` feature, every resolver also runs in a
+//! `graphql.resolve` span, so subscribers record per-field timings.
+//!
+//! ```rust,ignore
+//! use aivory_monitor::graphql::AivoryExtension;
+//!
+//! let schema = Schema::build(Query, EmptyMutation, EmptySubscription)
+//!     .extension(AivoryExtension)
+//!     .finish();
+//! ```
+
+use crate::capture::Mechanism;
+use crate::EventBuilder;
+use aivory_monitor_core::hash_fingerprint;
+use async_graphql::extensions::{
+    Extension, ExtensionContext, ExtensionFactory, NextExecute, NextParseQuery,
+};
+use async_graphql::parser::types::ExecutableDocument;
+use async_graphql::{PathSegment, Response, ServerError, ServerResult, Variables};
+use parking_lot::Mutex;
+use serde_json::json;
+use std::sync::Arc;
+
+#[cfg(feature = "tracing")]
+use async_graphql::extensions::{NextResolve, ResolveInfo};
+#[cfg(feature = "tracing")]
+use async_graphql::Value;
+#[cfg(feature = "tracing")]
+use tracing::Instrument;
+
+/// Exception type reported for resolver errors.
+const EXCEPTION_TYPE: &str = "GraphQLError";
+
+/// Schema extension that reports resolver errors to the global agent.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AivoryExtension;
+
+impl ExtensionFactory for AivoryExtension {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(RequestExtension::default())
+    }
+}
+
+/// Per-request state, created for each request by [`AivoryExtension`].
+#[derive(Default)]
+struct RequestExtension {
+    operation: Mutex<Operation>,
+}
+
+#[derive(Clone, Default)]
+struct Operation {
+    name: Option<String>,
+    query: String,
+}
+
+#[async_graphql::async_trait::async_trait]
+impl Extension for RequestExtension {
+    async fn parse_query(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        query: &str,
+        variables: &Variables,
+        next: NextParseQuery<'_>,
+    ) -> ServerResult<ExecutableDocument> {
+        let document = next.run(ctx, query, variables).await?;
+        let mut operation = self.operation.lock();
+        operation.query = sanitize_query(query);
+        // A document with a single named operation needs no `operationName`.
+        let mut names = document.operations.iter().map(|(name, _)| name);
+        if let (Some(Some(name)), None) = (names.next(), names.next()) {
+            operation.name = Some(name.to_string());
+        }
+        Ok(document)
+    }
+
+    async fn execute(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        operation_name: Option<&str>,
+        next: NextExecute<'_>,
+    ) -> Response {
+        let response = next.run(ctx, operation_name).await;
+        if !response.errors.is_empty() {
+            let mut operation = self.operation.lock().clone();
+            if let Some(name) = operation_name {
+                operation.name = Some(name.to_string());
+            }
+            for error in &response.errors {
+                crate::capture_event_with(|| error_event(error, &operation));
+            }
+        }
+        response
+    }
+
+    #[cfg(feature = "tracing")]
+    async fn resolve(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        info: ResolveInfo<'_>,
+        next: NextResolve<'_>,
+    ) -> ServerResult<Option<Value>> {
+        if info.is_for_introspection {
+            return next.run(ctx, info).await;
+        }
+        let span = tracing::info_span!(
+            target: "aivory_monitor::graphql",
+            "graphql.resolve",
+            path = %info.path_node,
+            parent_type = info.parent_type,
+            return_type = info.return_type,
+        );
+        next.run(ctx, info).instrument(span).await
+    }
+}
+
+fn error_event(error: &ServerError, operation: &Operation) -> EventBuilder {
+    let path = error
+        .path
+        .iter()
+        .map(|segment| match segment {
+            PathSegment::Field(name) => name.clone(),
+            PathSegment::Index(index) => index.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(".");
+    let locations: Vec<_> = error
+        .locations
+        .iter()
+        .map(|pos| json!({ "line": pos.line, "column": pos.column }))
+        .collect();
+
+    // Group by field rather than by list index, since there is no stack trace.
+    let mut parts = vec![EXCEPTION_TYPE.to_string()];
+    parts.extend(error.path.iter().filter_map(|segment| match segment {
+        PathSegment::Field(name) => Some(name.clone()),
+        PathSegment::Index(_) => None,
+    }));
+
+    EventBuilder::new(EXCEPTION_TYPE)
+        .message(error.message.clone())
+        .fingerprint(hash_fingerprint(&parts))
+        .mechanism(Mechanism::Middleware)
+        .context(
+            "graphql",
+            json!({
+                "operation_name": operation.name,
+                "query": operation.query,
+                "path": path,
+                "locations": locations,
+            }),
+        )
+}
+
+/// Replaces string and number literals in `query` and collapses whitespace,
+/// commas and comments, so arguments such as passwords or emails are not sent.
+fn sanitize_query(query: &str) -> String {
+    let mut out = String::with_capacity(query.len());
+    let mut chars = query.chars().peekable();
+    let mut separated = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '#' => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+                separated = true;
+                continue;
+            }
+            c if c.is_whitespace() || c == ',' || c == '\u{feff}' => {
+                separated = true;
+                continue;
+            }
+            _ => {}
+        }
+
+        if separated && !out.is_empty() {
+            out.push(' ');
+        }
+        let in_name = !separated && out.ends_with(|p: char| p.is_alphanumeric() || p == '_');
+        separated = false;
+
+        match c {
+            '"' => {
+                skip_string(&mut chars);
+                out.push_str("\"\"");
+            }
+            c if !in_name
+                && (c.is_ascii_digit()
+                    || (c == '-' && chars.peek().is_some_and(char::is_ascii_digit))) =>
+            {
+                while chars
+                    .peek()
+                    .is_some_and(|&c| c.is_ascii_alphanumeric() || matches!(c, '.' | '+' | '-'))
+                {
+                    chars.next();
+                }
+                out.push('0');
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Consumes the rest of a string literal whose opening quote was just read.
+fn skip_string(chars: &mut std::iter::Peekable<std::str::Chars<'_>>) {
+    let mut lookahead = chars.clone();
+    if lookahead.next() == Some('"') && lookahead.next() == Some('"') {
+        // Block string: runs to the next unescaped `"""`.
+        *chars = lookahead;
+        let mut quotes = 0;
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    quotes = 0;
+                }
+                '"' => {
+                    quotes += 1;
+                    if quotes == 3 {
+                        return;
+                    }
+                }
+                _ => quotes = 0,
+            }
+        }
+        return;
+    }
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '"' => return,
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,180 @@
+//! Per-thread context bound to a client.
+//!
+//! Every thread has a [`Hub`]: the client the global functions report to,
+//! the context, user and breadcrumbs recorded on the thread, and its stack of
+//! active [scopes](crate::scope). [`set_context`](crate::set_context) and
+//! [`set_user`](crate::set_user) set what every thread reports;
+//! [`set_thread_context`](crate::set_thread_context) and
+//! [`set_thread_user`](crate::set_thread_user) change the current thread's
+//! hub only, so a server thread tagging the user of the request it handles
+//! does not leak that user into events captured by other threads:
+//!
+//! ```rust,no_run
+//! use aivory_monitor::User;
+//!
+//! // Every event from this process.
+//! aivory_monitor::set_context_typed("service", "checkout").unwrap();
+//!
+//! std::thread::spawn(|| {
+//!     // Only events captured on this thread.
+//!     aivory_monitor::set_thread_user(User::new("user-123"));
+//! });
+//! ```
+//!
+//! A thread starts out bound to the global agent. Binding another client
+//! routes the thread's global calls, such as
+//! [`capture_error`](crate::capture_error), to it:
+//!
+//! ```rust,no_run
+//! use aivory_monitor::{Client, Config, Hub};
+//!
+//! let plugin = Client::new(Config::builder().api_key("plugin-key").build().unwrap());
+//! Hub::with_current(|hub| hub.bind_client(Some(plugin)));
+//! aivory_monitor::capture_error(&std::io::Error::new(std::io::ErrorKind::Other, "plugin failed"));
+//! ```
+
+use crate::breadcrumbs::Breadcrumb;
+use crate::scope::Scope;
+use crate::{capture, Client, User};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::Arc;
+
+thread_local! {
+    static CURRENT: Hub = Hub::new();
+}
+
+/// The client, context and scope stack of one thread.
+pub struct Hub {
+    /// Client bound with [`bind_client`](Self::bind_client); the global
+    /// agent if none.
+    client: RefCell<Option<Client>>,
+    context: RefCell<HashMap<String, serde_json::Value>>,
+    user: RefCell<Option<User>>,
+    /// Correlation ID set while no scope was active.
+    pub(crate) correlation_id: RefCell<Option<String>>,
+    /// Feature flags set while no scope was active.
+    pub(crate) feature_flags: RefCell<BTreeMap<String, String>>,
+    /// Baggage set while no scope was active.
+    pub(crate) baggage: RefCell<BTreeMap<String, String>>,
+    /// Breadcrumbs recorded while no scope was active, oldest first.
+    pub(crate) breadcrumbs: RefCell<VecDeque<Breadcrumb>>,
+    /// Active scopes, outermost first.
+    pub(crate) stack: RefCell<Vec<Arc<Scope>>>,
+}
+
+impl Hub {
+    fn new() -> Self {
+        Hub {
+            client: RefCell::new(None),
+            context: RefCell::new(HashMap::new()),
+            user: RefCell::new(None),
+            correlation_id: RefCell::new(None),
+            feature_flags: RefCell::new(BTreeMap::new()),
+            baggage: RefCell::new(BTreeMap::new()),
+            breadcrumbs: RefCell::new(VecDeque::new()),
+            stack: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Runs `f` with the current thread's hub.
+    pub fn with_current<R>(f: impl FnOnce(&Hub) -> R) -> R {
+        CURRENT.with(f)
+    }
+
+    /// Like [`with_current`](Self::with_current), but returns `None` instead
+    /// of panicking while the thread is being torn down, when the panic hook
+    /// may still run.
+    pub(crate) fn try_with_current<R>(f: impl FnOnce(&Hub) -> R) -> Option<R> {
+        CURRENT.try_with(f).ok()
+    }
+
+    /// The client this thread reports to: the bound one, else the global agent.
+    pub fn client(&self) -> Option<Client> {
+        self.client.borrow().clone().or_else(crate::client)
+    }
+
+    /// Routes this thread's global calls to `client`, or back to the global
+    /// agent with `None`.
+    pub fn bind_client(&self, client: Option<Client>) {
+        *self.client.borrow_mut() = client;
+    }
+
+    /// Replaces the context of this thread, trimmed to the capture limits
+    /// and `max_context_keys` of the thread's client.
+    pub fn set_context(&self, mut context: HashMap<String, serde_json::Value>) {
+        if let Some(client) = self.client() {
+            context = capture::limit_context(context, &client.config.read());
+        }
+        *self.context.borrow_mut() = context;
+    }
+
+    /// Sets a single context entry of this thread from any serializable
+    /// value, trimmed to the capture limits of the thread's client.
+    pub fn set_context_typed<T: serde::Serialize + ?Sized>(
+        &self,
+        key: impl Into<String>,
+        value: &T,
+    ) -> Result<(), serde_json::Error> {
+        let value = serde_json::to_value(value)?;
+        let key = key.into();
+        match self.client() {
+            Some(client) => capture::insert_context(
+                &mut self.context.borrow_mut(),
+                key,
+                value,
+                &client.config.read(),
+            ),
+            None => {
+                self.context.borrow_mut().insert(key, value);
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets the user affected by errors captured on this thread, in place of
+    /// the global one.
+    pub fn set_user(&self, user: User) {
+        *self.user.borrow_mut() = Some(user).filter(|u| !u.is_empty());
+    }
+
+    /// Clears this thread's user; the global one applies again.
+    pub fn clear_user(&self) {
+        *self.user.borrow_mut() = None;
+    }
+
+    /// Removes this thread's context, user, correlation ID, feature flags,
+    /// baggage and breadcrumbs, e.g. before a pooled thread picks up the next
+    /// request. The bound client and active scopes are kept.
+    pub fn clear(&self) {
+        self.context.borrow_mut().clear();
+        *self.user.borrow_mut() = None;
+        *self.correlation_id.borrow_mut() = None;
+        self.feature_flags.borrow_mut().clear();
+        self.baggage.borrow_mut().clear();
+        self.breadcrumbs.borrow_mut().clear();
+    }
+}
+
+/// The client the current thread reports to; see [`Hub::client`].
+pub(crate) fn client() -> Option<Client> {
+    Hub::try_with_current(Hub::client).unwrap_or_else(crate::client)
+}
+
+/// Copies the current thread's context into `context`.
+pub(crate) fn apply(context: &mut HashMap<String, serde_json::Value>) {
+    // Not `borrow`, as the panic hook may run while it is being changed.
+    Hub::try_with_current(|hub| {
+        let Ok(thread_context) = hub.context.try_borrow() else {
+            return;
+        };
+        for (k, v) in thread_context.iter() {
+            context.insert(k.clone(), v.clone());
+        }
+    });
+}
+
+/// The user set on the current thread.
+pub(crate) fn user() -> Option<User> {
+    Hub::try_with_current(|hub| hub.user.try_borrow().ok()?.clone()).flatten()
+}
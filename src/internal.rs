@@ -0,0 +1,155 @@
+//! Self-monitoring of agent failures.
+//!
+//! Failures inside the agent itself (events that cannot be serialized,
+//! writes that fail, panics on agent-owned threads) are reported to the
+//! backend as `internal_error` messages so operators notice when telemetry
+//! is broken. Reports are rate limited so a persistent fault cannot turn
+//! into a flood, and buffered while disconnected.
+
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Maximum reports sent per [`WINDOW`].
+const MAX_PER_WINDOW: u32 = 10;
+
+/// Rate limiting window.
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Reports buffered while disconnected; older ones are discarded first.
+const MAX_PENDING: usize = 16;
+
+thread_local! {
+    static AGENT_THREAD: Cell<bool> = const { Cell::new(false) };
+    static CAPTURING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Marks the current thread as owned by the agent.
+#[cfg_attr(any(target_os = "wasi", feature = "async-std"), allow(dead_code))]
+pub(crate) fn mark_agent_thread() {
+    AGENT_THREAD.with(|flag| flag.set(true));
+}
+
+/// Returns true on threads owned by the agent, such as its runtime workers.
+pub(crate) fn is_agent_thread() -> bool {
+    AGENT_THREAD.with(|flag| flag.get())
+}
+
+/// Marks the current thread as capturing an event until dropped.
+pub(crate) struct CaptureGuard {
+    outer: bool,
+}
+
+impl CaptureGuard {
+    pub(crate) fn enter() -> Self {
+        CaptureGuard {
+            outer: CAPTURING
+                .try_with(|flag| flag.replace(true))
+                .unwrap_or(false),
+        }
+    }
+}
+
+impl Drop for CaptureGuard {
+    fn drop(&mut self) {
+        let _ = CAPTURING.try_with(|flag| flag.set(self.outer));
+    }
+}
+
+/// Returns true while the current thread is capturing an event, when the
+/// agent's locks may be held by it.
+pub(crate) fn is_capturing() -> bool {
+    CAPTURING.try_with(|flag| flag.get()).unwrap_or(false)
+}
+
+/// Category of an internal failure.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InternalErrorKind {
+    /// An event could not be serialized.
+    Serialization,
+    /// Writing to the backend connection failed.
+    SendFailed,
+    /// A panic occurred on an agent-owned thread, or while capturing an event.
+    AgentPanic,
+}
+
+/// Payload of an `internal_error` message.
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct InternalErrorReport {
+    pub kind: InternalErrorKind,
+    pub message: String,
+    /// Reports dropped by rate limiting since the previous one was sent.
+    pub suppressed: u64,
+    pub agent_version: &'static str,
+    pub occurred_at: String,
+}
+
+struct Limiter {
+    window_start: Instant,
+    sent_in_window: u32,
+    suppressed: u64,
+}
+
+/// Rate limiter and pending buffer for internal error reports.
+pub(crate) struct InternalErrors {
+    limiter: Mutex<Limiter>,
+    pending: Mutex<VecDeque<InternalErrorReport>>,
+}
+
+impl InternalErrors {
+    pub(crate) fn new() -> Self {
+        InternalErrors {
+            limiter: Mutex::new(Limiter {
+                window_start: Instant::now(),
+                sent_in_window: 0,
+                suppressed: 0,
+            }),
+            pending: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Builds a report if the rate limit allows one, otherwise counts it as suppressed.
+    pub(crate) fn record(
+        &self,
+        kind: InternalErrorKind,
+        message: String,
+    ) -> Option<InternalErrorReport> {
+        let mut limiter = self.limiter.lock();
+        if limiter.window_start.elapsed() >= WINDOW {
+            limiter.window_start = Instant::now();
+            limiter.sent_in_window = 0;
+        }
+        if limiter.sent_in_window >= MAX_PER_WINDOW {
+            limiter.suppressed += 1;
+            return None;
+        }
+        limiter.sent_in_window += 1;
+        let suppressed = std::mem::take(&mut limiter.suppressed);
+
+        Some(InternalErrorReport {
+            kind,
+            message,
+            suppressed,
+            agent_version: env!("CARGO_PKG_VERSION"),
+            occurred_at: crate::clock::now().to_rfc3339(),
+        })
+    }
+
+    /// Buffers a report until the next connection.
+    pub(crate) fn defer(&self, report: InternalErrorReport) {
+        let mut pending = self.pending.lock();
+        if pending.len() >= MAX_PENDING {
+            pending.pop_front();
+        }
+        pending.push_back(report);
+    }
+
+    /// Takes all buffered reports.
+    #[cfg_attr(target_os = "wasi", allow(dead_code))]
+    pub(crate) fn take_pending(&self) -> Vec<InternalErrorReport> {
+        self.pending.lock().drain(..).collect()
+    }
+}
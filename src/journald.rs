@@ -0,0 +1,154 @@
+//! systemd journal integration.
+//!
+//! When the process runs as a systemd unit, every event gets a `systemd`
+//! context with the unit name and invocation ID, so events can be matched
+//! to `journalctl _SYSTEMD_INVOCATION_ID=...`. With the `journald` setting,
+//! events are also written to the journal as structured entries:
+//!
+//! ```text
+//! journalctl AIVORY_FINGERPRINT=3f2a9c1d8e7b6a50 -o verbose
+//! ```
+//!
+//! Entries use the journal's native protocol, so no libsystemd is needed.
+//! Linux only.
+
+use crate::capture::ExceptionCapture;
+use once_cell::sync::Lazy;
+use serde_json::json;
+use std::io;
+use std::os::unix::net::UnixDatagram;
+
+/// Socket of the journal's native protocol.
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Returned when an entry exceeds the socket's datagram size.
+const EMSGSIZE: i32 = 90;
+
+/// Syslog priority of captured errors and panics.
+const PRIORITY_ERR: u8 = 3;
+const PRIORITY_CRIT: u8 = 2;
+
+static SYSTEMD_CONTEXT: Lazy<Option<serde_json::Value>> = Lazy::new(|| {
+    let invocation_id = std::env::var("INVOCATION_ID").ok();
+    let unit = std::fs::read_to_string("/proc/self/cgroup")
+        .ok()
+        .and_then(|cgroup| unit_name(&cgroup));
+    if invocation_id.is_none() && unit.is_none() {
+        return None;
+    }
+    Some(json!({ "unit": unit, "invocation_id": invocation_id }))
+});
+
+/// Adds the `systemd` context when running under systemd.
+pub(crate) fn enrich(exc: &mut ExceptionCapture) {
+    if let Some(context) = SYSTEMD_CONTEXT.as_ref() {
+        exc.context
+            .entry("systemd".to_string())
+            .or_insert_with(|| context.clone());
+    }
+}
+
+/// The unit (service or scope) in a `/proc/self/cgroup` listing.
+fn unit_name(cgroup: &str) -> Option<String> {
+    cgroup
+        .lines()
+        // The unified hierarchy (`0::`), else the legacy systemd one.
+        .filter_map(|line| {
+            line.strip_prefix("0::")
+                .or_else(|| line.strip_prefix("1:name=systemd:"))
+        })
+        .flat_map(|path| path.rsplit('/'))
+        .find(|segment| segment.ends_with(".service") || segment.ends_with(".scope"))
+        .map(|segment| segment.to_string())
+}
+
+/// Writes events to the journal.
+pub(crate) struct Journal {
+    socket: UnixDatagram,
+}
+
+impl Journal {
+    pub(crate) fn open() -> io::Result<Self> {
+        Ok(Journal {
+            socket: UnixDatagram::unbound()?,
+        })
+    }
+
+    /// Writes `exc` as one journal entry.
+    pub(crate) fn write(&self, exc: &ExceptionCapture) -> io::Result<()> {
+        match self.socket.send_to(&entry(exc, true), JOURNAL_SOCKET) {
+            // Large entries must be passed as a memfd; drop the bulky fields instead.
+            Err(e) if e.raw_os_error() == Some(EMSGSIZE) => self
+                .socket
+                .send_to(&entry(exc, false), JOURNAL_SOCKET)
+                .map(|_| ()),
+            result => result.map(|_| ()),
+        }
+    }
+}
+
+/// Serializes `exc` in the journal's native protocol.
+fn entry(exc: &ExceptionCapture, detailed: bool) -> Vec<u8> {
+    let mut buf = Vec::new();
+    let priority = if exc.exception_type == "panic" {
+        PRIORITY_CRIT
+    } else {
+        PRIORITY_ERR
+    };
+    field(
+        &mut buf,
+        "MESSAGE",
+        &format!("{}: {}", exc.exception_type, exc.message),
+    );
+    field(&mut buf, "PRIORITY", &priority.to_string());
+    field(&mut buf, "AIVORY_EVENT_ID", &exc.id);
+    field(&mut buf, "AIVORY_EXCEPTION_TYPE", &exc.exception_type);
+    field(&mut buf, "AIVORY_FINGERPRINT", &exc.fingerprint);
+    field(&mut buf, "AIVORY_ENVIRONMENT", &exc.environment);
+    field(&mut buf, "AIVORY_AGENT_ID", &exc.agent_id);
+
+    if let Some(frame) = exc.stack_trace.iter().find(|frame| !frame.is_native) {
+        field(&mut buf, "CODE_FUNC", &frame.method_name);
+        if let Some(path) = &frame.file_path {
+            field(&mut buf, "CODE_FILE", path);
+        }
+        if let Some(line) = frame.line_number {
+            field(&mut buf, "CODE_LINE", &line.to_string());
+        }
+    }
+
+    if detailed {
+        let stack: Vec<String> = exc
+            .stack_trace
+            .iter()
+            .map(|frame| match (&frame.file_path, frame.line_number) {
+                (Some(path), Some(line)) => format!("at {} ({}:{})", frame.method_name, path, line),
+                _ => format!("at {}", frame.method_name),
+            })
+            .collect();
+        if !stack.is_empty() {
+            field(&mut buf, "AIVORY_STACK_TRACE", &stack.join("\n"));
+        }
+        if !exc.context.is_empty() {
+            field(
+                &mut buf,
+                "AIVORY_CONTEXT",
+                &serde_json::to_string(&exc.context).unwrap_or_default(),
+            );
+        }
+    }
+    buf
+}
+
+/// Appends one field. Values with newlines use the length-prefixed form.
+fn field(buf: &mut Vec<u8>, name: &str, value: &str) {
+    buf.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        buf.push(b'\n');
+        buf.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        buf.push(b'=');
+    }
+    buf.extend_from_slice(value.as_bytes());
+    buf.push(b'\n');
+}
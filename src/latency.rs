@@ -0,0 +1,138 @@
+//! Latency of the ingest path.
+//!
+//! Each event passes through three stages: it is captured on the caller's
+//! thread and handed to the transport, waits in the outbound queue until it
+//! is written to the connection, and is acknowledged by the backend. The
+//! duration of each stage is aggregated and sent with every heartbeat, then
+//! reset, so delivery lag and throughput regressions in the agent or the
+//! backend show up per heartbeat interval:
+//!
+//! ```json
+//! "latency": {
+//!   "capture": { "count": 12, "mean_ms": 0.41, "max_ms": 1.9 },
+//!   "queue": { "count": 12, "mean_ms": 2.3, "max_ms": 9.7 },
+//!   "ack": { "count": 11, "mean_ms": 38.0, "max_ms": 112.5 },
+//!   "delivery": { "count": 11, "mean_ms": 40.6, "max_ms": 118.2 }
+//! }
+//! ```
+//!
+//! `delivery` spans from handing an event to the transport to its ack.
+
+use crate::audit::EventRef;
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Events awaiting an ack before the oldest are forgotten, for backends
+/// that do not acknowledge events.
+const MAX_PENDING: usize = 10_000;
+
+/// Stage durations since the last heartbeat.
+#[derive(Default)]
+pub(crate) struct Latency {
+    capture: Stage,
+    queue: Stage,
+    ack: Stage,
+    delivery: Stage,
+    /// When each written event was handed to the transport and written.
+    pending: Mutex<HashMap<String, (Instant, Instant)>>,
+}
+
+impl Latency {
+    /// Records the time a capture took on the caller's thread.
+    pub(crate) fn captured(&self, duration: Duration) {
+        self.capture.record(duration);
+    }
+
+    /// Records that `event` was written to the connection and now awaits
+    /// its ack.
+    #[cfg_attr(target_os = "wasi", allow(dead_code))]
+    pub(crate) fn written(&self, event: &EventRef) {
+        let now = Instant::now();
+        self.queue.record(now.duration_since(event.queued_at));
+        let mut pending = self.pending.lock();
+        if pending.len() >= MAX_PENDING {
+            pending.clear();
+        }
+        pending.insert(event.id.clone(), (event.queued_at, now));
+    }
+
+    /// Records the ack of an event written earlier.
+    #[cfg_attr(target_os = "wasi", allow(dead_code))]
+    pub(crate) fn acked(&self, id: &str) {
+        let Some((queued_at, written_at)) = self.pending.lock().remove(id) else {
+            return;
+        };
+        self.ack.record(written_at.elapsed());
+        self.delivery.record(queued_at.elapsed());
+    }
+
+    /// Records an event sent in a request started at `sent_at`, whose
+    /// successful response is the ack.
+    pub(crate) fn delivered(&self, event: &EventRef, sent_at: Instant) {
+        self.queue
+            .record(sent_at.saturating_duration_since(event.queued_at));
+        self.ack.record(sent_at.elapsed());
+        self.delivery.record(event.queued_at.elapsed());
+    }
+
+    /// Returns the aggregates since the last call and starts over.
+    #[cfg_attr(target_os = "wasi", allow(dead_code))]
+    pub(crate) fn take(&self) -> LatencySummary {
+        LatencySummary {
+            capture: self.capture.take(),
+            queue: self.queue.take(),
+            ack: self.ack.take(),
+            delivery: self.delivery.take(),
+        }
+    }
+}
+
+/// Durations of one stage, in microseconds.
+#[derive(Default)]
+struct Stage {
+    count: AtomicU64,
+    total_us: AtomicU64,
+    max_us: AtomicU64,
+}
+
+impl Stage {
+    fn record(&self, duration: Duration) {
+        let us = duration.as_micros() as u64;
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.total_us.fetch_add(us, Ordering::Relaxed);
+        self.max_us.fetch_max(us, Ordering::Relaxed);
+    }
+
+    fn take(&self) -> StageSummary {
+        let count = self.count.swap(0, Ordering::Relaxed);
+        let total_us = self.total_us.swap(0, Ordering::Relaxed);
+        let max_us = self.max_us.swap(0, Ordering::Relaxed);
+        StageSummary {
+            count,
+            mean_ms: match count {
+                0 => 0.0,
+                n => total_us as f64 / n as f64 / 1000.0,
+            },
+            max_ms: max_us as f64 / 1000.0,
+        }
+    }
+}
+
+/// The `latency` of a heartbeat.
+#[derive(Serialize)]
+pub(crate) struct LatencySummary {
+    capture: StageSummary,
+    queue: StageSummary,
+    ack: StageSummary,
+    delivery: StageSummary,
+}
+
+#[derive(Serialize)]
+struct StageSummary {
+    count: u64,
+    mean_ms: f64,
+    max_ms: f64,
+}
@@ -9,94 +9,688 @@
 //!
 //! fn main() {
 //!     // Initialize the agent
-//!     aivory_monitor::init(aivory_monitor::Config {
-//!         api_key: "your-api-key".to_string(),
-//!         environment: "production".to_string(),
-//!         ..Default::default()
-//!     });
+//!     let config = aivory_monitor::Config::builder()
+//!         .api_key("your-api-key")
+//!         .environment("production")
+//!         .build()
+//!         .expect("invalid AIVory configuration");
+//!     aivory_monitor::init(config);
 //!
 //!     // Or use the panic hook (automatically installed)
 //! }
 //! ```
 
-pub mod config;
+#![cfg_attr(feature = "error-provider", feature(error_generic_member_access))]
+
+mod aggregation;
+mod anomaly;
+mod audit;
+pub mod baggage;
+#[cfg(feature = "bevy")]
+pub mod bevy;
+pub mod breadcrumbs;
 pub mod capture;
+mod client;
+pub mod clock;
+pub mod config;
+pub mod consumer;
+pub mod context_span;
+pub mod cron;
+pub mod diagnostics;
+#[cfg(not(target_os = "wasi"))]
+pub mod drop_guard;
+#[cfg(feature = "postcard")]
+pub mod embedded;
+#[cfg(feature = "cdylib")]
+pub mod ffi;
+#[cfg(feature = "async-graphql")]
+pub mod graphql;
+pub mod hub;
+mod internal;
+#[cfg(feature = "journald")]
+mod journald;
+mod latency;
+pub mod logger;
+#[cfg(target_os = "macos")]
+mod macos;
+#[cfg(feature = "prometheus")]
+pub mod metrics;
+#[cfg(any(windows, target_os = "macos"))]
+mod os_crashes;
+pub mod performance;
+pub mod process;
+#[cfg(feature = "hot-reload")]
+mod reload;
+pub mod request;
+#[cfg(not(target_os = "wasi"))]
+mod runtime;
+pub mod sampling;
+pub mod scope;
+mod session;
+#[cfg(all(feature = "shutdown-hooks", unix))]
+mod signals;
+#[cfg(feature = "source-upload")]
+mod sources;
+#[cfg(not(target_os = "wasi"))]
+pub mod task;
+#[cfg(feature = "tauri")]
+pub mod tauri;
+#[cfg(feature = "tracing")]
+pub mod tracing_layer;
 pub mod transport;
+pub mod user;
+#[cfg(not(target_os = "wasi"))]
+mod vcs;
+#[cfg(windows)]
+mod windows;
 
-pub use config::Config;
-pub use capture::{CaptureError, ExceptionCapture};
+#[cfg(feature = "derive")]
+pub use aivory_monitor_derive::main;
+pub use breadcrumbs::{Breadcrumb, BreadcrumbLevel};
+pub use capture::{Capturable, CaptureError, ErrorCause, EventBuilder, ExceptionCapture};
+pub use client::Client;
+pub use config::{Config, ConfigBuilder, ConfigError};
+pub use diagnostics::{AgentDiagnostics, ConnectionState, DropReason, OverheadStats};
+pub use hub::Hub;
+pub use internal::InternalErrorKind;
+pub use logger::{LogLevel, LogOutput, Logger};
+pub use request::RequestContext;
+pub use sampling::{SamplingContext, SamplingMode};
+pub use scope::{
+    correlation_id, new_correlation_id, set_baggage, set_correlation_id, set_feature_flag,
+};
+pub use transport::{DeliveryError, EventId, SendError, TransportMode};
+pub use user::User;
 
 use once_cell::sync::OnceCell;
 use parking_lot::RwLock;
 use std::collections::HashMap;
 use std::panic;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static CLIENT: OnceCell<Client> = OnceCell::new();
 
-static AGENT: OnceCell<Arc<Agent>> = OnceCell::new();
+/// How long [`send_test_event`] and [`capture_error_and_wait`] wait for the
+/// connection and the ack.
+const ACK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
 
 /// The main AIVory Monitor agent.
 pub struct Agent {
-    config: Config,
+    config: RwLock<Config>,
     connection: transport::Connection,
+    /// Whether captures can go anywhere: there is an API key or dry run is
+    /// on, the configuration is valid and the agent has not been stopped.
+    enabled: AtomicBool,
     custom_context: RwLock<HashMap<String, serde_json::Value>>,
-    user: RwLock<HashMap<String, String>>,
+    /// Values of `capture_env_vars`, read at startup.
+    env_vars: Option<serde_json::Value>,
+    /// Git revision and uncommitted changes, read at startup.
+    vcs: Option<serde_json::Value>,
+    user: RwLock<Option<User>>,
+    sampler: sampling::AdaptiveSampler,
+    aggregator: aggregation::Aggregator,
+    spikes: anomaly::SpikeDetector,
+    shutdown: tokio::sync::Notify,
+    #[cfg(feature = "journald")]
+    journal: Option<journald::Journal>,
 }
 
 impl Agent {
     /// Creates a new agent with the given configuration.
-    pub fn new(config: Config) -> Self {
+    pub fn new(mut config: Config) -> Self {
+        config.logger.set_level(config.effective_log_level());
+        config::resolve_identity(&mut config);
+        config::anonymize_hostname(&mut config);
+        #[cfg(feature = "source-upload")]
+        if config.upload_sources && config.build_id.is_none() {
+            config.build_id = sources::build_id();
+        }
+
+        #[cfg(feature = "journald")]
+        let journal = if config.journald {
+            journald::Journal::open()
+                .map_err(|e| {
+                    config
+                        .logger
+                        .warn(format_args!("Failed to open the systemd journal: {}", e))
+                })
+                .ok()
+        } else {
+            None
+        };
+
+        let env_vars: serde_json::Map<String, serde_json::Value> = config
+            .capture_env_vars
+            .iter()
+            .filter_map(|name| {
+                std::env::var(name)
+                    .ok()
+                    .map(|value| (name.clone(), value.into()))
+            })
+            .collect();
+
+        #[cfg(not(target_os = "wasi"))]
+        let vcs = if config.capture_vcs_diff && config.environment != "production" {
+            std::env::current_dir()
+                .ok()
+                .and_then(|dir| vcs::context(&dir, &config))
+        } else {
+            None
+        };
+        #[cfg(target_os = "wasi")]
+        let vcs = None;
+
+        let connection = transport::Connection::new();
+        connection.open_spool(&config);
+        if let Some(path) = &config.audit_log {
+            match audit::AuditLog::open(path) {
+                Ok(log) => connection.diagnostics().set_audit_log(log),
+                Err(e) => config.logger.warn(format_args!(
+                    "Failed to open audit log {}: {}",
+                    path.display(),
+                    e
+                )),
+            }
+        }
+
+        let enabled = !config.api_key.is_empty() || config.dry_run;
         Agent {
-            config,
-            connection: transport::Connection::new(),
+            config: RwLock::new(config),
+            connection,
+            enabled: AtomicBool::new(enabled),
             custom_context: RwLock::new(HashMap::new()),
-            user: RwLock::new(HashMap::new()),
+            env_vars: (!env_vars.is_empty()).then_some(serde_json::Value::Object(env_vars)),
+            vcs,
+            user: RwLock::new(None),
+            sampler: sampling::AdaptiveSampler::new(),
+            aggregator: aggregation::Aggregator::new(),
+            spikes: anomaly::SpikeDetector::new(),
+            shutdown: tokio::sync::Notify::new(),
+            #[cfg(feature = "journald")]
+            journal,
         }
     }
 
     /// Starts the agent and connects to the backend.
+    #[cfg(not(target_os = "wasi"))]
     pub async fn start(&self) {
-        if self.config.api_key.is_empty() {
-            eprintln!("[AIVory Monitor] API key is required");
+        let Some(config) = self.startup_config() else {
+            return;
+        };
+
+        // Connect to backend
+        self.connection.connect(&config).await;
+        Self::log_started(&config);
+    }
+
+    /// Starts the agent in dry-run mode, without a connection or runtime.
+    pub(crate) fn start_dry_run(&self) {
+        let config = self.config.read().clone();
+        if let Err(e) = config.validate() {
+            config
+                .logger
+                .error(format_args!("Invalid configuration: {}", e));
+            self.enabled.store(false, Ordering::Relaxed);
             return;
         }
+        self.connection.open_dry_run();
+        Self::log_started(&config);
+        config.logger.info(format_args!(
+            "Dry run: events are written to stderr instead of being sent"
+        ));
+    }
 
-        // Install panic hook
-        self.install_panic_hook();
+    /// Starts the agent on the HTTP transport, without a runtime.
+    pub(crate) fn start_http(&self) {
+        let Some(config) = self.startup_config() else {
+            return;
+        };
+        self.connection.open_http(&config);
+        Self::log_started(&config);
+    }
 
-        // Connect to backend
-        self.connection.connect(&self.config).await;
+    /// Returns the configuration to start with, or `None` if it is unusable.
+    fn startup_config(&self) -> Option<Config> {
+        let config = self.config.read().clone();
+        if config.api_key.is_empty() {
+            config.logger.error(format_args!("API key is required"));
+            self.enabled.store(false, Ordering::Relaxed);
+            return None;
+        }
+        if let Err(e) = config.validate() {
+            config
+                .logger
+                .error(format_args!("Invalid configuration: {}", e));
+            self.enabled.store(false, Ordering::Relaxed);
+            return None;
+        }
+        Some(config)
+    }
 
-        println!("[AIVory Monitor] Agent v1.0.0 initialized");
-        println!("[AIVory Monitor] Environment: {}", self.config.environment);
+    fn log_started(config: &Config) {
+        config.logger.info(format_args!(
+            "Agent v{} initialized",
+            env!("CARGO_PKG_VERSION")
+        ));
+        if config.environment_source == config::EnvironmentSource::Explicit {
+            config
+                .logger
+                .info(format_args!("Environment: {}", config.environment));
+        } else {
+            config.logger.info(format_args!(
+                "Environment: {} (detected: {:?})",
+                config.environment, config.environment_source
+            ));
+        }
     }
 
     /// Stops the agent.
     pub async fn stop(&self) {
-        self.connection.disconnect().await;
-        println!("[AIVory Monitor] Agent stopped");
+        self.stop_now();
+    }
+
+    pub(crate) fn stop_now(&self) {
+        self.enabled.store(false, Ordering::Relaxed);
+        // The connection closes right away, so held events are sent directly.
+        let deadline = std::time::Instant::now() + self.config.read().http_timeout;
+        for exc in self.aggregator.take() {
+            let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+            self.connection.send_exception_blocking(exc, remaining);
+        }
+        self.connection.close();
+        self.shutdown.notify_one();
+        self.config
+            .read()
+            .logger
+            .info(format_args!("Agent stopped"));
+    }
+
+    /// Blocks until captured events have been sent or `timeout` elapses.
+    ///
+    /// Returns true if all of them were sent, and false if some are left or
+    /// were dropped meanwhile, e.g. with a connection that went away. Call
+    /// this before returning from a serverless function, which may be frozen
+    /// as soon as it returns.
+    pub fn flush_blocking(&self, timeout: std::time::Duration) -> bool {
+        self.send_aggregated();
+        self.connection.flush(timeout)
+    }
+
+    /// Sends the events held for aggregation.
+    pub(crate) fn send_aggregated(&self) {
+        for exc in self.aggregator.take() {
+            self.connection.send_exception(exc);
+        }
+    }
+
+    /// Sends held events at the end of every aggregation interval, for as
+    /// long as the agent exists.
+    #[cfg(not(target_os = "wasi"))]
+    pub(crate) async fn run_aggregation(agent: std::sync::Weak<Agent>) {
+        loop {
+            let Some(interval) = agent
+                .upgrade()
+                .and_then(|agent| agent.config.read().aggregation_interval)
+            else {
+                return;
+            };
+            runtime::sleep(interval).await;
+            match agent.upgrade() {
+                Some(agent) => agent.send_aggregated(),
+                None => return,
+            }
+        }
+    }
+
+    /// Returns a copy of the current configuration.
+    pub fn config(&self) -> Config {
+        self.config.read().clone()
+    }
+
+    /// Returns a snapshot of the agent's health counters.
+    pub fn diagnostics(&self) -> AgentDiagnostics {
+        self.connection.diagnostics().snapshot()
+    }
+
+    /// Returns the time spent in the agent per capture; see [`overhead_stats`].
+    pub fn overhead_stats(&self) -> OverheadStats {
+        self.connection.diagnostics().overhead()
     }
 
     /// Captures an error.
-    pub fn capture_error<E: std::error::Error>(&self, error: &E, context: Option<HashMap<String, serde_json::Value>>) {
-        if !self.config.should_sample() {
+    pub fn capture_error<E: std::error::Error>(
+        &self,
+        error: &E,
+        context: Option<HashMap<String, serde_json::Value>>,
+    ) {
+        self.report(context, |config| capture::capture_error(error, config));
+    }
+
+    /// Captures an error reported as `exception_type` instead of its type
+    /// name, which also groups it under that name.
+    pub fn capture_error_as<E: std::error::Error>(
+        &self,
+        exception_type: &str,
+        error: &E,
+        context: Option<HashMap<String, serde_json::Value>>,
+    ) {
+        self.report(context, |config| {
+            capture::capture_error_as(exception_type, error, config)
+        });
+    }
+
+    /// Captures an error with its fields as local variables; see [`Capturable`].
+    pub fn capture_typed_error<E: Capturable>(
+        &self,
+        error: &E,
+        context: Option<HashMap<String, serde_json::Value>>,
+    ) {
+        self.report(context, |config| {
+            capture::capture_typed_error(error, config)
+        });
+    }
+
+    /// Captures an error wrapping several failures; see
+    /// [`capture_aggregate_error`].
+    pub fn capture_aggregate_error<'a, E, I, F>(
+        &self,
+        error: &E,
+        failures: I,
+        context: Option<HashMap<String, serde_json::Value>>,
+    ) where
+        E: std::error::Error + ?Sized,
+        I: IntoIterator<Item = &'a F>,
+        F: std::error::Error + ?Sized + 'a,
+    {
+        self.report(context, |config| {
+            capture::capture_aggregate_error(error, failures, config)
+        });
+    }
+
+    /// Captures a type-erased error, e.g. `&*boxed` for a `Box<dyn Error>`.
+    pub fn capture_dyn_error(
+        &self,
+        error: &(dyn std::error::Error + 'static),
+        context: Option<HashMap<String, serde_json::Value>>,
+    ) {
+        self.report(context, |config| capture::capture_dyn_error(error, config));
+    }
+
+    /// Captures a Tokio task that panicked or was cancelled; see
+    /// [`capture_join_error`].
+    #[cfg(not(target_os = "wasi"))]
+    pub fn capture_join_error(
+        &self,
+        error: &tokio::task::JoinError,
+        context: Option<HashMap<String, serde_json::Value>>,
+    ) {
+        self.report(context, |config| capture::capture_join_error(error, config));
+    }
+
+    /// Captures an error returned from `main`; see [`main_wrapper`].
+    pub(crate) fn capture_main_error<E: std::fmt::Debug + 'static>(&self, error: &E) {
+        self.report(None, |config| capture::capture_main_error(error, config));
+    }
+
+    /// Captures a panic caught while unwinding that the panic hook did not
+    /// report, from its payload.
+    #[cfg(not(target_os = "wasi"))]
+    pub(crate) fn capture_panic_payload(
+        &self,
+        payload: &(dyn std::any::Any + Send),
+        mechanism: capture::Mechanism,
+    ) {
+        self.report(None, |config| {
+            let payload = capture::extract_panic_payload(payload, &config.panic_payload_extractors);
+            let mut exc = capture::capture_panic(&payload.message, None, config);
+            exc.mechanism = mechanism;
+            if !payload.fields.is_empty() {
+                let fields = serde_json::Value::Object(payload.fields.into_iter().collect());
+                exc.context.insert(
+                    "panic_payload".to_string(),
+                    capture::limit_value(fields, config),
+                );
+            }
+            exc
+        });
+    }
+
+    /// Submits a manually constructed event.
+    pub fn capture_event(
+        &self,
+        event: EventBuilder,
+        context: Option<HashMap<String, serde_json::Value>>,
+    ) {
+        self.report(context, |config| event.build(config));
+    }
+
+    /// Submits the event built by `event`, which is only called if the event
+    /// is captured at all: not while the agent is disabled, the circuit is
+    /// open, or when sampling drops it up front.
+    pub fn capture_event_with(
+        &self,
+        event: impl FnOnce() -> EventBuilder,
+        context: Option<HashMap<String, serde_json::Value>>,
+    ) {
+        self.report(context, |config| event().build(config));
+    }
+
+    /// Whether captured events go anywhere. False without an API key (unless
+    /// in dry-run mode), with an invalid configuration, and after shutdown;
+    /// capturing then returns right away without doing any work.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Sends a marker event and blocks until the backend acknowledges it;
+    /// see [`send_test_event`].
+    pub fn send_test_event(&self) -> Result<EventId, SendError> {
+        let exc = EventBuilder::new("AivoryTestEvent")
+            .message("Test event to verify the AIVory agent is connected")
+            .context("aivory_test_event", true)
+            .build(&self.config.read());
+        // Not subject to sampling, aggregation or the circuit breaker.
+        self.connection.send_acknowledged(exc, ACK_TIMEOUT)
+    }
+
+    /// Sends the messages another process left in `spool` because it could
+    /// not send them, removing them from it; see `aivory spool replay`.
+    ///
+    /// Waits up to `timeout` for the connection and for the messages to be
+    /// delivered: events acknowledged by the backend, and the other messages
+    /// written over a connection that stayed registered throughout. They are
+    /// removed from the spool only then, so after an error some may be sent
+    /// twice. Returns the number of messages sent.
+    pub fn replay_spool(
+        &self,
+        spool: &transport::Spool,
+        timeout: std::time::Duration,
+    ) -> Result<usize, SendError> {
+        let deadline = std::time::Instant::now() + timeout;
+        if !self.connection.wait_registered(timeout) {
+            return Err(match self.connection.diagnostics().connection_state() {
+                ConnectionState::AuthFailed => SendError::AuthFailed,
+                _ => SendError::NotConnected,
+            });
+        }
+        let messages = spool.messages();
+        self.connection.resend(
+            &messages,
+            deadline.saturating_duration_since(std::time::Instant::now()),
+        )?;
+        spool.remove(&messages);
+        Ok(messages.len())
+    }
+
+    fn report(
+        &self,
+        context: Option<HashMap<String, serde_json::Value>>,
+        capture: impl FnOnce(&Config) -> ExceptionCapture,
+    ) {
+        if !self.is_enabled() {
             return;
         }
+        self.guarded(|| self.report_unguarded(context, capture));
+    }
+
+    /// Captures an event for [`Client::capture_error_and_wait`], with the
+    /// same context as [`report`](Self::report) but not subject to sampling,
+    /// aggregation or the circuit breaker.
+    pub(crate) fn capture_unsampled(
+        &self,
+        context: Option<HashMap<String, serde_json::Value>>,
+        capture: impl FnOnce(&Config) -> ExceptionCapture,
+    ) -> Result<ExceptionCapture, DeliveryError> {
+        if !self.is_enabled() {
+            return Err(DeliveryError::Disabled);
+        }
+        let exc = self.guarded(|| {
+            let _timer = self.connection.diagnostics().time_capture();
+            let elapsed_ms = scope::elapsed_ms();
+            let mut exc = capture(&self.config.read());
+            self.enrich(&mut exc, context, elapsed_ms);
+            self.write_local(&mut exc);
+            exc
+        });
+        exc.ok_or(DeliveryError::CaptureFailed)
+    }
+
+    /// Runs a capture, returning `None` if it panicked.
+    fn guarded<R>(&self, capture: impl FnOnce() -> R) -> Option<R> {
+        // A panic while capturing, e.g. in a `Debug` implementation or a
+        // sampling callback, must not reach the application: capturing from
+        // a destructor during unwinding would turn it into an abort.
+        let _capturing = internal::CaptureGuard::enter();
+        match panic::catch_unwind(panic::AssertUnwindSafe(capture)) {
+            Ok(result) => Some(result),
+            Err(payload) => {
+                let message = capture::extract_panic_payload(&*payload, &[]).message;
+                self.connection.report_internal_error(
+                    InternalErrorKind::AgentPanic,
+                    format!("panic while capturing an event: {}", message),
+                );
+                None
+            }
+        }
+    }
+
+    fn report_unguarded(
+        &self,
+        context: Option<HashMap<String, serde_json::Value>>,
+        capture: impl FnOnce(&Config) -> ExceptionCapture,
+    ) {
+        let _timer = self.connection.diagnostics().time_capture();
+
+        // Skip all capture work while the backend is failing.
+        if !self.connection.allow_capture() {
+            self.connection
+                .diagnostics()
+                .record_drop(DropReason::CircuitOpen);
+            return;
+        }
+
+        // Measured before capturing, as symbolizing the stack takes a while.
+        let elapsed_ms = scope::elapsed_ms();
+
+        let mut exc = {
+            let config = self.config.read();
+            if Self::samples_before_capture(&config) && !config.should_sample() {
+                self.connection
+                    .diagnostics()
+                    .record_drop(DropReason::SampledOut);
+                return;
+            }
+            capture(&config)
+        };
+        self.enrich(&mut exc, context, elapsed_ms);
+
+        match self.sample(&exc) {
+            Some(rate) => exc.sample_rate = rate,
+            None => {
+                let diagnostics = self.connection.diagnostics();
+                diagnostics.record_drop(DropReason::SampledOut);
+                diagnostics.audit(
+                    &exc.id,
+                    Some(&exc.fingerprint),
+                    audit::Outcome::Dropped(DropReason::SampledOut),
+                );
+                return;
+            }
+        }
 
-        let mut exc = capture::capture_error(error, &self.config);
+        self.deliver(exc);
+    }
+
+    /// Adds the agent's, the current thread's and the caller's context to a
+    /// captured event, and its error rate.
+    fn enrich(
+        &self,
+        exc: &mut ExceptionCapture,
+        context: Option<HashMap<String, serde_json::Value>>,
+        elapsed_ms: Option<u64>,
+    ) {
+        self.enrich_with(exc, context, elapsed_ms, &self.config.read());
+    }
+
+    /// [`enrich`](Self::enrich) with the configuration already read, as the
+    /// panic hook has it. Like the hook, it only waits a bounded time for the
+    /// agent's locks, which the panicking thread may hold.
+    fn enrich_with(
+        &self,
+        exc: &mut ExceptionCapture,
+        context: Option<HashMap<String, serde_json::Value>>,
+        elapsed_ms: Option<u64>,
+        config: &Config,
+    ) {
+        // Add the environment variables read at startup
+        if let Some(env_vars) = &self.env_vars {
+            exc.context.insert("env_vars".to_string(), env_vars.clone());
+        }
+        if let Some(vcs) = &self.vcs {
+            exc.context.insert("vcs".to_string(), vcs.clone());
+        }
 
         // Add custom context
-        {
-            let custom = self.custom_context.read();
+        if let Some(custom) = self.custom_context.try_read_for(PANIC_HOOK_LOCK_TIMEOUT) {
             for (k, v) in custom.iter() {
                 exc.context.insert(k.clone(), v.clone());
             }
         }
 
-        // Add user
-        {
-            let user = self.user.read();
-            if !user.is_empty() {
-                exc.context.insert("user".to_string(), serde_json::json!(user.clone()));
+        // Add context of the current thread and its active scopes
+        hub::apply(&mut exc.context);
+        scope::apply(&mut exc.context);
+        exc.correlation_id = scope::correlation_id();
+        exc.feature_flags = scope::feature_flags();
+        exc.baggage = scope::baggage();
+        exc.elapsed_ms = elapsed_ms;
+        if exc.request.is_none() {
+            exc.request = scope::request();
+        }
+        if let (None, Some(request)) = (&exc.transaction, &exc.request) {
+            exc.transaction = Some(request.transaction(config.transaction_normalizer.as_ref()));
+        }
+        exc.attachments.extend(scope::attachments());
+        exc.breadcrumbs = scope::breadcrumbs(config.max_breadcrumbs);
+        let messages = std::iter::once(exc.message.as_str())
+            .chain(exc.causes.iter().map(|c| c.message.as_str()));
+        exc.await_trail = context_span::take_trail(messages);
+
+        // Add user, the current thread's in place of the global one
+        match hub::user() {
+            Some(user) => {
+                exc.context
+                    .insert("user".to_string(), serde_json::json!(user));
+            }
+            None => {
+                if let Some(user) = self
+                    .user
+                    .try_read_for(PANIC_HOOK_LOCK_TIMEOUT)
+                    .and_then(|user| user.clone())
+                {
+                    exc.context
+                        .insert("user".to_string(), serde_json::json!(user));
+                }
             }
         }
 
@@ -107,114 +701,792 @@ impl Agent {
             }
         }
 
-        // Send to backend
-        self.connection.send_exception(exc);
+        if config.detect_spikes {
+            exc.error_rate = Some(self.spikes.observe(&exc.fingerprint));
+        }
+        request::limit_client_ips(exc, config.capture_client_ip);
+        exc.project_key = config.project_key(exc);
     }
 
-    /// Sets custom context.
-    pub fn set_context(&self, context: HashMap<String, serde_json::Value>) {
-        let mut custom = self.custom_context.write();
-        *custom = context;
+    /// Whether the sampling decision needs nothing from the event, so it can
+    /// be made before the cost of capturing it.
+    fn samples_before_capture(config: &Config) -> bool {
+        config.sampling_mode == SamplingMode::Random
+            && config.target_events_per_minute.is_none()
+            && config.sampler.is_none()
     }
 
-    /// Sets user information.
-    pub fn set_user(&self, id: Option<&str>, email: Option<&str>, username: Option<&str>) {
-        let mut user = self.user.write();
-        user.clear();
-        if let Some(id) = id {
-            user.insert("id".to_string(), id.to_string());
+    /// Returns the rate `exc` was sampled at, or `None` if it is dropped.
+    fn sample(&self, exc: &ExceptionCapture) -> Option<f64> {
+        let config = self.config.read();
+        if Self::samples_before_capture(&config) {
+            return Some(config.sampling_rate);
         }
-        if let Some(email) = email {
-            user.insert("email".to_string(), email.to_string());
+        let mut rate = match config.target_events_per_minute {
+            Some(target) => self.sampler.rate(target, &exc.fingerprint),
+            None => config.sampling_rate,
+        };
+        if let Some(sampler) = &config.sampler {
+            rate = sampler(&SamplingContext::new(exc, rate)).min(1.0);
         }
-        if let Some(username) = username {
-            user.insert("username".to_string(), username.to_string());
+        config.sampling_mode.keep(exc, rate).then_some(rate)
+    }
+
+    /// Sends a finished event to the backend and any local sinks.
+    fn deliver(&self, mut exc: ExceptionCapture) {
+        self.write_local(&mut exc);
+
+        // Send to backend, holding back repeats while aggregating
+        let interval = self.config.read().aggregation_interval;
+        match interval {
+            Some(interval) if exc.exception_type != "panic" => {
+                for exc in self.aggregator.add(exc, interval) {
+                    self.connection.send_exception(exc);
+                }
+            }
+            _ => self.connection.send_exception(exc),
         }
     }
 
-    fn install_panic_hook(&self) {
-        let config = self.config.clone();
+    /// Like [`deliver`](Self::deliver), but sends from the calling thread
+    /// within `timeout`, or keeps the event for the next process, for events
+    /// the process may not outlive.
+    fn deliver_blocking(&self, mut exc: ExceptionCapture, timeout: std::time::Duration) -> bool {
+        self.write_local(&mut exc);
+        self.connection.send_exception_blocking(exc, timeout)
+    }
 
-        panic::set_hook(Box::new(move |panic_info| {
-            if let Some(agent) = AGENT.get() {
-                // Create an error from panic info
-                let message = if let Some(s) = panic_info.payload().downcast_ref::<&str>() {
-                    s.to_string()
-                } else if let Some(s) = panic_info.payload().downcast_ref::<String>() {
-                    s.clone()
-                } else {
-                    "panic occurred".to_string()
-                };
+    /// Writes a finished event to the local sinks, which may add to it.
+    #[cfg_attr(not(feature = "journald"), allow(unused_variables))]
+    fn write_local(&self, exc: &mut ExceptionCapture) {
+        #[cfg(feature = "journald")]
+        {
+            journald::enrich(exc);
+            if let Some(journal) = &self.journal {
+                if let Err(e) = journal.write(exc) {
+                    self.connection
+                        .diagnostics()
+                        .record_error(format!("journal write failed: {}", e));
+                }
+            }
+        }
+    }
 
-                let location = panic_info.location().map(|loc| {
-                    format!("{}:{}:{}", loc.file(), loc.line(), loc.column())
-                });
+    /// Sends a cron check-in for `monitor` and returns its ID.
+    ///
+    /// See [`cron`] for pairing start and finish check-ins.
+    pub fn check_in(
+        &self,
+        monitor: &str,
+        status: cron::Status,
+        duration: Option<std::time::Duration>,
+    ) -> String {
+        let id = uuid::Uuid::new_v4().to_string();
+        self.check_in_with_id(id.clone(), monitor, status, duration);
+        id
+    }
 
-                let exc = capture::capture_panic(&message, location, &config);
-                agent.connection.send_exception(exc);
+    /// Sends a cron check-in for an existing run.
+    pub fn check_in_with_id(
+        &self,
+        check_in_id: String,
+        monitor: &str,
+        status: cron::Status,
+        duration: Option<std::time::Duration>,
+    ) {
+        let check_in =
+            cron::CheckIn::new(check_in_id, monitor, status, duration, &self.config.read());
+        self.connection.send_check_in(&check_in);
+    }
+
+    /// Records the response to the request of the innermost active scope,
+    /// and reports it if its status is in one of the `capture_status_classes`.
+    ///
+    /// See [`request::record_response`].
+    pub fn record_response(&self, status: u16, duration: std::time::Duration, size: Option<u64>) {
+        request::set_response(status, duration, size);
+        let spans = scope::take_spans();
+        if !spans.is_empty() {
+            let transaction = scope::request().and_then(|request| {
+                performance::Transaction::new(&request, spans, &self.config.read())
+            });
+            if let Some(transaction) = transaction {
+                self.connection.send_transaction(&transaction);
+            }
+        }
+        let capture = self
+            .config
+            .read()
+            .capture_status_classes
+            .iter()
+            .any(|class| class.contains(status));
+        if capture {
+            let normalizer = self.config.read().transaction_normalizer.clone();
+            self.capture_event_with(|| request::status_event(status, normalizer.as_ref()), None);
+        }
+    }
+
+    /// Sets custom context for events from every thread.
+    ///
+    /// Values are trimmed to the capture limits, and keys beyond
+    /// `max_context_keys` are dropped.
+    pub fn set_context(&self, context: HashMap<String, serde_json::Value>) {
+        let context = capture::limit_context(context, &self.config.read());
+        *self.custom_context.write() = context;
+    }
+
+    /// Sets a single context entry for events from every thread from any
+    /// serializable value.
+    ///
+    /// The value is trimmed to the configured capture limits.
+    pub fn set_context_typed<T: serde::Serialize + ?Sized>(
+        &self,
+        key: impl Into<String>,
+        value: &T,
+    ) -> Result<(), serde_json::Error> {
+        let value = serde_json::to_value(value)?;
+        capture::insert_context(
+            &mut self.custom_context.write(),
+            key.into(),
+            value,
+            &self.config.read(),
+        );
+        Ok(())
+    }
+
+    /// Sets the user affected by subsequent errors on threads that have not
+    /// set their own.
+    pub fn set_user(&self, user: User) {
+        *self.user.write() = Some(user).filter(|u| !u.is_empty());
+    }
+
+    /// Clears the current user.
+    pub fn clear_user(&self) {
+        *self.user.write() = None;
+    }
+
+    /// Records a breadcrumb on the innermost active scope of the current
+    /// thread, or on the thread itself, sent with the events captured there
+    /// after it; see [`breadcrumbs`].
+    pub fn add_breadcrumb(&self, breadcrumb: Breadcrumb) {
+        let max = {
+            let config = self.config.read();
+            if !config.keeps_breadcrumb(&breadcrumb.category, breadcrumb.level) {
+                return;
             }
+            config.max_breadcrumbs
+        };
+        scope::add_breadcrumb(breadcrumb, max);
+    }
 
-            // Print default panic message
-            eprintln!("{}", panic_info);
-        }));
+    /// Sets user information from individual fields.
+    #[deprecated(since = "2.0.0", note = "use `set_user` with a `User`")]
+    pub fn set_user_fields(&self, id: Option<&str>, email: Option<&str>, username: Option<&str>) {
+        self.set_user(User {
+            id: id.map(String::from),
+            email: email.map(String::from),
+            username: username.map(String::from),
+            ..Default::default()
+        });
+    }
+
+    /// Applies the settings that are safe to change while running.
+    ///
+    /// Only fields that differ between `previous` and `updated` are applied,
+    /// so values set programmatically survive reloads of an unchanged file.
+    /// Settings read once at startup, such as `capture_env_vars` and
+    /// `capture_command_line`, and those of the connection itself, such as
+    /// the backend URL and transport, still require a restart.
+    pub fn apply_reload(&self, previous: &Config, updated: &Config) {
+        let mut config = self.config.write();
+
+        macro_rules! reload_field {
+            ($field:ident) => {
+                reload_field!($field, updated.$field)
+            };
+            ($field:ident, $value:expr) => {
+                if previous.$field != updated.$field {
+                    config.logger.info(format_args!(
+                        "Config reloaded: {} {:?} -> {:?}",
+                        stringify!($field),
+                        config.$field,
+                        updated.$field
+                    ));
+                    config.$field = $value;
+                }
+            };
+        }
+
+        reload_field!(sampling_rate);
+        reload_field!(target_events_per_minute);
+        reload_field!(sampling_mode);
+        reload_field!(debug);
+        reload_field!(max_capture_depth);
+        reload_field!(max_string_length);
+        reload_field!(max_collection_size);
+        reload_field!(max_context_keys);
+        reload_field!(max_breadcrumbs);
+        reload_field!(min_breadcrumb_level);
+        reload_field!(log_level);
+        reload_field!(breadcrumb_levels, updated.breadcrumb_levels.clone());
+        reload_field!(
+            ignore_breadcrumb_categories,
+            updated.ignore_breadcrumb_categories.clone()
+        );
+        reload_field!(scrub_home_dir);
+        reload_field!(capture_client_ip);
+        reload_field!(circuit_breaker_threshold);
+        reload_field!(circuit_breaker_cooldown);
+        reload_field!(max_bytes_per_second);
+
+        config.logger.set_level(config.effective_log_level());
+        self.connection.configure_limits(&config);
     }
 }
 
-/// Initializes the global agent.
-pub fn init(config: Config) {
-    let agent = Arc::new(Agent::new(config));
+/// Memory set aside at startup and released at the start of the panic hook,
+/// so capturing can still allocate when the panic is due to memory pressure.
+static PANIC_RESERVE: parking_lot::Mutex<Vec<u8>> = parking_lot::Mutex::new(Vec::new());
+
+/// Size of [`PANIC_RESERVE`].
+const PANIC_RESERVE_BYTES: usize = 256 * 1024;
+
+/// How long the panic hook waits for the configuration, which the panicking
+/// thread itself may hold.
+const PANIC_HOOK_LOCK_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(100);
+
+/// Sets aside the [`PANIC_RESERVE`] again, if memory allows.
+fn fill_panic_reserve() {
+    if let Some(mut reserve) = PANIC_RESERVE.try_lock() {
+        if reserve.capacity() == 0 && reserve.try_reserve_exact(PANIC_RESERVE_BYTES).is_ok() {
+            // Touch the pages, so the reserve is backed by memory.
+            reserve.resize(PANIC_RESERVE_BYTES, 0);
+        }
+    }
+}
+
+/// Prints the standard panic message; unlike `eprintln!`, never panics
+/// itself when stderr is closed.
+fn print_panic(panic_info: &dyn std::fmt::Display) {
+    use std::io::Write;
+    let _ = writeln!(std::io::stderr(), "{}", panic_info);
+}
+
+// A panic inside a panic hook aborts the process and cannot be caught, so the
+// hook only takes locks with a timeout, reads thread state without panicking,
+// and leaves the agent alone when the panic came from inside it.
+fn install_panic_hook() {
+    fill_panic_reserve();
+    panic::set_hook(Box::new(move |panic_info| {
+        // The agent's locks may be held by this thread, and the panic is
+        // reported once `report` catches it.
+        if internal::is_capturing() {
+            print_panic(panic_info);
+            return;
+        }
+        if let Some(client) = CLIENT.get().filter(|client| client.is_enabled()) {
+            if let Some(mut reserve) = PANIC_RESERVE.try_lock() {
+                *reserve = Vec::new();
+            }
+            let Some(config) = client.config.try_read_for(PANIC_HOOK_LOCK_TIMEOUT) else {
+                print_panic(panic_info);
+                return;
+            };
+            let payload = capture::extract_panic_payload(
+                panic_info.payload(),
+                &config.panic_payload_extractors,
+            );
+            let message = payload.message;
+
+            let location = panic_info.location();
+
+            // A panic on one of our own threads is an agent bug, not an
+            // application exception.
+            if internal::is_agent_thread() {
+                let message = match location {
+                    Some(loc) => format!(
+                        "{} at {}:{}:{}",
+                        message,
+                        loc.file(),
+                        loc.line(),
+                        loc.column()
+                    ),
+                    None => message,
+                };
+                client
+                    .connection
+                    .report_internal_error(InternalErrorKind::AgentPanic, message);
+                print_panic(panic_info);
+                return;
+            }
+
+            let timer = client.connection.diagnostics().time_capture();
+            let elapsed_ms = scope::elapsed_ms();
+            let mut exc = capture::capture_panic_in_hook(&message, location, &config);
+            let panic_payload = (!payload.fields.is_empty()).then(|| {
+                let fields = serde_json::Value::Object(payload.fields.into_iter().collect());
+                HashMap::from([(
+                    "panic_payload".to_string(),
+                    capture::limit_value(fields, &config),
+                )])
+            });
+            client.enrich_with(&mut exc, panic_payload, elapsed_ms, &config);
+            if config.capture_all_threads {
+                exc.threads = capture::all_threads(&config);
+            }
+            // The process ends with the panic unless another thread panicked.
+            if cfg!(panic = "abort") || std::thread::current().name() == Some("main") {
+                session::end(session::ExitReason::Panic {
+                    message: exc.message.clone(),
+                });
+            }
+            // With `panic = "abort"` the process ends when the hook returns,
+            // before the background sender gets to the event.
+            let flush_timeout = config.panic_flush_timeout;
+            drop(config);
+            #[cfg(not(target_os = "wasi"))]
+            task::mark_panic_reported();
+            if cfg!(panic = "abort") && !flush_timeout.is_zero() {
+                drop(timer);
+                let deadline = std::time::Instant::now() + flush_timeout;
+                client.deliver_blocking(exc, flush_timeout);
+                client
+                    .flush_blocking(deadline.saturating_duration_since(std::time::Instant::now()));
+            } else {
+                client.deliver(exc);
+                fill_panic_reserve();
+            }
+        }
+
+        // Print default panic message
+        print_panic(panic_info);
+    }));
+}
 
-    if AGENT.set(agent.clone()).is_err() {
-        eprintln!("[AIVory Monitor] Agent already initialized");
+/// Initializes the global agent and installs the panic hook.
+pub fn init(config: Config) {
+    if let Some(client) = CLIENT.get() {
+        client
+            .config
+            .read()
+            .logger
+            .warn(format_args!("Agent already initialized"));
         return;
     }
 
-    // Start in background
-    std::thread::spawn(move || {
-        let rt = tokio::runtime::Runtime::new().unwrap();
-        rt.block_on(async {
-            agent.start().await;
-        });
-    });
+    let install_hook = !config.api_key.is_empty() || config.dry_run;
+    let dry_run = config.dry_run;
+    let client = CLIENT.get_or_init(|| Client::new(config));
+
+    if install_hook {
+        if !dry_run {
+            session::start(&client.config.read());
+        }
+        install_panic_hook();
+        #[cfg(all(feature = "shutdown-hooks", unix))]
+        signals::install(client.clone());
+    }
+}
+
+/// Returns a handle to the global agent, if initialized.
+pub fn client() -> Option<Client> {
+    CLIENT.get().cloned()
 }
 
 /// Captures an error using the global agent.
 pub fn capture_error<E: std::error::Error>(error: &E) {
-    if let Some(agent) = AGENT.get() {
-        agent.capture_error(error, None);
+    if let Some(client) = hub::client() {
+        client.capture_error(error, None);
+    }
+}
+
+/// Captures an error reported as `exception_type` instead of its type name
+/// using the global agent.
+///
+/// ```rust,no_run
+/// if let Err(e) = std::fs::read("orders.db") {
+///     aivory_monitor::capture_error_as("io::NotFound", &e);
+/// }
+/// ```
+pub fn capture_error_as<E: std::error::Error>(exception_type: &str, error: &E) {
+    if let Some(client) = hub::client() {
+        client.capture_error_as(exception_type, error, None);
     }
 }
 
+/// Captures an error with its fields as structured local variables using the
+/// global agent; see [`Capturable`].
+pub fn capture_typed_error<E: Capturable>(error: &E) {
+    if let Some(client) = hub::client() {
+        client.capture_typed_error(error, None);
+    }
+}
+
+/// Captures an error wrapping several failures, such as a batch of
+/// validation errors, using the global agent. Each failure is reported in
+/// `exceptions` with its own type, message and causes.
+///
+/// ```rust,no_run
+/// # #[derive(Debug)]
+/// # struct ImportErrors { rows: Vec<std::num::ParseIntError> }
+/// # impl std::fmt::Display for ImportErrors {
+/// #     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result { f.write_str("import failed") }
+/// # }
+/// # impl std::error::Error for ImportErrors {}
+/// # fn import() -> Result<(), ImportErrors> { Ok(()) }
+/// if let Err(e) = import() {
+///     aivory_monitor::capture_aggregate_error(&e, &e.rows);
+/// }
+/// ```
+pub fn capture_aggregate_error<'a, E, I, F>(error: &E, failures: I)
+where
+    E: std::error::Error + ?Sized,
+    I: IntoIterator<Item = &'a F>,
+    F: std::error::Error + ?Sized + 'a,
+{
+    if let Some(client) = hub::client() {
+        client.capture_aggregate_error(error, failures, None);
+    }
+}
+
+/// Captures a type-erased error using the global agent.
+///
+/// ```rust,no_run
+/// fn run() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+///     Ok(())
+/// }
+///
+/// if let Err(e) = run() {
+///     aivory_monitor::capture_dyn_error(&*e);
+/// }
+/// ```
+pub fn capture_dyn_error(error: &(dyn std::error::Error + 'static)) {
+    if let Some(client) = hub::client() {
+        client.capture_dyn_error(error, None);
+    }
+}
+
+/// Captures a Tokio task that panicked or was cancelled using the global agent.
+///
+/// Panics are reported with the message from the `JoinError` under the
+/// `panic` type, cancellations as `TaskCancelled`; both carry the `task_id`.
+/// Panics in tasks spawned with [`task::spawn_monitored`] are already
+/// reported, with the location they occurred at.
+///
+/// ```rust,no_run
+/// # async fn run() {
+/// let handle = tokio::spawn(async { panic!("worker crashed") });
+/// if let Err(e) = handle.await {
+///     aivory_monitor::capture_join_error(&e);
+/// }
+/// # }
+/// ```
+#[cfg(not(target_os = "wasi"))]
+pub fn capture_join_error(error: &tokio::task::JoinError) {
+    if let Some(client) = hub::client() {
+        client.capture_join_error(error, None);
+    }
+}
+
+/// Submits a manually constructed event using the global agent.
+pub fn capture_event(event: EventBuilder) {
+    if let Some(client) = hub::client() {
+        client.capture_event(event, None);
+    }
+}
+
+/// Submits the event built by `event` using the global agent, calling it only
+/// if the event is captured at all.
+///
+/// Formatting a message costs allocations even when the agent is disabled
+/// or would sample the event out; deferring it to the closure avoids them.
+///
+/// ```rust,no_run
+/// use aivory_monitor::EventBuilder;
+///
+/// # let (job, attempts) = ("import", 3);
+/// aivory_monitor::capture_event_with(|| {
+///     EventBuilder::new("JobFailed").message(format!("{} failed after {} attempts", job, attempts))
+/// });
+/// ```
+pub fn capture_event_with(event: impl FnOnce() -> EventBuilder) {
+    if let Some(client) = hub::client() {
+        client.capture_event_with(event, None);
+    }
+}
+
+/// Whether the current thread's client, normally the global agent, exists
+/// and captured events go anywhere; see [`Agent::is_enabled`].
+pub fn is_enabled() -> bool {
+    hub::client().is_some_and(|client| client.is_enabled())
+}
+
 /// Captures an error with context using the global agent.
 pub fn capture_error_with_context<E: std::error::Error>(
     error: &E,
     context: HashMap<String, serde_json::Value>,
 ) {
-    if let Some(agent) = AGENT.get() {
-        agent.capture_error(error, Some(context));
+    if let Some(client) = hub::client() {
+        client.capture_error(error, Some(context));
     }
 }
 
-/// Sets custom context using the global agent.
+/// Sets context added to events from every thread, using the current
+/// thread's client. [`set_thread_context`] sets it for the current thread
+/// only.
 pub fn set_context(context: HashMap<String, serde_json::Value>) {
-    if let Some(agent) = AGENT.get() {
-        agent.set_context(context);
+    if let Some(client) = hub::client() {
+        client.set_context(context);
+    }
+}
+
+/// Sets a single context entry for events from every thread from any
+/// serializable value, using the current thread's client.
+///
+/// ```rust,no_run
+/// #[derive(serde::Serialize)]
+/// struct Deployment {
+///     region: String,
+///     canary: bool,
+/// }
+///
+/// let deployment = Deployment { region: "eu-west-1".into(), canary: false };
+/// aivory_monitor::set_context_typed("deployment", &deployment).unwrap();
+/// ```
+pub fn set_context_typed<T: serde::Serialize + ?Sized>(
+    key: impl Into<String>,
+    value: &T,
+) -> Result<(), serde_json::Error> {
+    match hub::client() {
+        Some(client) => client.set_context_typed(key, value),
+        None => Ok(()),
+    }
+}
+
+/// Sets the user affected by errors on threads that have not set their own
+/// with [`set_thread_user`], using the current thread's client.
+pub fn set_user(user: User) {
+    if let Some(client) = hub::client() {
+        client.set_user(user);
+    }
+}
+
+/// Clears the user set with [`set_user`].
+pub fn clear_user() {
+    if let Some(client) = hub::client() {
+        client.clear_user();
+    }
+}
+
+/// Sets user information from individual fields.
+#[deprecated(since = "2.0.0", note = "use `set_user` with a `User`")]
+pub fn set_user_fields(id: Option<&str>, email: Option<&str>, username: Option<&str>) {
+    set_user(User {
+        id: id.map(String::from),
+        email: email.map(String::from),
+        username: username.map(String::from),
+        ..Default::default()
+    });
+}
+
+/// Sets the context of the current thread, added to events captured on it
+/// over the context set with [`set_context`]; see [`hub`].
+pub fn set_thread_context(context: HashMap<String, serde_json::Value>) {
+    Hub::with_current(|hub| hub.set_context(context));
+}
+
+/// Sets a single context entry of the current thread from any serializable
+/// value.
+///
+/// ```rust,no_run
+/// #[derive(serde::Serialize)]
+/// struct Request {
+///     method: String,
+///     path: String,
+/// }
+///
+/// let request = Request { method: "GET".into(), path: "/orders".into() };
+/// aivory_monitor::set_thread_context_typed("request", &request).unwrap();
+/// ```
+pub fn set_thread_context_typed<T: serde::Serialize + ?Sized>(
+    key: impl Into<String>,
+    value: &T,
+) -> Result<(), serde_json::Error> {
+    Hub::with_current(|hub| hub.set_context_typed(key, value))
+}
+
+/// Sets the user affected by subsequent errors on the current thread, in
+/// place of the one set with [`set_user`].
+pub fn set_thread_user(user: User) {
+    Hub::with_current(|hub| hub.set_user(user));
+}
+
+/// Clears the user of the current thread; the one set with [`set_user`]
+/// applies again.
+pub fn clear_thread_user() {
+    Hub::with_current(|hub| hub.clear_user());
+}
+
+/// Records a breadcrumb with the current thread's client; see [`breadcrumbs`].
+pub fn add_breadcrumb(breadcrumb: Breadcrumb) {
+    if let Some(client) = hub::client() {
+        client.add_breadcrumb(breadcrumb);
+    }
+}
+
+/// Returns a snapshot of the global agent's health counters.
+///
+/// Returns empty diagnostics if the agent has not been initialized.
+pub fn diagnostics() -> AgentDiagnostics {
+    CLIENT
+        .get()
+        .map(|client| client.diagnostics())
+        .unwrap_or_default()
+}
+
+/// Returns the time the global agent has spent per capture, to check that it
+/// stays within an application's overhead budget.
+///
+/// Returns zeroed stats if the agent has not been initialized.
+///
+/// ```rust,no_run
+/// use std::time::Duration;
+///
+/// let overhead = aivory_monitor::overhead_stats();
+/// if !overhead.within_budget(Duration::from_micros(500)) {
+///     eprintln!("{} captures, {:?} on average, {:?} at most", overhead.captures, overhead.mean(), overhead.max());
+/// }
+/// ```
+pub fn overhead_stats() -> OverheadStats {
+    CLIENT
+        .get()
+        .map(|client| client.overhead_stats())
+        .unwrap_or_default()
+}
+
+/// Blocks until the global agent has sent captured events or `timeout` elapses.
+///
+/// Returns true if all of them were sent, and false if some are left or were
+/// dropped meanwhile.
+///
+/// ```rust,no_run
+/// use std::time::Duration;
+///
+/// fn handler() -> Result<(), std::io::Error> {
+///     let result = run();
+///     aivory_monitor::flush_blocking(Duration::from_secs(2));
+///     result
+/// }
+/// # fn run() -> Result<(), std::io::Error> { Ok(()) }
+/// ```
+pub fn flush_blocking(timeout: std::time::Duration) -> bool {
+    match CLIENT.get() {
+        Some(client) => client.flush_blocking(timeout),
+        None => true,
     }
 }
 
-/// Sets user information using the global agent.
-pub fn set_user(id: Option<&str>, email: Option<&str>, username: Option<&str>) {
-    if let Some(agent) = AGENT.get() {
-        agent.set_user(id, email, username);
+/// Sends a marker event and blocks until the backend acknowledges it, for up
+/// to 10 seconds including the time to connect.
+///
+/// Deployment pipelines can call this after [`init`] to verify telemetry
+/// reaches the backend before going live. The event has the type
+/// `AivoryTestEvent` and the context value `aivory_test_event: true`, and is
+/// sent regardless of sampling.
+///
+/// ```rust,no_run
+/// aivory_monitor::init(aivory_monitor::Config::default());
+/// if let Err(e) = aivory_monitor::send_test_event() {
+///     eprintln!("AIVory is not receiving events: {}", e);
+///     std::process::exit(1);
+/// }
+/// ```
+pub fn send_test_event() -> Result<EventId, SendError> {
+    match CLIENT.get() {
+        Some(client) => client.send_test_event(),
+        None => Err(SendError::NotInitialized),
     }
 }
 
-/// Shuts down the global agent.
+/// Captures an error using the global agent and waits until the backend
+/// acknowledges it, for up to 10 seconds including the time to connect.
+///
+/// For critical flows, such as a failed payment, that should only go on once
+/// the error is known to be recorded. The event is captured right away with
+/// the current thread's context, is sent regardless of sampling, aggregation
+/// and the circuit breaker, and is delivered even if the future is dropped.
+/// The future works with any executor.
+///
+/// ```rust,no_run
+/// # async fn charge() -> Result<(), std::io::Error> { Ok(()) }
+/// # async fn run() {
+/// if let Err(e) = charge().await {
+///     match aivory_monitor::capture_error_and_wait(&e).await {
+///         Ok(id) => eprintln!("payment failure recorded as {}", id),
+///         Err(delivery) => eprintln!("payment failure not recorded: {}", delivery),
+///     }
+/// }
+/// # }
+/// ```
+#[cfg(not(target_os = "wasi"))]
+pub fn capture_error_and_wait<E: std::error::Error>(
+    error: &E,
+) -> impl std::future::Future<Output = Result<EventId, DeliveryError>> + Send + 'static {
+    let sent = hub::client().map(|client| client.capture_error_and_wait(error, None));
+    async move {
+        match sent {
+            Some(sent) => sent.await,
+            None => Err(DeliveryError::Disabled),
+        }
+    }
+}
+
+/// Runs `main` and captures the error it returns, which the panic hook does
+/// not see, flushing it for up to `panic_flush_timeout` before returning it so
+/// Rust prints it and exits.
+///
+/// Boxed errors are reported with their causes, other error types by their
+/// `Debug` output. With the `derive` feature, `#[aivory_monitor::main]` wraps
+/// `main` in this function.
+///
+/// ```rust,no_run
+/// use aivory_monitor::Config;
+///
+/// fn main() -> Result<(), Box<dyn std::error::Error>> {
+///     aivory_monitor::main_wrapper(|| {
+///         aivory_monitor::init(Config::default());
+///         std::fs::read("orders.db")?;
+///         Ok(())
+///     })
+/// }
+/// ```
+pub fn main_wrapper<T: 'static, E: std::fmt::Debug + 'static>(
+    main: impl FnOnce() -> Result<T, E>,
+) -> Result<T, E> {
+    let result = main();
+    // Rust exits with 1 when `main` returns an error.
+    let exit_code = match &result {
+        Ok(value) => (value as &dyn std::any::Any).is::<()>().then_some(0),
+        Err(_) => Some(1),
+    };
+    if let (Err(error), Some(client)) = (&result, hub::client()) {
+        client.capture_main_error(error);
+        let config = client.config();
+        if !client.flush_blocking(config.panic_flush_timeout) {
+            config.logger.warn(format_args!(
+                "Events not sent within {:?}",
+                config.panic_flush_timeout
+            ));
+        }
+    }
+    session::end(session::ExitReason::Exit { exit_code });
+    result
+}
+
+/// Shuts down the global agent, recording that the process exits.
 pub fn shutdown() {
-    if let Some(agent) = AGENT.get() {
-        let rt = tokio::runtime::Runtime::new().unwrap();
-        rt.block_on(async {
-            agent.stop().await;
-        });
+    if let Some(client) = CLIENT.get() {
+        session::end(session::ExitReason::Exit { exit_code: None });
+        client.shutdown();
     }
 }
@@ -0,0 +1,190 @@
+//! Internal logging.
+//!
+//! Every message the agent emits about itself goes through a [`Logger`], so
+//! applications with structured logging can redirect it to `log`/`tracing`
+//! (behind the features of the same name), to a callback, or silence it.
+
+use std::fmt;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+
+/// Severity of an internal log message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    /// Nothing is logged.
+    Off,
+    /// Failures that stop the agent from working.
+    Error,
+    /// Problems the agent recovers from.
+    Warn,
+    /// Lifecycle messages such as startup and shutdown.
+    Info,
+    /// Connection and protocol detail.
+    Debug,
+}
+
+impl LogLevel {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            0 => LogLevel::Off,
+            1 => LogLevel::Error,
+            2 => LogLevel::Warn,
+            3 => LogLevel::Info,
+            _ => LogLevel::Debug,
+        }
+    }
+
+    fn as_u8(self) -> u8 {
+        match self {
+            LogLevel::Off => 0,
+            LogLevel::Error => 1,
+            LogLevel::Warn => 2,
+            LogLevel::Info => 3,
+            LogLevel::Debug => 4,
+        }
+    }
+}
+
+impl std::str::FromStr for LogLevel {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "off" | "silent" | "none" => Ok(LogLevel::Off),
+            "error" => Ok(LogLevel::Error),
+            "warn" | "warning" => Ok(LogLevel::Warn),
+            "info" => Ok(LogLevel::Info),
+            "debug" => Ok(LogLevel::Debug),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Callback receiving internal log messages.
+pub type LogCallback = Arc<dyn Fn(LogLevel, &str) + Send + Sync>;
+
+/// Where internal log messages are written.
+#[derive(Clone, Default)]
+pub enum LogOutput {
+    /// `[AIVory Monitor]`-prefixed lines on stdout (info, debug) and stderr (warn, error).
+    #[default]
+    Stderr,
+    /// Discard all messages.
+    Silent,
+    /// Forward to the `log` crate under the `aivory_monitor` target.
+    #[cfg(feature = "log")]
+    Log,
+    /// Forward to `tracing` events under the `aivory_monitor` target.
+    #[cfg(feature = "tracing")]
+    Tracing,
+    /// Forward to a user callback.
+    Callback(LogCallback),
+}
+
+impl fmt::Debug for LogOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogOutput::Stderr => f.write_str("Stderr"),
+            LogOutput::Silent => f.write_str("Silent"),
+            #[cfg(feature = "log")]
+            LogOutput::Log => f.write_str("Log"),
+            #[cfg(feature = "tracing")]
+            LogOutput::Tracing => f.write_str("Tracing"),
+            LogOutput::Callback(_) => f.write_str("Callback"),
+        }
+    }
+}
+
+/// Handle used by the agent to emit internal messages.
+///
+/// Cheap to clone; clones share the active level so a runtime change (for
+/// example toggling `debug` through hot-reload) applies everywhere.
+#[derive(Clone)]
+pub struct Logger {
+    output: LogOutput,
+    level: Arc<AtomicU8>,
+}
+
+impl Logger {
+    /// Creates a logger writing to `output` at `level`.
+    pub fn new(output: LogOutput, level: LogLevel) -> Self {
+        Logger {
+            output,
+            level: Arc::new(AtomicU8::new(level.as_u8())),
+        }
+    }
+
+    /// Returns the most verbose level currently logged.
+    pub fn level(&self) -> LogLevel {
+        LogLevel::from_u8(self.level.load(Ordering::Relaxed))
+    }
+
+    pub(crate) fn set_level(&self, level: LogLevel) {
+        self.level.store(level.as_u8(), Ordering::Relaxed);
+    }
+
+    /// Returns true if messages at `level` are currently logged.
+    pub fn enabled(&self, level: LogLevel) -> bool {
+        level != LogLevel::Off && level <= self.level() && !matches!(self.output, LogOutput::Silent)
+    }
+
+    /// Logs a message at `level`.
+    pub fn log(&self, level: LogLevel, message: fmt::Arguments<'_>) {
+        if !self.enabled(level) {
+            return;
+        }
+
+        match &self.output {
+            LogOutput::Stderr => match level {
+                LogLevel::Error | LogLevel::Warn => eprintln!("[AIVory Monitor] {}", message),
+                _ => println!("[AIVory Monitor] {}", message),
+            },
+            LogOutput::Silent => {}
+            #[cfg(feature = "log")]
+            LogOutput::Log => {
+                let level = match level {
+                    LogLevel::Error => log::Level::Error,
+                    LogLevel::Warn => log::Level::Warn,
+                    LogLevel::Info => log::Level::Info,
+                    _ => log::Level::Debug,
+                };
+                log::log!(target: "aivory_monitor", level, "{}", message);
+            }
+            #[cfg(feature = "tracing")]
+            LogOutput::Tracing => match level {
+                LogLevel::Error => tracing::error!(target: "aivory_monitor", "{}", message),
+                LogLevel::Warn => tracing::warn!(target: "aivory_monitor", "{}", message),
+                LogLevel::Info => tracing::info!(target: "aivory_monitor", "{}", message),
+                _ => tracing::debug!(target: "aivory_monitor", "{}", message),
+            },
+            LogOutput::Callback(callback) => callback(level, &message.to_string()),
+        }
+    }
+
+    /// Logs at [`LogLevel::Error`].
+    pub fn error(&self, message: fmt::Arguments<'_>) {
+        self.log(LogLevel::Error, message);
+    }
+
+    /// Logs at [`LogLevel::Warn`].
+    pub fn warn(&self, message: fmt::Arguments<'_>) {
+        self.log(LogLevel::Warn, message);
+    }
+
+    /// Logs at [`LogLevel::Info`].
+    pub fn info(&self, message: fmt::Arguments<'_>) {
+        self.log(LogLevel::Info, message);
+    }
+
+    /// Logs at [`LogLevel::Debug`].
+    pub fn debug(&self, message: fmt::Arguments<'_>) {
+        self.log(LogLevel::Debug, message);
+    }
+}
+
+impl Default for Logger {
+    fn default() -> Self {
+        Logger::new(LogOutput::default(), LogLevel::Info)
+    }
+}
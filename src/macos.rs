@@ -0,0 +1,214 @@
+//! macOS crash reports.
+//!
+//! The crash reporter writes an `.ips` file to `DiagnosticReports` for every
+//! crash: a JSON header line followed by the JSON report, with the exception,
+//! the termination reason and the stacks of all threads.
+//! [`os_crashes`](crate::os_crashes) converts those of the current executable
+//! into events at the next startup, the crashed thread's stack as the trace.
+
+use crate::capture::{stack_frame, EventBuilder, Mechanism, StackFrame, MAX_FRAMES};
+use crate::config::Config;
+use crate::os_crashes::{OsCrash, EXCEPTION_TYPE};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+/// `bug_type` of a crash, as opposed to hangs, spins or resource reports.
+const CRASH_BUG_TYPE: &str = "309";
+
+/// First line of an `.ips` file.
+#[derive(Deserialize)]
+struct Header {
+    bug_type: String,
+    /// When the crash happened, e.g. `2026-05-01 10:11:12.00 +0200`.
+    timestamp: String,
+    #[serde(default)]
+    incident_id: Option<String>,
+    #[serde(default)]
+    app_version: Option<String>,
+    #[serde(default)]
+    os_version: Option<String>,
+}
+
+/// The parts of the report body that are converted.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Report {
+    proc_path: String,
+    #[serde(default)]
+    exception: Option<ExceptionInfo>,
+    #[serde(default)]
+    termination: Option<Value>,
+    /// Application specific information, such as `abort() called`.
+    #[serde(default)]
+    asi: Option<Value>,
+    #[serde(default)]
+    faulting_thread: Option<usize>,
+    #[serde(default)]
+    threads: Vec<Thread>,
+    #[serde(default)]
+    used_images: Vec<Image>,
+}
+
+#[derive(Deserialize)]
+struct ExceptionInfo {
+    #[serde(rename = "type")]
+    exception_type: String,
+    #[serde(default)]
+    signal: Option<String>,
+    #[serde(default)]
+    subtype: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Thread {
+    #[serde(default)]
+    triggered: bool,
+    #[serde(default)]
+    frames: Vec<Frame>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Frame {
+    #[serde(default)]
+    image_index: Option<usize>,
+    #[serde(default)]
+    image_offset: Option<u64>,
+    #[serde(default)]
+    symbol: Option<String>,
+    #[serde(default)]
+    source_file: Option<String>,
+    #[serde(default)]
+    source_line: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct Image {
+    #[serde(default)]
+    name: Option<String>,
+}
+
+/// Crashes of `exe` after `since`, in Unix milliseconds.
+pub(crate) fn crashes_since(exe: &Path, since: i64, config: &Config) -> Vec<OsCrash> {
+    let Some(exe_name) = exe
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+    else {
+        return Vec::new();
+    };
+    // Named `<process>-<date>-<time>.ips`.
+    let prefix = format!("{}-", exe_name);
+    report_dirs()
+        .iter()
+        .filter_map(|dir| std::fs::read_dir(dir).ok())
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let name = path.file_name()?.to_string_lossy().into_owned();
+            (name.starts_with(&prefix) && name.ends_with(".ips")).then_some(path)
+        })
+        .filter_map(|path| read_crash(&path, exe, since, config))
+        .collect()
+}
+
+/// Directories crash reports are written to, for the user and for the machine.
+fn report_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    if let Some(home) = std::env::var_os("HOME") {
+        dirs.push(PathBuf::from(home).join("Library/Logs/DiagnosticReports"));
+    }
+    dirs.push(PathBuf::from("/Library/Logs/DiagnosticReports"));
+    dirs
+}
+
+fn read_crash(path: &Path, exe: &Path, since: i64, config: &Config) -> Option<OsCrash> {
+    let contents = std::fs::read_to_string(path).ok()?;
+    let (header, body) = contents.split_once('\n')?;
+    let header: Header = serde_json::from_str(header).ok()?;
+    if header.bug_type != CRASH_BUG_TYPE {
+        return None;
+    }
+    let occurred_at =
+        DateTime::parse_from_str(&header.timestamp, "%Y-%m-%d %H:%M:%S%.f %z").ok()?;
+    if occurred_at.timestamp_millis() <= since {
+        return None;
+    }
+    let report: Report = serde_json::from_str(body).ok()?;
+    if Path::new(&report.proc_path) != exe {
+        return None;
+    }
+
+    let message = match &report.exception {
+        Some(exception) => {
+            let mut message = format!("previous run crashed with {}", exception.exception_type);
+            if let Some(signal) = &exception.signal {
+                message.push_str(&format!(" ({})", signal));
+            }
+            if let Some(subtype) = &exception.subtype {
+                message.push_str(&format!(": {}", subtype));
+            }
+            message
+        }
+        None => "previous run crashed".to_string(),
+    };
+    let event = EventBuilder::new(EXCEPTION_TYPE)
+        .message(message)
+        .stack_trace(crashed_stack(&report, config))
+        .mechanism(Mechanism::Signal)
+        .handled(false)
+        .captured_at(occurred_at.with_timezone(&Utc))
+        .context(
+            "crash_report",
+            serde_json::json!({
+                "incident_id": header.incident_id,
+                "app_version": header.app_version.filter(|version| !version.is_empty()),
+                "os_version": header.os_version,
+                "exception_type": report.exception.as_ref().map(|exception| &exception.exception_type),
+                "signal": report.exception.as_ref().and_then(|exception| exception.signal.as_ref()),
+                "termination": report.termination,
+                "application_specific_information": report.asi,
+            }),
+        );
+    Some(OsCrash {
+        occurred_at: occurred_at.timestamp_millis(),
+        event,
+    })
+}
+
+/// The stack of the thread that crashed, innermost first.
+fn crashed_stack(report: &Report, config: &Config) -> Vec<StackFrame> {
+    let thread = report
+        .faulting_thread
+        .and_then(|index| report.threads.get(index))
+        .or_else(|| report.threads.iter().find(|thread| thread.triggered));
+    let Some(thread) = thread else {
+        return Vec::new();
+    };
+    thread
+        .frames
+        .iter()
+        .filter_map(|frame| {
+            let image = frame
+                .image_index
+                .and_then(|index| report.used_images.get(index))
+                .and_then(|image| image.name.as_deref())
+                .unwrap_or("???");
+            // Unsymbolicated frames are named by image and offset, as in the
+            // crash reporter's own text rendering.
+            let name = match &frame.symbol {
+                Some(symbol) => symbol.clone(),
+                None => format!("{} + {}", image, frame.image_offset.unwrap_or(0)),
+            };
+            stack_frame(
+                &name,
+                frame.source_file.clone(),
+                frame.source_line,
+                None,
+                config,
+            )
+        })
+        .take(MAX_FRAMES)
+        .collect()
+}
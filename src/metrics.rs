@@ -0,0 +1,161 @@
+//! Prometheus metrics for agent internals.
+//!
+//! Exposes the counters behind [`crate::diagnostics()`] as a Prometheus
+//! collector, so agent health can be scraped alongside application metrics:
+//!
+//! ```rust,no_run
+//! let registry = prometheus::Registry::new();
+//! registry
+//!     .register(Box::new(aivory_monitor::metrics::AgentCollector::new()))
+//!     .unwrap();
+//! ```
+
+use crate::diagnostics::{AgentDiagnostics, ConnectionState, DropReason};
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::MetricFamily;
+use prometheus::{
+    Encoder, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+
+/// Collector that reads the global agent's diagnostics at scrape time.
+pub struct AgentCollector {
+    connection_state: IntGaugeVec,
+    queue_depth: IntGauge,
+    events_sent: IntCounter,
+    events_dropped: IntCounterVec,
+    reconnects: IntCounter,
+    bytes_sent: IntCounter,
+    circuit_open: IntGauge,
+}
+
+impl AgentCollector {
+    /// Creates a collector for the global agent.
+    pub fn new() -> Self {
+        AgentCollector {
+            connection_state: IntGaugeVec::new(
+                Opts::new(
+                    "aivory_agent_connection_state",
+                    "Current backend connection state (1 for the active state)",
+                ),
+                &["state"],
+            )
+            .unwrap(),
+            queue_depth: IntGauge::new(
+                "aivory_agent_queue_depth",
+                "Messages waiting in the outbound queue",
+            )
+            .unwrap(),
+            events_sent: IntCounter::new(
+                "aivory_agent_events_sent_total",
+                "Events written to the backend connection",
+            )
+            .unwrap(),
+            events_dropped: IntCounterVec::new(
+                Opts::new(
+                    "aivory_agent_events_dropped_total",
+                    "Events not delivered, by reason",
+                ),
+                &["reason"],
+            )
+            .unwrap(),
+            reconnects: IntCounter::new(
+                "aivory_agent_reconnects_total",
+                "Reconnect attempts since startup",
+            )
+            .unwrap(),
+            bytes_sent: IntCounter::new(
+                "aivory_agent_bytes_sent_total",
+                "Bytes written to the backend connection",
+            )
+            .unwrap(),
+            circuit_open: IntGauge::new(
+                "aivory_agent_circuit_open",
+                "1 while capture is paused by the circuit breaker",
+            )
+            .unwrap(),
+        }
+    }
+
+    fn update(&self, diagnostics: &AgentDiagnostics) {
+        for state in ConnectionState::ALL {
+            let value = i64::from(diagnostics.connection_state == state);
+            self.connection_state
+                .with_label_values(&[state.as_str()])
+                .set(value);
+        }
+
+        self.queue_depth.set(diagnostics.queue_depth as i64);
+        set_counter(&self.events_sent, diagnostics.events_sent);
+        set_counter(&self.reconnects, diagnostics.reconnect_count);
+        set_counter(&self.bytes_sent, diagnostics.bytes_sent);
+        self.circuit_open.set(i64::from(diagnostics.circuit_open));
+
+        for reason in DropReason::ALL {
+            let count = diagnostics
+                .events_dropped
+                .get(&reason)
+                .copied()
+                .unwrap_or(0);
+            set_counter(
+                &self.events_dropped.with_label_values(&[reason.as_str()]),
+                count,
+            );
+        }
+    }
+}
+
+impl Default for AgentCollector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Collector for AgentCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        let mut descs = Vec::new();
+        descs.extend(self.connection_state.desc());
+        descs.extend(self.queue_depth.desc());
+        descs.extend(self.events_sent.desc());
+        descs.extend(self.events_dropped.desc());
+        descs.extend(self.reconnects.desc());
+        descs.extend(self.bytes_sent.desc());
+        descs.extend(self.circuit_open.desc());
+        descs
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        self.update(&crate::diagnostics());
+
+        let mut families = Vec::new();
+        families.extend(self.connection_state.collect());
+        families.extend(self.queue_depth.collect());
+        families.extend(self.events_sent.collect());
+        families.extend(self.events_dropped.collect());
+        families.extend(self.reconnects.collect());
+        families.extend(self.bytes_sent.collect());
+        families.extend(self.circuit_open.collect());
+        families
+    }
+}
+
+/// Returns a new registry with an [`AgentCollector`] registered.
+pub fn registry() -> Registry {
+    let registry = Registry::new();
+    registry
+        .register(Box::new(AgentCollector::new()))
+        .expect("agent metrics have unique names");
+    registry
+}
+
+/// Renders the agent metrics in the Prometheus text exposition format.
+pub fn text() -> String {
+    let mut buffer = Vec::new();
+    let _ = TextEncoder::new().encode(&registry().gather(), &mut buffer);
+    String::from_utf8(buffer).unwrap_or_default()
+}
+
+/// Counters only go up; mirror an absolute value by resetting first.
+fn set_counter(counter: &IntCounter, value: u64) {
+    counter.reset();
+    counter.inc_by(value);
+}
@@ -0,0 +1,83 @@
+//! Crashes of earlier runs recorded by the OS.
+//!
+//! A process killed by an access violation, a stack overflow or `abort`
+//! never runs the panic hook, but the OS still writes a crash report: a
+//! Windows Error Reporting `Report.wer`, or a macOS `.ips` file. At startup
+//! the agent reports the ones written for its executable since the last
+//! check as `CrashedLastRun` events, dated when the crash happened.
+
+use crate::capture::EventBuilder;
+use crate::config;
+use crate::{internal, Agent};
+use chrono::Utc;
+use std::sync::Arc;
+
+#[cfg(target_os = "macos")]
+use crate::macos::crashes_since;
+#[cfg(windows)]
+use crate::windows::crashes_since;
+
+/// Exception type of a crash read from an OS crash report.
+pub(crate) const EXCEPTION_TYPE: &str = "CrashedLastRun";
+
+/// How far back crashes are reported when no check was recorded yet.
+const FIRST_RUN_WINDOW_MILLIS: i64 = 24 * 60 * 60 * 1000;
+
+/// Most crashes reported per startup, newest first.
+const MAX_REPORTS: usize = 10;
+
+/// A crash read from an OS crash report.
+pub(crate) struct OsCrash {
+    /// Unix time in milliseconds.
+    pub(crate) occurred_at: i64,
+    pub(crate) event: EventBuilder,
+}
+
+/// Reports the crashes recorded since the last check on a background thread.
+pub(crate) fn report_last_crashes(agent: Arc<Agent>) {
+    let logger = agent.config.read().logger.clone();
+    let spawned = std::thread::Builder::new()
+        .name("aivory-os-crashes".to_string())
+        .spawn(move || {
+            internal::mark_agent_thread();
+            let config = agent.config();
+            let Ok(exe) = std::env::current_exe() else {
+                return;
+            };
+            // Without a place to record what was reported, every start would report it again.
+            let Some(dir) = config.private_state_dir() else {
+                return;
+            };
+            let marker = dir.join(format!("os-crashes-{}.last", config::exe_name()));
+            let since = std::fs::read_to_string(&marker)
+                .ok()
+                .and_then(|contents| contents.trim().parse().ok())
+                .unwrap_or_else(|| Utc::now().timestamp_millis() - FIRST_RUN_WINDOW_MILLIS);
+
+            let mut crashes = crashes_since(&exe, since, &config);
+            let Some(latest) = crashes.iter().map(|crash| crash.occurred_at).max() else {
+                return;
+            };
+            crashes.sort_by_key(|crash| std::cmp::Reverse(crash.occurred_at));
+            config.logger.info(format_args!(
+                "Reporting {} crashes of earlier runs",
+                crashes.len()
+            ));
+            for crash in crashes.into_iter().take(MAX_REPORTS) {
+                agent.capture_event(crash.event, None);
+            }
+            if let Err(e) = config::create_state_dir(&dir)
+                .and_then(|_| config::write_private(&marker, latest.to_string().as_bytes()))
+            {
+                config
+                    .logger
+                    .warn(format_args!("Failed to record reported crashes: {}", e));
+            }
+        });
+    if let Err(e) = spawned {
+        logger.warn(format_args!(
+            "Failed to start the crash report check: {}",
+            e
+        ));
+    }
+}
@@ -0,0 +1,65 @@
+//! Performance data: how long requests, and the work within them, took.
+//!
+//! Spans recorded while a request is being served, such as the `tracing`
+//! spans mirrored by `tracing_layer::AivoryLayer` with the `tracing`
+//! feature, are kept on the request's scope. When the response is recorded
+//! with [`record_response`](crate::request::record_response), they are sent
+//! as a `transaction` message named like the request's events, e.g.
+//! `GET /users/:id`, with the response status and duration. Requests
+//! without spans send no transaction.
+
+use crate::RequestContext;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// A timed unit of work within a transaction.
+#[derive(Clone, Debug, Serialize)]
+pub struct Span {
+    /// Unique within the transaction.
+    pub span_id: String,
+    /// The enclosing span, if it was recorded as well.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent_span_id: Option<String>,
+    pub name: String,
+    /// Values recorded on the span, such as the arguments of an
+    /// `#[instrument]`ed function.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub fields: BTreeMap<String, serde_json::Value>,
+    /// When the span was created, as RFC 3339.
+    pub start_timestamp: String,
+    /// Time from creation to close, including time spent waiting.
+    pub duration_ms: f64,
+}
+
+/// Payload of a `transaction` message.
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct Transaction {
+    pub(crate) name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) correlation_id: Option<String>,
+    pub(crate) status: u16,
+    pub(crate) duration_ms: u64,
+    pub(crate) environment: String,
+    pub(crate) agent_id: String,
+    pub(crate) spans: Vec<Span>,
+}
+
+impl Transaction {
+    /// The transaction of `request`, which must have its response recorded.
+    pub(crate) fn new(
+        request: &RequestContext,
+        spans: Vec<Span>,
+        config: &crate::Config,
+    ) -> Option<Self> {
+        let response = request.response.as_ref()?;
+        Some(Transaction {
+            name: request.transaction(config.transaction_normalizer.as_ref()),
+            correlation_id: crate::scope::correlation_id(),
+            status: response.status,
+            duration_ms: response.duration_ms,
+            environment: config.environment.clone(),
+            agent_id: config.agent_id.clone(),
+            spans,
+        })
+    }
+}
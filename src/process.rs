@@ -0,0 +1,300 @@
+//! Crash monitoring for child processes.
+//!
+//! [`MonitoredCommand`] wraps a [`Command`] and reports an event when the
+//! child exits with a non-zero status or is killed by a signal. The last
+//! lines the child wrote to stderr are attached under the `process` context
+//! key, which is usually where the reason for the crash is. Stderr is still
+//! passed through to the parent's stderr.
+//!
+//! ```rust,no_run
+//! use aivory_monitor::process::MonitoredCommand;
+//! use std::process::Command;
+//!
+//! let mut worker = Command::new("./worker");
+//! worker.arg("--shard").arg("3");
+//! let status = MonitoredCommand::new(worker).status()?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use crate::EventBuilder;
+use aivory_monitor_core::hash_fingerprint;
+use serde_json::json;
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Child, ChildStderr, Command, ExitStatus, Output, Stdio};
+use std::thread::JoinHandle;
+use std::time::Instant;
+
+/// Exception type reported for failed child processes.
+const EXCEPTION_TYPE: &str = "ChildProcessFailed";
+
+/// Longest stderr line kept, in bytes.
+const MAX_LINE_LEN: usize = 1000;
+
+/// A [`Command`] whose failures are reported to the global agent.
+pub struct MonitoredCommand {
+    command: Command,
+    stderr_tail: usize,
+}
+
+impl MonitoredCommand {
+    /// Wraps `command`. Its stderr setting is overridden so the tail can be kept.
+    pub fn new(command: Command) -> Self {
+        MonitoredCommand {
+            command,
+            stderr_tail: 20,
+        }
+    }
+
+    /// Sets how many trailing stderr lines are attached. Defaults to 20.
+    pub fn stderr_tail(mut self, lines: usize) -> Self {
+        self.stderr_tail = lines;
+        self
+    }
+
+    /// Returns the wrapped command, e.g. to add arguments.
+    pub fn command_mut(&mut self) -> &mut Command {
+        &mut self.command
+    }
+
+    /// Spawns the child, passing its stderr through while keeping the tail.
+    pub fn spawn(&mut self) -> io::Result<MonitoredChild> {
+        let mut child = self.command.stderr(Stdio::piped()).spawn()?;
+        let stderr = child.stderr.take().map(|stderr| {
+            let lines = self.stderr_tail;
+            std::thread::spawn(move || tee_stderr(stderr, lines))
+        });
+        Ok(MonitoredChild {
+            info: ProcessInfo::new(&self.command, child.id()),
+            child,
+            stderr,
+        })
+    }
+
+    /// Runs the child to completion and returns its exit status.
+    pub fn status(&mut self) -> io::Result<ExitStatus> {
+        self.spawn()?.wait()
+    }
+
+    /// Runs the child to completion, collecting its stdout and stderr.
+    ///
+    /// Stderr is not passed through, since it is returned to the caller.
+    pub fn output(&mut self) -> io::Result<Output> {
+        let started = Instant::now();
+        let output = self.command.output()?;
+        if !output.status.success() {
+            let text = String::from_utf8_lossy(&output.stderr);
+            let mut tail = VecDeque::new();
+            for line in text.lines() {
+                push_line(&mut tail, line, self.stderr_tail);
+            }
+            // The PID is gone by the time `output` returns.
+            let info = ProcessInfo::new(&self.command, 0);
+            report(&info, output.status, tail, started);
+        }
+        Ok(output)
+    }
+}
+
+/// A running child process spawned by [`MonitoredCommand::spawn`].
+pub struct MonitoredChild {
+    child: Child,
+    stderr: Option<JoinHandle<VecDeque<String>>>,
+    info: ProcessInfo,
+}
+
+impl MonitoredChild {
+    /// The child's process ID.
+    pub fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    /// Returns the underlying child, e.g. to write to its stdin.
+    pub fn child_mut(&mut self) -> &mut Child {
+        &mut self.child
+    }
+
+    /// Kills the child. A kill requested here is still reported as a failure.
+    pub fn kill(&mut self) -> io::Result<()> {
+        self.child.kill()
+    }
+
+    /// Waits for the child to exit, reporting it if it failed.
+    pub fn wait(&mut self) -> io::Result<ExitStatus> {
+        let status = self.child.wait()?;
+        let tail = self
+            .stderr
+            .take()
+            .and_then(|handle| handle.join().ok())
+            .unwrap_or_default();
+        if !status.success() {
+            report(&self.info, status, tail, self.info.started);
+        }
+        Ok(status)
+    }
+}
+
+/// What is known about the child when it is spawned.
+struct ProcessInfo {
+    program: String,
+    args: Vec<String>,
+    pid: u32,
+    started: Instant,
+}
+
+impl ProcessInfo {
+    fn new(command: &Command, pid: u32) -> Self {
+        ProcessInfo {
+            program: command.get_program().to_string_lossy().into_owned(),
+            args: command
+                .get_args()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect(),
+            pid,
+            started: Instant::now(),
+        }
+    }
+}
+
+/// Copies the child's stderr to ours and returns its last `lines` lines.
+fn tee_stderr(stderr: ChildStderr, lines: usize) -> VecDeque<String> {
+    let mut tail = VecDeque::new();
+    let mut reader = BufReader::new(stderr);
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        match reader.read_until(b'\n', &mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {
+                let _ = io::stderr().write_all(&buf);
+                let line = String::from_utf8_lossy(&buf);
+                push_line(&mut tail, line.trim_end_matches(['\n', '\r']), lines);
+            }
+        }
+    }
+    tail
+}
+
+fn push_line(tail: &mut VecDeque<String>, line: &str, max_lines: usize) {
+    if max_lines == 0 {
+        return;
+    }
+    if tail.len() == max_lines {
+        tail.pop_front();
+    }
+    let mut end = line.len().min(MAX_LINE_LEN);
+    while !line.is_char_boundary(end) {
+        end -= 1;
+    }
+    tail.push_back(line[..end].to_string());
+}
+
+fn report(info: &ProcessInfo, status: ExitStatus, stderr_tail: VecDeque<String>, started: Instant) {
+    let name = info
+        .program
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or(&info.program);
+    let signal = signal(&status);
+    let (message, outcome) = match (status.code(), signal) {
+        (Some(code), _) => (
+            format!("`{}` exited with status {}", name, code),
+            format!("exit:{}", code),
+        ),
+        (None, Some(signal)) => (
+            format!("`{}` was killed by signal {}", name, signal_name(signal)),
+            format!("signal:{}", signal),
+        ),
+        (None, None) => (format!("`{}` failed: {}", name, status), status.to_string()),
+    };
+
+    // Redacted like the agent's own command line, as secrets are often
+    // passed to children as flags.
+    let command_line = std::iter::once(info.program.clone()).chain(info.args.iter().cloned());
+    let args: Vec<String> = match crate::hub::client() {
+        Some(client) => client
+            .config
+            .read()
+            .scrub_command_line(command_line)
+            .into_iter()
+            .skip(1)
+            .collect(),
+        None => Vec::new(),
+    };
+    let mut process = json!({
+        "program": info.program,
+        "args": args,
+        "exit_code": status.code(),
+        "signal": signal.map(signal_name),
+        "core_dumped": core_dumped(&status),
+        "duration_ms": started.elapsed().as_millis() as u64,
+        "stderr_tail": stderr_tail,
+    });
+    if info.pid != 0 {
+        process["pid"] = info.pid.into();
+    }
+
+    crate::capture_event(
+        EventBuilder::new(EXCEPTION_TYPE)
+            .message(message)
+            // One issue per program and kind of failure, not per invocation.
+            .fingerprint(hash_fingerprint(&[
+                EXCEPTION_TYPE.to_string(),
+                name.to_string(),
+                outcome,
+            ]))
+            .context("process", process),
+    );
+}
+
+#[cfg(unix)]
+fn signal(status: &ExitStatus) -> Option<i32> {
+    std::os::unix::process::ExitStatusExt::signal(status)
+}
+
+#[cfg(not(unix))]
+fn signal(_status: &ExitStatus) -> Option<i32> {
+    None
+}
+
+#[cfg(unix)]
+fn core_dumped(status: &ExitStatus) -> bool {
+    std::os::unix::process::ExitStatusExt::core_dumped(status)
+}
+
+#[cfg(not(unix))]
+fn core_dumped(_status: &ExitStatus) -> bool {
+    false
+}
+
+#[cfg(unix)]
+fn signal_name(signal: i32) -> String {
+    // Numbers differ between platforms: SIGBUS is 7 on Linux, 10 on macOS.
+    let name = match signal {
+        libc::SIGHUP => "SIGHUP",
+        libc::SIGINT => "SIGINT",
+        libc::SIGQUIT => "SIGQUIT",
+        libc::SIGILL => "SIGILL",
+        libc::SIGTRAP => "SIGTRAP",
+        libc::SIGABRT => "SIGABRT",
+        libc::SIGBUS => "SIGBUS",
+        libc::SIGFPE => "SIGFPE",
+        libc::SIGKILL => "SIGKILL",
+        libc::SIGUSR1 => "SIGUSR1",
+        libc::SIGSEGV => "SIGSEGV",
+        libc::SIGUSR2 => "SIGUSR2",
+        libc::SIGPIPE => "SIGPIPE",
+        libc::SIGALRM => "SIGALRM",
+        libc::SIGTERM => "SIGTERM",
+        libc::SIGXCPU => "SIGXCPU",
+        libc::SIGXFSZ => "SIGXFSZ",
+        libc::SIGSYS => "SIGSYS",
+        _ => return signal.to_string(),
+    };
+    name.to_string()
+}
+
+#[cfg(not(unix))]
+fn signal_name(signal: i32) -> String {
+    signal.to_string()
+}
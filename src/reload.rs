@@ -0,0 +1,77 @@
+//! Hot-reload of the configuration file.
+
+use crate::config::Config;
+use crate::Agent;
+use notify::{RecursiveMode, Watcher};
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Delay used to coalesce the burst of events editors emit on save.
+const DEBOUNCE: Duration = Duration::from_millis(250);
+
+/// Watches `path` and applies safe-to-change settings to `agent` whenever it changes.
+///
+/// The parent directory is watched rather than the file itself so that
+/// editors and config management tools that replace the file atomically
+/// are still picked up.
+pub(crate) fn watch(path: PathBuf, agent: Arc<Agent>) {
+    let logger = agent.config.read().logger.clone();
+    let mut previous = match Config::from_file(&path) {
+        Ok(config) => config,
+        Err(e) => {
+            logger.warn(format_args!("Config hot-reload disabled: {}", e));
+            return;
+        }
+    };
+
+    std::thread::spawn(move || {
+        let (tx, rx) = mpsc::channel();
+        let mut watcher = match notify::recommended_watcher(tx) {
+            Ok(w) => w,
+            Err(e) => {
+                logger.warn(format_args!("Failed to create config watcher: {}", e));
+                return;
+            }
+        };
+
+        let dir = path
+            .parent()
+            .filter(|p| !p.as_os_str().is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("."));
+        if let Err(e) = watcher.watch(&dir, RecursiveMode::NonRecursive) {
+            logger.warn(format_args!("Failed to watch {}: {}", dir.display(), e));
+            return;
+        }
+
+        let file_name = path.file_name().map(|n| n.to_os_string());
+
+        while let Ok(event) = rx.recv() {
+            let touches_config = match event {
+                Ok(event) => event
+                    .paths
+                    .iter()
+                    .any(|p| p.file_name().map(|n| n.to_os_string()) == file_name),
+                Err(_) => false,
+            };
+            if !touches_config {
+                continue;
+            }
+
+            std::thread::sleep(DEBOUNCE);
+            while rx.try_recv().is_ok() {}
+
+            match Config::from_file(&path) {
+                Ok(updated) => {
+                    agent.apply_reload(&previous, &updated);
+                    previous = updated;
+                }
+                Err(e) => {
+                    logger.warn(format_args!("Ignoring invalid config change: {}", e));
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,439 @@
+//! The HTTP request being served when an error was captured.
+//!
+//! Web framework integrations, or the application's own middleware, describe
+//! each request with a [`RequestContext`] on its scope and report how it ended
+//! with [`record_response`]. Responses in a configured status class, such as
+//! `5xx`, are captured as events even if no Rust error surfaced.
+//!
+//! Events captured while serving a request are tagged with a transaction
+//! name such as `GET /users/:id`: the method and the route the request
+//! matched, or its path with IDs replaced by `:id`. Unlike the raw URL it
+//! takes few distinct values, so events can be grouped and filtered by it.
+
+use crate::scope;
+use aivory_monitor_core::hash_fingerprint;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Headers whose values are replaced with `[redacted]`.
+const SENSITIVE_HEADERS: &[&str] = &[
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+    "x-api-key",
+];
+
+/// Headers set by proxies to comma-separated client addresses, which
+/// `capture_client_ip` applies to like the client IP.
+const CLIENT_IP_HEADERS: &[&str] = &[
+    "x-forwarded-for",
+    "x-real-ip",
+    "x-client-ip",
+    "cf-connecting-ip",
+    "true-client-ip",
+];
+
+/// Function that turns a request path into a route with few distinct values,
+/// such as `/users/:id` for `/users/42`.
+pub type TransactionNormalizer = Arc<dyn Fn(&str) -> String + Send + Sync>;
+
+/// The HTTP request being served when an error was captured.
+///
+/// Reported as the event's `request` field rather than ad hoc context keys,
+/// so the backend can render it the same way for every service. Attach it to
+/// a scope for everything captured while handling the request, or to a
+/// single event with [`EventBuilder::request`](crate::EventBuilder::request):
+///
+/// ```rust,no_run
+/// use aivory_monitor::scope::{self, Scope};
+/// use aivory_monitor::RequestContext;
+///
+/// let request = RequestContext::new("POST", "https://shop.example.com/orders")
+///     .header("content-type", "application/json")
+///     .body_size(512)
+///     .client_ip("203.0.113.7");
+/// scope::with_scope(Scope::new().request(request), || {
+///     // Errors captured here include the request.
+/// });
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RequestContext {
+    pub method: String,
+    /// URL without the query string.
+    pub url: String,
+    /// Route template the request matched, such as `/users/:id`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub route: Option<String>,
+    /// Header names lowercased and credentials redacted by
+    /// [`header`](Self::header); names set directly are matched in any case.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub query: Option<String>,
+    /// Request body length in bytes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body_size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_ip: Option<String>,
+    /// How the request ended, once [`record_response`] was called.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response: Option<ResponseContext>,
+}
+
+impl RequestContext {
+    /// Creates a request context. A query string in `url` is moved to `query`.
+    pub fn new(method: impl Into<String>, url: impl Into<String>) -> Self {
+        let url = url.into();
+        let (url, query) = match url.split_once('?') {
+            Some((url, query)) => (
+                url.to_string(),
+                Some(query.to_string()).filter(|q| !q.is_empty()),
+            ),
+            None => (url, None),
+        };
+        RequestContext {
+            method: method.into().to_uppercase(),
+            url,
+            query,
+            ..Default::default()
+        }
+    }
+
+    /// Sets the route template the request matched, such as `/users/:id`,
+    /// which names the transaction instead of the normalized path.
+    pub fn route(mut self, route: impl Into<String>) -> Self {
+        self.route = Some(route.into());
+        self
+    }
+
+    /// Adds a header. Values of `Authorization`, `Cookie` and similar
+    /// credential headers are replaced with `[redacted]`.
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        let name = name.into().to_lowercase();
+        let value = if SENSITIVE_HEADERS.contains(&name.as_str()) {
+            "[redacted]".to_string()
+        } else {
+            value.into()
+        };
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// Sets the query string, without the leading `?`.
+    pub fn query(mut self, query: impl Into<String>) -> Self {
+        self.query = Some(query.into());
+        self
+    }
+
+    /// Sets the request body length in bytes.
+    pub fn body_size(mut self, bytes: u64) -> Self {
+        self.body_size = Some(bytes);
+        self
+    }
+
+    /// Sets the address of the client that made the request.
+    pub fn client_ip(mut self, ip: impl Into<String>) -> Self {
+        self.client_ip = Some(ip.into());
+        self
+    }
+
+    /// Reduces the client IP and the proxy headers carrying client addresses
+    /// to what `mode` allows.
+    pub(crate) fn limit_client_ip(&mut self, mode: ClientIpMode) {
+        self.client_ip = self.client_ip.take().and_then(|ip| mode.apply(&ip));
+        // `for=` and `by=` pairs of the standard header are not worth parsing.
+        self.headers
+            .retain(|name, _| !name.eq_ignore_ascii_case("forwarded"));
+        let names: Vec<String> = self
+            .headers
+            .keys()
+            .filter(|name| {
+                CLIENT_IP_HEADERS
+                    .iter()
+                    .any(|header| name.eq_ignore_ascii_case(header))
+            })
+            .cloned()
+            .collect();
+        for name in names {
+            let Some(value) = self.headers.remove(&name) else {
+                continue;
+            };
+            let addresses: Vec<String> = value
+                .split(',')
+                .filter_map(|ip| mode.apply(ip.trim()))
+                .collect();
+            if !addresses.is_empty() {
+                self.headers.insert(name, addresses.join(", "));
+            }
+        }
+    }
+
+    /// The value of the header `name`, in whatever case it was set.
+    pub(crate) fn header_value(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The transaction name: the method followed by the route, or by the
+    /// path as rewritten by `normalizer` or [`normalize_path`].
+    pub(crate) fn transaction(&self, normalizer: Option<&TransactionNormalizer>) -> String {
+        let route = match (&self.route, normalizer) {
+            (Some(route), _) => route.clone(),
+            (None, Some(normalizer)) => normalizer(path(&self.url)),
+            (None, None) => normalize_path(path(&self.url)),
+        };
+        format!("{} {}", self.method, route)
+    }
+}
+
+/// Replaces the segments of `path` that look like IDs — numbers, UUIDs and
+/// long hex strings — with `:id`.
+///
+/// A [`TransactionNormalizer`] can fall back to this for paths it does not
+/// know:
+///
+/// ```rust
+/// use aivory_monitor::request::normalize_path;
+///
+/// assert_eq!(normalize_path("/users/42/orders/9f1c2e4b-8d3a-4c5e-a1b2-0c3d4e5f6a7b"), "/users/:id/orders/:id");
+/// assert_eq!(normalize_path("/health"), "/health");
+/// ```
+pub fn normalize_path(path: &str) -> String {
+    path.split('/')
+        .map(|segment| if is_id(segment) { ":id" } else { segment })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn is_id(segment: &str) -> bool {
+    let hex_digits = segment.chars().filter(char::is_ascii_hexdigit).count();
+    let is_uuid = segment.len() == 36
+        && hex_digits == 32
+        && segment
+            .char_indices()
+            .all(|(i, c)| (c == '-') == matches!(i, 8 | 13 | 18 | 23));
+    let is_number = !segment.is_empty() && segment.bytes().all(|b| b.is_ascii_digit());
+    let is_hex = segment.len() >= 16 && hex_digits == segment.len();
+    is_number || is_uuid || is_hex
+}
+
+/// The path of `url`, which may also be a path already.
+fn path(url: &str) -> &str {
+    match url.split_once("://") {
+        Some((_, rest)) => rest.find('/').map_or("/", |start| &rest[start..]),
+        None => url,
+    }
+}
+
+/// How the IP addresses of clients are reported.
+///
+/// Applies to [`RequestContext::client_ip`], to proxy headers such as
+/// `X-Forwarded-For` and to [`User::ip_address`](crate::User::ip_address),
+/// whichever integration set them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClientIpMode {
+    /// Not reported; proxy headers carrying client addresses are removed.
+    Never,
+    /// Reported with the host part removed by [`anonymize_ip`]. Values that
+    /// are not an IP address are removed.
+    Anonymized,
+    /// Reported as set.
+    #[default]
+    Full,
+}
+
+impl ClientIpMode {
+    /// What `ip` is reported as, if anything.
+    fn apply(self, ip: &str) -> Option<String> {
+        match self {
+            ClientIpMode::Never => None,
+            ClientIpMode::Anonymized => anonymize_ip(ip),
+            ClientIpMode::Full => Some(ip.to_string()),
+        }
+    }
+}
+
+impl FromStr for ClientIpMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "never" | "none" => Ok(ClientIpMode::Never),
+            "anonymized" | "anonymize" => Ok(ClientIpMode::Anonymized),
+            "full" => Ok(ClientIpMode::Full),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Truncates an IPv4 address to its /24 network and an IPv6 address to its
+/// /48, which identifies a network rather than a client. A port or IPv6
+/// brackets are dropped; returns `None` if `ip` is not an IP address.
+///
+/// ```rust
+/// use aivory_monitor::request::anonymize_ip;
+///
+/// assert_eq!(anonymize_ip("203.0.113.7").as_deref(), Some("203.0.113.0"));
+/// assert_eq!(anonymize_ip("203.0.113.7:52110").as_deref(), Some("203.0.113.0"));
+/// assert_eq!(anonymize_ip("2001:db8:85a3:8d3:1319:8a2e:370:7348").as_deref(), Some("2001:db8:85a3::"));
+/// assert_eq!(anonymize_ip("[::ffff:203.0.113.7]:443").as_deref(), Some("203.0.113.0"));
+/// assert_eq!(anonymize_ip("unknown"), None);
+/// ```
+pub fn anonymize_ip(ip: &str) -> Option<String> {
+    let ip = ip.trim();
+    let addr = match ip.parse::<IpAddr>() {
+        Ok(addr) => addr,
+        Err(_) => ip.parse::<SocketAddr>().ok()?.ip(),
+    };
+    let addr = match addr {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(addr, IpAddr::V4),
+        IpAddr::V4(_) => addr,
+    };
+    let anonymized = match addr {
+        IpAddr::V4(v4) => {
+            let [a, b, c, _] = v4.octets();
+            IpAddr::V4(Ipv4Addr::new(a, b, c, 0))
+        }
+        IpAddr::V6(v6) => {
+            let [a, b, c, ..] = v6.segments();
+            IpAddr::V6(Ipv6Addr::new(a, b, c, 0, 0, 0, 0, 0))
+        }
+    };
+    Some(anonymized.to_string())
+}
+
+/// Applies `capture_client_ip` to the request and the user of an event.
+pub(crate) fn limit_client_ips(exc: &mut crate::ExceptionCapture, mode: ClientIpMode) {
+    if mode == ClientIpMode::Full {
+        return;
+    }
+    if let Some(request) = &mut exc.request {
+        request.limit_client_ip(mode);
+    }
+    if let Some(serde_json::Value::Object(user)) = exc.context.get_mut("user") {
+        if let Some(ip) = user.remove("ip_address") {
+            if let Some(ip) = ip.as_str().and_then(|ip| mode.apply(ip)) {
+                user.insert("ip_address".to_string(), ip.into());
+            }
+        }
+    }
+}
+
+/// The response sent for a request.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ResponseContext {
+    pub status: u16,
+    /// Time from receiving the request to sending the response.
+    pub duration_ms: u64,
+    /// Response body length in bytes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+}
+
+/// A class of HTTP response statuses, written `5xx`, `4xx` and so on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub struct StatusClass(u8);
+
+impl StatusClass {
+    /// `4xx` responses.
+    pub const CLIENT_ERROR: StatusClass = StatusClass(4);
+    /// `5xx` responses.
+    pub const SERVER_ERROR: StatusClass = StatusClass(5);
+
+    /// Whether `status` belongs to this class.
+    pub fn contains(self, status: u16) -> bool {
+        status / 100 == u16::from(self.0)
+    }
+}
+
+impl FromStr for StatusClass {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_ascii_lowercase();
+        match s.as_bytes() {
+            [digit @ b'1'..=b'5', b'x', b'x'] => Ok(StatusClass(digit - b'0')),
+            _ => Err(format!("invalid status class {:?}, expected 1xx to 5xx", s)),
+        }
+    }
+}
+
+impl TryFrom<String> for StatusClass {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for StatusClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}xx", self.0)
+    }
+}
+
+/// Records the response to the request of the innermost active scope, and
+/// reports it with the global agent if its status is in one of the
+/// `capture_status_classes`.
+///
+/// Call it from middleware once the response is ready:
+///
+/// ```rust,no_run
+/// use aivory_monitor::request;
+/// use std::time::Instant;
+///
+/// let started = Instant::now();
+/// // ... handle the request ...
+/// request::record_response(503, started.elapsed(), Some(128));
+/// ```
+pub fn record_response(status: u16, duration: Duration, size: Option<u64>) {
+    match crate::client() {
+        Some(client) => client.record_response(status, duration, size),
+        None => set_response(status, duration, size),
+    }
+}
+
+pub(crate) fn set_response(status: u16, duration: Duration, size: Option<u64>) {
+    scope::update_request(|request| {
+        request.response = Some(ResponseContext {
+            status,
+            duration_ms: duration.as_millis() as u64,
+            size,
+        });
+    });
+}
+
+/// Event for a response whose status was configured to be captured, grouped
+/// by transaction and status.
+pub(crate) fn status_event(
+    status: u16,
+    normalizer: Option<&TransactionNormalizer>,
+) -> crate::EventBuilder {
+    let transaction = scope::request()
+        .map(|request| request.transaction(normalizer))
+        .unwrap_or_default();
+    let message = if transaction.is_empty() {
+        format!("responded with status {}", status)
+    } else {
+        format!("{} responded with status {}", transaction, status)
+    };
+    crate::EventBuilder::new("HttpErrorResponse")
+        .message(message)
+        .fingerprint(hash_fingerprint(&[
+            "HttpErrorResponse".to_string(),
+            transaction,
+            status.to_string(),
+        ]))
+        .mechanism(crate::capture::Mechanism::Middleware)
+        .context("http_status", status)
+}
@@ -0,0 +1,121 @@
+//! The async runtime the WebSocket transport runs on.
+//!
+//! By default the agent starts its own Tokio runtime on a background thread.
+//! With the `async-std` feature it runs on async-std's global executor
+//! instead, so applications on async-std don't embed a second executor.
+//! Channels and notifications use `tokio::sync`, which works on any executor.
+
+use crate::Agent;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[cfg(feature = "async-std")]
+pub(crate) use async_tungstenite::async_std::connect_async;
+#[cfg(not(feature = "async-std"))]
+pub(crate) use tokio_tungstenite::connect_async;
+
+/// The WebSocket stream returned by [`connect_async`].
+#[cfg(feature = "async-std")]
+pub(crate) type WsStream =
+    async_tungstenite::WebSocketStream<async_tungstenite::async_std::ConnectStream>;
+/// The WebSocket stream returned by [`connect_async`].
+#[cfg(not(feature = "async-std"))]
+pub(crate) type WsStream =
+    tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+/// Starts `agent` in the background and keeps its connection running until shutdown.
+#[cfg(not(feature = "async-std"))]
+pub(crate) fn start(agent: Arc<Agent>) {
+    use crate::internal;
+
+    std::thread::spawn(move || {
+        internal::mark_agent_thread();
+        let rt = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .on_thread_start(internal::mark_agent_thread)
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            agent.start().await;
+            spawn(Agent::run_aggregation(Arc::downgrade(&agent)));
+            // Keep the runtime, and with it the connection tasks, alive until shutdown.
+            agent.shutdown.notified().await;
+        });
+    });
+}
+
+/// Starts `agent` in the background and keeps its connection running until shutdown.
+#[cfg(feature = "async-std")]
+pub(crate) fn start(agent: Arc<Agent>) {
+    // The global executor outlives the task, so nothing has to be kept alive.
+    async_std::task::spawn(async move {
+        agent.start().await;
+        spawn(Agent::run_aggregation(Arc::downgrade(&agent)));
+    });
+}
+
+/// Runs `future` to completion on the current thread.
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    #[cfg(not(feature = "async-std"))]
+    let output = tokio::runtime::Runtime::new().unwrap().block_on(future);
+    #[cfg(feature = "async-std")]
+    let output = async_std::task::block_on(future);
+    output
+}
+
+/// Spawns a background task.
+pub(crate) fn spawn<F>(future: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    #[cfg(not(feature = "async-std"))]
+    tokio::spawn(future);
+    #[cfg(feature = "async-std")]
+    async_std::task::spawn(future);
+}
+
+/// Spawns tasks onto the agent's runtime from any thread, including threads
+/// of another runtime, where [`block_on`] would panic.
+#[derive(Clone)]
+pub(crate) struct Spawner {
+    #[cfg(not(feature = "async-std"))]
+    handle: tokio::runtime::Handle,
+}
+
+impl Spawner {
+    /// The runtime of the calling task.
+    pub(crate) fn current() -> Self {
+        Spawner {
+            #[cfg(not(feature = "async-std"))]
+            handle: tokio::runtime::Handle::current(),
+        }
+    }
+
+    pub(crate) fn spawn<F>(&self, future: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        #[cfg(not(feature = "async-std"))]
+        self.handle.spawn(future);
+        #[cfg(feature = "async-std")]
+        async_std::task::spawn(future);
+    }
+}
+
+/// Runs `future`, giving up after `duration`.
+pub(crate) async fn timeout<F: Future>(duration: Duration, future: F) -> Option<F::Output> {
+    #[cfg(not(feature = "async-std"))]
+    let output = tokio::time::timeout(duration, future).await.ok();
+    #[cfg(feature = "async-std")]
+    let output = async_std::future::timeout(duration, future).await.ok();
+    output
+}
+
+/// Waits for `duration`.
+pub(crate) async fn sleep(duration: Duration) {
+    #[cfg(not(feature = "async-std"))]
+    tokio::time::sleep(duration).await;
+    #[cfg(feature = "async-std")]
+    async_std::task::sleep(duration).await;
+}
@@ -0,0 +1,179 @@
+//! Event sampling.
+//!
+//! Events are kept at `sampling_rate`, at an adaptive rate when
+//! `target_events_per_minute` is set, or at the rate a [`Sampler`] callback
+//! returns. [`SamplingMode`] decides how the rate is applied to each event.
+//!
+//! The adaptive sampler counts the events offered in the current and the
+//! previous minute and samples at `budget / volume`, so roughly `budget`
+//! events a minute get through however many errors occur. The first
+//! occurrence of a fingerprint is always kept, so a rare error is not lost
+//! in an incident caused by another one.
+
+use crate::capture::ExceptionCapture;
+use crate::config::rand;
+use aivory_monitor_core::hash_fingerprint;
+use parking_lot::Mutex;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Length of the window the budget applies to.
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Fingerprints remembered before the set is cleared. A fingerprint seen
+/// again after that is treated as new once more.
+const MAX_FINGERPRINTS: usize = 10_000;
+
+/// Function that returns the rate (0.0 - 1.0) to sample an event at.
+pub type Sampler = Arc<dyn Fn(&SamplingContext<'_>) -> f64 + Send + Sync>;
+
+/// The event a [`Sampler`] decides on, with all context applied.
+pub struct SamplingContext<'a> {
+    event: &'a ExceptionCapture,
+    rate: f64,
+}
+
+impl<'a> SamplingContext<'a> {
+    pub(crate) fn new(event: &'a ExceptionCapture, rate: f64) -> Self {
+        SamplingContext { event, rate }
+    }
+
+    /// Type of the error.
+    pub fn exception_type(&self) -> &str {
+        &self.event.exception_type
+    }
+
+    /// Error message.
+    pub fn message(&self) -> &str {
+        &self.event.message
+    }
+
+    /// Grouping fingerprint.
+    pub fn fingerprint(&self) -> &str {
+        &self.event.fingerprint
+    }
+
+    /// A context value, from the event, its scopes or the global context.
+    pub fn context(&self, key: &str) -> Option<&'a serde_json::Value> {
+        self.event.context.get(key)
+    }
+
+    /// The rate the event would be sampled at without the callback, from
+    /// `sampling_rate` or adaptive sampling.
+    pub fn default_rate(&self) -> f64 {
+        self.rate
+    }
+}
+
+/// How the sampling rate is applied to each event.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SamplingMode {
+    /// An independent random decision per event.
+    #[default]
+    Random,
+    /// A decision derived from the event's `trace_id` context value, or its
+    /// fingerprint if it has none.
+    ///
+    /// Events of one request share a decision, as do services that sample
+    /// their traces the same way: an event is kept when the first 8 bytes of
+    /// the key's SHA-256, read big-endian, are below `rate * 2^64`.
+    Deterministic,
+}
+
+impl SamplingMode {
+    /// Whether to keep `exc` when sampling at `rate`.
+    pub(crate) fn keep(self, exc: &ExceptionCapture, rate: f64) -> bool {
+        if rate >= 1.0 {
+            return true;
+        }
+        if rate <= 0.0 {
+            return false;
+        }
+        match self {
+            SamplingMode::Random => rand::random::<f64>() < rate,
+            SamplingMode::Deterministic => {
+                let key = match exc.context.get("trace_id").and_then(|id| id.as_str()) {
+                    Some(trace_id) => trace_id,
+                    None => &exc.fingerprint,
+                };
+                (key_hash(key) as f64) < rate * (u64::MAX as f64)
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for SamplingMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "random" => Ok(SamplingMode::Random),
+            "deterministic" => Ok(SamplingMode::Deterministic),
+            _ => Err(()),
+        }
+    }
+}
+
+fn key_hash(key: &str) -> u64 {
+    // The fingerprint hash is the first 8 bytes of the SHA-256, in hex.
+    u64::from_str_radix(&hash_fingerprint(&[key.to_string()]), 16).unwrap_or(0)
+}
+
+/// Computes the sampling rate under `target_events_per_minute`.
+pub(crate) struct AdaptiveSampler {
+    state: Mutex<State>,
+}
+
+struct State {
+    window_start: Instant,
+    /// Events offered in the current window.
+    current: u32,
+    /// Events offered in the previous window.
+    previous: u32,
+    seen: HashSet<String>,
+}
+
+impl AdaptiveSampler {
+    pub(crate) fn new() -> Self {
+        AdaptiveSampler {
+            state: Mutex::new(State {
+                window_start: Instant::now(),
+                current: 0,
+                previous: 0,
+                seen: HashSet::new(),
+            }),
+        }
+    }
+
+    /// Records an event and returns the rate to sample it at.
+    pub(crate) fn rate(&self, budget: u32, fingerprint: &str) -> f64 {
+        let mut state = self.state.lock();
+
+        let elapsed = state.window_start.elapsed();
+        if elapsed >= WINDOW {
+            // A gap longer than a window means nothing was offered in the last one.
+            state.previous = if elapsed >= 2 * WINDOW {
+                0
+            } else {
+                state.current
+            };
+            state.current = 0;
+            state.window_start = Instant::now();
+        }
+        state.current = state.current.saturating_add(1);
+
+        if !state.seen.contains(fingerprint) {
+            if state.seen.len() >= MAX_FINGERPRINTS {
+                state.seen.clear();
+            }
+            state.seen.insert(fingerprint.to_string());
+            return 1.0;
+        }
+
+        // The busier window, so the rate drops as soon as a burst starts.
+        let volume = state.current.max(state.previous);
+        (f64::from(budget) / f64::from(volume)).min(1.0)
+    }
+}
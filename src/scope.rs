@@ -0,0 +1,598 @@
+//! Context scoped to a unit of work.
+//!
+//! A scope attaches context to every event captured while it is active, such
+//! as the message a consumer is handling or the request a server is serving.
+//! Scopes nest; inner values override outer ones, and both are layered over
+//! the context of the thread's [`Hub`](crate::Hub) and the agent's global
+//! context. Events also report `elapsed_ms`, the time
+//! since the outermost active scope was first entered.
+//!
+//! ```rust,no_run
+//! use aivory_monitor::scope::{self, Scope};
+//!
+//! scope::with_scope(Scope::new().context("job_id", "job-42"), || {
+//!     // Errors captured here include `job_id`.
+//! });
+//! ```
+//!
+//! A scope can also carry a correlation ID, sent as the event's
+//! `correlation_id` field so events can be joined with the application's own
+//! logs for the same request or job:
+//!
+//! ```rust,no_run
+//! use aivory_monitor::scope::{self, Scope};
+//!
+//! scope::with_scope(Scope::new(), || {
+//!     let id = scope::new_correlation_id();
+//!     eprintln!("[{}] processing order", id);
+//!     // Errors captured here carry the same ID.
+//! });
+//! ```
+//!
+//! Feature flags evaluated for the work are reported the same way, in the
+//! event's `feature_flags` field, so incidents can be tied to a flag change:
+//!
+//! ```rust,no_run
+//! use aivory_monitor::scope;
+//!
+//! scope::set_feature_flag("new-checkout", "variant-b");
+//! ```
+//!
+//! Scopes live in thread-local storage of this copy of the agent, which other
+//! Rust dylibs and C++ code in the process cannot see. [`export_token`]
+//! serializes what is active on the current thread into a string that
+//! [`Scope::from_token`], or `aivory_scope_enter` in the C API, turns back
+//! into a scope on the other side:
+//!
+//! ```rust,no_run
+//! use aivory_monitor::scope::{self, Scope};
+//!
+//! # fn call_plugin(_token: &str) {}
+//! // In the host:
+//! let token = scope::export_token();
+//! call_plugin(&token);
+//!
+//! // In the plugin, with its own copy of the agent:
+//! # let token = String::new();
+//! let scope = Scope::from_token(&token).unwrap_or_default();
+//! scope::with_scope(scope, || {
+//!     // Errors captured here carry the host's context.
+//! });
+//! ```
+
+use crate::baggage;
+use crate::breadcrumbs::{self, Breadcrumb};
+use crate::capture::Attachment;
+use crate::hub::Hub;
+use crate::performance::Span;
+use crate::request::RequestContext;
+use once_cell::sync::OnceCell;
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+/// Scopes being run by [`with_scope`], plus live [`scoped`] futures, on all
+/// threads; reported with heartbeats.
+static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+
+/// How long a capture waits for a breadcrumb being recorded on another
+/// thread polling the same scope.
+const LOCK_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Spans kept per request; later ones are dropped.
+#[cfg_attr(not(feature = "tracing"), allow(dead_code))]
+const MAX_SPANS: usize = 1000;
+
+/// Context applied to events captured while the scope is active.
+#[derive(Debug, Default)]
+pub struct Scope {
+    context: HashMap<String, serde_json::Value>,
+    // Behind a lock so `set_correlation_id` can change it once active.
+    correlation_id: RwLock<Option<String>>,
+    feature_flags: RwLock<BTreeMap<String, String>>,
+    // Behind a lock so `set_baggage` can add entries once active.
+    baggage: RwLock<BTreeMap<String, String>>,
+    // Behind a lock so `record_response` can add the response.
+    request: RwLock<Option<RequestContext>>,
+    attachments: Vec<Attachment>,
+    /// Breadcrumbs recorded while the scope was the innermost active one,
+    /// oldest first.
+    breadcrumbs: Mutex<VecDeque<Breadcrumb>>,
+    /// Performance spans recorded while serving the scope's request.
+    spans: Mutex<Vec<Span>>,
+    /// When the scope was first entered; a future's scope starts on first poll.
+    started: OnceCell<Instant>,
+}
+
+impl Clone for Scope {
+    fn clone(&self) -> Self {
+        Scope {
+            context: self.context.clone(),
+            correlation_id: RwLock::new(self.correlation_id.read().clone()),
+            feature_flags: RwLock::new(self.feature_flags.read().clone()),
+            baggage: RwLock::new(self.baggage.read().clone()),
+            request: RwLock::new(self.request.read().clone()),
+            attachments: self.attachments.clone(),
+            breadcrumbs: Mutex::new(self.breadcrumbs.lock().clone()),
+            spans: Mutex::new(Vec::new()),
+            started: OnceCell::new(),
+        }
+    }
+}
+
+impl Scope {
+    /// Creates an empty scope.
+    pub fn new() -> Self {
+        Scope::default()
+    }
+
+    /// Adds a context value.
+    pub fn context(mut self, key: impl Into<String>, value: impl Into<serde_json::Value>) -> Self {
+        self.context.insert(key.into(), value.into());
+        self
+    }
+
+    /// Sets the `trace_id` context value, which deterministic sampling keys on.
+    pub fn trace_id(self, trace_id: impl Into<String>) -> Self {
+        self.context("trace_id", trace_id.into())
+    }
+
+    /// Sets the correlation ID sent with events captured in this scope.
+    pub fn correlation_id(self, id: impl Into<String>) -> Self {
+        *self.correlation_id.write() = Some(id.into());
+        self
+    }
+
+    /// Records the variant of a feature flag evaluated in this scope.
+    pub fn feature_flag(self, name: impl Into<String>, variant: impl Into<String>) -> Self {
+        self.feature_flags
+            .write()
+            .insert(name.into(), variant.into());
+        self
+    }
+
+    /// Adds a [`baggage`](crate::baggage) entry, sent with events captured
+    /// in this scope and on to other services.
+    pub fn baggage(self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.baggage.write().insert(key.into(), value.into());
+        self
+    }
+
+    /// Sets the HTTP request reported with events captured in this scope.
+    ///
+    /// The entries of its `baggage` header are added to the scope's baggage,
+    /// below those set with [`baggage`](Self::baggage).
+    pub fn request(self, request: RequestContext) -> Self {
+        if let Some(header) = request.header_value(baggage::HEADER) {
+            let mut entries = self.baggage.write();
+            for (key, value) in baggage::parse(header) {
+                entries.entry(key).or_insert(value);
+            }
+        }
+        *self.request.write() = Some(request);
+        self
+    }
+
+    /// Adds a file sent with every event captured in this scope, such as the
+    /// input of the job it runs.
+    pub fn attachment(mut self, attachment: Attachment) -> Self {
+        self.attachments.push(attachment);
+        self
+    }
+
+    /// Records a span of the scope's request, up to [`MAX_SPANS`].
+    #[cfg_attr(not(feature = "tracing"), allow(dead_code))]
+    pub(crate) fn add_span(&self, span: Span) {
+        let mut spans = self.spans.lock();
+        if spans.len() < MAX_SPANS {
+            spans.push(span);
+        }
+    }
+
+    /// Creates a scope from a token made by [`export_token`], possibly in
+    /// another module or process.
+    pub fn from_token(token: &str) -> Result<Scope, serde_json::Error> {
+        let token: ScopeToken = serde_json::from_str(token)?;
+        Ok(Scope {
+            context: token.context,
+            correlation_id: RwLock::new(token.correlation_id),
+            feature_flags: RwLock::new(token.feature_flags),
+            baggage: RwLock::new(token.baggage),
+            request: RwLock::new(token.request),
+            attachments: Vec::new(),
+            breadcrumbs: Mutex::new(VecDeque::new()),
+            spans: Mutex::new(Vec::new()),
+            started: OnceCell::new(),
+        })
+    }
+}
+
+/// What [`export_token`] carries across a module boundary.
+#[derive(Default, Serialize, Deserialize)]
+struct ScopeToken {
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    context: HashMap<String, serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    correlation_id: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    feature_flags: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    baggage: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    request: Option<RequestContext>,
+}
+
+/// Serializes the context of the current thread and its active scopes, their
+/// correlation ID, feature flags, baggage and request into a JSON token for
+/// [`Scope::from_token`].
+pub fn export_token() -> String {
+    let mut context = HashMap::new();
+    crate::hub::apply(&mut context);
+    apply(&mut context);
+    let token = ScopeToken {
+        context,
+        correlation_id: correlation_id(),
+        feature_flags: feature_flags(),
+        baggage: baggage(),
+        request: request(),
+    };
+    serde_json::to_string(&token).unwrap_or_default()
+}
+
+#[cfg(feature = "cdylib")]
+thread_local! {
+    /// Scopes entered through [`enter`], innermost last.
+    static ENTERED: std::cell::RefCell<Vec<(Guard, Active)>> = const { std::cell::RefCell::new(Vec::new()) };
+}
+
+/// Makes `scope` active on the current thread until [`exit`], for hosts that
+/// cannot run a closure or future inside [`with_scope`].
+#[cfg(feature = "cdylib")]
+pub(crate) fn enter(scope: Scope) {
+    let entered = (push(Arc::new(scope)), Active::new());
+    ENTERED.with(|stack| stack.borrow_mut().push(entered));
+}
+
+/// Ends the scope entered last with [`enter`]; returns false if none is.
+#[cfg(feature = "cdylib")]
+pub(crate) fn exit() -> bool {
+    let entered = ENTERED.with(|stack| stack.borrow_mut().pop());
+    entered.is_some()
+}
+
+/// Runs `f` with `scope` active on the current thread.
+pub fn with_scope<R>(scope: Scope, f: impl FnOnce() -> R) -> R {
+    let _active = Active::new();
+    let _guard = push(Arc::new(scope));
+    f()
+}
+
+/// Wraps `future` so `scope` is active whenever it is polled.
+///
+/// Unlike [`with_scope`], this follows the future across threads and
+/// `.await` points on any executor.
+pub fn scoped<F: Future>(scope: Scope, future: F) -> Scoped<F> {
+    Scoped {
+        scope: Arc::new(scope),
+        future: Box::pin(future),
+        _active: Active::new(),
+    }
+}
+
+/// Future returned by [`scoped`].
+pub struct Scoped<F> {
+    scope: Arc<Scope>,
+    future: Pin<Box<F>>,
+    _active: Active,
+}
+
+impl<F: Future> Future for Scoped<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        let _guard = push(self.scope.clone());
+        self.future.as_mut().poll(cx)
+    }
+}
+
+/// Counts a scope in [`ACTIVE`] while alive.
+struct Active;
+
+impl Active {
+    fn new() -> Self {
+        ACTIVE.fetch_add(1, Ordering::Relaxed);
+        Active
+    }
+}
+
+impl Drop for Active {
+    fn drop(&mut self) {
+        ACTIVE.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Number of scopes in use across all threads, nested ones included.
+#[cfg_attr(target_os = "wasi", allow(dead_code))]
+pub(crate) fn active_count() -> usize {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+/// Pops the scope it pushed when dropped, including during unwinding.
+struct Guard;
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        Hub::try_with_current(|hub| hub.stack.borrow_mut().pop());
+    }
+}
+
+fn push(scope: Arc<Scope>) -> Guard {
+    scope.started.get_or_init(Instant::now);
+    Hub::with_current(|hub| hub.stack.borrow_mut().push(scope));
+    Guard
+}
+
+/// Copies the context of the active scopes into `context`, outermost first.
+pub(crate) fn apply(context: &mut HashMap<String, serde_json::Value>) {
+    // Not `with_current`, as the panic hook can run during thread teardown,
+    // and not `borrow`, as the panic may have occurred while the stack was
+    // being changed; the readers below do the same.
+    Hub::try_with_current(|hub| {
+        let Ok(stack) = hub.stack.try_borrow() else {
+            return;
+        };
+        for scope in stack.iter() {
+            for (k, v) in &scope.context {
+                context.insert(k.clone(), v.clone());
+            }
+        }
+    });
+}
+
+/// Sets the correlation ID of the innermost active scope.
+///
+/// With no scope active, the ID applies to the current thread until changed,
+/// which suits worker threads but not async tasks sharing a thread; wrap
+/// those in [`scoped`] first.
+pub fn set_correlation_id(id: &str) {
+    Hub::try_with_current(|hub| match hub.stack.borrow().last() {
+        Some(scope) => *scope.correlation_id.write() = Some(id.to_string()),
+        None => *hub.correlation_id.borrow_mut() = Some(id.to_string()),
+    });
+}
+
+/// Generates a random correlation ID, sets it like [`set_correlation_id`]
+/// and returns it for the application's own logs.
+pub fn new_correlation_id() -> String {
+    let id = uuid::Uuid::new_v4().to_string();
+    set_correlation_id(&id);
+    id
+}
+
+/// The correlation ID events captured now would carry: that of the innermost
+/// scope which has one, else the current thread's.
+pub fn correlation_id() -> Option<String> {
+    Hub::try_with_current(|hub| {
+        hub.stack
+            .try_borrow()
+            .ok()?
+            .iter()
+            .rev()
+            .find_map(|scope| scope.correlation_id.read().clone())
+            .or_else(|| hub.correlation_id.try_borrow().ok()?.clone())
+    })
+    .flatten()
+}
+
+/// Records the variant of a feature flag on the innermost active scope.
+///
+/// With no scope active, the flag applies to the current thread, like
+/// [`set_correlation_id`].
+pub fn set_feature_flag(name: &str, variant: &str) {
+    Hub::try_with_current(|hub| match hub.stack.borrow().last() {
+        Some(scope) => {
+            scope
+                .feature_flags
+                .write()
+                .insert(name.to_string(), variant.to_string());
+        }
+        None => {
+            hub.feature_flags
+                .borrow_mut()
+                .insert(name.to_string(), variant.to_string());
+        }
+    });
+}
+
+/// Feature flags of the current thread and the active scopes, inner values
+/// overriding outer ones.
+pub(crate) fn feature_flags() -> BTreeMap<String, String> {
+    Hub::try_with_current(|hub| {
+        let mut flags = hub.feature_flags.try_borrow().ok()?.clone();
+        for scope in hub.stack.try_borrow().ok()?.iter() {
+            flags.extend(
+                scope
+                    .feature_flags
+                    .read()
+                    .iter()
+                    .map(|(k, v)| (k.clone(), v.clone())),
+            );
+        }
+        Some(flags)
+    })
+    .flatten()
+    .unwrap_or_default()
+}
+
+/// Sets a [`baggage`](crate::baggage) entry on the innermost active scope,
+/// such as the tenant or experiment of the work. Events captured meanwhile
+/// carry it, and [`baggage::header_value`] passes it on to other services.
+///
+/// With no scope active, the entry applies to the current thread, like
+/// [`set_correlation_id`].
+pub fn set_baggage(key: &str, value: &str) {
+    Hub::try_with_current(|hub| match hub.stack.borrow().last() {
+        Some(scope) => {
+            scope
+                .baggage
+                .write()
+                .insert(key.to_string(), value.to_string());
+        }
+        None => {
+            hub.baggage
+                .borrow_mut()
+                .insert(key.to_string(), value.to_string());
+        }
+    });
+}
+
+/// Baggage of the current thread and the active scopes, inner values
+/// overriding outer ones.
+pub(crate) fn baggage() -> BTreeMap<String, String> {
+    Hub::try_with_current(|hub| {
+        let mut baggage = hub.baggage.try_borrow().ok()?.clone();
+        for scope in hub.stack.try_borrow().ok()?.iter() {
+            baggage.extend(
+                scope
+                    .baggage
+                    .read()
+                    .iter()
+                    .map(|(k, v)| (k.clone(), v.clone())),
+            );
+        }
+        Some(baggage)
+    })
+    .flatten()
+    .unwrap_or_default()
+}
+
+/// Records a breadcrumb on the innermost active scope, or with no scope
+/// active on the current thread, keeping the last `max` of either.
+pub(crate) fn add_breadcrumb(breadcrumb: Breadcrumb, max: usize) {
+    Hub::try_with_current(|hub| match hub.stack.borrow().last() {
+        Some(scope) => breadcrumbs::push(&mut scope.breadcrumbs.lock(), breadcrumb, max),
+        None => breadcrumbs::push(&mut hub.breadcrumbs.borrow_mut(), breadcrumb, max),
+    });
+}
+
+/// The last `max` breadcrumbs of the current thread and its active scopes,
+/// oldest first. Those of a scope another thread keeps locked, as after a
+/// panic while recording one, are left out.
+pub(crate) fn breadcrumbs(max: usize) -> Vec<Breadcrumb> {
+    let mut trail = Hub::try_with_current(|hub| {
+        let mut trail: Vec<Breadcrumb> =
+            hub.breadcrumbs.try_borrow().ok()?.iter().cloned().collect();
+        for scope in hub.stack.try_borrow().ok()?.iter() {
+            if let Some(breadcrumbs) = scope.breadcrumbs.try_lock_for(LOCK_TIMEOUT) {
+                trail.extend(breadcrumbs.iter().cloned());
+            }
+        }
+        Some(trail)
+    })
+    .flatten()
+    .unwrap_or_default();
+    // Interleave the thread's and the scopes' by time; the sort is stable.
+    trail
+        .sort_by_key(|breadcrumb| chrono::DateTime::parse_from_rfc3339(&breadcrumb.timestamp).ok());
+    let excess = trail.len().saturating_sub(max);
+    trail.drain(..excess);
+    trail
+}
+
+/// Milliseconds since the outermost active scope was first entered.
+pub(crate) fn elapsed_ms() -> Option<u64> {
+    Hub::try_with_current(|hub| {
+        let started = hub
+            .stack
+            .try_borrow()
+            .ok()?
+            .first()?
+            .started
+            .get()
+            .copied()?;
+        Some(started.elapsed().as_millis() as u64)
+    })
+    .flatten()
+}
+
+/// The HTTP request of the innermost scope which has one.
+pub(crate) fn request() -> Option<RequestContext> {
+    Hub::try_with_current(|hub| {
+        hub.stack
+            .try_borrow()
+            .ok()?
+            .iter()
+            .rev()
+            .find_map(|scope| scope.request.read().clone())
+    })
+    .flatten()
+}
+
+/// The innermost active scope with an HTTP request, which the spans of the
+/// request are recorded on.
+#[cfg_attr(not(feature = "tracing"), allow(dead_code))]
+pub(crate) fn request_scope() -> Option<Arc<Scope>> {
+    Hub::try_with_current(|hub| {
+        hub.stack
+            .try_borrow()
+            .ok()?
+            .iter()
+            .rev()
+            .find(|scope| scope.request.read().is_some())
+            .cloned()
+    })
+    .flatten()
+}
+
+/// Removes the spans recorded for the request of the innermost scope which
+/// has one.
+pub(crate) fn take_spans() -> Vec<Span> {
+    Hub::try_with_current(|hub| {
+        let stack = hub.stack.try_borrow().ok()?;
+        let scope = stack
+            .iter()
+            .rev()
+            .find(|scope| scope.request.read().is_some())?;
+        let spans = std::mem::take(&mut *scope.spans.lock());
+        Some(spans)
+    })
+    .flatten()
+    .unwrap_or_default()
+}
+
+/// The attachments of the active scopes, outermost first.
+pub(crate) fn attachments() -> Vec<Attachment> {
+    Hub::try_with_current(|hub| {
+        let stack = hub.stack.try_borrow().ok()?;
+        Some(
+            stack
+                .iter()
+                .flat_map(|scope| scope.attachments.iter().cloned())
+                .collect(),
+        )
+    })
+    .flatten()
+    .unwrap_or_default()
+}
+
+/// Changes the HTTP request of the innermost scope which has one.
+pub(crate) fn update_request(f: impl FnOnce(&mut RequestContext)) {
+    Hub::try_with_current(|hub| {
+        let stack = hub.stack.borrow();
+        if let Some(scope) = stack
+            .iter()
+            .rev()
+            .find(|scope| scope.request.read().is_some())
+        {
+            if let Some(request) = scope.request.write().as_mut() {
+                f(request);
+            }
+        }
+    });
+}
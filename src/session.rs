@@ -0,0 +1,209 @@
+//! How earlier runs ended.
+//!
+//! While the global agent runs, a marker file in the state directory records
+//! that its process is running, locked until the process ends. The panic
+//! hook, signal handling, [`main_wrapper`](crate::main_wrapper) and
+//! [`shutdown`](crate::shutdown) rewrite it with how the process ended: an
+//! exit code, the panic or the signal. A marker that still says the process
+//! is running once its lock is gone belongs to a process that ended without
+//! any of these: killed by SIGKILL or the OOM killer, an OS-level crash, or
+//! `std::process::exit` without a shutdown.
+//!
+//! Each agent started by the same executable reports the markers of ended
+//! processes as `session` messages once connected, so the backend can tell
+//! abnormal terminations apart even if the events describing them were
+//! never sent:
+//!
+//! ```json
+//! {
+//!   "status": "ended",
+//!   "abnormal": true,
+//!   "exit_reason": "panic",
+//!   "message": "index out of bounds: the len is 3 but the index is 7",
+//!   "agent_id": "agent-...",
+//!   "incarnation": 41,
+//!   "pid": 5120,
+//!   "started_at": "2026-03-02T09:12:44.120Z",
+//!   "ended_at": "2026-03-02T11:40:03.981Z"
+//! }
+//! ```
+
+use crate::clock;
+use crate::config::{self, Config};
+use crate::transport::Connection;
+use fs2::FileExt;
+use once_cell::sync::OnceCell;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{Read, Seek, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// How long writing the exit reason waits for the marker, as the panic hook
+/// must not block on a lock this thread may hold.
+const LOCK_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// The marker of this process, locked while it runs.
+static MARKER: OnceCell<Mutex<(File, Marker)>> = OnceCell::new();
+
+/// How a process ended.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "exit_reason", rename_all = "snake_case")]
+pub(crate) enum ExitReason {
+    /// Still running, or ended without the agent noticing.
+    Unknown,
+    /// Returned from `main` or shut the agent down; the exit code where known.
+    Exit {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        exit_code: Option<i32>,
+    },
+    /// Panicked on the main thread, or anywhere with `panic = "abort"`.
+    Panic { message: String },
+    /// Terminated by a signal.
+    Signal { signal: String },
+}
+
+impl ExitReason {
+    /// Whether the process ended other than by exiting successfully or
+    /// being asked to terminate.
+    fn is_abnormal(&self) -> bool {
+        match self {
+            ExitReason::Unknown | ExitReason::Panic { .. } => true,
+            ExitReason::Exit { exit_code } => exit_code.is_some_and(|code| code != 0),
+            ExitReason::Signal { .. } => false,
+        }
+    }
+}
+
+/// Contents of a marker file.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Marker {
+    agent_id: String,
+    incarnation: u64,
+    pid: u32,
+    started_at: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ended_at: Option<String>,
+    #[serde(flatten)]
+    reason: ExitReason,
+}
+
+fn marker_dir(config: &Config) -> Option<PathBuf> {
+    config.private_state_dir()
+}
+
+fn marker_prefix() -> String {
+    format!("session-{}-", config::exe_name())
+}
+
+/// Moves the markers of ended processes of this executable into the spool
+/// of `connection`, to be sent once connected.
+pub(crate) fn report_ended(connection: &Connection, config: &Config) {
+    let Some(Ok(entries)) = marker_dir(config).map(fs::read_dir) else {
+        return;
+    };
+    let prefix = marker_prefix();
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if !name.starts_with(&prefix) || !name.ends_with(".json") {
+            continue;
+        }
+        let path = entry.path();
+        let Ok(mut file) = File::open(&path) else {
+            continue;
+        };
+        // A marker still locked belongs to a running process.
+        match file.try_lock_exclusive() {
+            Ok(()) => {}
+            Err(e) if e.kind() == fs2::lock_contended_error().kind() => continue,
+            Err(e) => {
+                config
+                    .logger
+                    .debug(format_args!("Failed to lock {}: {}", path.display(), e));
+                continue;
+            }
+        }
+        let mut contents = String::new();
+        let read = file.read_to_string(&mut contents);
+        drop(file);
+        // Whoever removes the marker reports it, so it is reported once.
+        if fs::remove_file(&path).is_err() {
+            continue;
+        }
+        let Some(marker) = read
+            .ok()
+            .and_then(|_| serde_json::from_str::<Marker>(&contents).ok())
+        else {
+            continue;
+        };
+        let abnormal = marker.reason.is_abnormal();
+        let Ok(serde_json::Value::Object(mut session)) = serde_json::to_value(&marker) else {
+            continue;
+        };
+        session.insert("status".to_string(), "ended".into());
+        session.insert("abnormal".to_string(), abnormal.into());
+        connection.spool_session(serde_json::Value::Object(session));
+    }
+}
+
+/// Creates the marker of this process, for the global agent.
+pub(crate) fn start(config: &Config) {
+    let Some(dir) = marker_dir(config) else {
+        config.logger.debug(format_args!(
+            "No private state directory; how the process ends is not recorded"
+        ));
+        return;
+    };
+    let path = dir.join(format!("{}{}.json", marker_prefix(), std::process::id()));
+    let marker = Marker {
+        agent_id: config.agent_id.clone(),
+        incarnation: config.incarnation,
+        pid: std::process::id(),
+        started_at: clock::now().to_rfc3339(),
+        ended_at: None,
+        reason: ExitReason::Unknown,
+    };
+    // A marker left by an earlier process with the same PID was reported already.
+    let _ = fs::remove_file(&path);
+    let created = config::create_state_dir(&dir)
+        .and_then(|_| config::open_private(File::options().write(true).create_new(true), &path))
+        .and_then(|mut file| {
+            file.try_lock_exclusive()?;
+            write(&mut file, &marker)?;
+            Ok(file)
+        });
+    match created {
+        Ok(file) => {
+            let _ = MARKER.set(Mutex::new((file, marker)));
+        }
+        Err(e) => config.logger.debug(format_args!(
+            "Failed to create session marker {}: {}",
+            path.display(),
+            e
+        )),
+    }
+}
+
+/// Records how this process ends, replacing any reason recorded before.
+pub(crate) fn end(reason: ExitReason) {
+    let Some(marker) = MARKER.get() else {
+        return;
+    };
+    let Some(mut marker) = marker.try_lock_for(LOCK_TIMEOUT) else {
+        return;
+    };
+    let (file, marker) = &mut *marker;
+    marker.ended_at = Some(clock::now().to_rfc3339());
+    marker.reason = reason;
+    let _ = write(file, marker);
+}
+
+fn write(file: &mut File, marker: &Marker) -> std::io::Result<()> {
+    let json = serde_json::to_vec(marker)?;
+    file.set_len(0)?;
+    file.rewind()?;
+    file.write_all(&json)?;
+    file.sync_data()
+}
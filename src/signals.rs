@@ -0,0 +1,76 @@
+//! Graceful shutdown on termination signals.
+//!
+//! Containers are usually stopped with SIGTERM, often during the very error
+//! storm that should be reported, and killed shortly after. On SIGTERM or
+//! SIGINT the global agent reports that the process exited, flushes queued
+//! events for up to `shutdown_timeout`, and then lets the signal take its
+//! default effect. A second signal while flushing exits immediately.
+
+use crate::Client;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::flag;
+use signal_hook::iterator::Signals;
+use signal_hook::low_level;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Installs the signal handler for `client` on a dedicated thread.
+pub(crate) fn install(client: Client) {
+    let logger = client.config.read().logger.clone();
+
+    // A repeated signal while flushing terminates right away.
+    let terminating = Arc::new(AtomicBool::new(false));
+    for signal in [SIGTERM, SIGINT] {
+        if let Err(e) = flag::register_conditional_shutdown(signal, 1, terminating.clone()) {
+            logger.warn(format_args!("Failed to install signal handler: {}", e));
+            return;
+        }
+    }
+
+    let mut signals = match Signals::new([SIGTERM, SIGINT]) {
+        Ok(signals) => signals,
+        Err(e) => {
+            logger.warn(format_args!("Failed to install signal handler: {}", e));
+            return;
+        }
+    };
+
+    std::thread::spawn(move || {
+        for signal in signals.forever() {
+            let name = if signal == SIGTERM {
+                "SIGTERM"
+            } else {
+                "SIGINT"
+            };
+            let config = client.config();
+            config
+                .logger
+                .info(format_args!("Received {}, flushing events", name));
+
+            terminating.store(true, Ordering::SeqCst);
+            client.connection.send_session_exited(&config, name);
+            if !client.flush_blocking(config.shutdown_timeout) {
+                config.logger.warn(format_args!(
+                    "Events not sent within {:?}",
+                    config.shutdown_timeout
+                ));
+            }
+
+            if !config.exit_on_signal {
+                // The application handles the signal itself.
+                terminating.store(false, Ordering::SeqCst);
+                continue;
+            }
+            // Terminate as the signal would have without the agent.
+            crate::session::end(crate::session::ExitReason::Signal {
+                signal: name.to_string(),
+            });
+            if let Err(e) = low_level::emulate_default_handler(signal) {
+                config
+                    .logger
+                    .warn(format_args!("Failed to re-raise {}: {}", name, e));
+            }
+            std::process::exit(128 + signal);
+        }
+    });
+}
@@ -0,0 +1,300 @@
+//! Upload of the application's source files, keyed by build ID.
+//!
+//! With `upload_sources`, the agent sends a gzip-compressed bundle of the
+//! `.rs` files under the source root once connected, so fixes are generated
+//! against the source of the running build even when the repository is
+//! private. Files ignored by a `.gitignore`, `target` and `.git` directories,
+//! and paths matching `source_exclude` are left out.
+
+use crate::config::Config;
+use crate::{internal, Agent};
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Largest source file included.
+const MAX_FILE_BYTES: u64 = 1024 * 1024;
+
+/// Uncompressed size at which no further files are added.
+const MAX_BUNDLE_BYTES: usize = 16 * 1024 * 1024;
+
+/// How long the upload waits for the connection.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Directories never searched.
+const SKIPPED_DIRS: &[&str] = &[".git", "target"];
+
+/// The first 16 bytes of the SHA-256 of the running executable, in hex.
+pub(crate) fn build_id() -> Option<String> {
+    let mut file = std::fs::File::open(std::env::current_exe().ok()?).ok()?;
+    let mut hasher = Sha256::new();
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        match file.read(&mut buf).ok()? {
+            0 => break,
+            n => hasher.update(&buf[..n]),
+        }
+    }
+    Some(hex::encode(&hasher.finalize()[..16]))
+}
+
+/// Bundles the sources on a background thread and sends them once connected.
+pub(crate) fn upload(agent: Arc<Agent>) {
+    let logger = agent.config.read().logger.clone();
+    let spawned = std::thread::Builder::new()
+        .name("aivory-sources".to_string())
+        .spawn(move || {
+            internal::mark_agent_thread();
+            let config = agent.config();
+            let root = match &config.source_root {
+                Some(root) => root.clone(),
+                None => match std::env::current_dir() {
+                    Ok(dir) => dir,
+                    Err(_) => return,
+                },
+            };
+            let files = collect(&root, &config);
+            if files.is_empty() {
+                config.logger.warn(format_args!(
+                    "No source files to upload under {}",
+                    root.display()
+                ));
+                return;
+            }
+            let payload = match bundle(&files, &root, &config) {
+                Ok(payload) => payload,
+                Err(e) => {
+                    config
+                        .logger
+                        .warn(format_args!("Failed to bundle sources: {}", e));
+                    return;
+                }
+            };
+            if agent.connection.wait_registered(CONNECT_TIMEOUT) {
+                config
+                    .logger
+                    .debug(format_args!("Uploading {} source files", files.len()));
+                agent.connection.send_source_bundle(payload);
+            }
+        });
+    if let Err(e) = spawned {
+        logger.warn(format_args!("Failed to start the source upload: {}", e));
+    }
+}
+
+/// Builds the `source_bundle` payload: the files as a gzip-compressed JSON
+/// object from path, relative to `root` with `/` separators, to contents.
+fn bundle(
+    files: &BTreeMap<String, String>,
+    root: &Path,
+    config: &Config,
+) -> std::io::Result<serde_json::Value> {
+    let json = serde_json::to_vec(files)?;
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(&json)?;
+    let compressed = encoder.finish()?;
+    Ok(serde_json::json!({
+        "agent_id": config.agent_id,
+        "build_id": config.build_id,
+        "root": config.map_path(&root.to_string_lossy()),
+        "file_count": files.len(),
+        "size": json.len(),
+        "encoding": "gzip",
+        "data": base64::engine::general_purpose::STANDARD.encode(compressed),
+    }))
+}
+
+/// Reads the `.rs` files under `root` that are neither ignored nor excluded;
+/// `.gitignore` negations do not re-include excluded files.
+fn collect(root: &Path, config: &Config) -> BTreeMap<String, String> {
+    let exclude = IgnoreFile {
+        dir: root.to_path_buf(),
+        patterns: config
+            .source_exclude
+            .iter()
+            .filter_map(|line| Pattern::parse(line))
+            .collect(),
+    };
+    let mut files = BTreeMap::new();
+    let mut size = 0;
+    let started = Instant::now();
+    walk(root, root, &exclude, &mut Vec::new(), &mut files, &mut size);
+    config.logger.debug(format_args!(
+        "Collected {} source files ({} bytes) in {:?}",
+        files.len(),
+        size,
+        started.elapsed()
+    ));
+    files
+}
+
+fn walk(
+    root: &Path,
+    dir: &Path,
+    exclude: &IgnoreFile,
+    ignores: &mut Vec<IgnoreFile>,
+    files: &mut BTreeMap<String, String>,
+    size: &mut usize,
+) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    let pushed = match std::fs::read_to_string(dir.join(".gitignore")) {
+        Ok(contents) => {
+            ignores.push(IgnoreFile {
+                dir: dir.to_path_buf(),
+                patterns: contents.lines().filter_map(Pattern::parse).collect(),
+            });
+            true
+        }
+        Err(_) => false,
+    };
+
+    let mut entries: Vec<_> = entries.filter_map(|entry| entry.ok()).collect();
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        if *size >= MAX_BUNDLE_BYTES {
+            break;
+        }
+        let path = entry.path();
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        let is_dir = file_type.is_dir();
+        if file_type.is_symlink()
+            || is_ignored(ignores, &path, is_dir)
+            || is_ignored(std::slice::from_ref(exclude), &path, is_dir)
+        {
+            continue;
+        }
+        if is_dir {
+            if !SKIPPED_DIRS.contains(&entry.file_name().to_string_lossy().as_ref()) {
+                walk(root, &path, exclude, ignores, files, size);
+            }
+            continue;
+        }
+        if path.extension().map_or(true, |ext| ext != "rs") {
+            continue;
+        }
+        if entry
+            .metadata()
+            .map_or(true, |meta| meta.len() > MAX_FILE_BYTES)
+        {
+            continue;
+        }
+        if let (Ok(contents), Some(relative)) =
+            (std::fs::read_to_string(&path), relative_path(root, &path))
+        {
+            *size += contents.len();
+            files.insert(relative, contents);
+        }
+    }
+
+    if pushed {
+        ignores.pop();
+    }
+}
+
+/// `path` relative to `dir`, with `/` separators.
+fn relative_path(dir: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(dir).ok()?;
+    let parts: Vec<_> = relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect();
+    Some(parts.join("/"))
+}
+
+/// Whether the last pattern matching `path`, across the ignore files from
+/// the outermost directory in, excludes it.
+fn is_ignored(ignores: &[IgnoreFile], path: &Path, is_dir: bool) -> bool {
+    let mut ignored = false;
+    for file in ignores {
+        let Some(relative) = relative_path(&file.dir, path) else {
+            continue;
+        };
+        for pattern in &file.patterns {
+            if pattern.matches(&relative, is_dir) {
+                ignored = !pattern.negated;
+            }
+        }
+    }
+    ignored
+}
+
+/// The patterns of a `.gitignore`, relative to its directory.
+struct IgnoreFile {
+    dir: PathBuf,
+    patterns: Vec<Pattern>,
+}
+
+/// A line of a `.gitignore`.
+struct Pattern {
+    glob: String,
+    /// `!pattern`: re-includes what earlier patterns excluded.
+    negated: bool,
+    /// `pattern/`: only matches directories.
+    dir_only: bool,
+    /// Contains a `/` before the end, so it matches from the directory of the
+    /// ignore file rather than against the name at any depth.
+    anchored: bool,
+}
+
+impl Pattern {
+    fn parse(line: &str) -> Option<Pattern> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let anchored = line.contains('/');
+        Some(Pattern {
+            glob: line.trim_start_matches('/').to_string(),
+            negated,
+            dir_only,
+            anchored,
+        })
+    }
+
+    /// Matches `relative`, a `/`-separated path from the ignore file's directory.
+    fn matches(&self, relative: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        if self.anchored {
+            glob_match(self.glob.as_bytes(), relative.as_bytes())
+        } else {
+            let name = relative.rsplit('/').next().unwrap_or(relative);
+            glob_match(self.glob.as_bytes(), name.as_bytes())
+        }
+    }
+}
+
+/// Matches `text` against a glob where `*` and `?` stop at `/` and `**`
+/// spans directories.
+fn glob_match(glob: &[u8], text: &[u8]) -> bool {
+    match glob {
+        [] => text.is_empty(),
+        [b'*', b'*', rest @ ..] => {
+            let rest = rest.strip_prefix(b"/").unwrap_or(rest);
+            (0..=text.len()).any(|i| glob_match(rest, &text[i..]))
+        }
+        [b'*', rest @ ..] => {
+            let limit = text.iter().position(|&c| c == b'/').unwrap_or(text.len());
+            (0..=limit).any(|i| glob_match(rest, &text[i..]))
+        }
+        [b'?', rest @ ..] => matches!(text, [c, tail @ ..] if *c != b'/' && glob_match(rest, tail)),
+        [c, rest @ ..] => matches!(text, [t, tail @ ..] if t == c && glob_match(rest, tail)),
+    }
+}
@@ -0,0 +1,175 @@
+//! Monitoring of async tasks.
+//!
+//! A panic inside a spawned task does not bring down the process; it surfaces
+//! as a [`JoinError`] when the task is awaited, which only says that the task
+//! panicked. [`spawn_monitored`] spawns a task whose events carry the task ID
+//! and where it was spawned, and reports a panic even if the application has
+//! replaced the panic hook:
+//!
+//! ```rust,no_run
+//! # async fn sync_inventory() {}
+//! # async fn run() {
+//! let handle = aivory_monitor::task::spawn_monitored(sync_inventory());
+//! if let Err(e) = handle.await {
+//!     // Already reported; the handle behaves like that of `tokio::spawn`.
+//!     eprintln!("inventory sync failed: {}", e);
+//! }
+//! # }
+//! ```
+//!
+//! For tasks spawned some other way, pass the [`JoinError`] to
+//! [`capture_join_error`](crate::capture_join_error).
+//!
+//! A blocking call inside a future stalls every task on its executor thread.
+//! [`PollTimeExt::monitor_poll_time`] reports each poll of a future that takes
+//! longer than a threshold as a `SlowPoll` warning event, on any executor:
+//!
+//! ```rust,no_run
+//! use aivory_monitor::task::PollTimeExt;
+//! use std::time::Duration;
+//!
+//! # async fn handle_request() {}
+//! # async fn run() {
+//! handle_request().monitor_poll_time(Duration::from_millis(50)).await;
+//! # }
+//! ```
+
+use crate::scope::{self, Scope};
+use futures_util::FutureExt;
+use std::cell::Cell;
+use std::future::Future;
+use std::panic::{AssertUnwindSafe, Location};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::task::{JoinError, JoinHandle};
+
+thread_local! {
+    /// Set by the panic hook once it has reported the panic unwinding the
+    /// current thread.
+    static PANIC_REPORTED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Records that the panic hook reported the current panic.
+pub(crate) fn mark_panic_reported() {
+    let _ = PANIC_REPORTED.try_with(|reported| reported.set(true));
+}
+
+/// Whether the panic hook reported the panic caught last, resetting the flag.
+pub(crate) fn take_panic_reported() -> bool {
+    PANIC_REPORTED
+        .try_with(|reported| reported.replace(false))
+        .unwrap_or(false)
+}
+
+/// Spawns `future` on the current Tokio runtime, like `tokio::spawn`, and
+/// reports a panic in it.
+///
+/// Events captured in the task carry its `task_id` and the `spawned_at`
+/// location of this call. A panic is normally reported by the panic hook,
+/// with the location it occurred at; if the hook did not report it, it is
+/// reported here with its message. Either way the panic then resumes, so the
+/// returned handle yields the same [`JoinError`] as with `tokio::spawn`.
+///
+/// # Panics
+///
+/// Panics when called outside of a Tokio runtime.
+#[track_caller]
+pub fn spawn_monitored<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let spawned_at = Location::caller();
+    tokio::spawn(async move {
+        let scope = Scope::new()
+            .context("task_id", tokio::task::id().to_string())
+            .context(
+                "spawned_at",
+                format!("{}:{}", spawned_at.file(), spawned_at.line()),
+            );
+        scope::scoped(scope, async move {
+            match AssertUnwindSafe(future).catch_unwind().await {
+                Ok(output) => output,
+                Err(payload) => {
+                    if !take_panic_reported() {
+                        if let Some(client) = crate::hub::client() {
+                            client.capture_panic_payload(
+                                &*payload,
+                                crate::capture::Mechanism::JoinError,
+                            );
+                        }
+                    }
+                    std::panic::resume_unwind(payload)
+                }
+            }
+        })
+        .await
+    })
+}
+
+/// The message of a panic as rendered by `JoinError`'s `Display`, e.g.
+/// `task 12 panicked with message "index out of bounds"`.
+pub(crate) fn join_error_message(error: &JoinError) -> String {
+    let rendered = error.to_string();
+    match rendered.split_once(" panicked with message ") {
+        // Rendered with `{:?}`, whose escapes JSON strings mostly share.
+        Some((_, quoted)) => {
+            serde_json::from_str(quoted).unwrap_or_else(|_| quoted.trim_matches('"').to_string())
+        }
+        None if error.is_panic() => "task panicked".to_string(),
+        None => "task was cancelled".to_string(),
+    }
+}
+
+/// Adds [`monitor_poll_time`](Self::monitor_poll_time) to futures.
+pub trait PollTimeExt: Future + Sized {
+    /// Reports every poll of this future that takes longer than `threshold`.
+    ///
+    /// Each slow poll is captured as a `SlowPoll` event with the `level`
+    /// context value `warning` and the poll's `duration_ms` and
+    /// `threshold_ms` under `slow_poll`. A poll is timed as a whole, so the
+    /// event names the future's type and where this was called, not the
+    /// blocking call itself; instrument nested futures to narrow it down.
+    #[track_caller]
+    fn monitor_poll_time(self, threshold: Duration) -> MonitorPollTime<Self> {
+        MonitorPollTime {
+            future: Box::pin(self),
+            threshold,
+            location: Location::caller(),
+        }
+    }
+}
+
+impl<F: Future> PollTimeExt for F {}
+
+/// Future returned by [`PollTimeExt::monitor_poll_time`].
+pub struct MonitorPollTime<F> {
+    future: Pin<Box<F>>,
+    threshold: Duration,
+    location: &'static Location<'static>,
+}
+
+impl<F: Future> Future for MonitorPollTime<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        let started = Instant::now();
+        let poll = self.future.as_mut().poll(cx);
+        let elapsed = started.elapsed();
+        if elapsed > self.threshold {
+            if let Some(client) = crate::hub::client() {
+                client.report(None, |config| {
+                    crate::capture::capture_slow_poll(
+                        std::any::type_name::<F>(),
+                        self.location,
+                        elapsed,
+                        self.threshold,
+                        config,
+                    )
+                });
+            }
+        }
+        poll
+    }
+}
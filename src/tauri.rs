@@ -0,0 +1,188 @@
+//! [Tauri](https://tauri.app) plugin for desktop apps.
+//!
+//! The plugin tags every event with the app's name and version, and injects
+//! a script into each webview that forwards uncaught JavaScript errors and
+//! unhandled promise rejections to the agent. Backend panics are reported by
+//! the panic hook as usual.
+//!
+//! ```rust,ignore
+//! fn main() {
+//!     aivory_monitor::init(config);
+//!
+//!     tauri::Builder::default()
+//!         .plugin(aivory_monitor::tauri::init())
+//!         .run(tauri::generate_context!())
+//!         .expect("error while running tauri application");
+//! }
+//! ```
+//!
+//! Webview errors are sent as the `aivory://webview-error` event, so the
+//! window's capability must allow emitting events (`core:event:default`,
+//! included in `core:default`).
+
+use crate::capture::{Mechanism, StackFrame};
+use crate::EventBuilder;
+use ::tauri::plugin::{Builder, TauriPlugin};
+use ::tauri::{Listener, Runtime, WebviewWindow};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+
+/// Event the injected script emits for each webview error.
+const WEBVIEW_ERROR_EVENT: &str = "aivory://webview-error";
+
+/// Script injected into every webview.
+const INIT_SCRIPT: &str = r#"(function () {
+  if (window.__AIVORY_MONITOR__) return;
+  window.__AIVORY_MONITOR__ = true;
+
+  function report(error, message, kind) {
+    var internals = window.__TAURI_INTERNALS__;
+    if (!internals) return;
+    var metadata = internals.metadata || {};
+    var payload = {
+      kind: kind,
+      name: error && error.name ? String(error.name) : "Error",
+      message: error && error.message ? String(error.message) : String(message),
+      stack: error && error.stack ? String(error.stack) : null,
+      window: metadata.currentWindow ? metadata.currentWindow.label : null,
+      url: window.location.href
+    };
+    internals
+      .invoke("plugin:event|emit", { event: "aivory://webview-error", payload: payload })
+      .catch(function () {});
+  }
+
+  window.addEventListener("error", function (event) {
+    report(event.error, event.message, "error");
+  });
+  window.addEventListener("unhandledrejection", function (event) {
+    report(event.reason, event.reason, "unhandledrejection");
+  });
+})();
+"#;
+
+/// Creates the plugin. Register it with `tauri::Builder::plugin`.
+pub fn init<R: Runtime>() -> TauriPlugin<R> {
+    Builder::new("aivory-monitor")
+        .js_init_script(INIT_SCRIPT.to_string())
+        .setup(|app, _api| {
+            let package = app.package_info();
+            let _ = crate::set_context_typed(
+                "app",
+                &AppInfo {
+                    name: &package.name,
+                    version: package.version.to_string(),
+                    identifier: &app.config().identifier,
+                },
+            );
+
+            app.listen_any(WEBVIEW_ERROR_EVENT, |event| {
+                if let Ok(error) = serde_json::from_str::<WebviewError>(event.payload()) {
+                    crate::capture_event_with(|| webview_event(error));
+                }
+            });
+            Ok(())
+        })
+        .build()
+}
+
+/// Captures an error raised while handling a command from `window`.
+///
+/// ```rust,ignore
+/// #[tauri::command]
+/// fn save(window: tauri::WebviewWindow, path: String) -> Result<(), String> {
+///     write_file(&path).map_err(|e| {
+///         aivory_monitor::tauri::capture_error(&window, &e);
+///         e.to_string()
+///     })
+/// }
+/// ```
+pub fn capture_error<R: Runtime, E: std::error::Error>(window: &WebviewWindow<R>, error: &E) {
+    let mut context = HashMap::new();
+    context.insert(
+        "window".to_string(),
+        json!({
+            "label": window.label(),
+            "url": window.url().ok().map(|url| url.to_string()),
+        }),
+    );
+    crate::capture_error_with_context(error, context);
+}
+
+#[derive(Serialize)]
+struct AppInfo<'a> {
+    name: &'a str,
+    version: String,
+    identifier: &'a str,
+}
+
+/// Payload emitted by [`INIT_SCRIPT`].
+#[derive(Deserialize)]
+struct WebviewError {
+    kind: String,
+    name: String,
+    message: String,
+    stack: Option<String>,
+    window: Option<String>,
+    url: Option<String>,
+}
+
+fn webview_event(error: WebviewError) -> EventBuilder {
+    let frames = error
+        .stack
+        .as_deref()
+        .map(parse_js_stack)
+        .unwrap_or_default();
+    EventBuilder::new(error.name)
+        .message(error.message)
+        .stack_trace(frames)
+        .mechanism(Mechanism::Middleware)
+        // Uncaught errors and rejections, which the page did not recover from.
+        .handled(false)
+        .context("window", json!({ "label": error.window, "url": error.url }))
+        .context("webview", json!({ "kind": error.kind }))
+}
+
+/// Parses a V8 (`at f (file:1:2)`) or WebKit (`f@file:1:2`) stack trace.
+fn parse_js_stack(stack: &str) -> Vec<StackFrame> {
+    // V8 traces start with the message, which may itself contain `@`.
+    let v8 = stack
+        .lines()
+        .any(|line| line.trim_start().starts_with("at "));
+    stack
+        .lines()
+        .filter_map(|line| parse_js_frame(line.trim(), v8))
+        .collect()
+}
+
+fn parse_js_frame(line: &str, v8: bool) -> Option<StackFrame> {
+    let (name, location) = if v8 {
+        let rest = line.strip_prefix("at ")?;
+        match rest.strip_suffix(')').and_then(|r| r.split_once(" (")) {
+            Some((name, location)) => (name, location),
+            None => ("<anonymous>", rest),
+        }
+    } else {
+        line.split_once('@')?
+    };
+    let name = if name.is_empty() { "<anonymous>" } else { name };
+
+    let mut parts = location.rsplitn(3, ':');
+    let (column, line_number, file) = (parts.next(), parts.next(), parts.next());
+    let (Some(column), Some(line_number), Some(file)) = (
+        column.and_then(|c| c.parse::<u32>().ok()),
+        line_number.and_then(|l| l.parse::<u32>().ok()),
+        file,
+    ) else {
+        // `[native code]` and other frames without a position.
+        let mut frame = StackFrame::new(name);
+        frame.is_native = true;
+        return Some(frame);
+    };
+
+    let mut frame = StackFrame::new(name).file(file, line_number);
+    frame.column_number = Some(column);
+    frame.source_available = true;
+    Some(frame)
+}
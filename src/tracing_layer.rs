@@ -0,0 +1,143 @@
+//! [tracing](https://docs.rs/tracing) integration.
+//!
+//! [`AivoryLayer`] mirrors the spans created while a request is being served
+//! as performance spans of its transaction, with their name, fields and
+//! duration, so code already instrumented with `#[instrument]` reports
+//! timings without further changes. Spans created outside a scope with a
+//! [`RequestContext`](crate::RequestContext) are ignored; see
+//! [`performance`](crate::performance).
+//!
+//! ```rust,ignore
+//! use tracing_subscriber::prelude::*;
+//!
+//! tracing_subscriber::registry()
+//!     .with(tracing_subscriber::fmt::layer())
+//!     .with(aivory_monitor::tracing_layer::AivoryLayer::new())
+//!     .init();
+//! ```
+
+use crate::clock;
+use crate::performance::Span;
+use crate::scope::{self, Scope};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::Subscriber;
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Layer recording `tracing` spans on the transaction of the request being
+/// served.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AivoryLayer;
+
+impl AivoryLayer {
+    /// Creates the layer.
+    pub fn new() -> Self {
+        AivoryLayer
+    }
+}
+
+/// A span being recorded, kept in the span's extensions until it closes.
+struct OpenSpan {
+    span_id: String,
+    parent_span_id: Option<String>,
+    fields: BTreeMap<String, serde_json::Value>,
+    start_timestamp: String,
+    started: Instant,
+    /// The scope of the request the span belongs to.
+    scope: Arc<Scope>,
+}
+
+impl<S> Layer<S> for AivoryLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(scope) = scope::request_scope() else {
+            return;
+        };
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let parent_span_id = span.parent().and_then(|parent| {
+            parent
+                .extensions()
+                .get::<OpenSpan>()
+                .map(|open| open.span_id.clone())
+        });
+        let mut fields = FieldVisitor::default();
+        attrs.record(&mut fields);
+        span.extensions_mut().insert(OpenSpan {
+            span_id: uuid::Uuid::new_v4().simple().to_string()[..16].to_string(),
+            parent_span_id,
+            fields: fields.0,
+            start_timestamp: clock::now().to_rfc3339(),
+            started: Instant::now(),
+            scope,
+        });
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut extensions = span.extensions_mut();
+        if let Some(open) = extensions.get_mut::<OpenSpan>() {
+            let mut fields = FieldVisitor::default();
+            values.record(&mut fields);
+            open.fields.extend(fields.0);
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let Some(open) = span.extensions_mut().remove::<OpenSpan>() else {
+            return;
+        };
+        open.scope.add_span(Span {
+            span_id: open.span_id,
+            parent_span_id: open.parent_span_id,
+            name: span.name().to_string(),
+            fields: open.fields,
+            start_timestamp: open.start_timestamp,
+            duration_ms: open.started.elapsed().as_secs_f64() * 1000.0,
+        });
+    }
+}
+
+/// Collects the fields of a span as JSON values.
+#[derive(Default)]
+struct FieldVisitor(BTreeMap<String, serde_json::Value>);
+
+impl Visit for FieldVisitor {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{:?}", value).into());
+    }
+}
@@ -1,17 +1,98 @@
-//! WebSocket transport to AIVory backend.
+//! Transports to the AIVory backend.
+//!
+//! Long-running processes keep a WebSocket connection open from a background
+//! runtime. Serverless functions and WASI modules use the [`http`] transport,
+//! which sends from the capturing thread instead.
 
+mod ack;
+mod circuit;
+mod envelope;
+mod http;
+#[cfg(not(target_os = "wasi"))]
+mod liveness;
+mod queue;
+mod spool;
+mod throttle;
+
+pub use ack::{DeliveryError, EventId, SendError};
+pub use http::{HttpRequest, HttpSender};
+pub use spool::Spool;
+
+use crate::audit::{EventRef, Outcome};
 use crate::capture::ExceptionCapture;
+use crate::clock;
 use crate::config::Config;
-use futures_util::{SinkExt, StreamExt};
+use crate::diagnostics::{AgentDiagnostics, ConnectionState, Diagnostics, DropReason};
+use crate::internal::{InternalErrorKind, InternalErrorReport, InternalErrors};
+use ack::Acks;
+use circuit::CircuitBreaker;
+use envelope::Envelope;
+use once_cell::sync::OnceCell;
 use parking_lot::RwLock;
-use serde::{Deserialize, Serialize};
-use std::sync::atomic::{AtomicBool, Ordering};
+use queue::{OutboundQueue, Priority};
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
-use tokio::sync::mpsc;
-use tokio_tungstenite::{connect_async, tungstenite::Message as WsMessage};
+use std::time::{Duration, Instant};
+use throttle::Throttle;
+#[cfg(not(target_os = "wasi"))]
+use {
+    crate::runtime::{self, connect_async},
+    crate::{diagnostics, scope},
+    futures_util::future::{self, Either},
+    futures_util::{SinkExt, StreamExt},
+    liveness::Liveness,
+    tokio_tungstenite::tungstenite::Message as WsMessage,
+};
+
+/// How events are delivered to the backend.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransportMode {
+    /// A persistent WebSocket connection driven by a background runtime.
+    WebSocket,
+    /// A blocking HTTP request per capture, with no background threads.
+    Http,
+}
+
+impl TransportMode {
+    /// [`Http`](TransportMode::Http) on WASI and in serverless functions
+    /// (AWS Lambda, Google Cloud Functions, Azure Functions, Vercel, Netlify),
+    /// otherwise [`WebSocket`](TransportMode::WebSocket).
+    pub fn detect() -> Self {
+        const SERVERLESS_VARS: &[&str] = &[
+            "AWS_LAMBDA_FUNCTION_NAME",
+            "FUNCTION_TARGET",
+            "FUNCTIONS_WORKER_RUNTIME",
+            "VERCEL",
+            "NETLIFY",
+        ];
+        if cfg!(target_os = "wasi")
+            || SERVERLESS_VARS
+                .iter()
+                .any(|var| std::env::var_os(var).is_some())
+        {
+            TransportMode::Http
+        } else {
+            TransportMode::WebSocket
+        }
+    }
+}
+
+impl std::str::FromStr for TransportMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "websocket" | "ws" => Ok(TransportMode::WebSocket),
+            "http" => Ok(TransportMode::Http),
+            _ => Err(()),
+        }
+    }
+}
 
 /// Result of a single connection attempt.
+#[cfg(not(target_os = "wasi"))]
 enum ConnectResult {
     /// Normal disconnect (server closed, network error, etc.) - should reconnect.
     Disconnected,
@@ -19,10 +100,58 @@ enum ConnectResult {
     AuthError,
 }
 
-/// WebSocket connection to the AIVory backend.
+/// A serialized message waiting in the outbound queue.
+struct Frame {
+    json: String,
+    /// Whether this frame carries an event (as opposed to control traffic
+    /// such as heartbeats), for diagnostics.
+    is_event: bool,
+    priority: Priority,
+    /// The exception this frame carries, for the audit log.
+    event: Option<EventRef>,
+    /// Whether this frame was taken from the spool, where it goes back to if
+    /// it is not written.
+    spooled: bool,
+}
+
+impl Frame {
+    /// Records the outcome of the exception this frame carries in the audit log.
+    fn audit(&self, diagnostics: &Diagnostics, outcome: Outcome) {
+        if let Some(event) = &self.event {
+            diagnostics.audit(&event.id, Some(&event.fingerprint), outcome);
+        }
+    }
+}
+
+type SenderSlot = Arc<RwLock<Option<Arc<OutboundQueue>>>>;
+
+/// The write half of the current WebSocket connection, for sending without
+/// going through the outbound queue.
+#[cfg(not(target_os = "wasi"))]
+#[derive(Clone)]
+struct DirectWriter {
+    sink: Arc<tokio::sync::Mutex<futures_util::stream::SplitSink<runtime::WsStream, WsMessage>>>,
+    spawner: runtime::Spawner,
+}
+
+#[cfg(not(target_os = "wasi"))]
+type DirectSlot = Arc<RwLock<Option<DirectWriter>>>;
+
+/// Connection to the AIVory backend.
 pub struct Connection {
-    sender: RwLock<Option<mpsc::UnboundedSender<String>>>,
-    connected: RwLock<bool>,
+    sender: SenderSlot,
+    #[cfg(not(target_os = "wasi"))]
+    direct: DirectSlot,
+    http: OnceCell<http::HttpTransport>,
+    diagnostics: Arc<Diagnostics>,
+    internal_errors: Arc<InternalErrors>,
+    breaker: Arc<CircuitBreaker>,
+    throttle: Arc<Throttle>,
+    acks: Arc<Acks>,
+    dry_run: AtomicBool,
+    spool: Arc<OnceCell<Spool>>,
+    /// Messages lost as of the last flush, which reports any lost since.
+    flushed_lost: AtomicU64,
 }
 
 #[derive(Serialize)]
@@ -33,7 +162,8 @@ struct OutgoingMessage<T: Serialize> {
     timestamp: i64,
 }
 
-#[derive(Deserialize)]
+#[cfg(not(target_os = "wasi"))]
+#[derive(serde::Deserialize)]
 struct IncomingMessage {
     #[serde(rename = "type")]
     msg_type: String,
@@ -44,193 +174,578 @@ struct IncomingMessage {
 impl Connection {
     /// Creates a new connection.
     pub fn new() -> Self {
+        let diagnostics = Arc::new(Diagnostics::new());
         Connection {
-            sender: RwLock::new(None),
-            connected: RwLock::new(false),
+            sender: Arc::new(RwLock::new(None)),
+            #[cfg(not(target_os = "wasi"))]
+            direct: Arc::new(RwLock::new(None)),
+            http: OnceCell::new(),
+            breaker: Arc::new(CircuitBreaker::new(diagnostics.clone())),
+            throttle: Arc::new(Throttle::new(None)),
+            diagnostics,
+            internal_errors: Arc::new(InternalErrors::new()),
+            acks: Arc::new(Acks::new()),
+            dry_run: AtomicBool::new(false),
+            spool: Arc::new(OnceCell::new()),
+            flushed_lost: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the health counters for this connection.
+    pub fn diagnostics(&self) -> &Arc<Diagnostics> {
+        &self.diagnostics
+    }
+
+    /// Whether an event may be captured, or the circuit breaker has paused
+    /// capture because the backend keeps failing.
+    pub(crate) fn allow_capture(&self) -> bool {
+        self.breaker.allow()
+    }
+
+    /// Applies the circuit breaker and bandwidth limit of `config`, also to
+    /// a connection already open.
+    pub(crate) fn configure_limits(&self, config: &Config) {
+        self.breaker.configure(
+            config.circuit_breaker_threshold,
+            config.circuit_breaker_cooldown,
+            config.logger.clone(),
+        );
+        self.throttle.set_limit(config.max_bytes_per_second);
+    }
+
+    /// Keeps last events that could not be sent in the state directory of
+    /// `config`, and sends those a previous process left there once connected.
+    pub(crate) fn open_spool(&self, config: &Config) {
+        match Spool::new(config) {
+            Some(spool) => {
+                let _ = self.spool.set(spool);
+            }
+            None => config.logger.debug(format_args!(
+                "No private state directory or usable spool key; unsent events are not spooled"
+            )),
         }
     }
 
+    /// Switches this connection to the HTTP transport. Nothing is sent until
+    /// the first message, or messages left in the spool.
+    pub(crate) fn open_http(&self, config: &Config) {
+        self.configure_limits(config);
+        let transport = http::HttpTransport::new(
+            config,
+            self.diagnostics.clone(),
+            self.breaker.clone(),
+            self.throttle.clone(),
+        );
+        if self.http.set(transport).is_ok() {
+            self.diagnostics
+                .set_connection_state(ConnectionState::Connected);
+        }
+        if let (Some(http), Some(spool)) = (self.http.get(), self.spool.get()) {
+            for json in spool.take() {
+                http.send(Frame {
+                    json,
+                    is_event: true,
+                    priority: Priority::Fatal,
+                    event: None,
+                    spooled: true,
+                });
+            }
+        }
+    }
+
+    /// Switches this connection to dry-run mode, where events are written to
+    /// stderr instead of being sent.
+    pub(crate) fn open_dry_run(&self) {
+        self.dry_run.store(true, Ordering::Relaxed);
+        self.diagnostics
+            .set_connection_state(ConnectionState::Connected);
+    }
+
+    /// Whether events are written to stderr instead of being sent.
+    pub(crate) fn is_dry_run(&self) -> bool {
+        self.dry_run.load(Ordering::Relaxed)
+    }
+
+    /// Whether this connection uses the HTTP transport.
+    pub(crate) fn is_http(&self) -> bool {
+        self.http.get().is_some()
+    }
+
+    /// Blocks until queued messages have been written or `timeout` elapses.
+    /// Returns true if nothing is left queued, and nothing queued since the
+    /// previous flush was dropped instead of written, e.g. with a connection
+    /// that went away.
+    pub(crate) fn flush(&self, timeout: Duration) -> bool {
+        let written = match self.http.get() {
+            Some(http) => http.flush(timeout),
+            None => {
+                let deadline = Instant::now() + timeout;
+                let mut written = true;
+                while self.diagnostics.snapshot().queue_depth > 0 {
+                    if Instant::now() >= deadline {
+                        written = false;
+                        break;
+                    }
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+                written
+            }
+        };
+        let lost = self.diagnostics.lost();
+        written && self.flushed_lost.swap(lost, Ordering::Relaxed) == lost
+    }
+
     /// Connects to the backend.
+    #[cfg(not(target_os = "wasi"))]
     pub async fn connect(&self, config: &Config) {
-        let url = match url::Url::parse(&config.backend_url) {
-            Ok(u) => u,
+        let urls = match config
+            .websocket_urls()
+            .iter()
+            .map(|url| url::Url::parse(url))
+            .collect::<Result<Vec<_>, _>>()
+        {
+            Ok(urls) => urls,
             Err(e) => {
-                eprintln!("[AIVory Monitor] Invalid backend URL: {}", e);
+                config
+                    .logger
+                    .error(format_args!("Invalid backend URL: {}", e));
+                self.diagnostics
+                    .record_error(format!("invalid backend URL: {}", e));
                 return;
             }
         };
 
+        self.configure_limits(config);
         let config = config.clone();
-        let sender_slot = Arc::new(RwLock::new(None::<mpsc::UnboundedSender<String>>));
-        let connected = Arc::new(RwLock::new(false));
-
-        let sender_slot_clone = sender_slot.clone();
-        let connected_clone = connected.clone();
+        let sender_slot = self.sender.clone();
+        let diagnostics = self.diagnostics.clone();
+        let internal_errors = self.internal_errors.clone();
+        let breaker = self.breaker.clone();
+        let throttle = self.throttle.clone();
+        let acks = self.acks.clone();
+        let direct_slot = self.direct.clone();
+        let spool = self.spool.clone();
 
-        tokio::spawn(async move {
+        runtime::spawn(async move {
             let mut reconnect_attempts = 0;
             let max_reconnect_attempts = 10;
+            // Index into `urls`. The host is resolved anew on every attempt,
+            // so a changed backend address is picked up on reconnect.
+            let mut endpoint = 0;
 
             loop {
                 // Create a cancellation flag for the heartbeat task
                 let heartbeat_cancel = Arc::new(AtomicBool::new(false));
 
+                diagnostics.set_connection_state(ConnectionState::Connecting);
                 let result = Self::connect_once(
-                    &url,
+                    &urls[endpoint],
                     &config,
-                    sender_slot_clone.clone(),
-                    connected_clone.clone(),
+                    sender_slot.clone(),
+                    diagnostics.clone(),
+                    internal_errors.clone(),
+                    breaker.clone(),
+                    throttle.clone(),
+                    acks.clone(),
                     heartbeat_cancel.clone(),
-                ).await;
+                    direct_slot.clone(),
+                    spool.clone(),
+                )
+                .await;
 
                 // Cancel the heartbeat task before reconnecting
                 heartbeat_cancel.store(true, Ordering::SeqCst);
 
-                *connected_clone.write() = false;
-                *sender_slot_clone.write() = None;
+                if let Some(queue) = sender_slot.write().take() {
+                    queue.close();
+                    // The connection is gone; what the writer did not get to is not sent.
+                    for frame in queue.drain() {
+                        respool(frame, &spool, &diagnostics);
+                    }
+                }
+                direct_slot.write().take();
+                diagnostics.reset_queue_depth();
+                diagnostics.set_connection_state(ConnectionState::Disconnected);
 
                 match result {
                     Ok(ConnectResult::AuthError) => {
-                        eprintln!("[AIVory Monitor] Authentication failed, stopping reconnect");
+                        config
+                            .logger
+                            .error(format_args!("Authentication failed, stopping reconnect"));
+                        diagnostics.set_connection_state(ConnectionState::AuthFailed);
                         break;
                     }
                     Ok(ConnectResult::Disconnected) => {
                         reconnect_attempts = 0;
+                        endpoint = 0;
                     }
                     Err(e) => {
-                        if config.debug {
-                            eprintln!("[AIVory Monitor] Connection error: {}", e);
+                        breaker.record_failure();
+                        diagnostics.record_error(format!("connection error: {}", e));
+                        config.logger.debug(format_args!("Connection error: {}", e));
+                        if urls.len() > 1 {
+                            endpoint = (endpoint + 1) % urls.len();
+                            config
+                                .logger
+                                .debug(format_args!("Failing over to {}", urls[endpoint]));
                         }
                     }
                 }
 
                 reconnect_attempts += 1;
                 if reconnect_attempts > max_reconnect_attempts {
-                    eprintln!("[AIVory Monitor] Max reconnect attempts reached");
+                    config
+                        .logger
+                        .error(format_args!("Max reconnect attempts reached"));
                     break;
                 }
 
+                diagnostics.record_reconnect();
+                diagnostics.set_connection_state(ConnectionState::Connecting);
+
                 let delay = Duration::from_secs(2u64.pow(reconnect_attempts.min(6)));
-                if config.debug {
-                    eprintln!(
-                        "[AIVory Monitor] Reconnecting in {:?} (attempt {})",
-                        delay, reconnect_attempts
-                    );
-                }
-                tokio::time::sleep(delay).await;
+                config.logger.debug(format_args!(
+                    "Reconnecting in {:?} (attempt {})",
+                    delay, reconnect_attempts
+                ));
+                runtime::sleep(delay).await;
             }
         });
-
-        // Store references
-        // Note: In a real implementation, we'd need better synchronization
     }
 
+    #[cfg(not(target_os = "wasi"))]
+    #[allow(clippy::too_many_arguments)]
     async fn connect_once(
         url: &url::Url,
         config: &Config,
-        sender_slot: Arc<RwLock<Option<mpsc::UnboundedSender<String>>>>,
-        connected: Arc<RwLock<bool>>,
+        sender_slot: SenderSlot,
+        diagnostics: Arc<Diagnostics>,
+        internal_errors: Arc<InternalErrors>,
+        breaker: Arc<CircuitBreaker>,
+        throttle: Arc<Throttle>,
+        acks: Arc<Acks>,
         heartbeat_cancel: Arc<AtomicBool>,
+        direct_slot: DirectSlot,
+        spool: Arc<OnceCell<Spool>>,
     ) -> Result<ConnectResult, Box<dyn std::error::Error + Send + Sync>> {
-        if config.debug {
-            println!("[AIVory Monitor] Connecting to {}", url);
-        }
+        let logger = &config.logger;
+        logger.debug(format_args!("Connecting to {}", url));
 
         let (ws_stream, _) = connect_async(url.as_str()).await?;
         let (mut write, mut read) = ws_stream.split();
 
-        if config.debug {
-            println!("[AIVory Monitor] WebSocket connected");
-        }
+        logger.debug(format_args!("WebSocket connected"));
 
-        // Create message channel
-        let (tx, mut rx) = mpsc::unbounded_channel::<String>();
+        // Create the outbound queue
+        let tx = Arc::new(OutboundQueue::new(queue::MAX_QUEUED));
         *sender_slot.write() = Some(tx.clone());
-        *connected.write() = true;
+        diagnostics.set_connection_state(ConnectionState::Connected);
 
-        // Send registration
+        // Send registration. The local send time doubles as the start of the
+        // clock synchronization round trip.
+        let register_sent_at = clock::local_millis();
+        let mut payload = register_payload(config);
+        payload["api_key"] = config.api_key.clone().into();
         let register_msg = OutgoingMessage {
             msg_type: "register".to_string(),
-            payload: serde_json::json!({
-                "api_key": config.api_key,
-                "agent_id": config.agent_id,
-                "hostname": config.hostname,
-                "environment": config.environment,
-                "agent_version": "1.0.2",
-                "runtime": "rust",
-                "runtime_version": env!("CARGO_PKG_VERSION"),
-                "platform": std::env::consts::OS,
-                "arch": std::env::consts::ARCH,
-            }),
-            timestamp: chrono::Utc::now().timestamp_millis(),
+            payload,
+            timestamp: register_sent_at,
         };
 
         let msg_json = serde_json::to_string(&register_msg)?;
+        let msg_len = msg_json.len();
         write.send(WsMessage::Text(msg_json)).await?;
+        diagnostics.record_sent(msg_len, false);
 
         // Message handling loop
-        let debug = config.debug;
-
-        // Spawn sender task
-        let mut write = write;
-        tokio::spawn(async move {
-            while let Some(msg) = rx.recv().await {
-                if write.send(WsMessage::Text(msg)).await.is_err() {
+        // Spawn sender task; the sink is shared with the pinger.
+        let write = Arc::new(tokio::sync::Mutex::new(write));
+        *direct_slot.write() = Some(DirectWriter {
+            sink: write.clone(),
+            spawner: runtime::Spawner::current(),
+        });
+        let liveness = Arc::new(Liveness::new());
+        let liveness_timeout = config.liveness_timeout;
+        let sender_write = write.clone();
+        let sender_liveness = liveness.clone();
+        let sender_diagnostics = diagnostics.clone();
+        let sender_internal_errors = internal_errors.clone();
+        let sender_breaker = breaker.clone();
+        let sender_spool = spool.clone();
+        let rx = tx.clone();
+        runtime::spawn(async move {
+            while let Some(frame) = rx.pop().await {
+                let len = frame.json.len();
+                // Hold the frame back, still counted as queued, until the bandwidth limit allows it.
+                let wait = throttle.reserve(len);
+                if !wait.is_zero() {
+                    runtime::sleep(wait).await;
+                }
+                sender_diagnostics.record_dequeued();
+                // The connection went away meanwhile.
+                if frame.spooled && rx.is_closed() {
+                    sender_diagnostics.record_lost();
+                    write_spool(&frame.json, &sender_spool, &sender_diagnostics);
+                    continue;
+                }
+                let Frame {
+                    json,
+                    is_event,
+                    event,
+                    spooled,
+                    ..
+                } = frame;
+                // Replayed messages carry their ID only in the JSON.
+                let ack_id = match &event {
+                    Some(event) => Some(event.id.clone()),
+                    None if is_event => event_id(&json),
+                    None => None,
+                };
+                // Kept for the spool until written.
+                let unsent = spooled.then(|| json.clone());
+                let send = async {
+                    sender_write
+                        .lock()
+                        .await
+                        .send(WsMessage::Text(json))
+                        .await
+                        .map_err(|e| e.to_string())
+                };
+                let sent = if liveness_timeout.is_zero() {
+                    send.await
+                } else {
+                    runtime::timeout(liveness_timeout, send)
+                        .await
+                        .unwrap_or_else(|| {
+                            Err(format!("write timed out after {:?}", liveness_timeout))
+                        })
+                };
+                if let Err(e) = sent {
+                    sender_breaker.record_failure();
+                    sender_diagnostics.record_lost();
+                    if let Some(json) = unsent {
+                        write_spool(&json, &sender_spool, &sender_diagnostics);
+                    }
+                    if is_event {
+                        sender_diagnostics.record_drop(DropReason::SendFailed);
+                    }
+                    if let Some(event) = &event {
+                        sender_diagnostics.audit(
+                            &event.id,
+                            Some(&event.fingerprint),
+                            Outcome::Dropped(DropReason::SendFailed),
+                        );
+                    }
+                    sender_diagnostics.record_error(format!("send failed: {}", e));
+                    // This connection is gone; report on the next one.
+                    if let Some(report) =
+                        sender_internal_errors.record(InternalErrorKind::SendFailed, e)
+                    {
+                        sender_internal_errors.defer(report);
+                    }
+                    // Otherwise the receiver keeps the connection open with no one sending.
+                    sender_liveness.declare_dead();
                     break;
                 }
+                sender_diagnostics.record_sent(len, is_event);
+                if let Some(event) = &event {
+                    sender_diagnostics.audit(&event.id, Some(&event.fingerprint), Outcome::Sent);
+                    sender_diagnostics.latency().written(event);
+                }
+                if let Some(id) = ack_id {
+                    sender_liveness.expect_ack(id);
+                }
+                if is_event {
+                    sender_breaker.record_success();
+                }
             }
         });
 
-        // Heartbeat with cancellation
-        let tx_heartbeat = tx.clone();
-        tokio::spawn(async move {
-            let mut interval = tokio::time::interval(Duration::from_secs(30));
-            loop {
-                interval.tick().await;
-                if heartbeat_cancel.load(Ordering::SeqCst) {
-                    break;
+        // Ping the backend, so an idle connection is checked too.
+        if !config.ping_interval.is_zero() {
+            let interval = config.ping_interval;
+            let liveness = liveness.clone();
+            let cancel = heartbeat_cancel.clone();
+            runtime::spawn(async move {
+                loop {
+                    runtime::sleep(interval).await;
+                    if cancel.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    if write
+                        .lock()
+                        .await
+                        .send(WsMessage::Ping(liveness.ping()))
+                        .await
+                        .is_err()
+                    {
+                        break;
+                    }
                 }
-                let heartbeat = OutgoingMessage {
-                    msg_type: "heartbeat".to_string(),
-                    payload: serde_json::json!({
-                        "timestamp": chrono::Utc::now().timestamp_millis()
-                    }),
-                    timestamp: chrono::Utc::now().timestamp_millis(),
-                };
-                if let Ok(json) = serde_json::to_string(&heartbeat) {
-                    if tx_heartbeat.send(json).is_err() {
+            });
+        }
+
+        // Tear the connection down once the backend stopped answering.
+        if !liveness_timeout.is_zero() {
+            let liveness = liveness.clone();
+            let cancel = heartbeat_cancel.clone();
+            let watchdog_diagnostics = diagnostics.clone();
+            runtime::spawn(async move {
+                loop {
+                    runtime::sleep(Duration::from_millis(500).min(liveness_timeout)).await;
+                    if cancel.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    if liveness.overdue(liveness_timeout) {
+                        watchdog_diagnostics.record_error(format!(
+                            "no reply from backend within {:?}",
+                            liveness_timeout
+                        ));
+                        liveness.declare_dead();
                         break;
                     }
                 }
-            }
-        });
+            });
+        }
+
+        // Heartbeat with cancellation
+        if let Some(interval) = config.heartbeat_interval {
+            let tx_heartbeat = tx.clone();
+            let heartbeat_diagnostics = diagnostics.clone();
+            runtime::spawn(async move {
+                let mut last_sent = heartbeat_diagnostics.snapshot().events_sent;
+                loop {
+                    if heartbeat_cancel.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let snapshot = heartbeat_diagnostics.snapshot();
+                    let heartbeat = OutgoingMessage {
+                        msg_type: "heartbeat".to_string(),
+                        payload: serde_json::json!({
+                            "timestamp": clock::now_millis(),
+                            "queue_depth": snapshot.queue_depth,
+                            "events_sent": snapshot.events_sent - last_sent,
+                            "memory_bytes": diagnostics::resident_memory_bytes(),
+                            "active_scopes": scope::active_count(),
+                            "latency": heartbeat_diagnostics.latency().take(),
+                        }),
+                        timestamp: clock::now_millis(),
+                    };
+                    last_sent = snapshot.events_sent;
+                    if let Ok(json) = serde_json::to_string(&heartbeat) {
+                        let frame = Frame {
+                            json,
+                            is_event: false,
+                            priority: Priority::Low,
+                            event: None,
+                            spooled: false,
+                        };
+                        if !push(&tx_heartbeat, frame, &heartbeat_diagnostics) {
+                            break;
+                        }
+                    }
+                    runtime::sleep(interval).await;
+                }
+            });
+        }
 
-        // Read messages
-        while let Some(msg) = read.next().await {
+        // Read messages until the connection closes or is declared dead
+        loop {
+            let msg = {
+                let dead = liveness.dead();
+                futures_util::pin_mut!(dead);
+                match future::select(read.next(), dead).await {
+                    Either::Left((Some(msg), _)) => msg,
+                    Either::Left((None, _)) => break,
+                    Either::Right(_) => {
+                        logger.debug(format_args!("Backend stopped answering, reconnecting"));
+                        break;
+                    }
+                }
+            };
             match msg {
                 Ok(WsMessage::Text(text)) => {
                     if let Ok(incoming) = serde_json::from_str::<IncomingMessage>(&text) {
-                        if debug {
-                            println!("[AIVory Monitor] Received: {}", incoming.msg_type);
-                        }
+                        logger.debug(format_args!("Received: {}", incoming.msg_type));
 
                         match incoming.msg_type.as_str() {
                             "registered" => {
-                                if debug {
-                                    println!("[AIVory Monitor] Agent registered");
+                                logger.debug(format_args!("Agent registered"));
+                                diagnostics.set_registered();
+
+                                for report in internal_errors.take_pending() {
+                                    if let Some(frame) = internal_error_frame(&report) {
+                                        push(&tx, frame, &diagnostics);
+                                    }
+                                }
+                                if let Some(spool) = spool.get() {
+                                    for json in spool.take() {
+                                        let frame = Frame {
+                                            json,
+                                            is_event: true,
+                                            priority: Priority::Fatal,
+                                            event: None,
+                                            spooled: true,
+                                        };
+                                        push(&tx, frame, &diagnostics);
+                                    }
+                                }
+
+                                let received_at = clock::local_millis();
+                                match server_time(&incoming.payload) {
+                                    Some(server_time) => {
+                                        let skew = clock::record_sync(
+                                            register_sent_at,
+                                            received_at,
+                                            server_time,
+                                        );
+                                        logger.debug(format_args!("Clock skew: {} ms", skew));
+                                    }
+                                    None => {
+                                        push(&tx, Self::time_sync_request(), &diagnostics);
+                                    }
+                                }
+                            }
+                            "ack" => {
+                                if let Some(id) =
+                                    incoming.payload.get("id").and_then(|v| v.as_str())
+                                {
+                                    diagnostics.audit(id, None, Outcome::Acked);
+                                    diagnostics.latency().acked(id);
+                                    liveness.acked(id);
+                                    acks.ack(id);
+                                }
+                            }
+                            "time_sync" => {
+                                let received_at = clock::local_millis();
+                                let client_time =
+                                    incoming.payload.get("client_time").and_then(|v| v.as_i64());
+                                if let (Some(sent_at), Some(server_time)) =
+                                    (client_time, server_time(&incoming.payload))
+                                {
+                                    let skew =
+                                        clock::record_sync(sent_at, received_at, server_time);
+                                    logger.debug(format_args!("Clock skew: {} ms", skew));
                                 }
                             }
                             "error" => {
-                                let code = incoming.payload.get("code")
+                                let code = incoming
+                                    .payload
+                                    .get("code")
                                     .and_then(|v| v.as_str())
                                     .unwrap_or("unknown");
-                                let message = incoming.payload.get("message")
+                                let message = incoming
+                                    .payload
+                                    .get("message")
                                     .and_then(|v| v.as_str())
                                     .unwrap_or("Unknown error");
-                                eprintln!("[AIVory Monitor] Backend error: {} - {}", code, message);
+                                logger.error(format_args!("Backend error: {} - {}", code, message));
+                                diagnostics
+                                    .record_error(format!("backend error: {} - {}", code, message));
+                                breaker.record_failure();
 
                                 if code == "auth_error" || code == "invalid_api_key" {
-                                    eprintln!("[AIVory Monitor] Authentication failed");
+                                    logger.error(format_args!("Authentication failed"));
                                     return Ok(ConnectResult::AuthError);
                                 }
                             }
@@ -238,11 +753,13 @@ impl Connection {
                         }
                     }
                 }
+                // tungstenite queues the pong itself and sends it on the next read.
+                Ok(WsMessage::Ping(_)) => logger.debug(format_args!("Received ping")),
+                Ok(WsMessage::Pong(payload)) => liveness.ponged(&payload),
                 Ok(WsMessage::Close(_)) => break,
                 Err(e) => {
-                    if debug {
-                        eprintln!("[AIVory Monitor] WebSocket error: {}", e);
-                    }
+                    diagnostics.record_error(format!("websocket error: {}", e));
+                    logger.debug(format_args!("WebSocket error: {}", e));
                     break;
                 }
                 _ => {}
@@ -252,57 +769,646 @@ impl Connection {
         Ok(ConnectResult::Disconnected)
     }
 
+    /// Builds a `time_sync` request. The backend echoes `client_time` back
+    /// together with its own `server_time`.
+    #[cfg(not(target_os = "wasi"))]
+    fn time_sync_request() -> Frame {
+        let msg = OutgoingMessage {
+            msg_type: "time_sync".to_string(),
+            payload: serde_json::json!({
+                "client_time": clock::local_millis(),
+            }),
+            timestamp: clock::local_millis(),
+        };
+        Frame {
+            json: serde_json::to_string(&msg).unwrap_or_default(),
+            is_event: false,
+            priority: Priority::Low,
+            event: None,
+            spooled: false,
+        }
+    }
+
     /// Disconnects from the backend.
     pub async fn disconnect(&self) {
-        *self.sender.write() = None;
-        *self.connected.write() = false;
+        self.close();
+    }
+
+    pub(crate) fn close(&self) {
+        if let Some(queue) = self.sender.write().take() {
+            queue.close();
+            // The runtime stops right after; spooled messages wait for the next process.
+            for frame in queue.take_spooled() {
+                respool(frame, &self.spool, &self.diagnostics);
+            }
+        }
+        if let Some(http) = self.http.get() {
+            for frame in http.take_spooled() {
+                respool(frame, &self.spool, &self.diagnostics);
+            }
+        }
+        #[cfg(not(target_os = "wasi"))]
+        self.direct.write().take();
+        self.diagnostics
+            .set_connection_state(ConnectionState::Disconnected);
     }
 
-    /// Sends an exception capture.
+    /// Sends an exception capture in an envelope with its attachments and
+    /// breadcrumbs.
     pub fn send_exception(&self, capture: ExceptionCapture) {
-        let sender = self.sender.read();
-        if let Some(tx) = sender.as_ref() {
-            let msg = OutgoingMessage {
-                msg_type: "exception".to_string(),
-                payload: capture,
-                timestamp: chrono::Utc::now().timestamp_millis(),
+        let priority = if capture.exception_type == "panic" {
+            Priority::Fatal
+        } else {
+            Priority::Error
+        };
+        let event = EventRef::new(&capture);
+        let msg = self.envelope_message(capture);
+        self.enqueue_event(&msg, priority, Some(event));
+    }
+
+    /// Wraps an exception capture and everything sent with it in an envelope.
+    fn envelope_message(&self, capture: ExceptionCapture) -> OutgoingMessage<Envelope> {
+        OutgoingMessage {
+            msg_type: "envelope".to_string(),
+            payload: Envelope::event(capture, self.diagnostics.take_unreported_drops()),
+            timestamp: clock::now_millis(),
+        }
+    }
+
+    /// Sends an exception capture from the calling thread, bypassing the
+    /// outbound queue, and waits up to `timeout` for it to be written; for the
+    /// last events of a process, which may end right after. An event that is
+    /// not sent in time is kept in the spool for the next process.
+    ///
+    /// Returns true if the event was sent.
+    pub(crate) fn send_exception_blocking(
+        &self,
+        capture: ExceptionCapture,
+        timeout: Duration,
+    ) -> bool {
+        if self.is_dry_run() {
+            self.send_exception(capture);
+            return true;
+        }
+
+        let event = EventRef::new(&capture);
+        let msg = self.envelope_message(capture);
+        let json = match serde_json::to_string(&msg) {
+            Ok(json) => json,
+            Err(e) => {
+                self.diagnostics
+                    .record_drop(DropReason::SerializationFailed);
+                self.diagnostics.audit(
+                    &event.id,
+                    Some(&event.fingerprint),
+                    Outcome::Dropped(DropReason::SerializationFailed),
+                );
+                self.diagnostics
+                    .record_error(format!("serialization failed: {}", e));
+                return false;
+            }
+        };
+
+        let unsent = match self.http.get() {
+            // The HTTP transport already sends from the calling thread.
+            Some(http) if self.diagnostics.connection_state() == ConnectionState::Connected => {
+                http.send(Frame {
+                    json,
+                    is_event: true,
+                    priority: Priority::Fatal,
+                    event: Some(event.clone()),
+                    spooled: false,
+                });
+                if http.flush(timeout) {
+                    return true;
+                }
+                http.take_pending()
+            }
+            Some(_) => vec![json],
+            None => {
+                let len = json.len();
+                if self.write_direct(json.clone(), timeout) {
+                    self.diagnostics.record_sent(len, true);
+                    self.diagnostics
+                        .audit(&event.id, Some(&event.fingerprint), Outcome::Sent);
+                    self.diagnostics.latency().written(&event);
+                    return true;
+                }
+                vec![json]
+            }
+        };
+
+        for json in unsent {
+            let stored = match self.spool.get() {
+                Some(spool) => spool
+                    .write(&json)
+                    .map_err(|e| format!("{}: {}", spool.path().display(), e)),
+                None => Err("no spool".to_string()),
             };
+            if let Err(e) = stored {
+                self.diagnostics.record_drop(DropReason::NotConnected);
+                self.diagnostics
+                    .record_error(format!("failed to store unsent event: {}", e));
+            }
+        }
+        false
+    }
 
-            if let Ok(json) = serde_json::to_string(&msg) {
-                let _ = tx.send(json);
+    /// Writes `json` to the registered WebSocket connection, if any, waiting
+    /// up to `timeout`. The write runs on the agent's runtime, so this works
+    /// from any thread.
+    #[cfg(not(target_os = "wasi"))]
+    fn write_direct(&self, json: String, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        // Events sent before registration are not processed.
+        while !self.diagnostics.snapshot().registered {
+            if Instant::now() >= deadline
+                || self.diagnostics.connection_state() == ConnectionState::AuthFailed
+            {
+                return false;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let Some(direct) = self.direct.read().clone() else {
+            return false;
+        };
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        let (done, written) = std::sync::mpsc::channel();
+        direct.spawner.clone().spawn(async move {
+            let send = async {
+                direct
+                    .sink
+                    .lock()
+                    .await
+                    .send(WsMessage::Text(json))
+                    .await
+                    .is_ok()
+            };
+            let _ = done.send(runtime::timeout(timeout, send).await.unwrap_or(false));
+        });
+        written.recv_timeout(timeout).unwrap_or(false)
+    }
+
+    #[cfg(target_os = "wasi")]
+    fn write_direct(&self, _json: String, _timeout: Duration) -> bool {
+        false
+    }
+
+    /// Sends an exception capture and blocks until the backend acknowledges
+    /// it or `timeout` elapses, first waiting for the connection if it is
+    /// still being established.
+    pub(crate) fn send_acknowledged(
+        &self,
+        capture: ExceptionCapture,
+        timeout: Duration,
+    ) -> Result<EventId, SendError> {
+        let deadline = Instant::now() + timeout;
+        let id = capture.id.clone();
+
+        if self.is_dry_run() {
+            self.send_exception(capture);
+            return Ok(EventId::new(id));
+        }
+
+        // Over HTTP, a successful response is the acknowledgement.
+        if let Some(http) = self.http.get() {
+            match self.diagnostics.connection_state() {
+                ConnectionState::Connected => {}
+                ConnectionState::AuthFailed => return Err(SendError::AuthFailed),
+                _ => return Err(SendError::NotConnected),
+            }
+            self.send_exception(capture);
+            let sent = http.flush(timeout);
+            return match self.diagnostics.connection_state() {
+                ConnectionState::AuthFailed => Err(SendError::AuthFailed),
+                _ if sent => Ok(EventId::new(id)),
+                _ => Err(SendError::Timeout(self.diagnostics.snapshot().last_error)),
+            };
+        }
+
+        // Events sent before registration are not processed.
+        while !self.diagnostics.snapshot().registered {
+            if self.diagnostics.connection_state() == ConnectionState::AuthFailed {
+                return Err(SendError::AuthFailed);
             }
+            if Instant::now() >= deadline {
+                return Err(SendError::NotConnected);
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        self.acks.expect(&id);
+        self.send_exception(capture);
+        if self
+            .acks
+            .wait(&id, deadline.saturating_duration_since(Instant::now()))
+        {
+            Ok(EventId::new(id))
+        } else if self.diagnostics.connection_state() == ConnectionState::AuthFailed {
+            Err(SendError::AuthFailed)
+        } else {
+            Err(SendError::Timeout(self.diagnostics.snapshot().last_error))
+        }
+    }
+
+    /// Blocks until the WebSocket connection is registered, or `timeout`
+    /// elapses. The HTTP transport sends registration with every request.
+    pub(crate) fn wait_registered(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        while !self.is_http() && !self.diagnostics.snapshot().registered {
+            if Instant::now() >= deadline
+                || self.diagnostics.connection_state() == ConnectionState::AuthFailed
+            {
+                return false;
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        true
+    }
+
+    /// Sends messages another process left in its spool, and blocks until
+    /// they are delivered or `timeout` elapses: acknowledged if they are
+    /// events, or written over a connection that stayed registered meanwhile.
+    pub(crate) fn resend(&self, messages: &[String], timeout: Duration) -> Result<(), SendError> {
+        let deadline = Instant::now() + timeout;
+        let before = self.diagnostics.snapshot();
+        // Events are acknowledged; other messages only count as delivered if
+        // the connection they were written to stayed up.
+        let ids: Vec<String> = messages.iter().filter_map(|json| event_id(json)).collect();
+        for id in &ids {
+            self.acks.expect(id);
+        }
+
+        let mut queued = 0;
+        for json in messages {
+            let frame = Frame {
+                json: json.clone(),
+                is_event: true,
+                priority: Priority::Fatal,
+                event: None,
+                spooled: false,
+            };
+            if let Some(http) = self.http.get() {
+                http.send(frame);
+                queued += 1;
+            } else if let Some(tx) = self.sender.read().as_ref() {
+                queued += usize::from(push(tx, frame, &self.diagnostics));
+            }
+        }
+        let mut delivered = queued == messages.len() && self.flush(timeout);
+        for id in &ids {
+            delivered &= self.is_http()
+                || self
+                    .acks
+                    .wait(id, deadline.saturating_duration_since(Instant::now()));
+        }
+
+        let after = self.diagnostics.snapshot();
+        let full = |snapshot: &AgentDiagnostics| {
+            snapshot.events_dropped.get(&DropReason::QueueFull).copied()
+        };
+        if after.connection_state == ConnectionState::AuthFailed {
+            Err(SendError::AuthFailed)
+        } else if !delivered
+            || !after.registered
+            || after.reconnect_count != before.reconnect_count
+            || full(&after) != full(&before)
+        {
+            Err(SendError::Timeout(after.last_error))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Sends a bundle of the application's source files.
+    #[cfg(feature = "source-upload")]
+    pub(crate) fn send_source_bundle(&self, payload: serde_json::Value) {
+        let msg = OutgoingMessage {
+            msg_type: "source_bundle".to_string(),
+            payload,
+            timestamp: clock::now_millis(),
+        };
+        let Ok(json) = serde_json::to_string(&msg) else {
+            return;
+        };
+        let frame = Frame {
+            json,
+            is_event: false,
+            priority: Priority::Low,
+            event: None,
+            spooled: false,
+        };
+        if let Some(http) = self.http.get() {
+            http.send(frame);
+        } else if let Some(tx) = self.sender.read().as_ref() {
+            push(tx, frame, &self.diagnostics);
+        }
+    }
+
+    /// Sends a cron check-in.
+    pub fn send_check_in(&self, check_in: &crate::cron::CheckIn) {
+        let msg = OutgoingMessage {
+            msg_type: "check_in".to_string(),
+            payload: check_in,
+            timestamp: clock::now_millis(),
+        };
+        self.enqueue_event(&msg, Priority::Low, None);
+    }
+
+    /// Sends the spans of a finished request.
+    pub(crate) fn send_transaction(&self, transaction: &crate::performance::Transaction) {
+        let msg = OutgoingMessage {
+            msg_type: "transaction".to_string(),
+            payload: transaction,
+            timestamp: clock::now_millis(),
+        };
+        self.enqueue_event(&msg, Priority::Low, None);
+    }
+
+    /// Tells the backend that this agent's process is exiting, e.g. because
+    /// it received a termination signal.
+    #[cfg(all(feature = "shutdown-hooks", unix))]
+    pub(crate) fn send_session_exited(&self, config: &Config, reason: &str) {
+        let msg = OutgoingMessage {
+            msg_type: "session".to_string(),
+            payload: serde_json::json!({
+                "agent_id": config.agent_id,
+                "incarnation": config.incarnation,
+                "status": "exited",
+                "reason": reason,
+            }),
+            timestamp: clock::now_millis(),
+        };
+        let Ok(json) = serde_json::to_string(&msg) else {
+            return;
+        };
+        let frame = Frame {
+            json,
+            is_event: false,
+            priority: Priority::Fatal,
+            event: None,
+            spooled: false,
+        };
+        if let Some(http) = self.http.get() {
+            http.send(frame);
+        } else if let Some(tx) = self.sender.read().as_ref() {
+            push(tx, frame, &self.diagnostics);
+        }
+    }
+
+    /// Keeps a `session` message about an earlier process in the spool, which
+    /// is sent once connected; see [`session`](crate::session).
+    pub(crate) fn spool_session(&self, session: serde_json::Value) {
+        let msg = OutgoingMessage {
+            msg_type: "session".to_string(),
+            payload: session,
+            timestamp: clock::now_millis(),
+        };
+        let (Some(spool), Ok(json)) = (self.spool.get(), serde_json::to_string(&msg)) else {
+            return;
+        };
+        if let Err(e) = spool.write(&json) {
+            self.diagnostics
+                .record_error(format!("failed to store session: {}", e));
         }
     }
 
     /// Sends a breakpoint hit event.
-    pub fn send_breakpoint_hit(&self, breakpoint_id: &str, agent_id: &str, data: serde_json::Value) {
+    pub fn send_breakpoint_hit(
+        &self,
+        breakpoint_id: &str,
+        agent_id: &str,
+        data: serde_json::Value,
+    ) {
         let sender = self.sender.read();
-        if let Some(tx) = sender.as_ref() {
+        let http = self.http.get();
+        if sender.is_some() || http.is_some() {
             let mut payload = match data {
                 serde_json::Value::Object(map) => map,
                 _ => serde_json::Map::new(),
             };
-            payload.insert("breakpoint_id".to_string(), serde_json::Value::String(breakpoint_id.to_string()));
-            payload.insert("agent_id".to_string(), serde_json::Value::String(agent_id.to_string()));
+            payload.insert(
+                "breakpoint_id".to_string(),
+                serde_json::Value::String(breakpoint_id.to_string()),
+            );
+            payload.insert(
+                "agent_id".to_string(),
+                serde_json::Value::String(agent_id.to_string()),
+            );
 
             let msg = OutgoingMessage {
                 msg_type: "breakpoint_hit".to_string(),
                 payload: serde_json::Value::Object(payload),
-                timestamp: chrono::Utc::now().timestamp_millis(),
+                timestamp: clock::now_millis(),
             };
 
             if let Ok(json) = serde_json::to_string(&msg) {
-                let _ = tx.send(json);
+                let frame = Frame {
+                    json,
+                    is_event: true,
+                    priority: Priority::Error,
+                    event: None,
+                    spooled: false,
+                };
+                match (http, sender.as_ref()) {
+                    (Some(http), _) => http.send(frame),
+                    (None, Some(tx)) => {
+                        push(tx, frame, &self.diagnostics);
+                    }
+                    (None, None) => {}
+                }
+            }
+        }
+    }
+
+    /// Serializes an event and queues it for sending, recording why if it can't be.
+    fn enqueue_event<T: Serialize>(
+        &self,
+        msg: &OutgoingMessage<T>,
+        priority: Priority,
+        event: Option<EventRef>,
+    ) {
+        let sender = self.sender.read();
+        let http = self.http.get();
+        let dry_run = self.is_dry_run();
+        let dropped = |reason| {
+            self.diagnostics.record_drop(reason);
+            if let Some(event) = &event {
+                self.diagnostics.audit(
+                    &event.id,
+                    Some(&event.fingerprint),
+                    Outcome::Dropped(reason),
+                );
+            }
+        };
+        if sender.is_none() && http.is_none() && !dry_run {
+            dropped(DropReason::NotConnected);
+            return;
+        }
+
+        let json = if dry_run {
+            serde_json::to_string_pretty(msg)
+        } else {
+            serde_json::to_string(msg)
+        };
+        let json = match json {
+            Ok(json) => json,
+            Err(e) => {
+                dropped(DropReason::SerializationFailed);
+                self.diagnostics
+                    .record_error(format!("serialization failed: {}", e));
+                drop(sender);
+                self.report_internal_error(InternalErrorKind::Serialization, e.to_string());
+                return;
+            }
+        };
+
+        if dry_run {
+            eprintln!("{}", json);
+            self.diagnostics.record_sent(json.len(), true);
+            return;
+        }
+
+        let frame = Frame {
+            json,
+            is_event: true,
+            priority,
+            event,
+            spooled: false,
+        };
+        match (http, sender.as_ref()) {
+            (Some(http), _) => http.send(frame),
+            (None, Some(tx)) => {
+                if !push(tx, frame, &self.diagnostics) {
+                    self.diagnostics.record_drop(DropReason::NotConnected);
+                }
             }
+            (None, None) => {}
+        }
+    }
+
+    /// Reports a failure inside the agent to the backend as an `internal_error`
+    /// message, subject to rate limiting. Buffered until the next connection if
+    /// currently disconnected.
+    pub(crate) fn report_internal_error(&self, kind: InternalErrorKind, message: String) {
+        let Some(report) = self.internal_errors.record(kind, message) else {
+            return;
+        };
+
+        if let Some(http) = self.http.get() {
+            if let Some(frame) = internal_error_frame(&report) {
+                http.send(frame);
+            }
+            return;
+        }
+
+        let sender = self.sender.read();
+        let frame = match sender.as_ref() {
+            Some(tx) => internal_error_frame(&report).map(|frame| (tx, frame)),
+            None => None,
+        };
+        match frame {
+            Some((tx, frame)) => {
+                if !push(tx, frame, &self.diagnostics) {
+                    self.internal_errors.defer(report);
+                }
+            }
+            None => self.internal_errors.defer(report),
         }
     }
 
     /// Returns true if connected.
     pub fn is_connected(&self) -> bool {
-        *self.connected.read()
+        self.diagnostics.connection_state() == ConnectionState::Connected
+    }
+}
+
+/// Queues a frame, keeping the queue depth counter in step. Returns false if
+/// the connection has gone away.
+fn push(queue: &OutboundQueue, frame: Frame, diagnostics: &Diagnostics) -> bool {
+    if queue.is_closed() {
+        frame.audit(diagnostics, Outcome::Dropped(DropReason::NotConnected));
+        return false;
+    }
+    diagnostics.record_enqueued();
+    if let Some(dropped) = queue.push(frame) {
+        diagnostics.record_dequeued();
+        diagnostics.record_lost();
+        if dropped.is_event {
+            diagnostics.record_drop(DropReason::QueueFull);
+        }
+        dropped.audit(diagnostics, Outcome::Dropped(DropReason::QueueFull));
+    }
+    true
+}
+
+/// Puts a frame that was queued but won't be written back in the spool, if
+/// it was taken from there.
+fn respool(frame: Frame, spool: &OnceCell<Spool>, diagnostics: &Diagnostics) {
+    diagnostics.record_dequeued();
+    diagnostics.record_lost();
+    if frame.spooled {
+        write_spool(&frame.json, spool, diagnostics);
+    }
+}
+
+fn write_spool(json: &str, spool: &OnceCell<Spool>, diagnostics: &Diagnostics) {
+    if let Some(spool) = spool.get() {
+        if let Err(e) = spool.write(json) {
+            diagnostics.record_error(format!("failed to store unsent event: {}", e));
+        }
     }
 }
 
+/// Serializes an internal error report as an `internal_error` message.
+fn internal_error_frame(report: &InternalErrorReport) -> Option<Frame> {
+    let msg = OutgoingMessage {
+        msg_type: "internal_error".to_string(),
+        payload: report,
+        timestamp: clock::now_millis(),
+    };
+    serde_json::to_string(&msg).ok().map(|json| Frame {
+        json,
+        is_event: false,
+        priority: Priority::Low,
+        event: None,
+        spooled: false,
+    })
+}
+
+/// Agent identity sent on registration, and with every HTTP request.
+fn register_payload(config: &Config) -> serde_json::Value {
+    serde_json::json!({
+        "agent_id": config.agent_id,
+        "incarnation": config.incarnation,
+        "hostname": config.hostname,
+        "service_name": config.service_name,
+        "server_name": config.server_name,
+        "environment": config.environment,
+        "environment_source": config.environment_source,
+        "agent_version": env!("CARGO_PKG_VERSION"),
+        "runtime": "rust",
+        "runtime_version": env!("CARGO_PKG_VERSION"),
+        "platform": std::env::consts::OS,
+        "arch": std::env::consts::ARCH,
+        "build_id": config.build_id,
+        // Lets the backend tell a missed heartbeat from a disabled one.
+        "heartbeat_interval_ms": config.heartbeat_interval.map(|interval| interval.as_millis() as u64),
+    })
+}
+
+/// ID of the event in a serialized message, which the backend acknowledges.
+fn event_id(json: &str) -> Option<String> {
+    let msg: serde_json::Value = serde_json::from_str(json).ok()?;
+    Some(msg.get("payload")?.get("event_id")?.as_str()?.to_string())
+}
+
+/// Extracts the backend timestamp (milliseconds) from a message payload.
+#[cfg(not(target_os = "wasi"))]
+fn server_time(payload: &serde_json::Value) -> Option<i64> {
+    payload.get("server_time").and_then(|v| v.as_i64())
+}
+
 impl Default for Connection {
     fn default() -> Self {
         Self::new()
@@ -0,0 +1,144 @@
+//! Acknowledgements of sent events.
+//!
+//! The backend answers each `envelope` message with an `ack` carrying the
+//! event's ID. Acks are only tracked for IDs a caller is waiting for, such as
+//! the marker event of [`send_test_event`](crate::send_test_event) or an
+//! event of [`capture_error_and_wait`](crate::capture_error_and_wait); the
+//! HTTP transport's response status serves as the ack there.
+
+use parking_lot::{Condvar, Mutex};
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// ID of an event the backend acknowledged.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct EventId(String);
+
+impl EventId {
+    pub(crate) fn new(id: String) -> Self {
+        EventId(id)
+    }
+
+    /// The ID as sent in the event's `id` field.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for EventId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Why an event could not be confirmed as received.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SendError {
+    /// [`init`](crate::init) has not been called.
+    NotInitialized,
+    /// No connection to the backend could be established in time.
+    NotConnected,
+    /// The backend rejected the API key.
+    AuthFailed,
+    /// The event was sent but not acknowledged in time, with the most recent
+    /// connection or backend error, if any.
+    Timeout(Option<String>),
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendError::NotInitialized => write!(f, "agent not initialized"),
+            SendError::NotConnected => write!(f, "not connected to the backend"),
+            SendError::AuthFailed => write!(f, "backend rejected the API key"),
+            SendError::Timeout(None) => write!(f, "backend did not acknowledge the event"),
+            SendError::Timeout(Some(error)) => {
+                write!(f, "backend did not acknowledge the event: {}", error)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SendError {}
+
+/// Why [`capture_error_and_wait`](crate::capture_error_and_wait) could not
+/// confirm that an event reached the backend.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DeliveryError {
+    /// The agent is not initialized, has no API key or was shut down.
+    Disabled,
+    /// Capturing the event panicked; the panic is reported as an internal
+    /// error of the agent.
+    CaptureFailed,
+    /// The event was captured, but not confirmed as received.
+    Send(SendError),
+}
+
+impl fmt::Display for DeliveryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeliveryError::Disabled => write!(f, "agent not enabled"),
+            DeliveryError::CaptureFailed => write!(f, "capturing the event failed"),
+            DeliveryError::Send(error) => error.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for DeliveryError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DeliveryError::Send(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<SendError> for DeliveryError {
+    fn from(error: SendError) -> Self {
+        DeliveryError::Send(error)
+    }
+}
+
+/// Event IDs being waited for, and whether each was acknowledged.
+pub(crate) struct Acks {
+    pending: Mutex<HashMap<String, bool>>,
+    acked: Condvar,
+}
+
+impl Acks {
+    pub(crate) fn new() -> Self {
+        Acks {
+            pending: Mutex::new(HashMap::new()),
+            acked: Condvar::new(),
+        }
+    }
+
+    /// Starts tracking `id`; call before sending the event so a quick ack
+    /// is not missed.
+    pub(crate) fn expect(&self, id: &str) {
+        self.pending.lock().insert(id.to_string(), false);
+    }
+
+    /// Records an ack from the backend. Acks nobody waits for are ignored.
+    #[cfg_attr(target_os = "wasi", allow(dead_code))]
+    pub(crate) fn ack(&self, id: &str) {
+        if let Some(acked) = self.pending.lock().get_mut(id) {
+            *acked = true;
+            self.acked.notify_all();
+        }
+    }
+
+    /// Waits until `id` is acknowledged or `timeout` elapses, and stops
+    /// tracking it. Returns true if it was acknowledged.
+    pub(crate) fn wait(&self, id: &str, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut pending = self.pending.lock();
+        while pending.get(id) == Some(&false) {
+            if self.acked.wait_until(&mut pending, deadline).timed_out() {
+                break;
+            }
+        }
+        pending.remove(id).unwrap_or(false)
+    }
+}
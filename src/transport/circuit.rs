@@ -0,0 +1,118 @@
+//! Circuit breaker for a failing backend.
+//!
+//! After `circuit_breaker_threshold` consecutive failures (rejected or failed
+//! sends, failed connection attempts) the circuit opens: events are not
+//! captured or serialized at all, only counted as dropped. Once the cool-down
+//! has passed a single event is let through as a probe. If it is delivered
+//! the circuit closes, otherwise it opens for another cool-down.
+
+use crate::diagnostics::Diagnostics;
+use crate::logger::Logger;
+use parking_lot::Mutex;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub(crate) struct CircuitBreaker {
+    state: Mutex<State>,
+    diagnostics: Arc<Diagnostics>,
+}
+
+struct State {
+    circuit: Circuit,
+    /// Consecutive failures that open the circuit; zero disables it.
+    threshold: u32,
+    cooldown: Duration,
+    logger: Logger,
+}
+
+enum Circuit {
+    Closed {
+        failures: u32,
+    },
+    Open {
+        until: Instant,
+    },
+    /// A probe was let through at `since`; another is allowed if it is
+    /// neither delivered nor failed within a cool-down.
+    HalfOpen {
+        since: Instant,
+    },
+}
+
+impl CircuitBreaker {
+    pub(crate) fn new(diagnostics: Arc<Diagnostics>) -> Self {
+        CircuitBreaker {
+            state: Mutex::new(State {
+                circuit: Circuit::Closed { failures: 0 },
+                threshold: 0,
+                cooldown: Duration::ZERO,
+                logger: Logger::default(),
+            }),
+            diagnostics,
+        }
+    }
+
+    /// Applies the settings of the connection being opened.
+    pub(crate) fn configure(&self, threshold: u32, cooldown: Duration, logger: Logger) {
+        let mut state = self.state.lock();
+        state.threshold = threshold;
+        state.cooldown = cooldown;
+        state.logger = logger;
+    }
+
+    /// Whether an event may be captured now. In the half-open state this
+    /// lets through one probe.
+    pub(crate) fn allow(&self) -> bool {
+        let mut state = self.state.lock();
+        let now = Instant::now();
+        match state.circuit {
+            Circuit::Closed { .. } => true,
+            Circuit::Open { until } if now >= until => {
+                state.circuit = Circuit::HalfOpen { since: now };
+                true
+            }
+            Circuit::HalfOpen { since } if now >= since + state.cooldown => {
+                state.circuit = Circuit::HalfOpen { since: now };
+                true
+            }
+            Circuit::Open { .. } | Circuit::HalfOpen { .. } => false,
+        }
+    }
+
+    /// Records a message the backend accepted.
+    pub(crate) fn record_success(&self) {
+        let mut state = self.state.lock();
+        if !matches!(state.circuit, Circuit::Closed { .. }) {
+            state
+                .logger
+                .info(format_args!("Backend recovered, resuming capture"));
+            self.diagnostics.set_circuit_open(false);
+        }
+        state.circuit = Circuit::Closed { failures: 0 };
+    }
+
+    /// Records a rejected or failed send, or a failed connection attempt.
+    pub(crate) fn record_failure(&self) {
+        let mut state = self.state.lock();
+        if state.threshold == 0 {
+            return;
+        }
+        let failures = match state.circuit {
+            Circuit::Closed { failures } => failures + 1,
+            Circuit::HalfOpen { .. } => state.threshold,
+            Circuit::Open { .. } => return,
+        };
+        if failures < state.threshold {
+            state.circuit = Circuit::Closed { failures };
+            return;
+        }
+        state.circuit = Circuit::Open {
+            until: Instant::now() + state.cooldown,
+        };
+        self.diagnostics.set_circuit_open(true);
+        state.logger.warn(format_args!(
+            "Backend failing, pausing capture for {:?}",
+            state.cooldown
+        ));
+    }
+}
@@ -0,0 +1,91 @@
+//! Envelopes: several items sent to the backend as one message.
+//!
+//! An event goes out together with its attachments, the breadcrumbs leading
+//! up to it and a client report of events dropped since the last envelope.
+//! All of them are written as a single `envelope` message, so the backend
+//! receives them together or not at all, and an attachment can never arrive
+//! without its event:
+//!
+//! ```json
+//! {
+//!   "type": "envelope",
+//!   "payload": {
+//!     "event_id": "…",
+//!     "project_key": "…",
+//!     "items": [
+//!       { "type": "exception", "payload": { … } },
+//!       { "type": "attachment", "payload": { "filename": "…", "content_type": "…", "data": "<base64>" } },
+//!       { "type": "breadcrumbs", "payload": [ … ] },
+//!       { "type": "client_report", "payload": { "discarded": { "queue_full": 2 } } }
+//!     ]
+//!   },
+//!   "timestamp": 1700000000000
+//! }
+//! ```
+//!
+//! `project_key` is present only for events a project route sends to
+//! another project than the agent's own.
+
+use crate::breadcrumbs::Breadcrumb;
+use crate::capture::{Attachment, ExceptionCapture};
+use crate::diagnostics::DropReason;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Items delivered to the backend together.
+#[derive(Serialize)]
+pub(crate) struct Envelope {
+    /// ID of the event the items belong to, which the backend acknowledges.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    event_id: Option<String>,
+    /// API key of the project the event is routed to, if not the agent's.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    project_key: Option<String>,
+    items: Vec<Item>,
+}
+
+/// One part of an [`Envelope`].
+#[derive(Serialize)]
+#[serde(tag = "type", content = "payload", rename_all = "snake_case")]
+enum Item {
+    Exception(Box<ExceptionCapture>),
+    Attachment(Attachment),
+    Breadcrumbs(Vec<Breadcrumb>),
+    ClientReport(ClientReport),
+}
+
+/// Events the agent dropped since the previous client report, so the
+/// backend can account for what it never received.
+#[derive(Serialize)]
+struct ClientReport {
+    discarded: BTreeMap<DropReason, u64>,
+}
+
+impl Envelope {
+    /// Packs an event with its attachments and breadcrumbs, and the drops
+    /// not reported yet.
+    pub(crate) fn event(
+        mut capture: ExceptionCapture,
+        discarded: BTreeMap<DropReason, u64>,
+    ) -> Self {
+        let attachments = std::mem::take(&mut capture.attachments);
+        let breadcrumbs = std::mem::take(&mut capture.breadcrumbs);
+        let mut envelope = Envelope {
+            event_id: Some(capture.id.clone()),
+            project_key: capture.project_key.take(),
+            items: vec![Item::Exception(Box::new(capture))],
+        };
+        envelope
+            .items
+            .extend(attachments.into_iter().map(Item::Attachment));
+        if !breadcrumbs.is_empty() {
+            envelope.items.push(Item::Breadcrumbs(breadcrumbs));
+        }
+        if !discarded.is_empty() {
+            envelope
+                .items
+                .push(Item::ClientReport(ClientReport { discarded }));
+        }
+        envelope
+    }
+}
@@ -0,0 +1,280 @@
+//! HTTP transport for serverless functions and WASI.
+//!
+//! Events are POSTed to the backend from the capturing thread as soon as
+//! they are captured, without a background runtime, so nothing is lost when
+//! the host freezes or tears down the process right after a function
+//! returns. Messages that fail to send are kept and retried on the next
+//! capture or [`flush_blocking`](crate::flush_blocking).
+//!
+//! When a request fails without a response or with a server error, the same
+//! flush retries on the next of the `fallback_urls`, and later flushes stay
+//! on the endpoint that worked.
+
+use super::circuit::CircuitBreaker;
+use super::queue::Lanes;
+use super::throttle::Throttle;
+use super::Frame;
+use crate::audit::Outcome;
+use crate::config::Config;
+use crate::diagnostics::{ConnectionState, Diagnostics, DropReason};
+use crate::logger::Logger;
+use parking_lot::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Unsent messages kept for retry; see [`Lanes::push`] for which are discarded.
+const MAX_PENDING: usize = 100;
+
+/// A request the HTTP transport asks an [`HttpSender`] to perform.
+#[derive(Clone, Debug)]
+pub struct HttpRequest {
+    /// Absolute `http` or `https` URL to POST to.
+    pub url: String,
+    /// Request headers, including `Content-Type` and `Authorization`.
+    pub headers: Vec<(String, String)>,
+    /// JSON request body.
+    pub body: String,
+    /// Give up on the request after this long.
+    pub timeout: Duration,
+}
+
+/// Performs a blocking HTTP POST and returns the response status code.
+///
+/// The default uses a bundled HTTP client. WASI hosts without sockets must
+/// supply one built on the host's HTTP API, e.g. `wasi:http`.
+pub type HttpSender = Arc<dyn Fn(&HttpRequest) -> Result<u16, String> + Send + Sync>;
+
+/// Sends messages with one blocking POST per flush.
+pub(crate) struct HttpTransport {
+    /// The primary backend URL, then the fallbacks.
+    urls: Vec<String>,
+    /// Index into `urls` of the endpoint requests go to first.
+    endpoint: AtomicUsize,
+    api_key: String,
+    agent: serde_json::Value,
+    timeout: Duration,
+    sender: Option<HttpSender>,
+    logger: Logger,
+    pending: Mutex<Lanes>,
+    diagnostics: Arc<Diagnostics>,
+    breaker: Arc<CircuitBreaker>,
+    throttle: Arc<Throttle>,
+}
+
+impl HttpTransport {
+    pub(crate) fn new(
+        config: &Config,
+        diagnostics: Arc<Diagnostics>,
+        breaker: Arc<CircuitBreaker>,
+        throttle: Arc<Throttle>,
+    ) -> Self {
+        HttpTransport {
+            urls: config.http_urls(),
+            endpoint: AtomicUsize::new(0),
+            api_key: config.api_key.clone(),
+            agent: super::register_payload(config),
+            timeout: config.http_timeout,
+            sender: config.http_sender.clone().or_else(default_sender),
+            logger: config.logger.clone(),
+            pending: Mutex::new(Lanes::new(MAX_PENDING)),
+            diagnostics,
+            breaker,
+            throttle,
+        }
+    }
+
+    /// Queues a message and sends everything queued.
+    pub(crate) fn send(&self, frame: Frame) {
+        // Shut down, or the API key was rejected.
+        if self.diagnostics.connection_state() != ConnectionState::Connected {
+            if frame.is_event {
+                self.diagnostics.record_drop(DropReason::NotConnected);
+            }
+            frame.audit(
+                &self.diagnostics,
+                Outcome::Dropped(DropReason::NotConnected),
+            );
+            return;
+        }
+
+        self.diagnostics.record_enqueued();
+        if let Some(dropped) = self.pending.lock().push(frame) {
+            self.diagnostics.record_dequeued();
+            self.diagnostics.record_lost();
+            if dropped.is_event {
+                self.diagnostics.record_drop(DropReason::QueueFull);
+            }
+            dropped.audit(&self.diagnostics, Outcome::Dropped(DropReason::QueueFull));
+        }
+        // Over the bandwidth limit, a burst waits for a later capture or flush.
+        if self.throttle_wait(&self.pending.lock()).is_zero() {
+            self.flush(self.timeout);
+        }
+    }
+
+    /// Removes the messages not sent yet and returns them serialized.
+    pub(crate) fn take_pending(&self) -> Vec<String> {
+        let mut pending = self.pending.lock();
+        pending
+            .drain()
+            .map(|frame| {
+                self.diagnostics.record_dequeued();
+                self.diagnostics.record_lost();
+                frame.json
+            })
+            .collect()
+    }
+
+    /// Removes the messages taken from the spool that were not sent yet.
+    pub(crate) fn take_spooled(&self) -> Vec<Frame> {
+        self.pending.lock().take_spooled()
+    }
+
+    /// How long the bandwidth limit delays sending everything `pending`.
+    fn throttle_wait(&self, pending: &Lanes) -> Duration {
+        self.throttle
+            .wait(pending.iter().map(|frame| frame.json.len()).sum())
+    }
+
+    /// Sends every queued message in one request. Returns true if they were
+    /// all delivered.
+    pub(crate) fn flush(&self, timeout: Duration) -> bool {
+        // Holding the lock for the request keeps concurrent flushes from
+        // sending the same messages twice.
+        let mut pending = self.pending.lock();
+        if pending.is_empty() {
+            return true;
+        }
+        let Some(sender) = &self.sender else {
+            self.diagnostics.record_error("no HTTP sender configured");
+            return false;
+        };
+        let wait = self.throttle_wait(&pending);
+        if wait > timeout {
+            return false;
+        }
+        std::thread::sleep(wait);
+
+        let messages: Vec<&str> = pending.iter().map(|frame| frame.json.as_str()).collect();
+        let body = format!(
+            r#"{{"agent":{},"messages":[{}]}}"#,
+            self.agent,
+            messages.join(",")
+        );
+        let deadline = Instant::now() + timeout;
+        let mut request = HttpRequest {
+            url: String::new(),
+            headers: vec![
+                ("Content-Type".to_string(), "application/json".to_string()),
+                (
+                    "Authorization".to_string(),
+                    format!("Bearer {}", self.api_key),
+                ),
+            ],
+            body,
+            timeout: timeout.min(self.timeout),
+        };
+        self.throttle.reserve(request.body.len());
+
+        let started = Instant::now();
+        let first = self.endpoint.load(Ordering::Relaxed);
+        let mut result = Err("no backend URL".to_string());
+        for attempt in 0..self.urls.len() {
+            let endpoint = (first + attempt) % self.urls.len();
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if attempt > 0 {
+                if remaining.is_zero() {
+                    break;
+                }
+                self.logger
+                    .debug(format_args!("Failing over to {}", self.urls[endpoint]));
+            }
+            request.url = self.urls[endpoint].clone();
+            request.timeout = remaining.min(self.timeout);
+            result = sender(&request);
+            if matches!(result, Ok(status) if status < 500) {
+                self.endpoint.store(endpoint, Ordering::Relaxed);
+                break;
+            }
+        }
+        match result {
+            Ok(status) if (200..300).contains(&status) => {
+                self.logger.debug(format_args!(
+                    "Sent {} message(s) in {:?}",
+                    pending.len(),
+                    started.elapsed()
+                ));
+                self.breaker.record_success();
+                self.diagnostics.set_registered();
+                for frame in pending.drain() {
+                    self.diagnostics.record_dequeued();
+                    self.diagnostics
+                        .record_sent(frame.json.len(), frame.is_event);
+                    frame.audit(&self.diagnostics, Outcome::Acked);
+                    if let Some(event) = &frame.event {
+                        self.diagnostics.latency().delivered(event, started);
+                    }
+                }
+                true
+            }
+            Ok(401) | Ok(403) => {
+                self.logger.error(format_args!("Authentication failed"));
+                self.diagnostics
+                    .set_connection_state(ConnectionState::AuthFailed);
+                self.diagnostics
+                    .record_error("backend rejected the API key");
+                for frame in pending.drain() {
+                    self.diagnostics.record_dequeued();
+                    self.diagnostics.record_lost();
+                    if frame.is_event {
+                        self.diagnostics.record_drop(DropReason::NotConnected);
+                    }
+                    frame.audit(
+                        &self.diagnostics,
+                        Outcome::Dropped(DropReason::NotConnected),
+                    );
+                }
+                false
+            }
+            Ok(status) => {
+                self.diagnostics
+                    .record_error(format!("send failed: HTTP {}", status));
+                self.logger
+                    .debug(format_args!("Send failed: HTTP {}", status));
+                self.breaker.record_failure();
+                false
+            }
+            Err(e) => {
+                self.diagnostics.record_error(format!("send failed: {}", e));
+                self.logger.debug(format_args!("Send failed: {}", e));
+                self.breaker.record_failure();
+                false
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "wasi"))]
+fn default_sender() -> Option<HttpSender> {
+    let tls = native_tls::TlsConnector::new().ok()?;
+    let agent = ureq::AgentBuilder::new()
+        .tls_connector(Arc::new(tls))
+        .build();
+    Some(Arc::new(move |request: &HttpRequest| {
+        let mut call = agent.post(&request.url).timeout(request.timeout);
+        for (name, value) in &request.headers {
+            call = call.set(name, value);
+        }
+        match call.send_string(&request.body) {
+            Ok(response) => Ok(response.status()),
+            Err(ureq::Error::Status(status, _)) => Ok(status),
+            Err(e) => Err(e.to_string()),
+        }
+    }))
+}
+
+#[cfg(target_os = "wasi")]
+fn default_sender() -> Option<HttpSender> {
+    None
+}
@@ -0,0 +1,92 @@
+//! Detection of half-open WebSocket connections.
+//!
+//! A connection whose peer vanished, or that a proxy or NAT dropped silently,
+//! still accepts writes for a while and shows no error. The backend answers
+//! every event with an `ack` carrying its ID and every ping with a pong, so
+//! once an event or ping has gone without its own answer for longer than
+//! `liveness_timeout`, the connection is dead and is torn down. An answer to
+//! one write says nothing about the others, which may have been lost since.
+
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+/// Reference point of [`now`], unaffected by changes to the wall clock.
+static START: Lazy<Instant> = Lazy::new(Instant::now);
+
+/// Nanoseconds since [`START`], never zero.
+fn now() -> i64 {
+    START.elapsed().as_nanos() as i64 + 1
+}
+
+pub(crate) struct Liveness {
+    /// When each unacknowledged event was written, by event ID.
+    unacked: Mutex<HashMap<String, i64>>,
+    /// When the oldest unanswered ping was written, or zero.
+    ping_sent: AtomicI64,
+    dead: Notify,
+}
+
+impl Liveness {
+    pub(crate) fn new() -> Self {
+        Liveness {
+            unacked: Mutex::new(HashMap::new()),
+            ping_sent: AtomicI64::new(0),
+            dead: Notify::new(),
+        }
+    }
+
+    /// Records a written event the backend acknowledges as `id`.
+    pub(crate) fn expect_ack(&self, id: String) {
+        self.unacked.lock().entry(id).or_insert_with(now);
+    }
+
+    /// Records the acknowledgement of the event `id`.
+    pub(crate) fn acked(&self, id: &str) {
+        self.unacked.lock().remove(id);
+    }
+
+    /// Returns the payload of a ping about to be written, which the pong
+    /// echoes back.
+    pub(crate) fn ping(&self) -> Vec<u8> {
+        let sent = now();
+        let _ = self
+            .ping_sent
+            .compare_exchange(0, sent, Ordering::SeqCst, Ordering::SeqCst);
+        sent.to_be_bytes().to_vec()
+    }
+
+    /// Records a pong echoing `payload`, which answers the ping it was sent
+    /// with and those written before.
+    pub(crate) fn ponged(&self, payload: &[u8]) {
+        let Ok(sent) = <[u8; 8]>::try_from(payload).map(i64::from_be_bytes) else {
+            return;
+        };
+        let _ = self
+            .ping_sent
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |oldest| {
+                (oldest <= sent).then_some(0)
+            });
+    }
+
+    /// Whether an event or ping has gone unanswered for longer than `timeout`.
+    pub(crate) fn overdue(&self, timeout: Duration) -> bool {
+        let deadline = now() - timeout.as_nanos() as i64;
+        let ping_sent = self.ping_sent.load(Ordering::SeqCst);
+        (ping_sent != 0 && ping_sent < deadline)
+            || self.unacked.lock().values().any(|&sent| sent < deadline)
+    }
+
+    /// Ends the connection; the read loop returns and the agent reconnects.
+    pub(crate) fn declare_dead(&self) {
+        self.dead.notify_one();
+    }
+
+    /// Completes once the connection was declared dead.
+    pub(crate) async fn dead(&self) {
+        self.dead.notified().await;
+    }
+}
@@ -0,0 +1,161 @@
+//! Outbound message queue with priority lanes.
+//!
+//! Under congestion a panic should not wait behind hundreds of handled
+//! errors. Frames are sent highest priority first, and oldest first within a
+//! priority. When the queue is full, the oldest frame of the lowest priority
+//! is dropped to make room.
+
+use super::Frame;
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::Notify;
+
+/// Messages the WebSocket transport queues before dropping.
+#[cfg_attr(target_os = "wasi", allow(dead_code))]
+pub(crate) const MAX_QUEUED: usize = 1000;
+
+/// How urgently a message is sent, lowest first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Priority {
+    /// Check-ins, heartbeats and other agent traffic.
+    Low,
+    /// Handled errors and breakpoint hits.
+    Error,
+    /// Panics, and messages sent while the process exits.
+    Fatal,
+}
+
+/// Frames by priority, bounded to a total capacity.
+pub(crate) struct Lanes {
+    lanes: [VecDeque<Frame>; 3],
+    capacity: usize,
+}
+
+impl Lanes {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Lanes {
+            lanes: Default::default(),
+            capacity,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.lanes.iter().map(VecDeque::len).sum()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.lanes.iter().all(VecDeque::is_empty)
+    }
+
+    /// Queues `frame`, returning the frame dropped to stay within capacity.
+    ///
+    /// That is the oldest frame of the lowest non-empty priority up to
+    /// `frame`'s own, or `frame` itself if everything queued outranks it.
+    pub(crate) fn push(&mut self, frame: Frame) -> Option<Frame> {
+        let priority = frame.priority as usize;
+        let dropped = if self.len() >= self.capacity {
+            match self.lanes[..=priority]
+                .iter_mut()
+                .find(|lane| !lane.is_empty())
+            {
+                Some(lane) => lane.pop_front(),
+                None => return Some(frame),
+            }
+        } else {
+            None
+        };
+        self.lanes[priority].push_back(frame);
+        dropped
+    }
+
+    /// Takes the next frame to send.
+    #[cfg_attr(target_os = "wasi", allow(dead_code))]
+    pub(crate) fn pop(&mut self) -> Option<Frame> {
+        self.lanes.iter_mut().rev().find_map(VecDeque::pop_front)
+    }
+
+    /// Queued frames in the order they are sent.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &Frame> {
+        self.lanes.iter().rev().flatten()
+    }
+
+    /// Removes every frame, in the order they are sent.
+    pub(crate) fn drain(&mut self) -> impl Iterator<Item = Frame> + '_ {
+        self.lanes.iter_mut().rev().flat_map(|lane| lane.drain(..))
+    }
+
+    /// Removes the frames taken from the spool, in the order they are sent.
+    pub(crate) fn take_spooled(&mut self) -> Vec<Frame> {
+        let mut spooled = Vec::new();
+        for lane in self.lanes.iter_mut().rev() {
+            let (taken, kept): (VecDeque<Frame>, VecDeque<Frame>) =
+                lane.drain(..).partition(|frame| frame.spooled);
+            spooled.extend(taken);
+            *lane = kept;
+        }
+        spooled
+    }
+}
+
+/// Queue between the capturing threads and a WebSocket connection's writer.
+pub(crate) struct OutboundQueue {
+    lanes: Mutex<Lanes>,
+    ready: Notify,
+    closed: AtomicBool,
+}
+
+impl OutboundQueue {
+    #[cfg_attr(target_os = "wasi", allow(dead_code))]
+    pub(crate) fn new(capacity: usize) -> Self {
+        OutboundQueue {
+            lanes: Mutex::new(Lanes::new(capacity)),
+            ready: Notify::new(),
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    /// Queues `frame`; see [`Lanes::push`].
+    pub(crate) fn push(&self, frame: Frame) -> Option<Frame> {
+        let dropped = self.lanes.lock().push(frame);
+        self.ready.notify_one();
+        dropped
+    }
+
+    /// Waits for the next frame to send. Returns `None` once the queue is
+    /// closed and empty.
+    #[cfg_attr(target_os = "wasi", allow(dead_code))]
+    pub(crate) async fn pop(&self) -> Option<Frame> {
+        loop {
+            if let Some(frame) = self.lanes.lock().pop() {
+                return Some(frame);
+            }
+            if self.is_closed() {
+                return None;
+            }
+            // Only the writer waits, so a permit stored by `notify_one`
+            // between the check above and here is not lost.
+            self.ready.notified().await;
+        }
+    }
+
+    /// Removes every queued frame.
+    pub(crate) fn drain(&self) -> Vec<Frame> {
+        self.lanes.lock().drain().collect()
+    }
+
+    /// Removes the queued frames taken from the spool.
+    pub(crate) fn take_spooled(&self) -> Vec<Frame> {
+        self.lanes.lock().take_spooled()
+    }
+
+    /// Stops accepting frames; the writer finishes what is queued.
+    pub(crate) fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        self.ready.notify_one();
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,316 @@
+//! Last events of a process that could not be sent.
+//!
+//! When a blocking send from the panic hook or shutdown fails, or finds no
+//! registered connection, the message is appended to a file in the state
+//! directory instead, one JSON line per message. The next agent started by
+//! the same executable sends them once connected. Those it does not write
+//! before the connection drops or the agent stops are written back.
+//!
+//! Messages contain everything an event carries, so the file is readable by
+//! its owner only, and nothing is spooled to the shared temp directory the
+//! default state directory falls back to without a home directory. A process
+//! that keeps failing before it connects drops the oldest messages once the
+//! file reaches [`MAX_BYTES`].
+//!
+//! With the `spool-encryption` feature and a `spool_encryption_key`, or with
+//! `spool-encryption-keychain` and the key in the OS keychain, each line is
+//! instead the message encrypted with ChaCha20-Poly1305 under a random nonce,
+//! base64-encoded after the nonce. Lines that can't be decrypted are left in
+//! the file for an agent with the right key; messages spooled before a key
+//! was set are still sent.
+
+use crate::config::{self, Config};
+#[cfg(feature = "spool-encryption")]
+use base64::Engine;
+#[cfg(feature = "spool-encryption")]
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+#[cfg(feature = "spool-encryption")]
+use chacha20poly1305::{ChaCha20Poly1305, Nonce};
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Service and account of the OS keychain entry holding the encryption key.
+#[cfg(feature = "spool-encryption-keychain")]
+const KEYCHAIN_ENTRY: (&str, &str) = ("aivory-monitor", "spool-encryption-key");
+
+/// Size of the spool file beyond which the oldest messages are dropped.
+const MAX_BYTES: u64 = 4 * 1024 * 1024;
+
+/// Length of the nonce leading each encrypted line.
+#[cfg(feature = "spool-encryption")]
+const NONCE_LEN: usize = 12;
+
+/// Messages left in the state directory by a process that could not send
+/// them, which the next agent started by the same executable sends.
+///
+/// [`Agent::replay_spool`](crate::Agent::replay_spool) sends them from
+/// another process, as `aivory spool replay` does.
+pub struct Spool {
+    path: PathBuf,
+    #[cfg(feature = "spool-encryption")]
+    cipher: Option<ChaCha20Poly1305>,
+}
+
+impl Spool {
+    /// The spool in the state directory of `config`, or `None` if there is
+    /// no state directory private to the user or its encryption key is
+    /// invalid.
+    pub(crate) fn new(config: &Config) -> Option<Self> {
+        Self::for_executable(config, &config::exe_name())
+    }
+
+    /// The spool of the executable named `executable` in the state directory
+    /// of `config`, or `None` if there is no state directory private to the
+    /// user or its encryption key is invalid or can't be read.
+    pub fn for_executable(config: &Config, executable: &str) -> Option<Self> {
+        let dir = config.private_state_dir()?;
+        #[cfg(feature = "spool-encryption")]
+        let cipher = if config.spool_encryption_key.is_some() {
+            Some(ChaCha20Poly1305::new(&config.spool_key()?.into()))
+        } else if config.spool_encrypted() {
+            Some(ChaCha20Poly1305::new(&keychain_key(config)?.into()))
+        } else {
+            None
+        };
+        Some(Spool {
+            path: dir.join(format!("pending-{}.jsonl", executable)),
+            #[cfg(feature = "spool-encryption")]
+            cipher,
+        })
+    }
+
+    /// Appends a serialized message, dropping the oldest ones if the file
+    /// would grow past [`MAX_BYTES`].
+    pub(crate) fn write(&self, json: &str) -> io::Result<()> {
+        self.append(&self.seal(json)?)
+    }
+
+    /// Appends a line as stored, dropping the oldest ones if the file would
+    /// grow past [`MAX_BYTES`].
+    fn append(&self, line: &str) -> io::Result<()> {
+        let line = format!("{}\n", line);
+        if line.len() as u64 > MAX_BYTES {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "message larger than the spool",
+            ));
+        }
+        if let Some(dir) = self.path.parent() {
+            config::create_state_dir(dir)?;
+        }
+        let mut file =
+            config::open_private(OpenOptions::new().create(true).append(true), &self.path)?;
+        if file.metadata()?.len() + line.len() as u64 > MAX_BYTES {
+            drop(file);
+            self.drop_oldest(line.len() as u64)?;
+            file = config::open_private(OpenOptions::new().create(true).append(true), &self.path)?;
+        }
+        // One write per line keeps messages from processes ending together intact.
+        file.write_all(line.as_bytes())
+    }
+
+    /// Rewrites the file without its oldest messages, leaving room for `needed` bytes.
+    fn drop_oldest(&self, needed: u64) -> io::Result<()> {
+        let contents = fs::read_to_string(&self.path)?;
+        let mut kept = contents.as_str();
+        while kept.len() as u64 + needed > MAX_BYTES {
+            kept = kept.split_once('\n').map_or("", |(_, rest)| rest);
+        }
+        config::write_private(&self.path, kept.as_bytes())
+    }
+
+    /// The spools in the state directory of `config`, one per executable
+    /// that left messages there.
+    pub fn list(config: &Config) -> Vec<Self> {
+        let Some(dir) = config.private_state_dir() else {
+            return Vec::new();
+        };
+        let Ok(entries) = fs::read_dir(dir) else {
+            return Vec::new();
+        };
+        let mut executables: Vec<String> = entries
+            .filter_map(|entry| {
+                let name = entry.ok()?.file_name().into_string().ok()?;
+                Some(
+                    name.strip_prefix("pending-")?
+                        .strip_suffix(".jsonl")?
+                        .to_string(),
+                )
+            })
+            .collect();
+        executables.sort();
+        executables
+            .iter()
+            .filter_map(|executable| Self::for_executable(config, executable))
+            .collect()
+    }
+
+    /// Name of the executable whose messages these are.
+    pub fn executable(&self) -> &str {
+        let name = self
+            .path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+        name.strip_prefix("pending-")
+            .and_then(|name| name.strip_suffix(".jsonl"))
+            .unwrap_or(name)
+    }
+
+    /// Returns the stored messages, leaving them in place. Messages that
+    /// can't be decrypted are left out.
+    pub fn messages(&self) -> Vec<String> {
+        self.read(&self.path)
+    }
+
+    /// Removes and returns the stored messages. Those that can't be
+    /// decrypted stay in the file, or if they can't be written back, in the
+    /// file moved aside while sending.
+    pub fn take(&self) -> Vec<String> {
+        self.take_matching(|_| true)
+    }
+
+    /// Removes the messages in `sent`, which were read before, keeping any
+    /// stored since.
+    pub(crate) fn remove(&self, sent: &[String]) {
+        let mut sent = sent.iter().fold(HashMap::new(), |mut counts, json| {
+            *counts.entry(json.as_str()).or_insert(0) += 1;
+            counts
+        });
+        self.take_matching(|json| match sent.get_mut(json) {
+            Some(count) if *count > 0 => {
+                *count -= 1;
+                true
+            }
+            _ => false,
+        });
+    }
+
+    /// Removes and returns the stored messages `select` picks, leaving the
+    /// others as [`take`](Self::take) leaves those it can't decrypt.
+    fn take_matching(&self, mut select: impl FnMut(&str) -> bool) -> Vec<String> {
+        // Moved aside first, so messages appended meanwhile are kept for next time.
+        let taken = self
+            .path
+            .with_extension(format!("{}.sending", std::process::id()));
+        if fs::rename(&self.path, &taken).is_err() {
+            return Vec::new();
+        }
+        let contents = fs::read_to_string(&taken).unwrap_or_default();
+        let mut messages = Vec::new();
+        let mut kept = true;
+        for line in contents.lines().filter(|line| !line.is_empty()) {
+            match self.unseal(line) {
+                Some(json) if select(&json) => messages.push(json),
+                // Put back as stored, also for an agent with the key it was encrypted with.
+                _ => kept &= self.append(line).is_ok(),
+            }
+        }
+        if kept {
+            let _ = fs::remove_file(&taken);
+        }
+        messages
+    }
+
+    /// The messages stored in the file at `path`.
+    fn read(&self, path: &Path) -> Vec<String> {
+        let contents = fs::read_to_string(path).unwrap_or_default();
+        contents
+            .lines()
+            .filter(|line| !line.is_empty())
+            .filter_map(|line| self.unseal(line))
+            .collect()
+    }
+
+    /// `json` as stored, encrypted if the spool has a key.
+    fn seal(&self, json: &str) -> io::Result<String> {
+        #[cfg(feature = "spool-encryption")]
+        if let Some(cipher) = &self.cipher {
+            let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+            let ciphertext = cipher
+                .encrypt(&nonce, json.as_bytes())
+                .map_err(|_| io::Error::new(io::ErrorKind::Other, "failed to encrypt message"))?;
+            let sealed = [nonce.as_slice(), &ciphertext].concat();
+            return Ok(base64::engine::general_purpose::STANDARD.encode(sealed));
+        }
+        Ok(json.to_string())
+    }
+
+    /// The message stored as `line`, or `None` if it can't be decrypted.
+    fn unseal(&self, line: &str) -> Option<String> {
+        // Messages are JSON objects; any other line was encrypted.
+        if line.starts_with('{') {
+            return Some(line.to_string());
+        }
+        #[cfg(feature = "spool-encryption")]
+        if let Some(cipher) = &self.cipher {
+            let sealed = base64::engine::general_purpose::STANDARD
+                .decode(line)
+                .ok()?;
+            if sealed.len() < NONCE_LEN {
+                return None;
+            }
+            let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+            let json = cipher.decrypt(Nonce::from_slice(nonce), ciphertext).ok()?;
+            return String::from_utf8(json).ok();
+        }
+        None
+    }
+
+    /// Path of the file the messages are stored in.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// The encryption key kept in the OS keychain, stored there first if there
+/// is none, or `None` if the keychain can't be used.
+#[cfg(feature = "spool-encryption-keychain")]
+fn keychain_key(config: &Config) -> Option<[u8; 32]> {
+    let (service, account) = KEYCHAIN_ENTRY;
+    let read = || -> keyring::Result<Option<[u8; 32]>> {
+        let entry = keyring::Entry::new(service, account)?;
+        let key = match entry.get_password() {
+            Ok(key) => key,
+            Err(keyring::Error::NoEntry) => {
+                let key = ChaCha20Poly1305::generate_key(&mut OsRng);
+                entry.set_password(&base64::engine::general_purpose::STANDARD.encode(key))?;
+                // Read back, in case another agent stored its own meanwhile.
+                entry.get_password()?
+            }
+            Err(e) => return Err(e),
+        };
+        Ok(base64::engine::general_purpose::STANDARD
+            .decode(key.trim())
+            .ok()
+            .and_then(|key| key.try_into().ok()))
+    };
+    match read() {
+        Ok(Some(key)) => Some(key),
+        Ok(None) => {
+            config.logger.error(format_args!(
+                "Spool encryption key in the keychain is not 32 bytes, base64-encoded"
+            ));
+            None
+        }
+        Err(e) => {
+            config.logger.error(format_args!(
+                "Failed to read the spool encryption key from the keychain: {}",
+                e
+            ));
+            None
+        }
+    }
+}
+
+/// Without the `spool-encryption-keychain` feature, validation rejects a
+/// configuration using the keychain.
+#[cfg(all(
+    feature = "spool-encryption",
+    not(feature = "spool-encryption-keychain")
+))]
+fn keychain_key(_config: &Config) -> Option<[u8; 32]> {
+    None
+}
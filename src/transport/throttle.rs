@@ -0,0 +1,86 @@
+//! Bandwidth limit for constrained links.
+//!
+//! A token bucket holding up to one second's worth of bytes. Messages that
+//! don't fit wait in the outbound queue, which absorbs bursts and drops the
+//! lowest priority messages first once full. Without a limit, nothing waits.
+
+use parking_lot::Mutex;
+use std::time::{Duration, Instant};
+
+pub(crate) struct Throttle {
+    bucket: Mutex<Option<Bucket>>,
+}
+
+struct Bucket {
+    bytes_per_second: f64,
+    /// Bytes that may be sent now; negative after a message larger than the
+    /// bucket, which is let through and paid for afterwards.
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.bytes_per_second).min(self.bytes_per_second);
+        self.refilled_at = now;
+    }
+
+    /// Time until `bytes` can be sent, capped at a full bucket.
+    fn wait(&self, bytes: usize) -> Duration {
+        let needed = (bytes as f64).min(self.bytes_per_second);
+        if self.tokens >= needed {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((needed - self.tokens) / self.bytes_per_second)
+        }
+    }
+}
+
+impl Throttle {
+    pub(crate) fn new(bytes_per_second: Option<u64>) -> Self {
+        let throttle = Throttle {
+            bucket: Mutex::new(None),
+        };
+        throttle.set_limit(bytes_per_second);
+        throttle
+    }
+
+    /// Replaces the limit, starting with a full bucket if it changed.
+    pub(crate) fn set_limit(&self, bytes_per_second: Option<u64>) {
+        let mut bucket = self.bucket.lock();
+        let bytes_per_second = bytes_per_second.map(|bytes| bytes as f64);
+        if bucket.as_ref().map(|bucket| bucket.bytes_per_second) == bytes_per_second {
+            return;
+        }
+        *bucket = bytes_per_second.map(|bytes_per_second| Bucket {
+            bytes_per_second,
+            tokens: bytes_per_second,
+            refilled_at: Instant::now(),
+        });
+    }
+
+    /// How long to wait before `bytes` can be sent.
+    pub(crate) fn wait(&self, bytes: usize) -> Duration {
+        let mut bucket = self.bucket.lock();
+        let Some(bucket) = bucket.as_mut() else {
+            return Duration::ZERO;
+        };
+        bucket.refill();
+        bucket.wait(bytes)
+    }
+
+    /// Takes `bytes` from the bucket and returns how long to wait before
+    /// sending them.
+    pub(crate) fn reserve(&self, bytes: usize) -> Duration {
+        let mut bucket = self.bucket.lock();
+        let Some(bucket) = bucket.as_mut() else {
+            return Duration::ZERO;
+        };
+        bucket.refill();
+        let wait = bucket.wait(bytes);
+        bucket.tokens -= bytes as f64;
+        wait
+    }
+}
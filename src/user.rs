@@ -0,0 +1,53 @@
+//! The user affected by captured errors.
+
+use serde::{Deserialize, Serialize};
+
+/// The user affected by captured errors.
+///
+/// Well-known fields are reported under their own names; anything in `data`
+/// (tenant, plan, role, ...) is reported alongside them.
+///
+/// ```rust,no_run
+/// use aivory_monitor::User;
+///
+/// let mut user = User::new("user-123");
+/// user.email = Some("user@example.com".into());
+/// user.data.insert("plan".into(), "enterprise".into());
+/// aivory_monitor::set_user(user);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct User {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip_address: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub segment: Option<String>,
+    /// Additional attributes.
+    #[serde(flatten)]
+    pub data: serde_json::Map<String, serde_json::Value>,
+}
+
+impl User {
+    /// Creates a user with the given ID.
+    pub fn new(id: impl Into<String>) -> Self {
+        User {
+            id: Some(id.into()),
+            ..Default::default()
+        }
+    }
+
+    /// Returns true if no field is set.
+    pub fn is_empty(&self) -> bool {
+        self.id.is_none()
+            && self.email.is_none()
+            && self.username.is_none()
+            && self.ip_address.is_none()
+            && self.segment.is_none()
+            && self.data.is_empty()
+    }
+}
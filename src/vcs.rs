@@ -0,0 +1,73 @@
+//! Uncommitted changes of the git checkout the process runs from.
+//!
+//! Outside of production, events carry the revision, branch and diff of
+//! uncommitted changes under the `vcs` context key, read once at startup, so
+//! a failure that only happens locally can be traced to the local delta.
+
+use crate::config::Config;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Files whose changes are never included: environment files and keys.
+const EXCLUDED_PATHSPECS: &[&str] = &[
+    ":(exclude,glob)**/.env*",
+    ":(exclude,glob)**/*.pem",
+    ":(exclude,glob)**/*.key",
+    ":(exclude,glob)**/*secret*",
+];
+
+/// The `vcs` context value for the checkout containing `dir`, or `None`
+/// outside a git checkout or if git is not installed.
+pub(crate) fn context(dir: &Path, config: &Config) -> Option<serde_json::Value> {
+    let revision = git(dir, &["rev-parse", "HEAD"])?;
+    let branch = git(dir, &["rev-parse", "--abbrev-ref", "HEAD"]).filter(|branch| branch != "HEAD");
+
+    let mut args = vec!["diff", "HEAD", "--no-color", "--no-ext-diff", "--", "."];
+    args.extend(EXCLUDED_PATHSPECS);
+    let mut diff = git(dir, &args).unwrap_or_default();
+    if let Some(scrubber) = &config.vcs_diff_scrubber {
+        diff = scrubber(diff);
+    }
+
+    let truncated = diff.len() > config.max_vcs_diff_bytes;
+    if truncated {
+        let mut end = config.max_vcs_diff_bytes;
+        while !diff.is_char_boundary(end) {
+            end -= 1;
+        }
+        // Cut at a line end, so the diff stays readable.
+        end = diff[..end].rfind('\n').map_or(end, |newline| newline + 1);
+        diff.truncate(end);
+    }
+
+    let mut vcs = serde_json::json!({
+        "revision": revision,
+        "branch": branch,
+        "dirty": !diff.is_empty(),
+    });
+    if !diff.is_empty() {
+        vcs["diff"] = diff.into();
+        vcs["diff_truncated"] = truncated.into();
+    }
+    Some(vcs)
+}
+
+/// Runs git in `dir` and returns its trimmed output if it succeeds.
+fn git(dir: &Path, args: &[&str]) -> Option<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(
+        String::from_utf8_lossy(&output.stdout)
+            .trim_end()
+            .to_string(),
+    )
+}
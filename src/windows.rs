@@ -0,0 +1,185 @@
+//! Windows crash records and session details.
+//!
+//! Windows Error Reporting writes a `Report.wer` for every crash, which
+//! [`os_crashes`](crate::os_crashes) reports at the next startup.
+
+use crate::capture::{EventBuilder, Mechanism};
+use crate::config::{Config, SessionInfo};
+use crate::os_crashes::{OsCrash, EXCEPTION_TYPE};
+use chrono::{TimeZone, Utc};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// WER event types for crashes, as opposed to hangs or other reports.
+const CRASH_EVENT_TYPES: &[&str] = &["APPCRASH", "BEX", "BEX64", "MoAppCrash"];
+
+/// Milliseconds between the FILETIME epoch, 1601, and the Unix epoch.
+const FILETIME_UNIX_OFFSET_MILLIS: i64 = 11_644_473_600_000;
+
+#[link(name = "kernel32")]
+extern "system" {
+    fn GetCurrentProcessId() -> u32;
+    fn ProcessIdToSessionId(process_id: u32, session_id: *mut u32) -> i32;
+}
+
+/// The session the process runs in.
+pub(crate) fn session() -> Option<SessionInfo> {
+    let mut id = 0;
+    // SAFETY: `id` is a valid out pointer for the duration of the call.
+    if unsafe { ProcessIdToSessionId(GetCurrentProcessId(), &mut id) } == 0 {
+        return None;
+    }
+    let name = std::env::var("SESSIONNAME")
+        .ok()
+        .filter(|name| !name.is_empty());
+    Some(SessionInfo {
+        id,
+        interactive: id != 0,
+        remote: name.as_deref().is_some_and(|name| name.starts_with("RDP-")),
+        name,
+        client_name: std::env::var("CLIENTNAME")
+            .ok()
+            .filter(|name| !name.is_empty()),
+    })
+}
+
+/// A crash of `exe` read from a `Report.wer`.
+struct Crash {
+    event_type: String,
+    /// Unix time in milliseconds.
+    occurred_at: i64,
+    report_id: Option<String>,
+    app_version: Option<String>,
+    fault_module: Option<String>,
+    fault_module_version: Option<String>,
+    exception_code: Option<String>,
+    exception_offset: Option<String>,
+}
+
+impl Crash {
+    fn parse(report: &HashMap<String, String>) -> Option<Crash> {
+        let event_type = report.get("EventType")?.clone();
+        let filetime: i64 = report.get("EventTime")?.parse().ok()?;
+        // `Sig[N].Name` labels `Sig[N].Value`; the order varies by event type.
+        let signature = |label: &str| {
+            (0..16).find_map(|i| {
+                (report.get(&format!("Sig[{}].Name", i))? == label)
+                    .then(|| report.get(&format!("Sig[{}].Value", i)).cloned())
+                    .flatten()
+            })
+        };
+        Some(Crash {
+            event_type,
+            occurred_at: filetime / 10_000 - FILETIME_UNIX_OFFSET_MILLIS,
+            report_id: report.get("ReportIdentifier").cloned(),
+            app_version: signature("Application Version"),
+            fault_module: signature("Fault Module Name"),
+            fault_module_version: signature("Fault Module Version"),
+            exception_code: signature("Exception Code"),
+            exception_offset: signature("Exception Offset"),
+        })
+    }
+
+    fn into_os_crash(self) -> OsCrash {
+        let occurred_at = self.occurred_at;
+        OsCrash {
+            occurred_at,
+            event: self.event(),
+        }
+    }
+
+    fn event(self) -> EventBuilder {
+        let code = self.exception_code.as_deref().unwrap_or("unknown");
+        let message = match &self.fault_module {
+            Some(module) => format!(
+                "previous run crashed with exception 0x{} in {}",
+                code, module
+            ),
+            None => format!("previous run crashed with exception 0x{}", code),
+        };
+        let mut event = EventBuilder::new(EXCEPTION_TYPE)
+            .message(message)
+            .mechanism(Mechanism::Signal)
+            .handled(false)
+            .context(
+                "wer",
+                serde_json::json!({
+                    "event_type": self.event_type,
+                    "report_id": self.report_id,
+                    "app_version": self.app_version,
+                    "fault_module": self.fault_module,
+                    "fault_module_version": self.fault_module_version,
+                    "exception_code": self.exception_code,
+                    "exception_offset": self.exception_offset,
+                }),
+            );
+        if let Some(at) = Utc.timestamp_millis_opt(self.occurred_at).single() {
+            event = event.captured_at(at);
+        }
+        event
+    }
+}
+
+/// Crashes of `exe` after `since`, in Unix milliseconds.
+pub(crate) fn crashes_since(exe: &Path, since: i64, _config: &Config) -> Vec<OsCrash> {
+    let mut seen = HashSet::new();
+    report_dirs()
+        .iter()
+        .filter_map(|dir| std::fs::read_dir(dir).ok())
+        .flatten()
+        .filter_map(|entry| read_report(&entry.ok()?.path().join("Report.wer")))
+        .filter(|report| {
+            report
+                .get("AppPath")
+                .is_some_and(|path| path.eq_ignore_ascii_case(&exe.to_string_lossy()))
+        })
+        .filter_map(|report| Crash::parse(&report))
+        .filter(|crash| {
+            CRASH_EVENT_TYPES.contains(&crash.event_type.as_str()) && crash.occurred_at > since
+        })
+        // A report moves from the queue to the archive once sent to Microsoft.
+        .filter(|crash| {
+            crash
+                .report_id
+                .as_ref()
+                .map_or(true, |id| seen.insert(id.clone()))
+        })
+        .map(Crash::into_os_crash)
+        .collect()
+}
+
+/// Directories WER keeps reports in, for the user and for the machine.
+fn report_dirs() -> Vec<PathBuf> {
+    ["LOCALAPPDATA", "ProgramData"]
+        .iter()
+        .filter_map(std::env::var_os)
+        .flat_map(|base| {
+            let wer = PathBuf::from(base)
+                .join("Microsoft")
+                .join("Windows")
+                .join("WER");
+            [wer.join("ReportArchive"), wer.join("ReportQueue")]
+        })
+        .collect()
+}
+
+/// Reads the `key=value` lines of a `Report.wer`, which is usually UTF-16.
+fn read_report(path: &Path) -> Option<HashMap<String, String>> {
+    let bytes = std::fs::read(path).ok()?;
+    let text = match bytes.strip_prefix(&[0xFF, 0xFE]) {
+        Some(utf16) => {
+            let units: Vec<u16> = utf16
+                .chunks_exact(2)
+                .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        None => String::from_utf8_lossy(&bytes).into_owned(),
+    };
+    Some(
+        text.lines()
+            .filter_map(|line| line.split_once('='))
+            .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+            .collect(),
+    )
+}